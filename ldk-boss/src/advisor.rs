@@ -5,12 +5,12 @@ use crate::autopilot::{candidate, decider, opener};
 use crate::client::LdkClient;
 use crate::config::Config;
 use crate::db::Database;
-use crate::fees::{balance_modder, competitor, price_theory, size_modder, ABS_MAX_FEE_PPM, ABS_MIN_FEE_PPM};
+use crate::fees::{self, setter};
 use crate::judge::{algo as judge_algo, gatherer as judge_gatherer};
+use crate::rebalancer::earnings;
 use crate::state::NodeState;
-use crate::tracker::earnings as earnings_tracker;
 use serde::Serialize;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 // ───────────────────────────────────────────────────────────
 // Advisory data structures
@@ -28,6 +28,8 @@ pub struct Advisory {
     pub closes: Vec<CloseAdvice>,
     pub rebalances: Vec<RebalanceAdvice>,
     pub reconnects: Vec<ReconnectAdvice>,
+    /// Operator labels of all labeled peers, by node ID
+    pub labels: BTreeMap<String, String>,
 }
 
 #[derive(Serialize)]
@@ -42,6 +44,9 @@ pub struct FeeAdvice {
     pub balance_mult: f64,
     pub price_mult: f64,
     pub size_mult: f64,
+    pub probation_mult: f64,
+    pub drain_mult: f64,
+    pub liquidity_mult: f64,
     pub competitor_base_ppm: Option<u32>,
     pub changed: bool,
 }
@@ -72,6 +77,8 @@ pub struct RebalanceAdvice {
     pub dest_peer: String,
     pub dest_spendable_pct: f64,
     pub amount_msat: u64,
+    /// Largest single payment the amount is split into
+    pub max_chunk_msat: u64,
     pub max_fee_msat: u64,
 }
 
@@ -91,16 +98,15 @@ pub async fn collect(
     db: &Database,
     state: &NodeState,
 ) -> anyhow::Result<Advisory> {
-    let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
+    let timestamp = db.now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
     let node_id = state.node_info.node_id.clone();
-    let own_node_id = &state.node_info.node_id;
     let own_capacity_sats = state.total_channel_capacity_sats();
 
-    let fees = collect_fee_advice(config, client, db, state, own_node_id, own_capacity_sats).await;
+    let fees = collect_fee_advice(config, client, db, state).await;
     let opens = collect_open_advice(config, client, db, state).await;
     let closes = collect_close_advice(config, db, state);
     let rebalances = collect_rebalance_advice(config, db, state);
-    let reconnects = collect_reconnect_advice(state, db);
+    let reconnects = collect_reconnect_advice(state, db)?;
 
     Ok(Advisory {
         timestamp,
@@ -113,98 +119,84 @@ pub async fn collect(
         closes,
         rebalances,
         reconnects,
+        labels: crate::labels::all(db)?,
     })
 }
 
+/// Read-only planning view: the same recommendations as `collect`, but run
+/// inside a transaction that is always rolled back, so lazily-initialised
+/// state (price theory decks, pruned blacklist entries, ...) is never
+/// persisted. Only fee changes that would actually be applied are kept.
+pub async fn plan(
+    config: &Config,
+    client: &(impl LdkClient + Sync),
+    db: &Database,
+    state: &NodeState,
+) -> anyhow::Result<Advisory> {
+    // Every query must go through the connection holding the transaction
+    let pinned = db.pinned()?;
+    let db = pinned.as_ref().unwrap_or(db);
+
+    let conn = db.conn()?;
+    let tx = conn.unchecked_transaction()?;
+    let mut advisory = collect(config, client, db, state).await?;
+    tx.rollback()?;
+
+    advisory.fees.retain(|f| f.changed);
+    Ok(advisory)
+}
+
 async fn collect_fee_advice(
     config: &Config,
     client: &(impl LdkClient + Sync),
     db: &Database,
     state: &NodeState,
-    own_node_id: &str,
-    own_capacity_sats: u64,
 ) -> Vec<FeeAdvice> {
     let mut advice = Vec::new();
 
-    for channel in state.channels.iter().filter(|c| c.is_usable) {
-        let channel_value_sats = channel.channel_value_sats;
-        if channel_value_sats == 0 {
+    let managed: Vec<_> = state
+        .channels
+        .iter()
+        .filter(|c| fees::is_fee_managed(config, c) && state.is_automated(config, c))
+        .collect();
+    let ctx = fees::FeeContext::new(config, db, state, managed.len());
+    for channel in managed {
+        if channel.channel_value_sats == 0 {
             continue;
         }
-
-        // Competitor baseline
-        let (base_ppm, base_base_msat, competitor_ppm) = if config.fees.competitor_fee_enabled {
-            match competitor::get_competitor_fees(
-                client,
-                &channel.counterparty_node_id,
-                own_node_id,
-            )
-            .await
-            {
-                Some(cf) => (cf.median_ppm, cf.median_base_msat, Some(cf.median_ppm)),
-                None => (config.fees.default_ppm, config.fees.default_base_msat, None),
-            }
-        } else {
-            (config.fees.default_ppm, config.fees.default_base_msat, None)
-        };
-
-        // Balance ratio
-        let our_balance_ratio = channel.outbound_capacity_msat as f64
-            / (channel_value_sats as f64 * 1000.0);
-
-        let balance_mult = if config.fees.balance_modder_enabled {
-            balance_modder::get_ratio_binned(
-                our_balance_ratio,
-                channel_value_sats,
-                config.fees.preferred_bin_size_sats,
-            )
-        } else {
-            1.0
-        };
-
-        let price_mult = if config.fees.price_theory_enabled {
-            price_theory::get_fee_modifier(db, &channel.counterparty_node_id).unwrap_or(1.0)
-        } else {
-            1.0
-        };
-
-        let size_mult = if config.fees.size_modder_enabled {
-            size_modder::get_size_modifier(
-                client,
-                &channel.counterparty_node_id,
-                own_node_id,
-                own_capacity_sats,
-            )
-            .await
-            .unwrap_or(1.0)
-        } else {
-            1.0
+        let Ok(computed) = fees::compute_fees(config, client, db, &ctx, channel).await else {
+            continue;
         };
 
-        let combined = balance_mult * price_mult * size_mult;
-        let base_msat = ((base_base_msat as f64) * combined) as u32;
-        let ppm = ((base_ppm as f64) * combined) as u32;
-        let ppm = ppm.clamp(ABS_MIN_FEE_PPM, ABS_MAX_FEE_PPM);
-
         let current = channel.channel_config.as_ref();
         let current_base = current.and_then(|c| c.forwarding_fee_base_msat).unwrap_or(0);
         let current_ppm = current
             .and_then(|c| c.forwarding_fee_proportional_millionths)
             .unwrap_or(0);
+        let changed = setter::is_material_change(
+            config,
+            current_base,
+            current_ppm,
+            computed.base_msat,
+            computed.ppm,
+        );
 
         advice.push(FeeAdvice {
             channel_id: channel.channel_id.clone(),
             peer: channel.counterparty_node_id.clone(),
-            channel_sats: channel_value_sats,
+            channel_sats: channel.channel_value_sats,
             current_base_msat: current_base,
             current_ppm,
-            suggested_base_msat: base_msat,
-            suggested_ppm: ppm,
-            balance_mult,
-            price_mult,
-            size_mult,
-            competitor_base_ppm: competitor_ppm,
-            changed: current_base != base_msat || current_ppm != ppm,
+            suggested_base_msat: computed.base_msat,
+            suggested_ppm: computed.ppm,
+            balance_mult: computed.balance_mult,
+            price_mult: computed.price_mult,
+            size_mult: computed.size_mult,
+            probation_mult: computed.probation_mult,
+            drain_mult: computed.drain_mult,
+            liquidity_mult: computed.liquidity_mult,
+            competitor_base_ppm: computed.competitor_ppm,
+            changed,
         });
     }
 
@@ -244,7 +236,13 @@ async fn collect_open_advice(
         config.autopilot.max_proposals
     };
 
-    let plan = opener::plan_opens(config, &candidates, budget, max_proposals);
+    let plan = opener::plan_opens(
+        config,
+        &candidates,
+        budget,
+        max_proposals,
+        opener::observed_reserve_fraction(&state.channels),
+    );
 
     plan.into_iter()
         .map(|p| OpenAdvice {
@@ -267,7 +265,11 @@ fn collect_close_advice(config: &Config, db: &Database, state: &NodeState) -> Ve
         return Vec::new();
     }
 
-    let recs = judge_algo::judge(&peer_infos, config.judge.estimated_reopen_cost_sats);
+    let recs = judge_algo::judge(
+        &peer_infos,
+        config.judge.estimated_reopen_cost_sats,
+        config.judge.min_improvement_sats,
+    );
 
     recs.into_iter()
         .map(|r| {
@@ -291,121 +293,52 @@ fn collect_close_advice(config: &Config, db: &Database, state: &NodeState) -> Ve
 }
 
 fn collect_rebalance_advice(config: &Config, db: &Database, state: &NodeState) -> Vec<RebalanceAdvice> {
-    let usable: Vec<_> = state.channels.iter().filter(|c| c.is_usable).collect();
+    let usable: Vec<_> = state.channels.iter().filter(|c| state.is_automated(config, c)).collect();
     if usable.len() < 2 {
         return Vec::new();
     }
 
-    let max_spendable = config.rebalancer.max_spendable_percent;
-    let source_gap = config.rebalancer.source_gap_percent;
-    let target_pct = config.rebalancer.target_spendable_percent;
-    let max_fee_ppm = config.rebalancer.max_fee_ppm;
-    let since = chrono::Utc::now().timestamp() as f64 - 30.0 * 86400.0;
-
-    struct Bal {
-        peer: String,
-        spendable_msat: u64,
-        total_msat: u64,
-        spendable_pct: f64,
-    }
+    // The rebalancer's own plan, so advice follows primary_goal, margins,
+    // weighted targets and HTLC limits exactly as a cycle would
+    let Ok(plan) = earnings::plan(config, db, &usable) else {
+        return Vec::new();
+    };
+    let max_total_fee_msat = config
+        .rebalancer
+        .max_total_fee_sats
+        .min(earnings::ABS_MAX_REBALANCE_FEE_SATS)
+        * 1000;
 
-    let balances: Vec<Bal> = usable
+    plan.pairs
         .iter()
-        .filter_map(|ch| {
-            let total_msat = ch.channel_value_sats * 1000;
-            if total_msat == 0 {
-                return None;
+        .filter(|p| p.skip.is_none())
+        .map(|p| {
+            let src = &plan.channels[p.source].balance;
+            let dst = &plan.channels[p.destination].balance;
+            RebalanceAdvice {
+                source_peer: src.counterparty_node_id.clone(),
+                source_spendable_pct: src.spendable_percent,
+                dest_peer: dst.counterparty_node_id.clone(),
+                dest_spendable_pct: dst.spendable_percent,
+                amount_msat: p.amount_msat,
+                max_chunk_msat: p.max_chunk_msat,
+                max_fee_msat: p.fee_budget_msat.min(max_total_fee_msat),
             }
-            let spendable_pct = (ch.outbound_capacity_msat as f64 / total_msat as f64) * 100.0;
-            Some(Bal {
-                peer: ch.counterparty_node_id.clone(),
-                spendable_msat: ch.outbound_capacity_msat,
-                total_msat,
-                spendable_pct,
-            })
         })
-        .collect();
-
-    let mut destinations: Vec<(usize, i64)> = Vec::new();
-    let mut sources: Vec<(usize, i64)> = Vec::new();
-
-    for (i, bal) in balances.iter().enumerate() {
-        let peer_earnings =
-            match earnings_tracker::peer_earnings_since(db, &bal.peer, since) {
-                Ok(e) => e,
-                Err(_) => continue,
-            };
-
-        if bal.spendable_pct < max_spendable {
-            destinations.push((i, peer_earnings.out_net()));
-        } else if bal.spendable_pct > max_spendable + source_gap {
-            sources.push((i, peer_earnings.in_net()));
-        }
-    }
-
-    if destinations.is_empty() || sources.is_empty() {
-        return Vec::new();
-    }
-
-    destinations.sort_by(|a, b| b.1.cmp(&a.1));
-    sources.sort_by(|a, b| b.1.cmp(&a.1));
-
-    let num = destinations.len().min(sources.len());
-    let num_rebalance = ((num as f64 * 20.0 / 100.0) as usize).max(1);
-
-    let mut advice = Vec::new();
-
-    for i in 0..num_rebalance {
-        let (dst_idx, dst_earnings) = destinations[i];
-        let (src_idx, _) = sources[i];
-
-        if dst_earnings <= 0 {
-            break;
-        }
-
-        let dst = &balances[dst_idx];
-        let src = &balances[src_idx];
-
-        let dest_target_msat = (dst.total_msat as f64 * target_pct / 100.0) as u64;
-        let dest_needed_msat = dest_target_msat.saturating_sub(dst.spendable_msat);
-
-        let src_min_allowed_msat =
-            (src.total_msat as f64 * (max_spendable + source_gap) / 100.0) as u64;
-        let src_budget_msat = src.spendable_msat.saturating_sub(src_min_allowed_msat);
-
-        let amount_msat = dest_needed_msat.min(src_budget_msat);
-        if amount_msat == 0 {
-            continue;
-        }
-
-        let fee_budget_msat = (amount_msat as f64 * max_fee_ppm as f64 / 1_000_000.0) as u64;
-        let fee_budget_msat = fee_budget_msat.min(dst_earnings as u64);
-
-        if fee_budget_msat == 0 {
-            continue;
-        }
-
-        advice.push(RebalanceAdvice {
-            source_peer: src.peer.clone(),
-            source_spendable_pct: src.spendable_pct,
-            dest_peer: dst.peer.clone(),
-            dest_spendable_pct: dst.spendable_pct,
-            amount_msat,
-            max_fee_msat: fee_budget_msat,
-        });
-    }
-
-    advice
+        .collect()
 }
 
-fn collect_reconnect_advice(state: &NodeState, db: &Database) -> Vec<ReconnectAdvice> {
+fn collect_reconnect_advice(
+    state: &NodeState,
+    db: &Database,
+) -> anyhow::Result<Vec<ReconnectAdvice>> {
     let disconnected: Vec<_> = state
         .channels
         .iter()
         .filter(|ch| ch.is_channel_ready && !ch.is_usable)
         .collect();
 
-    let conn = db.conn();
+    let conn = db.conn()?;
     let mut advice = Vec::new();
 
     for ch in disconnected {
@@ -427,7 +360,7 @@ fn collect_reconnect_advice(state: &NodeState, db: &Database) -> Vec<ReconnectAd
         });
     }
 
-    advice
+    Ok(advice)
 }
 
 // ───────────────────────────────────────────────────────────
@@ -460,7 +393,7 @@ impl Advisory {
             println!(
                 "  {} with {}",
                 abbreviate(&f.channel_id),
-                abbreviate(&f.peer),
+                self.peer(&f.peer),
             );
             println!(
                 "    {} sat channel, {:.0}% outbound",
@@ -481,10 +414,14 @@ impl Advisory {
                 f.suggested_base_msat, f.suggested_ppm
             );
             println!(
-                "    Modifiers: balance={:.2}x price={:.2}x size={:.2}x{}",
+                "    Modifiers: balance={:.2}x price={:.2}x size={:.2}x probation={:.2}x \
+                 drain={:.2}x liquidity={:.2}x{}",
                 f.balance_mult,
                 f.price_mult,
                 f.size_mult,
+                f.probation_mult,
+                f.drain_mult,
+                f.liquidity_mult,
                 f.competitor_base_ppm
                     .map(|p| format!(" competitor={}ppm", p))
                     .unwrap_or_default(),
@@ -502,7 +439,7 @@ impl Advisory {
                 "  {}. Open {} sat with {}",
                 i + 1,
                 fmt_sats(o.amount_sats),
-                abbreviate(&o.node_id),
+                self.peer(&o.node_id),
             );
             println!("     Address: {}", o.address);
             println!("     Source:  {} (score: {:.1})", o.source, o.score);
@@ -518,7 +455,7 @@ impl Advisory {
             println!(
                 "  {}. Close {} with {} ({} sat)",
                 i + 1,
-                abbreviate(&c.peer),
+                self.peer(&c.peer),
                 fmt_sats(c.channel_sats),
                 c.channel_sats,
             );
@@ -537,19 +474,20 @@ impl Advisory {
         }
         for (i, r) in self.rebalances.iter().enumerate() {
             println!(
-                "  {}. Move ~{} sat toward {}",
+                "  {}. Move ~{} sat toward {}, in payments of <= {} sat",
                 i + 1,
                 fmt_sats(r.amount_msat / 1000),
-                abbreviate(&r.dest_peer),
+                self.peer(&r.dest_peer),
+                fmt_sats(r.max_chunk_msat / 1000),
             );
             println!(
                 "     Dest:   {} ({:.0}% spendable, needs outbound)",
-                abbreviate(&r.dest_peer),
+                self.peer(&r.dest_peer),
                 r.dest_spendable_pct,
             );
             println!(
                 "     Source:  {} ({:.0}% spendable, excess outbound)",
-                abbreviate(&r.source_peer),
+                self.peer(&r.source_peer),
                 r.source_spendable_pct,
             );
             println!("     Max fee: {} sat", r.max_fee_msat / 1000);
@@ -562,7 +500,7 @@ impl Advisory {
             println!("  All peers connected.");
         }
         for r in &self.reconnects {
-            println!("  {} at {}", abbreviate(&r.peer), r.address);
+            println!("  {} at {}", self.peer(&r.peer), r.address);
         }
 
         // Summary
@@ -587,6 +525,14 @@ impl Advisory {
         println!("{}", "=".repeat(w));
     }
 
+    /// Abbreviated node ID, followed by the peer's label if it has one.
+    fn peer(&self, node_id: &str) -> String {
+        match self.labels.get(node_id) {
+            Some(label) => format!("{} ({})", abbreviate(node_id), label),
+            None => abbreviate(node_id),
+        }
+    }
+
     pub fn print_json(&self) {
         println!(
             "{}",
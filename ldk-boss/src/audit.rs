@@ -0,0 +1,67 @@
+/// Append-only JSONL event stream for operators' own log pipelines.
+///
+/// When `audit.jsonl_path` is set, every significant action (fee change,
+/// open, close, rebalance, swap, reconnect) appends one line:
+/// `{"ts": <unix secs>, "type": "...", ...fields}`. The database audit
+/// tables stay the record of truth; a failed write is logged and otherwise
+/// ignored, so it never holds up the action it describes.
+
+use crate::config::Config;
+use log::warn;
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// Append an event of type `kind`. `fields` should be a JSON object.
+pub fn emit(config: &Config, kind: &str, fields: Value) {
+    let Some(path) = &config.audit.jsonl_path else {
+        return;
+    };
+
+    let mut event = json!({
+        "ts": chrono::Utc::now().timestamp(),
+        "type": kind,
+    });
+    if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), fields) {
+        event.extend(fields);
+    }
+
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", event));
+    if let Err(e) = result {
+        warn!("Audit: failed to append to {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+
+        // Unset: nothing written
+        emit(&config, "open", json!({"peer": "a"}));
+        assert!(!path.exists());
+
+        config.audit.jsonl_path = Some(path.clone());
+        emit(&config, "open", json!({"peer": "a", "amount_sats": 1_000_000}));
+        emit(&config, "close", json!({"peer": "b"}));
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "open");
+        assert_eq!(lines[0]["amount_sats"], 1_000_000);
+        assert!(lines[0]["ts"].as_i64().unwrap() > 0);
+        assert_eq!(lines[1]["peer"], "b");
+    }
+}
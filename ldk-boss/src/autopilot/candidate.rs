@@ -1,9 +1,10 @@
 use crate::client::LdkClient;
 use crate::config::Config;
 use crate::db::Database;
+use crate::tracker::uptime as uptime_tracker;
 use ldk_server_protos::api::GraphGetNodeRequest;
 use ldk_server_protos::api::GraphGetChannelRequest;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use rand::seq::SliceRandom;
 use std::collections::HashSet;
 
@@ -20,6 +21,7 @@ pub struct Candidate {
 pub enum CandidateSource {
     Hardcoded,
     SeedNode,
+    Retry,
     Earnings,
     External,
     GraphPopularity,
@@ -93,125 +95,351 @@ const TOP_EARNERS_COUNT: usize = 5;
 /// Earnings lookback window in seconds (30 days).
 const EARNINGS_LOOKBACK_SECS: i64 = 30 * 86400;
 
-/// Get a ranked list of channel candidates.
-pub async fn get_candidates(
-    config: &Config,
-    client: &impl LdkClient,
-    db: &Database,
-    existing_peers: &HashSet<String>,
-) -> anyhow::Result<Vec<Candidate>> {
-    let mut candidates = Vec::new();
-    let own_node_id = client
-        .get_node_info()
-        .await
-        .map(|info| info.node_id)
-        .unwrap_or_default();
+/// What a candidate finder gets to work with.
+pub struct FinderContext<'a, C> {
+    pub config: &'a Config,
+    pub client: &'a C,
+    pub db: &'a Database,
+    /// Peers we already have a channel with
+    pub existing_peers: &'a HashSet<String>,
+    pub own_node_id: &'a str,
+}
 
-    // Source 1: User-configured seed nodes
-    for seed in &config.autopilot.seed_nodes {
-        if let Some((node_id, address)) = parse_node_address(seed) {
-            if !existing_peers.contains(&node_id) && !is_blacklisted(config, &node_id) {
-                candidates.push(Candidate {
+/// One source of channel candidates.
+///
+/// Finders only propose; `get_candidates_from` drops existing peers, the
+/// blacklists and duplicates (the earlier finder wins), then scores and
+/// orders everything in one place. Implement this to plug in a source of
+/// your own and pass it to `get_candidates_from` with the built-in ones.
+#[async_trait::async_trait(?Send)]
+pub trait CandidateFinder<C: LdkClient> {
+    /// Short name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Propose candidates. `None` vetoes the round: no channels are opened
+    /// this cycle, whatever the other finders propose.
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>>;
+}
+
+/// User-configured seed nodes.
+pub struct SeedNodeFinder;
+/// Peers whose previous open failed transiently.
+pub struct RetryFinder;
+/// Peers of our top-earning counterparties (graph-based).
+pub struct EarningsFinder;
+/// Popular nodes from the gossip graph.
+pub struct PopularityFinder;
+/// Distance-based candidates (Dijkstra over the gossip graph).
+pub struct DistanceFinder;
+/// External ranking API.
+pub struct ExternalFinder;
+/// Hardcoded well-known nodes.
+pub struct HardcodedFinder;
+
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for SeedNodeFinder {
+    fn name(&self) -> &'static str {
+        "seed"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        Ok(Some(
+            ctx.config
+                .autopilot
+                .seed_nodes
+                .iter()
+                .filter_map(|seed| parse_node_address(seed))
+                .map(|(node_id, address)| Candidate {
                     node_id,
                     address,
                     score: 100.0, // Highest priority
                     source: CandidateSource::SeedNode,
-                });
-            }
-        }
+                })
+                .collect(),
+        ))
     }
+}
 
-    // Source 2: Peers of our top-earning counterparties (graph-based)
-    match get_earnings_candidates(client, db, existing_peers, &own_node_id).await {
-        Ok(earner_candidates) => {
-            for c in earner_candidates {
-                if !is_blacklisted(config, &c.node_id)
-                    && !candidates.iter().any(|e| e.node_id == c.node_id)
-                {
-                    candidates.push(c);
-                }
-            }
-        }
-        Err(e) => {
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for RetryFinder {
+    fn name(&self) -> &'static str {
+        "retry"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        Ok(Some(super::failures::retry_candidates(ctx.db)?))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for EarningsFinder {
+    fn name(&self) -> &'static str {
+        "earnings"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        let found = get_earnings_candidates(
+            ctx.client,
+            ctx.db,
+            ctx.existing_peers,
+            ctx.own_node_id,
+            &ctx.config.autopilot.address_preference,
+            ctx.config.autopilot.older_earnings_weight,
+        )
+        .await;
+        Ok(Some(found.unwrap_or_else(|e| {
             warn!("Graph earnings candidate discovery failed: {}", e);
-        }
+            Vec::new()
+        })))
     }
+}
 
-    // Source 3: Popular nodes from gossip graph
-    match get_popularity_candidates(client, existing_peers, &own_node_id).await {
-        Ok(pop_candidates) => {
-            for c in pop_candidates {
-                if !is_blacklisted(config, &c.node_id)
-                    && !candidates.iter().any(|e| e.node_id == c.node_id)
-                {
-                    candidates.push(c);
-                }
-            }
-        }
-        Err(e) => {
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for PopularityFinder {
+    fn name(&self) -> &'static str {
+        "popularity"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        let found = get_popularity_candidates(
+            ctx.client,
+            ctx.existing_peers,
+            ctx.own_node_id,
+            &ctx.config.autopilot.address_preference,
+        )
+        .await;
+        Ok(Some(found.unwrap_or_else(|e| {
             warn!("Graph popularity candidate discovery failed: {}", e);
-        }
+            Vec::new()
+        })))
     }
+}
 
-    // Source 4: Distance-based candidates (Dijkstra over gossip graph)
-    match super::distance::get_distance_candidates(client, &own_node_id, &existing_peers).await {
-        Ok(dist_candidates) => {
-            for c in dist_candidates {
-                if !is_blacklisted(config, &c.node_id)
-                    && !candidates.iter().any(|e| e.node_id == c.node_id)
-                {
-                    candidates.push(c);
-                }
-            }
-        }
-        Err(e) => {
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for DistanceFinder {
+    fn name(&self) -> &'static str {
+        "distance"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        let found = super::distance::get_distance_candidates(
+            ctx.client,
+            ctx.own_node_id,
+            ctx.existing_peers,
+            &ctx.config.autopilot.address_preference,
+        )
+        .await;
+        Ok(Some(found.unwrap_or_else(|e| {
             warn!("Graph distance candidate discovery failed: {}", e);
-        }
+            Vec::new()
+        })))
     }
+}
 
-    // Source 5: External ranking API (if configured)
-    if !config.autopilot.ranking_api_url.is_empty() {
-        match fetch_external_candidates(&config.autopilot.ranking_api_url).await {
-            Ok(external) => {
-                for c in external {
-                    if !existing_peers.contains(&c.node_id)
-                        && !is_blacklisted(config, &c.node_id)
-                        && !candidates.iter().any(|e| e.node_id == c.node_id)
-                    {
-                        candidates.push(c);
-                    }
-                }
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for ExternalFinder {
+    fn name(&self) -> &'static str {
+        "external"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        let (config, db) = (ctx.config, ctx.db);
+        let external = external_candidates(config, db).await?;
+
+        if !external.is_empty() {
+            set_ranking_alert(db, false)?;
+            return Ok(Some(external));
+        }
+        match config.autopilot.on_ranking_empty.as_str() {
+            "skip" => {
+                warn!("Autopilot: ranking API returned no candidates, skipping opens this cycle");
+                return Ok(None);
             }
-            Err(e) => {
-                warn!("Failed to fetch external candidates: {}", e);
+            "alert" => {
+                error!(
+                    "Autopilot: ranking API {} returned no candidates -- check the integration; \
+                     falling back to graph and hardcoded nodes",
+                    config.autopilot.ranking_api_url
+                );
+                set_ranking_alert(db, true)?;
+            }
+            _ => {
+                warn!("Autopilot: ranking API returned no candidates, using other sources");
             }
         }
+        Ok(Some(Vec::new()))
     }
+}
 
-    // Source 6: Hardcoded well-known nodes
-    for (node_id, address) in HARDCODED_NODES {
-        let node_id = node_id.to_string();
-        if !existing_peers.contains(&node_id)
-            && !is_blacklisted(config, &node_id)
-            && !candidates.iter().any(|c| c.node_id == node_id)
-        {
-            candidates.push(Candidate {
-                node_id,
-                address: address.to_string(),
-                score: 10.0,
-                source: CandidateSource::Hardcoded,
-            });
+#[async_trait::async_trait(?Send)]
+impl<C: LdkClient> CandidateFinder<C> for HardcodedFinder {
+    fn name(&self) -> &'static str {
+        "hardcoded"
+    }
+
+    async fn find(&self, ctx: &FinderContext<'_, C>) -> anyhow::Result<Option<Vec<Candidate>>> {
+        Ok(Some(
+            hardcoded_nodes(ctx.config)
+                .map(|(node_id, address)| Candidate {
+                    node_id: node_id.to_string(),
+                    address: address.to_string(),
+                    score: 10.0,
+                    source: CandidateSource::Hardcoded,
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// The built-in finders, in priority order, as configured.
+pub fn default_finders<C: LdkClient>(config: &Config) -> Vec<Box<dyn CandidateFinder<C>>> {
+    let mut finders: Vec<Box<dyn CandidateFinder<C>>> = vec![
+        Box::new(SeedNodeFinder),
+        Box::new(RetryFinder),
+        Box::new(EarningsFinder),
+        Box::new(PopularityFinder),
+        Box::new(DistanceFinder),
+    ];
+    if !config.autopilot.ranking_api_url.is_empty() {
+        finders.push(Box::new(ExternalFinder));
+    }
+    finders.push(Box::new(HardcodedFinder));
+    finders
+}
+
+/// Get a ranked list of channel candidates from the built-in finders.
+pub async fn get_candidates<C: LdkClient>(
+    config: &Config,
+    client: &C,
+    db: &Database,
+    existing_peers: &HashSet<String>,
+) -> anyhow::Result<Vec<Candidate>> {
+    get_candidates_from(config, client, db, existing_peers, &default_finders(config)).await
+}
+
+/// Get a ranked list of channel candidates from `finders`, earlier finders
+/// taking precedence for nodes proposed more than once.
+pub async fn get_candidates_from<C: LdkClient>(
+    config: &Config,
+    client: &C,
+    db: &Database,
+    existing_peers: &HashSet<String>,
+    finders: &[Box<dyn CandidateFinder<C>>],
+) -> anyhow::Result<Vec<Candidate>> {
+    let own_node_id = client
+        .get_node_info()
+        .await
+        .map(|info| info.node_id)
+        .unwrap_or_default();
+    let ctx = FinderContext {
+        config,
+        client,
+        db,
+        existing_peers,
+        own_node_id: &own_node_id,
+    };
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for finder in finders {
+        let Some(found) = finder.find(&ctx).await? else {
+            return Ok(Vec::new());
+        };
+        debug!("Autopilot: {} finder proposed {} candidates", finder.name(), found.len());
+        for c in found {
+            if c.node_id != own_node_id
+                && !existing_peers.contains(&c.node_id)
+                && !is_blacklisted(config, &c.node_id)
+                && !candidates.iter().any(|e| e.node_id == c.node_id)
+            {
+                candidates.push(c);
+            }
         }
     }
 
-    // Sort by score descending
-    candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    // Drop peers temporarily blacklisted after a failed open
+    let temp_blacklist = super::failures::temporarily_blacklisted(db)?;
+    candidates.retain(|c| !temp_blacklist.contains(&c.node_id));
+
+    // Tor-only: seed, retry and hardcoded addresses weren't chosen by
+    // preference, so enforce it here; nothing may leak a clearnet connection
+    if config.autopilot.address_preference == "tor" {
+        candidates.retain(|c| is_onion(&c.address));
+    }
+
+    if config.autopilot.stability_weight > 0.0 {
+        apply_stability(config, db, &mut candidates)?;
+    }
+
+    if config.autopilot.selection == "weighted_random" {
+        // Random order biased by score, so opens spread beyond the top few hubs
+        weighted_shuffle(&mut candidates, &mut rand::thread_rng());
+    } else {
+        // Sort by score descending
+        candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    }
+
+    // After ranking, so the best of an operator's nodes are the ones kept
+    super::operators::limit_per_operator(config, db, &mut candidates)?;
 
     debug!("Autopilot: {} candidates available", candidates.len());
 
     Ok(candidates)
 }
 
+/// Scale down candidates we've seen disconnect repeatedly while we had a
+/// channel with them. Nodes we never had a channel with are unaffected.
+fn apply_stability(
+    config: &Config,
+    db: &Database,
+    candidates: &mut [Candidate],
+) -> anyhow::Result<()> {
+    let since = (db.now().timestamp() - EARNINGS_LOOKBACK_SECS) as f64;
+    for c in candidates.iter_mut() {
+        let disconnects = uptime_tracker::disconnects_since(db, &c.node_id, since)?;
+        if disconnects > 0 {
+            c.score /= 1.0 + config.autopilot.stability_weight * disconnects as f64;
+            debug!(
+                "Autopilot: {} disconnected {} times, score lowered to {:.1}",
+                c.node_id, disconnects, c.score
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Our highest-earning counterparties by outgoing fee (direction='out'
+/// means we forwarded through them), with their weighted earnings. Earnings
+/// of the last 30 days count in full, older ones at `older_weight` (0 =
+/// not at all), so a peer that earned well long ago but not lately ranks
+/// below one earning now.
+fn top_earners(
+    db: &Database,
+    existing_peers: &HashSet<String>,
+    older_weight: f64,
+) -> anyhow::Result<Vec<(String, i64)>> {
+    let since = db.now().timestamp() - EARNINGS_LOOKBACK_SECS;
+    let since_bucket = since - (since % 86400);
+
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare(
+        "SELECT counterparty_node_id, CAST(SUM(CASE WHEN day_bucket >= ?1 \
+             THEN fee_earned_msat ELSE fee_earned_msat * ?2 END) AS INTEGER) AS weighted_fee \
+         FROM earnings \
+         WHERE (day_bucket >= ?1 OR ?2 > 0) AND direction = 'out' \
+         GROUP BY counterparty_node_id \
+         ORDER BY weighted_fee DESC \
+         LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(
+        rusqlite::params![since_bucket, older_weight, TOP_EARNERS_COUNT as i64],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+    )?;
+    Ok(rows
+        .filter_map(|r| r.ok())
+        .filter(|(node_id, fee)| *fee > 0 && !existing_peers.contains(node_id.as_str()))
+        .collect())
+}
+
 /// Find peers of our highest-earning counterparties via the gossip graph.
 ///
 /// Port of CLBoss `ChannelFinderByEarnedFee`: finds the peers with the highest
@@ -221,31 +449,10 @@ async fn get_earnings_candidates(
     db: &Database,
     existing_peers: &HashSet<String>,
     own_node_id: &str,
+    address_preference: &str,
+    older_earnings_weight: f64,
 ) -> anyhow::Result<Vec<Candidate>> {
-    let since = chrono::Utc::now().timestamp() - EARNINGS_LOOKBACK_SECS;
-    let since_bucket = since - (since % 86400);
-
-    // Query top earners by outgoing fee (direction='out' means we forwarded through them)
-    let top_earners: Vec<(String, i64)> = {
-        let conn = db.conn();
-        let mut stmt = conn.prepare(
-            "SELECT counterparty_node_id, SUM(fee_earned_msat) as total_fee \
-             FROM earnings \
-             WHERE day_bucket >= ?1 AND direction = 'out' \
-             GROUP BY counterparty_node_id \
-             ORDER BY total_fee DESC \
-             LIMIT ?2",
-        )?;
-        let rows = stmt.query_map(
-            rusqlite::params![since_bucket, TOP_EARNERS_COUNT as i64],
-            |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
-            },
-        )?;
-        rows.filter_map(|r| r.ok())
-            .filter(|(node_id, fee)| *fee > 0 && !existing_peers.contains(node_id.as_str()))
-            .collect()
-    };
+    let top_earners = top_earners(db, existing_peers, older_earnings_weight)?;
 
     if top_earners.is_empty() {
         debug!("Autopilot: no earning peers found for graph discovery");
@@ -316,7 +523,7 @@ async fn get_earnings_candidates(
             }
 
             // Get the peer's address from graph announcement
-            if let Some(address) = resolve_node_address(client, peer_id).await {
+            if let Some(address) = resolve_node_address(client, peer_id, address_preference).await {
                 // Score: 50.0 for rank 0, decreasing for lower ranks
                 let score = 50.0 - (rank as f64 * 5.0);
                 candidates.push(Candidate {
@@ -345,6 +552,7 @@ async fn get_popularity_candidates(
     client: &impl LdkClient,
     existing_peers: &HashSet<String>,
     own_node_id: &str,
+    address_preference: &str,
 ) -> anyhow::Result<Vec<Candidate>> {
     // Step 1: Get all node IDs
     let all_nodes = client.graph_list_nodes().await?;
@@ -403,7 +611,9 @@ async fn get_popularity_candidates(
             && popular_id != own_node_id
             && !candidates.iter().any(|c: &Candidate| c.node_id == *popular_id)
         {
-            if let Some(address) = resolve_node_address(client, popular_id).await {
+            if let Some(address) =
+                resolve_node_address(client, popular_id, address_preference).await
+            {
                 let score = 30.0 * (*degree as f64 / max_degree);
                 candidates.push(Candidate {
                     node_id: popular_id.clone(),
@@ -462,7 +672,7 @@ async fn get_popularity_candidates(
                 continue;
             }
 
-            if let Some(address) = resolve_node_address(client, peer_id).await {
+            if let Some(address) = resolve_node_address(client, peer_id, address_preference).await {
                 // Peers of popular nodes get slightly lower score than the hub itself
                 let score = 25.0 * (*degree as f64 / max_degree);
                 candidates.push(Candidate {
@@ -482,8 +692,13 @@ async fn get_popularity_candidates(
     Ok(candidates)
 }
 
-/// Resolve a node's reachable address from its gossip graph announcement.
-pub async fn resolve_node_address(client: &impl LdkClient, node_id: &str) -> Option<String> {
+/// Resolve a node's reachable address from its gossip graph announcement,
+/// per `autopilot.address_preference`.
+pub async fn resolve_node_address(
+    client: &impl LdkClient,
+    node_id: &str,
+    preference: &str,
+) -> Option<String> {
     let resp = client
         .graph_get_node(GraphGetNodeRequest {
             node_id: node_id.to_string(),
@@ -492,7 +707,59 @@ pub async fn resolve_node_address(client: &impl LdkClient, node_id: &str) -> Opt
         .ok()?;
     let node = resp.node?;
     let ann = node.announcement_info?;
-    ann.addresses.into_iter().next()
+    pick_address(ann.addresses, preference)
+}
+
+fn is_onion(address: &str) -> bool {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.ends_with(".onion")
+}
+
+/// Choose among a node's advertised addresses: "tor" takes only an onion
+/// address, "clearnet" prefers a non-onion one but falls back to onion, and
+/// "any" takes the first advertised.
+fn pick_address(addresses: Vec<String>, preference: &str) -> Option<String> {
+    match preference {
+        "tor" => addresses.into_iter().find(|a| is_onion(a)),
+        "clearnet" => {
+            let clearnet = addresses.iter().find(|a| !is_onion(a)).cloned();
+            clearnet.or_else(|| addresses.into_iter().next())
+        }
+        _ => addresses.into_iter().next(),
+    }
+}
+
+/// Reorder candidates randomly, with each one's chance of landing near the
+/// front proportional to its score (weighted sampling without replacement,
+/// Efraimidis-Spirakis: sort by `u^(1/score)` descending).
+fn weighted_shuffle(candidates: &mut [Candidate], rng: &mut impl rand::Rng) {
+    let mut keyed: Vec<(f64, Candidate)> = candidates
+        .iter()
+        .map(|c| {
+            let weight = c.score.max(f64::MIN_POSITIVE);
+            let u: f64 = rng.gen_range(f64::MIN_POSITIVE..1.0);
+            (u.powf(1.0 / weight), c.clone())
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    for (slot, (_, c)) in candidates.iter_mut().zip(keyed) {
+        *slot = c;
+    }
+}
+
+/// HARDCODED_NODES minus any entries the operator excluded in config.
+pub fn hardcoded_nodes(
+    config: &Config,
+) -> impl Iterator<Item = &'static (&'static str, &'static str)> + '_ {
+    HARDCODED_NODES
+        .iter()
+        .filter(|(node_id, _)| {
+            !config
+                .autopilot
+                .exclude_hardcoded
+                .iter()
+                .any(|e| e.as_str() == *node_id)
+        })
 }
 
 fn is_blacklisted(config: &Config, node_id: &str) -> bool {
@@ -509,6 +776,63 @@ pub fn parse_node_address(s: &str) -> Option<(String, String)> {
     }
 }
 
+const RANKING_ALERT_KEY: &str = "ranking_api_empty_since";
+
+/// Record (or clear) that the ranking API came back empty, so `status` can
+/// surface it. Keeps the time of the first empty response.
+fn set_ranking_alert(db: &Database, active: bool) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+    if active {
+        conn.execute(
+            "INSERT OR IGNORE INTO run_state (key, value) VALUES (?1, ?2)",
+            rusqlite::params![RANKING_ALERT_KEY, db.now().timestamp().to_string()],
+        )?;
+    } else {
+        conn.execute("DELETE FROM run_state WHERE key = ?1", [RANKING_ALERT_KEY])?;
+    }
+    Ok(())
+}
+
+/// Unix time since which the ranking API has been returning nothing, if an
+/// "alert" is outstanding.
+pub fn ranking_alert_since(db: &Database) -> Option<i64> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [RANKING_ALERT_KEY],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
+
+/// Candidates from the ranking API, through the cache: a fresh cached
+/// response is reused, and a failed refresh falls back to the stale one.
+async fn external_candidates(config: &Config, db: &Database) -> anyhow::Result<Vec<Candidate>> {
+    let ttl_mins = config.autopilot.ranking_cache_ttl_mins;
+    if let Some(cached) = super::ranking_cache::fresh(db, ttl_mins)? {
+        debug!("Autopilot: using {} cached ranking candidates", cached.len());
+        return Ok(cached);
+    }
+
+    match fetch_external_candidates(&config.autopilot.ranking_api_url).await {
+        Ok(external) => {
+            super::ranking_cache::store(db, &external)?;
+            Ok(external)
+        }
+        Err(e) => {
+            let cached = super::ranking_cache::stale(db, ttl_mins)?;
+            warn!(
+                "Failed to fetch external candidates: {}; using {} stale cached candidates",
+                e,
+                cached.len()
+            );
+            Ok(cached)
+        }
+    }
+}
+
 async fn fetch_external_candidates(_url: &str) -> anyhow::Result<Vec<Candidate>> {
     // External ranking API integration is not yet implemented.
     // Could integrate with 1ML, Amboss, or a custom ranking service.
@@ -592,6 +916,43 @@ mod tests {
         assert!(!is_blacklisted(&config, "goodnode456"));
     }
 
+    #[test]
+    fn test_hardcoded_nodes_exclusion() {
+        let mut config = test_config();
+        assert_eq!(hardcoded_nodes(&config).count(), HARDCODED_NODES.len());
+
+        let excluded = HARDCODED_NODES[0].0.to_string();
+        config.autopilot.exclude_hardcoded = vec![excluded.clone()];
+        assert_eq!(hardcoded_nodes(&config).count(), HARDCODED_NODES.len() - 1);
+        assert!(!hardcoded_nodes(&config).any(|(id, _)| *id == excluded));
+    }
+
+    #[test]
+    fn test_weighted_shuffle_favors_high_scores() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+
+        let make = |id: &str, score: f64| Candidate {
+            node_id: id.to_string(),
+            address: String::new(),
+            score,
+            source: CandidateSource::Hardcoded,
+        };
+
+        let mut high_first = 0;
+        for _ in 0..1000 {
+            let mut candidates = vec![make("low", 1.0), make("high", 50.0), make("zero", 0.0)];
+            weighted_shuffle(&mut candidates, &mut rng);
+            assert_eq!(candidates.len(), 3);
+            if candidates[0].node_id == "high" {
+                high_first += 1;
+            }
+        }
+        // P(high first) = 50/51; allow plenty of slack, but it must not always win
+        assert!(high_first > 900, "high first only {} times", high_first);
+        assert!(high_first < 1000);
+    }
+
     #[test]
     fn test_is_blacklisted_empty() {
         let config = test_config();
@@ -669,7 +1030,7 @@ mod tests {
 
         let existing_peers = HashSet::new();
         let candidates =
-            get_popularity_candidates(&mock, &existing_peers, &own_id).await.unwrap();
+            get_popularity_candidates(&mock, &existing_peers, &own_id, "any").await.unwrap();
 
         assert!(
             !candidates.is_empty(),
@@ -691,6 +1052,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_top_earners_weight_recent_earnings() {
+        let db = crate::db::Database::open_in_memory().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let bucket = now - (now % 86400);
+        for (peer, days_ago, fee) in [("recent", 1, 10_000), ("old_star", 200, 100_000)] {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                     fee_earned_msat, amount_forwarded_msat, direction) \
+                     VALUES (?1, ?1, ?2, ?3, 0, 'out')",
+                    rusqlite::params![peer, bucket - days_ago * 86400, fee],
+                )
+                .unwrap();
+        }
+        let none = HashSet::new();
+
+        let recent_only = top_earners(&db, &none, 0.0).unwrap();
+        assert_eq!(recent_only, vec![("recent".to_string(), 10_000)]);
+
+        let blended = top_earners(&db, &none, 0.05).unwrap();
+        assert_eq!(blended[0], ("recent".to_string(), 10_000));
+        assert_eq!(blended[1], ("old_star".to_string(), 5_000));
+
+        let full = top_earners(&db, &none, 1.0).unwrap();
+        assert_eq!(full[0].0, "old_star");
+    }
+
     #[tokio::test]
     async fn test_earnings_candidates_with_graph() {
         let db = crate::db::Database::open_in_memory().unwrap();
@@ -701,6 +1091,7 @@ mod tests {
         let now = chrono::Utc::now().timestamp();
         let bucket = now - (now % 86400);
         db.conn()
+            .unwrap()
             .execute(
                 "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
                  fee_earned_msat, amount_forwarded_msat, direction) \
@@ -729,7 +1120,9 @@ mod tests {
 
         let existing_peers = HashSet::new();
         let candidates =
-            get_earnings_candidates(&mock, &db, &existing_peers, &own_id).await.unwrap();
+            get_earnings_candidates(&mock, &db, &existing_peers, &own_id, "any", 0.0)
+                .await
+                .unwrap();
 
         assert!(!candidates.is_empty(), "Should find earnings-based candidates");
         // Should find peer_x and/or peer_y (peers of our top earner)
@@ -751,7 +1144,7 @@ mod tests {
         // Empty graph data - should return empty, not error
         let existing_peers = HashSet::new();
         let candidates =
-            get_popularity_candidates(&mock, &existing_peers, "own_node").await.unwrap();
+            get_popularity_candidates(&mock, &existing_peers, "own_node", "any").await.unwrap();
         assert!(candidates.is_empty(), "Should gracefully return empty on empty graph");
     }
 
@@ -780,4 +1173,111 @@ mod tests {
             "Should not include existing peers"
         );
     }
+
+    #[tokio::test]
+    async fn test_empty_ranking_api_skip_and_alert() {
+        let db = crate::db::Database::open_in_memory().unwrap();
+        let mock = MockLdkClient::new();
+        let mut config = test_config();
+        config.autopilot.ranking_api_url = "https://ranking.invalid".to_string();
+
+        config.autopilot.on_ranking_empty = "skip".to_string();
+        let candidates = get_candidates(&config, &mock, &db, &HashSet::new())
+            .await
+            .unwrap();
+        assert!(candidates.is_empty(), "skip must not fall back to hardcoded nodes");
+
+        config.autopilot.on_ranking_empty = "alert".to_string();
+        let candidates = get_candidates(&config, &mock, &db, &HashSet::new())
+            .await
+            .unwrap();
+        assert!(candidates.iter().any(|c| matches!(c.source, CandidateSource::Hardcoded)));
+        assert!(ranking_alert_since(&db).is_some());
+    }
+
+    /// A finder of the kind an operator might plug in.
+    struct ListFinder(Vec<&'static str>);
+
+    #[async_trait::async_trait(?Send)]
+    impl<C: LdkClient> CandidateFinder<C> for ListFinder {
+        fn name(&self) -> &'static str {
+            "list"
+        }
+
+        async fn find(
+            &self,
+            _ctx: &FinderContext<'_, C>,
+        ) -> anyhow::Result<Option<Vec<Candidate>>> {
+            Ok(Some(
+                self.0
+                    .iter()
+                    .map(|node_id| Candidate {
+                        node_id: node_id.to_string(),
+                        address: "1.2.3.4:9735".to_string(),
+                        score: 70.0,
+                        source: CandidateSource::External,
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_finder_goes_through_common_filters() {
+        let db = crate::db::Database::open_in_memory().unwrap();
+        let mock = MockLdkClient::new();
+        let mut config = test_config();
+        config.autopilot.blacklist = vec!["banned".to_string()];
+        let existing: HashSet<String> = ["peer".to_string()].into();
+
+        let mut finders = default_finders(&config);
+        finders.insert(0, Box::new(ListFinder(vec!["mine", "banned", "peer", "mine"])));
+        let candidates = get_candidates_from(&config, &mock, &db, &existing, &finders)
+            .await
+            .unwrap();
+
+        assert_eq!(candidates[0].node_id, "mine");
+        assert_eq!(candidates.iter().filter(|c| c.node_id == "mine").count(), 1);
+        assert!(!candidates.iter().any(|c| c.node_id == "banned" || c.node_id == "peer"));
+        // The built-in finders still ran
+        assert!(candidates.iter().any(|c| matches!(c.source, CandidateSource::Hardcoded)));
+    }
+
+    #[test]
+    fn test_stability_lowers_flapping_candidates() {
+        let db = crate::db::Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.autopilot.stability_weight = 0.5;
+        let peers: HashSet<String> = ["flappy".to_string()].into();
+        for down in [false, true, false, true] {
+            let disconnected = if down { peers.clone() } else { HashSet::new() };
+            uptime_tracker::record(&db, &peers, &disconnected).unwrap();
+        }
+
+        let candidate = |node_id: &str| Candidate {
+            node_id: node_id.to_string(),
+            address: "127.0.0.1:9735".to_string(),
+            score: 60.0,
+            source: CandidateSource::GraphPopularity,
+        };
+        let mut candidates = vec![candidate("flappy"), candidate("steady")];
+        apply_stability(&config, &db, &mut candidates).unwrap();
+
+        // Two disconnects: 60 / (1 + 0.5 * 2)
+        assert_eq!(candidates[0].score, 30.0);
+        assert_eq!(candidates[1].score, 60.0);
+    }
+
+    #[test]
+    fn test_pick_address_preference() {
+        let both = || vec!["abcdef.onion:9735".to_string(), "1.2.3.4:9735".to_string()];
+        assert_eq!(pick_address(both(), "clearnet").unwrap(), "1.2.3.4:9735");
+        assert_eq!(pick_address(both(), "tor").unwrap(), "abcdef.onion:9735");
+        assert_eq!(pick_address(both(), "any").unwrap(), "abcdef.onion:9735");
+
+        let clearnet_only = || vec!["1.2.3.4:9735".to_string()];
+        assert_eq!(pick_address(clearnet_only(), "tor"), None);
+        let onion_only = vec!["abcdef.onion:9735".to_string()];
+        assert_eq!(pick_address(onion_only, "clearnet").unwrap(), "abcdef.onion:9735");
+    }
 }
@@ -2,8 +2,11 @@
 ///
 /// Decides whether we should open new channels based on:
 /// - Available on-chain balance (minus reserve)
-/// - On-chain fee regime (low vs high)
+/// - On-chain fee regime (low vs high), and how long it has been low
 /// - Percentage of funds on-chain vs in channels
+/// - Channels we funded that haven't confirmed yet, counted as already spent
+///   so a bootstrapping node doesn't open a second batch before the first
+///   confirms
 ///
 /// Reference: clboss/Boss/Mod/ChannelCreationDecider.cpp
 
@@ -11,7 +14,81 @@ use crate::config::Config;
 use crate::db::Database;
 use crate::state::NodeState;
 use crate::tracker::onchain_fees;
-use log::{debug, info};
+use log::{debug, info, warn};
+
+/// A fee sample older than this, or two loop intervals if that is longer, is
+/// too stale to check `max_open_feerate_sat_per_vb` against.
+const FEERATE_SAMPLE_MAX_AGE_SECS: u64 = 3600;
+
+/// On-chain funds to keep back: the larger of the absolute reserve and
+/// `onchain_reserve_percent` of total funds.
+pub fn reserve_sats(config: &Config, total_funds_sats: u64) -> u64 {
+    let pct = config.autopilot.onchain_reserve_percent;
+    let proportional = (total_funds_sats as f64 * pct / 100.0) as u64;
+    config.autopilot.onchain_reserve_sats.max(proportional)
+}
+
+/// How long an autopilot open may go without its channel showing up in the
+/// channel tracker before it is taken to have failed.
+const OPEN_GRACE_SECS: f64 = 86_400.0;
+
+/// Capacity (sats) of autopilot-opened channels that are still open or not
+/// yet confirmed. Opens are recorded by user channel ID; one the channel
+/// tracker has seen close no longer counts, and neither does one it still
+/// hasn't seen after `OPEN_GRACE_SECS` (the funding never happened).
+pub fn deployed_sats(db: &Database) -> anyhow::Result<u64> {
+    let grace_start = db.now().timestamp() as f64 - OPEN_GRACE_SECS;
+    let sats: i64 = db.conn()?.query_row(
+        "SELECT COALESCE(SUM(o.amount_sats), 0) FROM autopilot_opens o \
+         WHERE NOT EXISTS (SELECT 1 FROM channel_history h \
+                           WHERE h.user_channel_id = o.channel_id AND h.is_open = 0) \
+         AND (o.opened_at >= ?1 OR EXISTS (SELECT 1 FROM channel_history h \
+                                           WHERE h.user_channel_id = o.channel_id))",
+        [grace_start],
+        |row| row.get(0),
+    )?;
+    Ok(sats as u64)
+}
+
+/// Total funds (sats) to decide with. The balances call can lag the channel
+/// list or count pending funds; when its lightning figure is more than
+/// `balance_mismatch_percent` off the channel list's, that is logged, and
+/// with `prefer_channel_balances` the channel list's figure is used.
+pub fn total_funds_sats(config: &Config, state: &NodeState) -> u64 {
+    let reported = state.balances.total_lightning_balance_sats;
+    let from_channels = state.channel_lightning_sats();
+    let threshold = config.autopilot.balance_mismatch_percent;
+    let larger = reported.max(from_channels);
+    let mismatch_pct = if larger == 0 {
+        0.0
+    } else {
+        reported.abs_diff(from_channels) as f64 * 100.0 / larger as f64
+    };
+
+    if threshold == 0.0 || mismatch_pct <= threshold {
+        return state.total_funds_sats();
+    }
+    let lightning = if config.autopilot.prefer_channel_balances {
+        from_channels
+    } else {
+        reported
+    };
+    warn!(
+        "Autopilot decider: lightning balance {} sat disagrees with the channel list's {} sat \
+         ({:.0}%); using {} sat",
+        reported, from_channels, mismatch_pct, lightning
+    );
+    state.balances.total_onchain_balance_sats + lightning
+}
+
+/// What the decider concluded this cycle.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Open channels with up to this many sats
+    Open(u64),
+    /// Don't open; the reason, phrased for operators
+    Wait(String),
+}
 
 /// Returns Some(budget_sats) if we should open channels, None otherwise.
 pub fn should_open(
@@ -19,8 +96,21 @@ pub fn should_open(
     db: &Database,
     state: &NodeState,
 ) -> anyhow::Result<Option<u64>> {
+    Ok(match decide(config, db, state)? {
+        Decision::Open(budget) => Some(budget),
+        Decision::Wait(_) => None,
+    })
+}
+
+/// Like `should_open`, but says why when the answer is no.
+pub fn decide(config: &Config, db: &Database, state: &NodeState) -> anyhow::Result<Decision> {
+    // The server reports no confirmed-only balance. Its spendable figure is
+    // confirmed funds plus our own unconfirmed change, less the anchor
+    // reserve; unconfirmed payments from others (which could be replaced)
+    // only appear in the total, so they never fund an open.
     let onchain = state.balances.spendable_onchain_balance_sats;
-    let reserve = config.autopilot.onchain_reserve_sats;
+    let total_funds = total_funds_sats(config, state);
+    let reserve = reserve_sats(config, total_funds);
 
     // Must have more than the reserve
     if onchain <= reserve {
@@ -28,10 +118,38 @@ pub fn should_open(
             "Autopilot decider: on-chain balance ({} sat) <= reserve ({} sat)",
             onchain, reserve
         );
-        return Ok(None);
+        return Ok(Decision::Wait(format!(
+            "on-chain balance ({} sat) is not above the reserve ({} sat)",
+            onchain, reserve
+        )));
     }
 
-    let available = onchain - reserve;
+    let mut available = onchain - reserve;
+
+    // Funds in channels whose funding isn't broadcast yet still show
+    // on-chain; don't deploy them twice
+    let pending = state.pending_outbound_sats();
+    if pending > 0 {
+        debug!(
+            "Autopilot decider: {} sat in channels still confirming, counted as spent",
+            pending
+        );
+        available = available.saturating_sub(pending);
+    }
+
+    // Lifetime ceiling on what the autopilot has committed to channels
+    let ceiling = config.autopilot.max_total_deployed_sats;
+    if ceiling > 0 {
+        let deployed = deployed_sats(db)?;
+        let room = ceiling.saturating_sub(deployed);
+        if room < available {
+            debug!(
+                "Autopilot decider: {} of {} sat ceiling deployed, {} sat left",
+                deployed, ceiling, room
+            );
+            available = room;
+        }
+    }
 
     // Must meet minimum channel size
     if available < config.autopilot.min_channel_sats {
@@ -39,16 +157,23 @@ pub fn should_open(
             "Autopilot decider: available ({} sat) < min channel size ({} sat)",
             available, config.autopilot.min_channel_sats
         );
-        return Ok(None);
+        let confirming = if pending > 0 {
+            format!(" ({} sat in channels still confirming)", pending)
+        } else {
+            String::new()
+        };
+        return Ok(Decision::Wait(format!(
+            "{} sat available, below the minimum channel size ({} sat){}",
+            available, config.autopilot.min_channel_sats, confirming
+        )));
     }
 
     // Check on-chain percentage
-    let onchain_pct = state.onchain_percent();
-    let total_funds = state.total_funds_sats();
+    let onchain_pct = state.onchain_percent_of(total_funds);
 
     if total_funds == 0 {
         debug!("Autopilot decider: no funds at all");
-        return Ok(None);
+        return Ok(Decision::Wait("no funds".to_string()));
     }
 
     // If on-chain % is below minimum and we don't have excess, don't deploy more
@@ -57,23 +182,71 @@ pub fn should_open(
             "Autopilot decider: on-chain {:.1}% < min {:.1}%, preserving on-chain funds",
             onchain_pct, config.autopilot.min_onchain_percent
         );
-        return Ok(None);
+        return Ok(Decision::Wait(format!(
+            "on-chain funds at {:.1}%, below min_onchain_percent ({:.1}%)",
+            onchain_pct, config.autopilot.min_onchain_percent
+        )));
+    }
+
+    // Hard feerate ceiling, whatever the regime or on-chain share. Without a
+    // recent sample the feerate is unknown, and the ceiling can't be trusted
+    let max_feerate = config.autopilot.max_open_feerate_sat_per_vb;
+    if max_feerate > 0.0 {
+        let max_age = FEERATE_SAMPLE_MAX_AGE_SECS.max(2 * config.general.loop_interval_secs);
+        let now = db.now().timestamp() as f64;
+        match onchain_fees::latest_sample(db) {
+            Some((feerate, _)) if feerate > max_feerate => {
+                info!(
+                    "Autopilot decider: feerate {:.1} sat/vB above max_open_feerate_sat_per_vb ({:.1}), waiting",
+                    feerate, max_feerate
+                );
+                return Ok(Decision::Wait(format!(
+                    "feerate {:.1} sat/vB above max_open_feerate_sat_per_vb ({:.1})",
+                    feerate, max_feerate
+                )));
+            }
+            Some((_, sampled_at)) if now - sampled_at <= max_age as f64 => {}
+            _ => {
+                info!(
+                    "Autopilot decider: no fee sample in the last {}s to check \
+                     max_open_feerate_sat_per_vb against, waiting",
+                    max_age
+                );
+                return Ok(Decision::Wait(format!(
+                    "no fee sample in the last {}s to check max_open_feerate_sat_per_vb",
+                    max_age
+                )));
+            }
+        }
     }
 
     // Check fee regime
-    let regime = onchain_fees::current_regime(
-        db,
-        config.onchain_fees.hi_to_lo_percentile,
-        config.onchain_fees.lo_to_hi_percentile,
-    )?;
+    let regime = onchain_fees::current_regime(db, &config.onchain_fees)?;
 
     match regime {
         onchain_fees::FeeRegime::Low => {
+            let min_mins = config.autopilot.min_low_regime_duration_mins;
+            if min_mins > 0 {
+                let now = db.now().timestamp();
+                let low_for_mins = onchain_fees::low_regime_since(db).map(|since| (now - since) / 60);
+                if low_for_mins.map_or(true, |mins| mins < min_mins as i64) {
+                    debug!(
+                        "Autopilot decider: low-fee regime for {} of {} minutes, waiting",
+                        low_for_mins.unwrap_or(0),
+                        min_mins
+                    );
+                    return Ok(Decision::Wait(format!(
+                        "low-fee regime for {} of the required {} minutes",
+                        low_for_mins.unwrap_or(0),
+                        min_mins
+                    )));
+                }
+            }
             info!(
                 "Autopilot decider: low-fee regime, deploying {} sat",
                 available
             );
-            Ok(Some(available))
+            Ok(Decision::Open(available))
         }
         onchain_fees::FeeRegime::High => {
             // In high-fee regime, only deploy if we have excess on-chain
@@ -82,14 +255,251 @@ pub fn should_open(
                     "Autopilot decider: high-fee regime but on-chain {:.1}% > max {:.1}%, deploying {} sat",
                     onchain_pct, config.autopilot.max_onchain_percent, available
                 );
-                Ok(Some(available))
+                Ok(Decision::Open(available))
             } else {
                 debug!(
                     "Autopilot decider: high-fee regime and on-chain {:.1}% <= max {:.1}%, waiting",
                     onchain_pct, config.autopilot.max_onchain_percent
                 );
-                Ok(None)
+                Ok(Decision::Wait("high-fee regime, waiting for lower fees".to_string()))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_is_larger_of_absolute_and_percent() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.autopilot.onchain_reserve_sats = 30_000;
+        assert_eq!(reserve_sats(&config, 10_000_000), 30_000);
+
+        config.autopilot.onchain_reserve_percent = 1.0;
+        assert_eq!(reserve_sats(&config, 10_000_000), 100_000);
+        assert_eq!(reserve_sats(&config, 1_000_000), 30_000);
+    }
+
+    #[test]
+    fn test_waits_for_low_regime_to_last() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.onchain_fees.default_regime = "low".to_string();
+        config.autopilot.min_low_regime_duration_mins = 60;
+        let state = NodeState {
+            node_info: Default::default(),
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                spendable_onchain_balance_sats: 5_000_000,
+                ..Default::default()
+            },
+            channels: Vec::new(),
+            closing: Default::default(),
+        };
+
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        assert_eq!(should_open(&config, &db, &state).unwrap(), None);
+
+        let two_hours_ago = chrono::Utc::now().timestamp() - 7200;
+        db.conn()
+            .unwrap()
+            .execute(
+                "UPDATE run_state SET value = ?1 WHERE key = 'fee_regime_since'",
+                [two_hours_ago.to_string()],
+            )
+            .unwrap();
+        assert!(should_open(&config, &db, &state).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_deployed_ceiling_limits_budget() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.onchain_fees.default_regime = "low".to_string();
+        config.autopilot.max_total_deployed_sats = 3_000_000;
+        let state = NodeState {
+            node_info: Default::default(),
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                spendable_onchain_balance_sats: 5_000_000,
+                total_onchain_balance_sats: 5_000_000,
+                ..Default::default()
+            },
+            channels: Vec::new(),
+            closing: Default::default(),
+        };
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        for (user_id, sats, open) in [("u1", 1_000_000, true), ("u2", 2_000_000, false)] {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO autopilot_opens (channel_id, counterparty_node_id, amount_sats, \
+                     opened_at) VALUES (?1, 'peer', ?2, 0)",
+                    rusqlite::params![user_id, sats],
+                )
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO channel_history (channel_id, user_channel_id, \
+                     counterparty_node_id, channel_value_sats, first_seen_at, last_seen_at, \
+                     is_open) VALUES (?1, ?1, 'peer', ?2, 0, 0, ?3)",
+                    rusqlite::params![user_id, sats, open],
+                )
+                .unwrap();
+        }
+
+        // The closed channel no longer counts: 2M of room
+        assert_eq!(deployed_sats(&db).unwrap(), 1_000_000);
+
+        // An open the tracker never saw counts only during the grace period
+        let now = chrono::Utc::now().timestamp() as f64;
+        for (user_id, at) in [("recent", now - 600.0), ("failed", now - 2.0 * 86400.0)] {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO autopilot_opens (channel_id, counterparty_node_id, amount_sats, \
+                     opened_at) VALUES (?1, 'peer', 500000, ?2)",
+                    rusqlite::params![user_id, at],
+                )
+                .unwrap();
+        }
+        assert_eq!(deployed_sats(&db).unwrap(), 1_500_000);
+        db.conn()
+            .unwrap()
+            .execute("DELETE FROM autopilot_opens WHERE channel_id = 'recent'", [])
+            .unwrap();
+        assert_eq!(should_open(&config, &db, &state).unwrap(), Some(2_000_000));
+
+        config.autopilot.max_total_deployed_sats = 1_000_000;
+        assert_eq!(should_open(&config, &db, &state).unwrap(), None);
+    }
+
+    #[test]
+    fn test_feerate_ceiling_overrides_regime() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.onchain_fees.default_regime = "low".to_string();
+        config.autopilot.max_open_feerate_sat_per_vb = 50.0;
+        let state = NodeState {
+            node_info: Default::default(),
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                spendable_onchain_balance_sats: 5_000_000,
+                total_onchain_balance_sats: 5_000_000,
+                ..Default::default()
+            },
+            channels: Vec::new(),
+            closing: Default::default(),
+        };
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+
+        let sample = |feerate: f64, at: f64| {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) \
+                     VALUES (?1, ?2)",
+                    rusqlite::params![feerate, at],
+                )
+                .unwrap();
+        };
+
+        // No samples yet: the feerate is unknown, so no opens
+        assert_eq!(should_open(&config, &db, &state).unwrap(), None);
+
+        // A stale low sample doesn't help either
+        let now = chrono::Utc::now().timestamp() as f64;
+        sample(10.0, now - 2.0 * 3600.0);
+        assert_eq!(should_open(&config, &db, &state).unwrap(), None);
+
+        sample(10.0, now - 600.0);
+        assert!(should_open(&config, &db, &state).unwrap().is_some());
+
+        sample(80.0, now);
+        assert_eq!(should_open(&config, &db, &state).unwrap(), None);
+
+        config.autopilot.max_open_feerate_sat_per_vb = 0.0;
+        assert!(should_open(&config, &db, &state).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_pending_opens_count_against_budget() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.onchain_fees.default_regime = "low".to_string();
+        let pending = |id: &str, sats: u64| ldk_server_protos::types::Channel {
+            channel_id: id.to_string(),
+            counterparty_node_id: id.to_string(),
+            channel_value_sats: sats,
+            is_outbound: true,
+            is_channel_ready: false,
+            ..Default::default()
+        };
+        let make_state = |onchain_sats: u64| NodeState {
+            node_info: Default::default(),
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                spendable_onchain_balance_sats: onchain_sats,
+                total_onchain_balance_sats: onchain_sats,
+                ..Default::default()
+            },
+            channels: Vec::new(),
+            closing: Default::default(),
+        };
+        let mut state = make_state(5_000_000);
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        let fresh = should_open(&config, &db, &state).unwrap().unwrap();
+
+        state.channels.push(pending("a", 1_000_000));
+        assert_eq!(state.pending_outbound_sats(), 1_000_000);
+        assert_eq!(should_open(&config, &db, &state).unwrap(), Some(fresh - 1_000_000));
+
+        // Nothing left once the first batch is accounted for
+        state.channels.push(pending("b", fresh - 1_000_000));
+        match decide(&config, &db, &state).unwrap() {
+            Decision::Wait(reason) => assert!(reason.contains("still confirming"), "{}", reason),
+            Decision::Open(budget) => panic!("opened {} sat while channels confirm", budget),
+        }
+
+        // Once the funding is broadcast the on-chain balance drops by the
+        // channel values, and they no longer count a second time
+        let mut broadcast = make_state(5_000_000 - fresh);
+        broadcast.channels = state.channels.clone();
+        for ch in &mut broadcast.channels {
+            ch.funding_txo = Some(Default::default());
+        }
+        assert_eq!(broadcast.pending_outbound_sats(), 0);
+        assert_eq!(
+            should_open(&config, &db, &broadcast).unwrap(),
+            should_open(&config, &db, &make_state(5_000_000 - fresh)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_total_funds_on_balance_mismatch() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let state = NodeState {
+            node_info: Default::default(),
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                total_onchain_balance_sats: 1_000_000,
+                total_lightning_balance_sats: 3_000_000,
+                ..Default::default()
+            },
+            channels: vec![ldk_server_protos::types::Channel {
+                outbound_capacity_msat: 2_000_000_000,
+                ..Default::default()
+            }],
+            closing: Default::default(),
+        };
+
+        // A third off: logged, but the balances figure stands by default
+        config.autopilot.balance_mismatch_percent = 10.0;
+        assert_eq!(total_funds_sats(&config, &state), 4_000_000);
+
+        config.autopilot.prefer_channel_balances = true;
+        assert_eq!(total_funds_sats(&config, &state), 3_000_000);
+
+        // Within the threshold
+        config.autopilot.balance_mismatch_percent = 50.0;
+        assert_eq!(total_funds_sats(&config, &state), 4_000_000);
+    }
+}
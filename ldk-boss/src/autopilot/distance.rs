@@ -84,6 +84,7 @@ pub async fn get_distance_candidates(
     client: &impl LdkClient,
     own_node_id: &str,
     existing_peers: &HashSet<String>,
+    address_preference: &str,
 ) -> anyhow::Result<Vec<Candidate>> {
     let tree = run_dijkstra(client, own_node_id).await?;
 
@@ -132,7 +133,7 @@ pub async fn get_distance_candidates(
             continue;
         }
 
-        if let Some(address) = resolve_node_address(client, &node_id).await {
+        if let Some(address) = resolve_node_address(client, &node_id, address_preference).await {
             // Score: proportional to distance (more distant = higher score)
             // Normalize to [15, 40] range
             let normalized = if max_cost > 0.0 {
@@ -661,7 +662,7 @@ mod tests {
         );
 
         let existing_peers = HashSet::new();
-        let candidates = get_distance_candidates(&mock, &own_id, &existing_peers)
+        let candidates = get_distance_candidates(&mock, &own_id, &existing_peers, "any")
             .await
             .unwrap();
 
@@ -690,7 +691,7 @@ mod tests {
 
         let mock = MockLdkClient::new();
         let existing_peers = HashSet::new();
-        let candidates = get_distance_candidates(&mock, "own_node", &existing_peers)
+        let candidates = get_distance_candidates(&mock, "own_node", &existing_peers, "any")
             .await
             .unwrap();
         assert!(candidates.is_empty());
@@ -0,0 +1,289 @@
+/// Bookkeeping for failed channel opens.
+///
+/// A failed `open_channel` is classified as either transient (peer briefly
+/// unreachable, feerate too low, etc.) or permanent (the peer rejects us).
+/// Transient failures are retried on later cycles, up to a configured number
+/// of attempts. Permanent failures -- and transient ones that exhaust their
+/// attempts -- temporarily blacklist the peer.
+
+use crate::autopilot::candidate::{Candidate, CandidateSource};
+use crate::config::Config;
+use crate::db::Database;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+
+/// Score given to candidates being retried after a transient failure.
+/// Below user seed nodes, above all graph-derived sources.
+const RETRY_SCORE: f64 = 60.0;
+
+/// Error substrings indicating the peer will not accept a channel from us.
+/// Rejections are matched as the peer's, not bare "reject": a transaction
+/// rejected for its fee says nothing about the peer.
+const PERMANENT_MARKERS: &[&str] = &[
+    "peer rejected",
+    "rejected by peer",
+    "rejected us",
+    "rejected our",
+    "rejected the channel",
+    "rejected channel",
+    "invalid node",
+    "invalidnodeid",
+    "invalid address",
+    "invalidsocketaddress",
+    "unknown required feature",
+    "not accepting",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureKind {
+    Transient,
+    Permanent,
+}
+
+impl FailureKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FailureKind::Transient => "transient",
+            FailureKind::Permanent => "permanent",
+        }
+    }
+}
+
+/// Error substrings of funding transaction problems (fees, mempool policy),
+/// which are transient whatever else the message says.
+const FUNDING_TX_MARKERS: &[&str] = &[
+    "feerate",
+    "fee rate",
+    "relay fee",
+    "mempool",
+    "transaction rejected",
+    "broadcast",
+];
+
+/// Classify an open-channel error message. Unknown errors are treated as
+/// transient so the candidate gets another chance.
+pub fn classify(error: &str) -> FailureKind {
+    let lower = error.to_lowercase();
+    if FUNDING_TX_MARKERS.iter().any(|m| lower.contains(m)) {
+        return FailureKind::Transient;
+    }
+    if PERMANENT_MARKERS.iter().any(|m| lower.contains(m)) {
+        FailureKind::Permanent
+    } else {
+        FailureKind::Transient
+    }
+}
+
+/// Record a failed open attempt. Returns the effective kind after escalation
+/// (a transient failure that exhausted its retries is reported as permanent).
+pub fn record_failure(
+    config: &Config,
+    db: &Database,
+    candidate: &Candidate,
+    error: &str,
+) -> anyhow::Result<FailureKind> {
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
+
+    let previous_attempts: u32 = conn
+        .query_row(
+            "SELECT attempts FROM autopilot_open_failures WHERE node_id = ?1",
+            [&candidate.node_id],
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+    let attempts = previous_attempts + 1;
+
+    let mut kind = classify(error);
+    if kind == FailureKind::Transient && attempts >= config.autopilot.open_retry_max_attempts {
+        info!(
+            "Autopilot: {} failed {} times, giving up on retries",
+            candidate.node_id, attempts
+        );
+        kind = FailureKind::Permanent;
+    }
+
+    let blacklisted_until = match kind {
+        FailureKind::Transient => None,
+        FailureKind::Permanent => {
+            Some(now + config.autopilot.failed_open_blacklist_hours as f64 * 3600.0)
+        }
+    };
+
+    conn.execute(
+        "INSERT INTO autopilot_open_failures \
+         (node_id, address, kind, attempts, last_error, last_attempt_at, blacklisted_until) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+         ON CONFLICT(node_id) DO UPDATE SET \
+         address = ?2, kind = ?3, attempts = ?4, last_error = ?5, \
+         last_attempt_at = ?6, blacklisted_until = ?7",
+        rusqlite::params![
+            candidate.node_id,
+            candidate.address,
+            kind.as_str(),
+            attempts,
+            error,
+            now,
+            blacklisted_until,
+        ],
+    )?;
+
+    match kind {
+        FailureKind::Transient => debug!(
+            "Autopilot: transient failure #{} for {}, will retry next cycle",
+            attempts, candidate.node_id
+        ),
+        FailureKind::Permanent => warn!(
+            "Autopilot: permanent failure for {}, blacklisting for {}h",
+            candidate.node_id, config.autopilot.failed_open_blacklist_hours
+        ),
+    }
+
+    Ok(kind)
+}
+
+/// Forget any failure history for a peer (called after a successful open).
+pub fn clear(db: &Database, node_id: &str) -> anyhow::Result<()> {
+    db.conn()?.execute(
+        "DELETE FROM autopilot_open_failures WHERE node_id = ?1",
+        [node_id],
+    )?;
+    Ok(())
+}
+
+/// Candidates whose last open attempt failed transiently and should be retried.
+pub fn retry_candidates(db: &Database) -> anyhow::Result<Vec<Candidate>> {
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare(
+        "SELECT node_id, address FROM autopilot_open_failures \
+         WHERE kind = 'transient' ORDER BY last_attempt_at ASC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Candidate {
+            node_id: row.get(0)?,
+            address: row.get(1)?,
+            score: RETRY_SCORE,
+            source: CandidateSource::Retry,
+        })
+    })?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+/// Node IDs currently under a temporary blacklist. Expired entries are pruned.
+pub fn temporarily_blacklisted(db: &Database) -> anyhow::Result<HashSet<String>> {
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
+
+    conn.execute(
+        "DELETE FROM autopilot_open_failures \
+         WHERE kind = 'permanent' AND blacklisted_until < ?1",
+        [now],
+    )?;
+
+    let mut stmt = conn.prepare(
+        "SELECT node_id FROM autopilot_open_failures \
+         WHERE kind = 'permanent' AND blacklisted_until >= ?1",
+    )?;
+    let rows = stmt.query_map([now], |row| row.get::<_, String>(0))?;
+    Ok(rows.filter_map(|r| r.ok()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> Config {
+        Config::test_default(std::path::PathBuf::from("/dev/null"))
+    }
+
+    fn make_candidate(id: &str) -> Candidate {
+        Candidate {
+            node_id: id.to_string(),
+            address: "1.2.3.4:9735".to_string(),
+            score: 10.0,
+            source: CandidateSource::Hardcoded,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("ConnectionFailed"), FailureKind::Transient);
+        assert_eq!(classify("feerate too low"), FailureKind::Transient);
+        assert_eq!(classify("Peer rejected channel open"), FailureKind::Permanent);
+        assert_eq!(classify("InvalidNodeId"), FailureKind::Permanent);
+
+        // Fee and mempool rejections are the funding transaction's, not the peer's
+        assert_eq!(
+            classify("transaction rejected: min relay fee not met"),
+            FailureKind::Transient
+        );
+        assert_eq!(classify("mempool min fee not met, rejecting"), FailureKind::Transient);
+        assert_eq!(classify("Failed to broadcast: rejected"), FailureKind::Transient);
+        assert_eq!(classify("Rejected: feerate below minimum"), FailureKind::Transient);
+        assert_eq!(classify("rejected"), FailureKind::Transient);
+    }
+
+    #[test]
+    fn test_transient_failure_is_retried() {
+        let db = Database::open_in_memory().unwrap();
+        let config = test_config();
+
+        let kind = record_failure(&config, &db, &make_candidate("a"), "ConnectionFailed").unwrap();
+        assert_eq!(kind, FailureKind::Transient);
+
+        let retries = retry_candidates(&db).unwrap();
+        assert_eq!(retries.len(), 1);
+        assert_eq!(retries[0].node_id, "a");
+        assert!(matches!(retries[0].source, CandidateSource::Retry));
+        assert!(temporarily_blacklisted(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_permanent_failure_blacklists() {
+        let db = Database::open_in_memory().unwrap();
+        let config = test_config();
+
+        let kind = record_failure(&config, &db, &make_candidate("a"), "peer rejected us").unwrap();
+        assert_eq!(kind, FailureKind::Permanent);
+
+        assert!(retry_candidates(&db).unwrap().is_empty());
+        assert!(temporarily_blacklisted(&db).unwrap().contains("a"));
+    }
+
+    #[test]
+    fn test_transient_escalates_after_max_attempts() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.autopilot.open_retry_max_attempts = 2;
+
+        let c = make_candidate("a");
+        assert_eq!(record_failure(&config, &db, &c, "timeout").unwrap(), FailureKind::Transient);
+        assert_eq!(record_failure(&config, &db, &c, "timeout").unwrap(), FailureKind::Permanent);
+        assert!(temporarily_blacklisted(&db).unwrap().contains("a"));
+    }
+
+    #[test]
+    fn test_clear_forgets_failures() {
+        let db = Database::open_in_memory().unwrap();
+        let config = test_config();
+
+        record_failure(&config, &db, &make_candidate("a"), "timeout").unwrap();
+        clear(&db, "a").unwrap();
+        assert!(retry_candidates(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expired_blacklist_pruned() {
+        let db = Database::open_in_memory().unwrap();
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO autopilot_open_failures \
+                 (node_id, address, kind, attempts, last_error, last_attempt_at, blacklisted_until) \
+                 VALUES ('a', '1.2.3.4:9735', 'permanent', 1, 'rejected', 0, 1)",
+                [],
+            )
+            .unwrap();
+        assert!(temporarily_blacklisted(&db).unwrap().is_empty());
+    }
+}
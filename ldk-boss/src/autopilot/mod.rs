@@ -1,13 +1,45 @@
 pub mod candidate;
 pub mod decider;
 pub mod distance;
+pub mod failures;
 pub mod opener;
+pub mod operators;
+pub mod ranking_cache;
 
 use crate::client::LdkClient;
 use crate::config::Config;
+use crate::cycle_summary::CycleSummary;
 use crate::db::Database;
 use crate::state::NodeState;
-use log::{debug, info};
+use log::{debug, info, warn};
+
+const IDLE_REASON_KEY: &str = "autopilot_idle_reason";
+
+/// Record why the last run opened nothing, or clear it when it planned opens,
+/// so `status` can answer "why isn't the autopilot opening?".
+fn set_idle_reason(db: &Database, reason: Option<&str>) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+    match reason {
+        Some(reason) => conn.execute(
+            "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+            rusqlite::params![IDLE_REASON_KEY, reason],
+        )?,
+        None => conn.execute("DELETE FROM run_state WHERE key = ?1", [IDLE_REASON_KEY])?,
+    };
+    Ok(())
+}
+
+/// Why the last autopilot run opened nothing, if it did.
+pub fn idle_reason(db: &Database) -> Option<String> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [IDLE_REASON_KEY],
+            |row| row.get(0),
+        )
+        .ok()
+}
 
 /// Run the channel autopilot: evaluate whether to open channels, select candidates, execute.
 pub async fn run(
@@ -15,12 +47,14 @@ pub async fn run(
     client: &(impl LdkClient + Sync),
     db: &Database,
     state: &NodeState,
+    summary: &mut CycleSummary,
 ) -> anyhow::Result<()> {
     // Phase 1: Decide if we should open channels
-    let budget = match decider::should_open(config, db, state)? {
-        Some(budget) => budget,
-        None => {
+    let budget = match decider::decide(config, db, state)? {
+        decider::Decision::Open(budget) => budget,
+        decider::Decision::Wait(reason) => {
             debug!("Autopilot: conditions not met for channel opening");
+            set_idle_reason(db, Some(&reason))?;
             return Ok(());
         }
     };
@@ -41,6 +75,7 @@ pub async fn run(
 
     if candidates.is_empty() {
         info!("Autopilot: no suitable candidates found");
+        set_idle_reason(db, Some("no suitable candidates"))?;
         return Ok(());
     }
 
@@ -51,20 +86,90 @@ pub async fn run(
     } else {
         config.autopilot.max_proposals
     };
+    let max_proposals = if config.general.safe_mode && max_proposals > 1 {
+        info!("Autopilot: safe mode, limiting to 1 open this cycle");
+        1
+    } else {
+        max_proposals
+    };
 
-    let plan = opener::plan_opens(config, &candidates, budget, max_proposals);
+    let plan = opener::plan_opens(
+        config,
+        &candidates,
+        budget,
+        max_proposals,
+        opener::observed_reserve_fraction(&state.channels),
+    );
 
     if plan.is_empty() {
         debug!("Autopilot: no viable opens planned");
+        set_idle_reason(db, Some("candidates lack addresses or don't fit the budget"))?;
         return Ok(());
     }
+    set_idle_reason(db, None)?;
 
     info!("Autopilot: planning {} channel opens", plan.len());
 
-    // Phase 4: Execute
+    // Phase 4: Execute. A failed open is recorded for retry/blacklisting
+    // by the opener and must not abort the rest of the plan.
     for open in &plan {
-        opener::execute_open(config, client, db, open).await?;
+        match opener::execute_open(config, client, db, open).await {
+            Ok(true) => {
+                summary.opened += 1;
+                summary.opened_sats += open.amount_sats;
+            }
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Autopilot: open with {} failed, continuing with plan: {:#}",
+                open.candidate.node_id, e
+            ),
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockLdkClient;
+
+    #[tokio::test]
+    async fn test_run_records_idle_reason() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let client = MockLdkClient::new();
+        let state = NodeState {
+            node_info: Default::default(),
+            balances: Default::default(),
+            channels: Vec::new(),
+            closing: Default::default(),
+        };
+
+        run(&config, &client, &db, &state, &mut CycleSummary::default())
+            .await
+            .unwrap();
+        assert!(idle_reason(&db).unwrap().contains("reserve"));
+
+        let state = NodeState {
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                spendable_onchain_balance_sats: 5_000_000,
+                total_onchain_balance_sats: 5_000_000,
+                total_lightning_balance_sats: 20_000_000,
+                ..Default::default()
+            },
+            ..state
+        };
+        // No fee samples yet: the default regime, high
+        run(&config, &client, &db, &state, &mut CycleSummary::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            idle_reason(&db).as_deref(),
+            Some("high-fee regime, waiting for lower fees")
+        );
+
+        set_idle_reason(&db, None).unwrap();
+        assert!(idle_reason(&db).is_none());
+    }
+}
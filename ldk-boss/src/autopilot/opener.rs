@@ -1,9 +1,18 @@
+use crate::audit;
 use crate::autopilot::candidate::Candidate;
+use crate::autopilot::failures;
 use crate::client::LdkClient;
 use crate::config::Config;
 use crate::db::Database;
 use ldk_server_protos::api::{ConnectPeerRequest, OpenChannelRequest};
+use ldk_server_protos::types::Channel;
 use log::{error, info, warn};
+use serde_json::json;
+
+/// LDK's default channel reserve: 1% of capacity per side...
+pub const DEFAULT_RESERVE_FRACTION: f64 = 0.01;
+/// ...but never below the dust limit.
+const MIN_RESERVE_SATS: u64 = 1_000;
 
 /// A planned channel open.
 pub struct PlannedOpen {
@@ -17,11 +26,15 @@ pub struct PlannedOpen {
 /// - If few existing channels, open multiple to build connectivity.
 /// - If enough channels, open only 1 at a time.
 /// - Respect min/max channel size limits.
+/// - Bump channels up so capacity after both reserves stays above
+///   min_usable_channel_sats; skip them if that doesn't fit.
+/// - Round sizes to `open_amount_granularity_sats` within those limits.
 pub fn plan_opens(
     config: &Config,
     candidates: &[Candidate],
     budget_sats: u64,
     max_proposals: usize,
+    reserve_fraction: f64,
 ) -> Vec<PlannedOpen> {
     let mut plan = Vec::new();
     let mut remaining = budget_sats;
@@ -53,6 +66,50 @@ pub fn plan_opens(
             break;
         }
 
+        // Make sure the channel is still worth routing through after reserves
+        let min_usable = config.autopilot.min_usable_channel_sats();
+        let amount = if usable_after_reserves(amount, reserve_fraction) < min_usable {
+            let needed = min_size_for_usable(min_usable, reserve_fraction);
+            let ceiling = config
+                .autopilot
+                .max_channel_sats
+                .min(remaining)
+                .min(budget_sats / 2);
+            if needed > ceiling {
+                warn!(
+                    "Autopilot: {} sat channel with {} leaves < {} sat usable after reserves \
+                     and {} sat doesn't fit the budget, skipping",
+                    amount, candidates[i].node_id, min_usable, needed
+                );
+                continue;
+            }
+            info!(
+                "Autopilot: bumping channel with {} from {} to {} sat to cover reserves",
+                candidates[i].node_id, amount, needed
+            );
+            needed
+        } else {
+            amount
+        };
+
+        let granularity = config.autopilot.open_amount_granularity_sats;
+        let amount = if granularity > 0 {
+            let floor = if min_usable > 0 {
+                min_size_for_usable(min_usable, reserve_fraction)
+                    .max(config.autopilot.min_channel_sats)
+            } else {
+                config.autopilot.min_channel_sats
+            };
+            let ceiling = config
+                .autopilot
+                .max_channel_sats
+                .min(remaining)
+                .min(budget_sats / 2);
+            round_amount(amount, granularity, floor, ceiling)
+        } else {
+            amount
+        };
+
         plan.push(PlannedOpen {
             candidate: candidates[i].clone(),
             amount_sats: amount,
@@ -64,13 +121,65 @@ pub fn plan_opens(
     plan
 }
 
+/// `amount` rounded to the nearest multiple of `granularity` in
+/// `[floor, ceiling]`. Unchanged if no multiple lies in that range.
+fn round_amount(amount: u64, granularity: u64, floor: u64, ceiling: u64) -> u64 {
+    let nearest = (amount + granularity / 2) / granularity * granularity;
+    let rounded = if nearest > ceiling {
+        ceiling / granularity * granularity
+    } else if nearest < floor {
+        floor.div_ceil(granularity) * granularity
+    } else {
+        nearest
+    };
+    if rounded < floor || rounded > ceiling {
+        amount
+    } else {
+        rounded
+    }
+}
+
+/// Capacity left for routing after both sides' channel reserves.
+pub fn usable_after_reserves(amount_sats: u64, reserve_fraction: f64) -> u64 {
+    let reserve = ((amount_sats as f64 * reserve_fraction) as u64).max(MIN_RESERVE_SATS);
+    amount_sats.saturating_sub(2 * reserve)
+}
+
+/// Smallest channel whose capacity after reserves is at least `usable_sats`.
+fn min_size_for_usable(usable_sats: u64, reserve_fraction: f64) -> u64 {
+    let proportional = (usable_sats as f64 / (1.0 - 2.0 * reserve_fraction)).ceil() as u64;
+    proportional.max(usable_sats + 2 * MIN_RESERVE_SATS)
+}
+
+/// Average reserve fraction (per side) the server negotiated on our existing
+/// channels. Falls back to LDK's default when there is nothing to go on.
+pub fn observed_reserve_fraction(channels: &[Channel]) -> f64 {
+    let fractions: Vec<f64> = channels
+        .iter()
+        .filter(|c| c.channel_value_sats > 0)
+        .filter_map(|c| {
+            let ours = c.unspendable_punishment_reserve?;
+            let theirs = c.counterparty_unspendable_punishment_reserve;
+            Some((ours + theirs) as f64 / (2.0 * c.channel_value_sats as f64))
+        })
+        .filter(|f| *f > 0.0 && *f < 0.5)
+        .collect();
+
+    if fractions.is_empty() {
+        DEFAULT_RESERVE_FRACTION
+    } else {
+        fractions.iter().sum::<f64>() / fractions.len() as f64
+    }
+}
+
 /// Execute a planned channel open: connect to peer, then open channel.
+/// Returns whether a channel was opened (not in dry-run).
 pub async fn execute_open(
     config: &Config,
     client: &(impl LdkClient + Sync),
     db: &Database,
     open: &PlannedOpen,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<bool> {
     info!(
         "Autopilot: opening {} sat channel with {} ({})",
         open.amount_sats,
@@ -80,7 +189,7 @@ pub async fn execute_open(
 
     if config.general.dry_run {
         info!("  (dry-run: not executing)");
-        return Ok(());
+        return Ok(false);
     }
 
     // Step 1: Connect to peer
@@ -103,7 +212,9 @@ pub async fn execute_open(
         }
     }
 
-    // Step 2: Open channel
+    // Step 2: Open channel. OpenChannelRequest has no minimum_depth field,
+    // so funding confirmation depth is always the server's default; there
+    // is no per-peer control over it from here.
     let open_req = OpenChannelRequest {
         node_pubkey: open.candidate.node_id.clone(),
         address: open.candidate.address.clone(),
@@ -122,8 +233,8 @@ pub async fn execute_open(
             );
 
             // Save peer address for reconnection
-            let now = chrono::Utc::now().timestamp() as f64;
-            db.conn().execute(
+            let now = db.now().timestamp() as f64;
+            db.conn()?.execute(
                 "INSERT OR REPLACE INTO peer_addresses \
                  (node_id, address, last_connected_at, source) \
                  VALUES (?1, ?2, ?3, 'autopilot')",
@@ -135,7 +246,7 @@ pub async fn execute_open(
             )?;
 
             // Record in audit trail
-            db.conn().execute(
+            db.conn()?.execute(
                 "INSERT INTO autopilot_opens \
                  (channel_id, counterparty_node_id, amount_sats, opened_at, reason) \
                  VALUES (?1, ?2, ?3, ?4, ?5)",
@@ -147,17 +258,32 @@ pub async fn execute_open(
                     format!("source={:?}, score={:.2}", open.candidate.source, open.candidate.score),
                 ],
             )?;
+            audit::emit(
+                config,
+                "open",
+                json!({
+                    "user_channel_id": resp.user_channel_id,
+                    "counterparty_node_id": open.candidate.node_id,
+                    "address": open.candidate.address,
+                    "amount_sats": open.amount_sats,
+                    "source": format!("{:?}", open.candidate.source),
+                    "score": open.candidate.score,
+                }),
+            );
+
+            failures::clear(db, &open.candidate.node_id)?;
         }
         Err(e) => {
             error!(
                 "Autopilot: failed to open channel with {}: {}",
                 open.candidate.node_id, e
             );
+            failures::record_failure(config, db, &open.candidate, &format!("{:#}", e))?;
             return Err(e);
         }
     }
 
-    Ok(())
+    Ok(true)
 }
 
 #[cfg(test)]
@@ -186,7 +312,7 @@ mod tests {
             make_candidate("a", "1.2.3.4:9735", 100.0),
             make_candidate("b", "5.6.7.8:9735", 90.0),
         ];
-        let plan = plan_opens(&config, &candidates, 500_000, 2);
+        let plan = plan_opens(&config, &candidates, 500_000, 2, DEFAULT_RESERVE_FRACTION);
         assert_eq!(plan.len(), 2);
         // Budget split roughly evenly (250k each), both above min_channel_sats (100k)
         assert!(plan[0].amount_sats >= config.autopilot.min_channel_sats);
@@ -198,7 +324,7 @@ mod tests {
         let config = test_config();
         let candidates = vec![make_candidate("a", "1.2.3.4:9735", 100.0)];
         // Budget below min_channel_sats (100_000)
-        let plan = plan_opens(&config, &candidates, 50_000, 1);
+        let plan = plan_opens(&config, &candidates, 50_000, 1, DEFAULT_RESERVE_FRACTION);
         assert!(plan.is_empty());
     }
 
@@ -210,7 +336,7 @@ mod tests {
             make_candidate("b", "5.6.7.8:9735", 90.0),
             make_candidate("c", "9.10.11.12:9735", 80.0),
         ];
-        let plan = plan_opens(&config, &candidates, 1_000_000, 2);
+        let plan = plan_opens(&config, &candidates, 1_000_000, 2, DEFAULT_RESERVE_FRACTION);
         assert!(plan.len() <= 2);
     }
 
@@ -221,7 +347,7 @@ mod tests {
             make_candidate("a", "", 100.0), // No address
             make_candidate("b", "5.6.7.8:9735", 90.0),
         ];
-        let plan = plan_opens(&config, &candidates, 500_000, 2);
+        let plan = plan_opens(&config, &candidates, 500_000, 2, DEFAULT_RESERVE_FRACTION);
         // Should skip "a" and only open with "b"
         assert_eq!(plan.len(), 1);
         assert_eq!(plan[0].candidate.node_id, "b");
@@ -232,7 +358,7 @@ mod tests {
         let mut config = test_config();
         config.autopilot.max_channel_sats = 200_000;
         let candidates = vec![make_candidate("a", "1.2.3.4:9735", 100.0)];
-        let plan = plan_opens(&config, &candidates, 1_000_000, 1);
+        let plan = plan_opens(&config, &candidates, 1_000_000, 1, DEFAULT_RESERVE_FRACTION);
         assert_eq!(plan.len(), 1);
         assert!(plan[0].amount_sats <= 200_000);
     }
@@ -242,7 +368,7 @@ mod tests {
         let config = test_config();
         let candidates = vec![make_candidate("a", "1.2.3.4:9735", 100.0)];
         // With budget 400k and single candidate, 50% cap = 200k
-        let plan = plan_opens(&config, &candidates, 400_000, 1);
+        let plan = plan_opens(&config, &candidates, 400_000, 1, DEFAULT_RESERVE_FRACTION);
         assert_eq!(plan.len(), 1);
         assert!(plan[0].amount_sats <= 200_000);
     }
@@ -250,7 +376,110 @@ mod tests {
     #[test]
     fn test_plan_opens_empty_candidates() {
         let config = test_config();
-        let plan = plan_opens(&config, &[], 1_000_000, 5);
+        let plan = plan_opens(&config, &[], 1_000_000, 5, DEFAULT_RESERVE_FRACTION);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_plan_opens_bumps_for_reserves() {
+        let mut config = test_config();
+        config.autopilot.min_usable_channel_sats = Some(108_000);
+        let candidates = vec![
+            make_candidate("a", "1.2.3.4:9735", 100.0),
+            make_candidate("b", "5.6.7.8:9735", 90.0),
+            make_candidate("c", "9.10.11.12:9735", 80.0),
+        ];
+        // Even split gives 110k channels, leaving 107.8k usable after reserves:
+        // the first two get bumped, the last no longer fits the budget.
+        let plan = plan_opens(&config, &candidates, 330_000, 3, DEFAULT_RESERVE_FRACTION);
+        assert_eq!(plan.len(), 2);
+        for open in &plan {
+            assert!(usable_after_reserves(open.amount_sats, DEFAULT_RESERVE_FRACTION) >= 108_000);
+        }
+    }
+
+    #[test]
+    fn test_plan_opens_skips_when_reserves_cannot_be_covered() {
+        let mut config = test_config();
+        config.autopilot.min_usable_channel_sats = Some(99_000);
+        config.autopilot.max_channel_sats = 100_000;
+        let candidates = vec![make_candidate("a", "1.2.3.4:9735", 100.0)];
+        let plan = plan_opens(&config, &candidates, 200_000, 1, DEFAULT_RESERVE_FRACTION);
         assert!(plan.is_empty());
     }
+
+    #[test]
+    fn test_plan_opens_rounds_amounts() {
+        let mut config = test_config();
+        config.autopilot.open_amount_granularity_sats = 100_000;
+        let candidates = vec![
+            make_candidate("a", "1.2.3.4:9735", 100.0),
+            make_candidate("b", "5.6.7.8:9735", 90.0),
+            make_candidate("c", "9.10.11.12:9735", 80.0),
+        ];
+        // 333,333 each unrounded; rounding carries the remainder forward
+        let plan = plan_opens(&config, &candidates, 1_000_000, 3, DEFAULT_RESERVE_FRACTION);
+        let amounts: Vec<u64> = plan.iter().map(|o| o.amount_sats).collect();
+        assert_eq!(amounts, vec![300_000, 400_000, 300_000]);
+
+        // The 50% cap wins over rounding up
+        let plan = plan_opens(&config, &candidates[..1], 380_000, 1, DEFAULT_RESERVE_FRACTION);
+        assert_eq!(plan[0].amount_sats, 100_000);
+
+        // No multiple of 300k fits between the 100k minimum and the 190k cap
+        config.autopilot.open_amount_granularity_sats = 300_000;
+        let plan = plan_opens(&config, &candidates[..1], 380_000, 1, DEFAULT_RESERVE_FRACTION);
+        assert_eq!(plan[0].amount_sats, 190_000);
+    }
+
+    #[test]
+    fn test_observed_reserve_fraction() {
+        assert_eq!(observed_reserve_fraction(&[]), DEFAULT_RESERVE_FRACTION);
+
+        let channel = Channel {
+            channel_value_sats: 1_000_000,
+            unspendable_punishment_reserve: Some(20_000),
+            counterparty_unspendable_punishment_reserve: 20_000,
+            ..Default::default()
+        };
+        let f = observed_reserve_fraction(&[channel]);
+        assert!((f - 0.02).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_failed_open_leaves_no_audit_row_and_retries() {
+        let config = test_config();
+        let db = Database::open_in_memory().unwrap();
+        let mock = crate::client::mock::MockLdkClient::new();
+        let open = PlannedOpen {
+            candidate: make_candidate("a", "1.2.3.4:9735", 100.0),
+            amount_sats: 200_000,
+        };
+        let count = |table: &str| -> i64 {
+            db.conn()
+                .unwrap()
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |r| r.get(0))
+                .unwrap()
+        };
+
+        // A failed connect is tolerated; a failed open is not
+        mock.fail_next("connect_peer", 1);
+        mock.fail_next("open_channel", 2);
+        assert!(execute_open(&config, &mock, &db, &open).await.is_err());
+        assert!(execute_open(&config, &mock, &db, &open).await.is_err());
+        assert_eq!(count("autopilot_opens"), 0);
+        assert_eq!(count("peer_addresses"), 0);
+        let attempts: u32 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT attempts FROM autopilot_open_failures", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(attempts, 2);
+
+        execute_open(&config, &mock, &db, &open).await.unwrap();
+        assert_eq!(mock.call_count("open_channel"), 3);
+        assert_eq!(mock.open_channel_calls.lock().unwrap().len(), 1);
+        assert_eq!(count("autopilot_opens"), 1);
+        assert_eq!(count("autopilot_open_failures"), 0);
+    }
 }
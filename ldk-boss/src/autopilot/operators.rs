@@ -0,0 +1,154 @@
+/// Hard limit on channels to one operator (`autopilot.max_channels_per_operator`).
+///
+/// A large, well-ranked operator often runs several nodes, and the autopilot
+/// would happily open to all of them, leaving us fragile when that operator
+/// has an outage. Nodes are grouped by `autopilot.operators` when listed
+/// there, otherwise by network: the /24 of an IPv4 address or the /48 of an
+/// IPv6 one, which stands in for the hosting provider. Onion and DNS
+/// addresses say nothing about where a node runs, so unlisted nodes behind
+/// them are never limited.
+
+use crate::autopilot::candidate::Candidate;
+use crate::config::Config;
+use crate::db::Database;
+use log::debug;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// The operator a node is grouped under, if it can be told.
+pub fn operator_key(config: &Config, node_id: &str, address: Option<&str>) -> Option<String> {
+    if let Some((name, _)) = config
+        .autopilot
+        .operators
+        .iter()
+        .find(|(_, nodes)| nodes.iter().any(|n| n == node_id))
+    {
+        return Some(name.clone());
+    }
+    let addr: SocketAddr = address?.parse().ok()?;
+    Some(match addr.ip() {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(ip) => {
+            let s = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+        }
+    })
+}
+
+/// Drop candidates that would take an operator past the limit, counting our
+/// open channels and, in order, the candidates ahead of them in the ranking.
+pub fn limit_per_operator(
+    config: &Config,
+    db: &Database,
+    candidates: &mut Vec<Candidate>,
+) -> anyhow::Result<()> {
+    let limit = config.autopilot.max_channels_per_operator;
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    {
+        let conn = db.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT ch.counterparty_node_id, pa.address FROM channel_history ch \
+             LEFT JOIN peer_addresses pa ON pa.node_id = ch.counterparty_node_id \
+             WHERE ch.is_open = 1",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?;
+        for row in rows {
+            let (node_id, address) = row?;
+            if let Some(key) = operator_key(config, &node_id, address.as_deref()) {
+                *counts.entry(key).or_default() += 1;
+            }
+        }
+    }
+
+    candidates.retain(|c| {
+        let Some(key) = operator_key(config, &c.node_id, Some(&c.address)) else {
+            return true;
+        };
+        let count = counts.entry(key).or_default();
+        if *count >= limit {
+            debug!(
+                "Autopilot: skipping {}, already {} channels to its operator",
+                c.node_id, count
+            );
+            return false;
+        }
+        *count += 1;
+        true
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autopilot::candidate::CandidateSource;
+
+    fn make_candidate(id: &str, address: &str) -> Candidate {
+        Candidate {
+            node_id: id.to_string(),
+            address: address.to_string(),
+            score: 10.0,
+            source: CandidateSource::Hardcoded,
+        }
+    }
+
+    #[test]
+    fn test_operator_key() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config
+            .autopilot
+            .operators
+            .insert("acme".to_string(), vec!["a".to_string()]);
+
+        assert_eq!(operator_key(&config, "a", Some("1.2.3.4:9735")).unwrap(), "acme");
+        assert_eq!(operator_key(&config, "a", None).unwrap(), "acme");
+        assert_eq!(operator_key(&config, "b", Some("1.2.3.4:9735")).unwrap(), "1.2.3.0/24");
+        assert_eq!(
+            operator_key(&config, "b", Some("[2001:db8:1:2::5]:9735")).unwrap(),
+            "2001:db8:1::/48"
+        );
+        assert_eq!(operator_key(&config, "b", Some("abc.onion:9735")), None);
+        assert_eq!(operator_key(&config, "b", Some("node.example.com:9735")), None);
+    }
+
+    #[test]
+    fn test_limit_counts_open_channels_and_ranking() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.autopilot.max_channels_per_operator = 2;
+        db.conn()
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES ('ch', 'u', 'open', 1000000, 0, 0, 1); \
+                 INSERT INTO peer_addresses (node_id, address) VALUES ('open', '5.6.7.8:9735');",
+            )
+            .unwrap();
+
+        let mut candidates = vec![
+            make_candidate("a", "5.6.7.9:9735"),
+            make_candidate("b", "5.6.7.10:9735"),
+            make_candidate("c", "9.9.9.9:9735"),
+            make_candidate("d", "abc.onion:9735"),
+        ];
+        limit_per_operator(&config, &db, &mut candidates).unwrap();
+        let kept: Vec<&str> = candidates.iter().map(|c| c.node_id.as_str()).collect();
+        assert_eq!(kept, vec!["a", "c", "d"]);
+
+        // 0 = no limit
+        config.autopilot.max_channels_per_operator = 0;
+        let mut candidates = vec![make_candidate("b", "5.6.7.10:9735")];
+        limit_per_operator(&config, &db, &mut candidates).unwrap();
+        assert_eq!(candidates.len(), 1);
+    }
+}
@@ -0,0 +1,131 @@
+/// Cache of the external ranking API's candidates.
+///
+/// A fetch younger than `ranking_cache_ttl_mins` is served as is, without
+/// calling the API. When a refresh fails, the last good fetch is served
+/// instead of nothing, with its scores halved for every TTL it is past
+/// fresh, so an outage slowly hands priority back to the other sources.
+
+use super::candidate::{Candidate, CandidateSource};
+use crate::db::Database;
+
+const FETCHED_AT_KEY: &str = "ranking_cache_fetched_at";
+
+/// Replace the cache with a successful fetch (possibly empty).
+pub fn store(db: &Database, candidates: &[Candidate]) -> anyhow::Result<()> {
+    store_at(db, candidates, db.now().timestamp())
+}
+
+/// Written under a savepoint rather than a transaction: `advisor::plan`
+/// runs candidate selection inside a transaction of its own, and a nested
+/// BEGIN would fail.
+fn store_at(db: &Database, candidates: &[Candidate], fetched_at: i64) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+    conn.execute_batch("SAVEPOINT ranking_cache_store")?;
+    let result = (|| -> anyhow::Result<()> {
+        conn.execute("DELETE FROM ranking_cache", [])?;
+        for c in candidates {
+            conn.execute(
+                "INSERT OR REPLACE INTO ranking_cache (node_id, address, score) \
+                 VALUES (?1, ?2, ?3)",
+                rusqlite::params![c.node_id, c.address, c.score],
+            )?;
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+            rusqlite::params![FETCHED_AT_KEY, fetched_at.to_string()],
+        )?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = conn.execute_batch("ROLLBACK TO ranking_cache_store");
+    }
+    conn.execute_batch("RELEASE ranking_cache_store")?;
+    result
+}
+
+/// Seconds since the last successful fetch, if there ever was one.
+fn age_secs(db: &Database) -> Option<i64> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [FETCHED_AT_KEY],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .map(|at| db.now().timestamp() - at)
+}
+
+/// The cached candidates if the last fetch is within the TTL.
+pub fn fresh(db: &Database, ttl_mins: u64) -> anyhow::Result<Option<Vec<Candidate>>> {
+    match age_secs(db) {
+        Some(age) if age < (ttl_mins * 60) as i64 => Ok(Some(load(db, 1.0)?)),
+        _ => Ok(None),
+    }
+}
+
+/// The cached candidates whatever their age, scores decayed by staleness.
+pub fn stale(db: &Database, ttl_mins: u64) -> anyhow::Result<Vec<Candidate>> {
+    let Some(age) = age_secs(db) else {
+        return Ok(Vec::new());
+    };
+    let ttl_secs = (ttl_mins * 60) as f64;
+    let overdue = (age as f64 - ttl_secs).max(0.0) / ttl_secs;
+    load(db, 0.5f64.powf(overdue))
+}
+
+fn load(db: &Database, score_factor: f64) -> anyhow::Result<Vec<Candidate>> {
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare("SELECT node_id, address, score FROM ranking_cache")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Candidate {
+            node_id: row.get(0)?,
+            address: row.get(1)?,
+            score: row.get::<_, f64>(2)? * score_factor,
+            source: CandidateSource::External,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(node_id: &str, score: f64) -> Candidate {
+        Candidate {
+            node_id: node_id.to_string(),
+            address: "127.0.0.1:9735".to_string(),
+            score,
+            source: CandidateSource::External,
+        }
+    }
+
+    #[test]
+    fn test_fresh_within_ttl_only() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(fresh(&db, 60).unwrap().is_none());
+
+        store(&db, &[candidate("a", 50.0)]).unwrap();
+        let cached = fresh(&db, 60).unwrap().unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].score, 50.0);
+
+        let now = chrono::Utc::now().timestamp();
+        store_at(&db, &[candidate("a", 50.0)], now - 61 * 60).unwrap();
+        assert!(fresh(&db, 60).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stale_scores_decay() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(stale(&db, 60).unwrap().is_empty());
+
+        // Two TTLs old = one TTL past fresh = half the score
+        let now = chrono::Utc::now().timestamp();
+        store_at(&db, &[candidate("a", 50.0)], now - 2 * 3600).unwrap();
+        let cached = stale(&db, 60).unwrap();
+        assert!((cached[0].score - 25.0).abs() < 0.1, "{}", cached[0].score);
+    }
+}
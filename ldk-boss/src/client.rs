@@ -298,10 +298,24 @@ impl LdkClient for LdkBossClient {
 #[cfg(test)]
 pub mod mock {
     use super::*;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::sync::{Arc, Mutex};
 
+    /// Which calls fail, per method name (e.g. `"open_channel"`).
+    #[derive(Default)]
+    struct FailureScript {
+        /// Calls made so far, failed ones included
+        calls: HashMap<&'static str, u32>,
+        /// 1-based call numbers that fail
+        fail_calls: HashMap<String, HashSet<u32>>,
+        fail_always: HashSet<String>,
+    }
+
     /// Mock LDK client that returns preset responses and records API calls.
+    ///
+    /// Any method can be scripted to fail with `fail_nth`, `fail_next` or
+    /// `fail_always`. Failed calls count towards `call_count` but are not
+    /// added to the call recorders, which only hold calls that went through.
     pub struct MockLdkClient {
         pub node_info: GetNodeInfoResponse,
         pub balances: GetBalancesResponse,
@@ -320,6 +334,7 @@ pub mod mock {
         pub close_channel_calls: Arc<Mutex<Vec<CloseChannelRequest>>>,
         pub connect_peer_calls: Arc<Mutex<Vec<ConnectPeerRequest>>>,
         pub force_close_calls: Arc<Mutex<Vec<ForceCloseChannelRequest>>>,
+        failures: Mutex<FailureScript>,
     }
 
     impl MockLdkClient {
@@ -337,6 +352,7 @@ pub mod mock {
                 graph_channels: GraphListChannelsResponse::default(),
                 graph_channel_details: HashMap::new(),
                 peers: ListPeersResponse::default(),
+                failures: Mutex::new(FailureScript::default()),
                 update_config_calls: Arc::new(Mutex::new(Vec::new())),
                 open_channel_calls: Arc::new(Mutex::new(Vec::new())),
                 close_channel_calls: Arc::new(Mutex::new(Vec::new())),
@@ -344,19 +360,64 @@ pub mod mock {
                 force_close_calls: Arc::new(Mutex::new(Vec::new())),
             }
         }
+
+        /// Fail the `n`th call (1-based, counting from the first) of `method`.
+        pub fn fail_nth(&self, method: &str, n: u32) {
+            let mut script = self.failures.lock().unwrap();
+            script.fail_calls.entry(method.to_string()).or_default().insert(n);
+        }
+
+        /// Fail the next `times` calls of `method`, then succeed again.
+        pub fn fail_next(&self, method: &str, times: u32) {
+            let mut script = self.failures.lock().unwrap();
+            let made = script.calls.get(method).copied().unwrap_or(0);
+            script
+                .fail_calls
+                .entry(method.to_string())
+                .or_default()
+                .extend(made + 1..=made + times);
+        }
+
+        /// Fail every call of `method`, as if the server were unreachable.
+        pub fn fail_always(&self, method: &str) {
+            self.failures.lock().unwrap().fail_always.insert(method.to_string());
+        }
+
+        /// Calls of `method` made so far, failed ones included.
+        pub fn call_count(&self, method: &str) -> u32 {
+            self.failures.lock().unwrap().calls.get(method).copied().unwrap_or(0)
+        }
+
+        /// Count a call of `method` and fail it if scripted to.
+        fn check(&self, method: &'static str) -> anyhow::Result<()> {
+            let mut script = self.failures.lock().unwrap();
+            let n = {
+                let calls = script.calls.entry(method).or_insert(0);
+                *calls += 1;
+                *calls
+            };
+            let scripted = script.fail_calls.get(method).is_some_and(|c| c.contains(&n));
+            if scripted || script.fail_always.contains(method) {
+                anyhow::bail!("mock: {} call #{} failed", method, n);
+            }
+            Ok(())
+        }
     }
 
     #[async_trait::async_trait]
     impl LdkClient for MockLdkClient {
         async fn get_node_info(&self) -> anyhow::Result<GetNodeInfoResponse> {
+            self.check("get_node_info")?;
             Ok(self.node_info.clone())
         }
 
         async fn get_balances(&self) -> anyhow::Result<GetBalancesResponse> {
+            self.check("get_balances")?;
             Ok(self.balances.clone())
         }
 
         async fn list_channels(&self) -> anyhow::Result<ListChannelsResponse> {
+            self.check("list_channels")?;
             Ok(self.channels.clone())
         }
 
@@ -364,6 +425,7 @@ pub mod mock {
             &self,
             _page_token: Option<PageToken>,
         ) -> anyhow::Result<ListForwardedPaymentsResponse> {
+            self.check("list_forwarded_payments")?;
             Ok(self.forwarded_payments.clone())
         }
 
@@ -371,6 +433,7 @@ pub mod mock {
             &self,
             request: UpdateChannelConfigRequest,
         ) -> anyhow::Result<UpdateChannelConfigResponse> {
+            self.check("update_channel_config")?;
             self.update_config_calls.lock().unwrap().push(request);
             Ok(UpdateChannelConfigResponse {})
         }
@@ -379,6 +442,7 @@ pub mod mock {
             &self,
             request: ConnectPeerRequest,
         ) -> anyhow::Result<ConnectPeerResponse> {
+            self.check("connect_peer")?;
             self.connect_peer_calls.lock().unwrap().push(request);
             Ok(ConnectPeerResponse {})
         }
@@ -387,6 +451,7 @@ pub mod mock {
             &self,
             request: OpenChannelRequest,
         ) -> anyhow::Result<OpenChannelResponse> {
+            self.check("open_channel")?;
             let user_channel_id = format!("mock_user_channel_{}", request.node_pubkey);
             self.open_channel_calls.lock().unwrap().push(request);
             Ok(OpenChannelResponse {
@@ -398,6 +463,7 @@ pub mod mock {
             &self,
             request: CloseChannelRequest,
         ) -> anyhow::Result<CloseChannelResponse> {
+            self.check("close_channel")?;
             self.close_channel_calls.lock().unwrap().push(request);
             Ok(CloseChannelResponse {})
         }
@@ -406,6 +472,7 @@ pub mod mock {
             &self,
             _request: Bolt11ReceiveRequest,
         ) -> anyhow::Result<Bolt11ReceiveResponse> {
+            self.check("bolt11_receive")?;
             Ok(Bolt11ReceiveResponse {
                 invoice: "lnbcrt1mock_invoice".to_string(),
             })
@@ -415,6 +482,7 @@ pub mod mock {
             &self,
             _request: Bolt11SendRequest,
         ) -> anyhow::Result<Bolt11SendResponse> {
+            self.check("bolt11_send")?;
             Ok(Bolt11SendResponse {
                 payment_id: "mock_payment_id".to_string(),
             })
@@ -424,11 +492,13 @@ pub mod mock {
             &self,
             request: ForceCloseChannelRequest,
         ) -> anyhow::Result<ForceCloseChannelResponse> {
+            self.check("force_close_channel")?;
             self.force_close_calls.lock().unwrap().push(request);
             Ok(ForceCloseChannelResponse {})
         }
 
         async fn graph_list_nodes(&self) -> anyhow::Result<GraphListNodesResponse> {
+            self.check("graph_list_nodes")?;
             Ok(self.graph_nodes.clone())
         }
 
@@ -436,6 +506,7 @@ pub mod mock {
             &self,
             request: GraphGetNodeRequest,
         ) -> anyhow::Result<GraphGetNodeResponse> {
+            self.check("graph_get_node")?;
             Ok(self
                 .graph_node_details
                 .get(&request.node_id)
@@ -444,6 +515,7 @@ pub mod mock {
         }
 
         async fn graph_list_channels(&self) -> anyhow::Result<GraphListChannelsResponse> {
+            self.check("graph_list_channels")?;
             Ok(self.graph_channels.clone())
         }
 
@@ -451,6 +523,7 @@ pub mod mock {
             &self,
             request: GraphGetChannelRequest,
         ) -> anyhow::Result<GraphGetChannelResponse> {
+            self.check("graph_get_channel")?;
             Ok(self
                 .graph_channel_details
                 .get(&request.short_channel_id)
@@ -459,7 +532,39 @@ pub mod mock {
         }
 
         async fn list_peers(&self) -> anyhow::Result<ListPeersResponse> {
+            self.check("list_peers")?;
             Ok(self.peers.clone())
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_scripted_failures() {
+            let mock = MockLdkClient::new();
+            mock.fail_nth("get_balances", 2);
+            assert!(mock.get_balances().await.is_ok());
+            assert!(mock.get_balances().await.is_err());
+            assert!(mock.get_balances().await.is_ok());
+
+            // Fail then succeed, counted from the calls already made
+            mock.get_node_info().await.unwrap();
+            mock.fail_next("get_node_info", 2);
+            assert!(mock.get_node_info().await.is_err());
+            assert!(mock.get_node_info().await.is_err());
+            assert!(mock.get_node_info().await.is_ok());
+            assert_eq!(mock.call_count("get_node_info"), 4);
+
+            mock.fail_always("close_channel");
+            for _ in 0..3 {
+                let req = CloseChannelRequest::default();
+                assert!(mock.close_channel(req).await.is_err());
+            }
+            assert_eq!(mock.call_count("close_channel"), 3);
+            assert!(mock.close_channel_calls.lock().unwrap().is_empty());
+            assert_eq!(mock.call_count("list_peers"), 0);
+        }
+    }
 }
@@ -0,0 +1,50 @@
+/// Source of the current time.
+///
+/// Code that stores or compares timestamps reads the time through the
+/// database's clock (`Database::now`) rather than `chrono::Utc::now()`, so a
+/// test can install a `MockClock` and step over a cooldown, backoff or day
+/// boundary instead of sleeping or back-dating rows.
+
+use chrono::{DateTime, Utc};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct MockClock {
+    now: std::sync::Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// Start at `timestamp` (unix seconds).
+    pub fn at(timestamp: i64) -> std::sync::Arc<Self> {
+        std::sync::Arc::new(Self {
+            now: std::sync::Mutex::new(
+                DateTime::from_timestamp(timestamp, 0).expect("timestamp in range"),
+            ),
+        })
+    }
+
+    pub fn advance_secs(&self, secs: i64) {
+        *self.now.lock().unwrap() += chrono::Duration::seconds(secs);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
@@ -1,7 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub server: ServerConfig,
     #[serde(default)]
@@ -18,9 +19,29 @@ pub struct Config {
     pub reconnector: ReconnectorConfig,
     #[serde(default)]
     pub onchain_fees: OnchainFeesConfig,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Log level per module, on top of `general.log_level`
+    /// (e.g. `judge = "debug"`). Keys are in `LOG_MODULES`
+    #[serde(default)]
+    pub log_levels: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Modules `[log_levels]` can set a level for.
+pub const LOG_MODULES: &[&str] = &[
+    "autopilot",
+    "fees",
+    "judge",
+    "rebalancer",
+    "reconnector",
+    "tracker",
+];
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ServerConfig {
     /// LDK Server REST endpoint (host:port, no scheme)
     pub base_url: String,
@@ -30,12 +51,12 @@ pub struct ServerConfig {
     pub tls_cert_path: PathBuf,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GeneralConfig {
     /// Path to LDKBoss's SQLite database
     #[serde(default = "default_database_path")]
     pub database_path: PathBuf,
-    /// Logging level
+    /// Logging level, or a `tracing` filter directive string
     #[serde(default = "default_log_level")]
     pub log_level: String,
     /// Bitcoin network
@@ -50,9 +71,64 @@ pub struct GeneralConfig {
     /// Control loop interval in seconds
     #[serde(default = "default_loop_interval")]
     pub loop_interval_secs: u64,
+    /// Run the network-bound tracker updates (forward ingestion, on-chain fee
+    /// sampling) concurrently instead of one after the other
+    #[serde(default)]
+    pub concurrent_trackers: bool,
+    /// Bound how much a single cycle can change, regardless of module
+    /// settings: at most one open, one close, `safe_mode_max_fee_changes`
+    /// fee updates, and no force closes
+    #[serde(default)]
+    pub safe_mode: bool,
+    /// Fee updates allowed per cycle in safe mode
+    #[serde(default = "default_safe_mode_max_fee_changes")]
+    pub safe_mode_max_fee_changes: usize,
+    /// Keep fees updating but pause autopilot, rebalancer and judge,
+    /// regardless of their own `enabled` flags
+    #[serde(default)]
+    pub vacation_mode: bool,
+    /// Warn about peers with more channels than this (0 = never warn)
+    #[serde(default = "default_max_channels_per_peer")]
+    pub max_channels_per_peer: usize,
+    /// Which side of a forward earns its fee in a peer's total (the judge,
+    /// peer yields): "in" (the channel it arrived on), "out" (the channel it
+    /// left on) or "split" (half each). Either way the fee is counted once.
+    /// Per-direction earnings always record the full fee
+    #[serde(default = "default_earnings_attribution")]
+    pub earnings_attribution: String,
+    /// Abandon a cycle that runs longer than this, in seconds (0 = no limit).
+    /// With no limit, the instance lock assumes a cycle takes at most two
+    /// loop intervals; a longer one lets another instance take the database
+    /// over, and this one exits once the cycle ends
+    #[serde(default = "default_cycle_timeout")]
+    pub cycle_timeout_secs: u64,
+    /// Exit with an error after this many timed-out cycles in a row, so a
+    /// supervisor restarts the daemon (0 = keep going)
+    #[serde(default = "default_max_cycle_timeouts")]
+    pub max_cycle_timeouts: u32,
+    /// If listing channels fails, run the cycle on the last snapshot (up to
+    /// an hour old): trackers keep recording, channel actions are skipped
+    #[serde(default)]
+    pub tolerate_transient_failures: bool,
+    /// Keep the scheduler's tick count across restarts, so a restart doesn't
+    /// run the judge and autopilot immediately (tick 0 is due for every
+    /// module) but continues their normal schedule
+    #[serde(default = "default_true")]
+    pub persist_tick_count: bool,
+    /// End each cycle with one info line counting what it did; when false
+    /// the line is logged at debug level
+    #[serde(default = "default_true")]
+    pub log_cycle_summary: bool,
+    /// Channel IDs left entirely to the operator: no fee changes,
+    /// rebalancing or judge closures
+    #[serde(default)]
+    pub strategic_channels: Vec<String>,
+    /// Peers whose channels are all treated as strategic
+    #[serde(default)]
+    pub strategic_peers: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AutopilotConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -68,12 +144,45 @@ pub struct AutopilotConfig {
     /// Maximum channel size in satoshis
     #[serde(default = "default_max_channel_sats")]
     pub max_channel_sats: u64,
+    /// Minimum capacity left after both sides' channel reserves (satoshis).
+    /// Read it through `AutopilotConfig::min_usable_channel_sats()`, which
+    /// fills in the default when unset
+    #[serde(default)]
+    pub min_usable_channel_sats: Option<u64>,
     /// On-chain reserve (satoshis) to always keep
     #[serde(default = "default_onchain_reserve")]
     pub onchain_reserve_sats: u64,
+    /// On-chain reserve as a percentage of total funds (0 = disabled). When
+    /// both are set the larger reserve applies
+    #[serde(default)]
+    pub onchain_reserve_percent: f64,
     /// Minimum on-chain % before opening channels
     #[serde(default = "default_min_onchain_percent")]
     pub min_onchain_percent: f64,
+    /// Warn when the balances call's lightning total is more than this
+    /// percent off the channel list's (0 = don't check)
+    #[serde(default = "default_balance_mismatch_percent")]
+    pub balance_mismatch_percent: f64,
+    /// On such a mismatch, decide with the channel list's figure instead
+    #[serde(default)]
+    pub prefer_channel_balances: bool,
+    /// Only open once the low-fee regime has lasted this long (minutes,
+    /// 0 = as soon as it turns low)
+    #[serde(default)]
+    pub min_low_regime_duration_mins: u64,
+    /// Ceiling on the capacity of autopilot-opened channels still open or
+    /// pending, in satoshis (0 = no ceiling)
+    #[serde(default)]
+    pub max_total_deployed_sats: u64,
+    /// Never open while the latest on-chain fee sample is above this
+    /// (sat/vB), whatever the fee regime, or while there is no sample from
+    /// the last hour (0 = no ceiling)
+    #[serde(default)]
+    pub max_open_feerate_sat_per_vb: f64,
+    /// Round channel sizes to a multiple of this many satoshis, so opens
+    /// don't carry budget-derived amounts (0 = no rounding)
+    #[serde(default)]
+    pub open_amount_granularity_sats: u64,
     /// Max on-chain % before opening even in high-fee regime
     #[serde(default = "default_max_onchain_percent")]
     pub max_onchain_percent: f64,
@@ -83,15 +192,59 @@ pub struct AutopilotConfig {
     /// External node ranking API URL (empty = disabled)
     #[serde(default)]
     pub ranking_api_url: String,
+    /// What to do when the ranking API is configured but yields nothing:
+    /// "fallback" (use the other sources), "skip" (no opens this cycle) or
+    /// "alert" (fall back, but log an error and flag it in `status`)
+    #[serde(default = "default_on_ranking_empty")]
+    pub on_ranking_empty: String,
+    /// Minutes a ranking API response is reused before it is fetched again;
+    /// also the half-life of cached scores served while the API is down
+    #[serde(default = "default_ranking_cache_ttl")]
+    pub ranking_cache_ttl_mins: u64,
     /// Specific nodes to always consider (node_id@host:port)
     #[serde(default)]
     pub seed_nodes: Vec<String>,
     /// Nodes to never open channels with (node_id hex)
     #[serde(default)]
     pub blacklist: Vec<String>,
+    /// Candidate selection: "top" (highest score first) or "weighted_random"
+    #[serde(default = "default_selection")]
+    pub selection: String,
+    /// Candidate score is divided by `1 + stability_weight * disconnects`,
+    /// counting disconnects over the last 30 days while we had a channel
+    /// with the node (0 = ignore)
+    #[serde(default)]
+    pub stability_weight: f64,
+    /// Weight of forwarding earnings older than 30 days when ranking the
+    /// peers whose neighbors become candidates (0 = only the last 30 days
+    /// count, 1 = all history counts the same)
+    #[serde(default)]
+    pub older_earnings_weight: f64,
+    /// Which advertised address to open channels over: "any" (first
+    /// advertised), "clearnet" (prefer non-onion) or "tor" (onion only).
+    /// Applies to autopilot candidates only, not to reconnecting peers
+    #[serde(default = "default_address_preference")]
+    pub address_preference: String,
+    /// Built-in fallback nodes to drop from HARDCODED_NODES (node_id hex)
+    #[serde(default)]
+    pub exclude_hardcoded: Vec<String>,
+    /// Attempts before a transiently-failing open is treated as permanent
+    #[serde(default = "default_open_retry_max_attempts")]
+    pub open_retry_max_attempts: u32,
+    /// How long a peer that rejected our open stays blacklisted (hours)
+    #[serde(default = "default_failed_open_blacklist_hours")]
+    pub failed_open_blacklist_hours: u64,
+    /// Never have more than this many channels to one operator: nodes listed
+    /// together in `operators`, or otherwise on the same IPv4 /24 or IPv6
+    /// /48 (0 = no limit)
+    #[serde(default)]
+    pub max_channels_per_operator: usize,
+    /// Known operators' nodes, by operator name (node_id hex)
+    #[serde(default)]
+    pub operators: BTreeMap<String, Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct FeesConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -107,24 +260,78 @@ pub struct FeesConfig {
     /// Preferred bin size for balance modder (satoshis)
     #[serde(default = "default_preferred_bin_size")]
     pub preferred_bin_size_sats: u64,
+    /// What the balance modder's ratio is taken of: "spendable" (our
+    /// outbound over both sides' spendable amounts, reserves excluded) or
+    /// "capacity" (our outbound over the channel's capacity, which reads
+    /// low by our reserve)
+    #[serde(default = "default_balance_ratio_basis")]
+    pub balance_ratio_basis: String,
+    /// While the on-chain fee regime is high, let the balance modder raise
+    /// fees but not discount them
+    #[serde(default)]
+    pub freeze_balance_modder_in_high_regime: bool,
+    /// Keep the balance modder neutral while fewer channels than this are
+    /// fee-managed (0 = always active)
+    #[serde(default)]
+    pub balance_modder_min_channels: usize,
     /// Enable price theory card-game optimizer
     #[serde(default = "default_true")]
     pub price_theory_enabled: bool,
     /// Card lifetime in ticks (~10min each)
     #[serde(default = "default_card_lifetime")]
     pub price_theory_card_lifetime_ticks: u32,
+    /// Card lifetime in hours; when set, overrides price_theory_card_lifetime_ticks
+    /// and is converted to ticks using general.loop_interval_secs
+    #[serde(default)]
+    pub price_theory_card_lifetime_hours: Option<f64>,
     /// Max price step from center
     #[serde(default = "default_price_step")]
     pub price_theory_max_step: i32,
+    /// Stop exploring and charge the center price outright once a peer's
+    /// center has held this many days (0 = keep exploring forever)
+    #[serde(default)]
+    pub price_theory_graduate_days: u64,
+    /// Resume exploring when a graduated peer's daily earnings fall this
+    /// many percent below what they were at graduation
+    #[serde(default = "default_price_theory_regress_percent")]
+    pub price_theory_regress_percent: f64,
+    /// Learn from forwards which fee rates each channel bears, and start a
+    /// new peer's price theory center there instead of at 0
+    #[serde(default)]
+    pub learn_fee_demand: bool,
     /// Use competitor fee monitoring to set market-relative base fees
     #[serde(default = "default_true")]
     pub competitor_fee_enabled: bool,
     /// Enable size-based fee modulation (charge more if we're larger than competitors)
     #[serde(default = "default_true")]
     pub size_modder_enabled: bool,
+    /// Channels younger than this never go below default_ppm, regardless of balance (0 = off)
+    #[serde(default = "default_new_channel_protect_days")]
+    pub new_channel_protect_days: u64,
+    /// Also manage fees on unannounced (private) channels
+    #[serde(default = "default_true")]
+    pub manage_private_channels: bool,
+    /// Skip fee updates that move ppm by no more than this and leave the base
+    /// fee unchanged
+    #[serde(default = "default_fee_update_deadband")]
+    pub fee_update_deadband_ppm: u32,
+    /// Re-send a channel's fees after this many hours without an update, so
+    /// its channel_update doesn't age out of gossip (0 = off)
+    #[serde(default)]
+    pub max_fee_age_hours: u64,
+    /// Final factor applied to every computed fee, before clamping
+    #[serde(default = "default_global_fee_multiplier")]
+    pub global_multiplier: f64,
+    /// Fee multiplier on a channel while a registered liquidity cost is
+    /// being recouped (see `ldk-boss liquidity-cost`)
+    #[serde(default = "default_liquidity_premium_multiplier")]
+    pub liquidity_premium_multiplier: f64,
+    /// Drop the premium after this many days even if not recouped
+    #[serde(default = "default_liquidity_recoup_days")]
+    pub liquidity_recoup_days: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct RebalancerConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -143,12 +350,45 @@ pub struct RebalancerConfig {
     /// Maximum fee per rebalance in PPM
     #[serde(default = "default_rebalance_fee_ppm")]
     pub max_fee_ppm: u32,
+    /// Per-peer `max_fee_ppm` overrides, by node ID, applied to rebalances
+    /// into that peer's channels
+    #[serde(default)]
+    pub peer_max_fee_ppm: BTreeMap<String, u32>,
     /// Maximum total fee budget per cycle (satoshis)
     #[serde(default = "default_max_total_fee")]
     pub max_total_fee_sats: u64,
+    /// Largest single rebalance payment; bigger amounts are split (millisatoshis)
+    #[serde(default = "default_max_rebalance_amount")]
+    pub max_rebalance_amount_msat: u64,
+    /// Rebalances smaller than this are not attempted (millisatoshis)
+    #[serde(default = "default_min_rebalance_amount")]
+    pub min_rebalance_amount_msat: u64,
+    /// Only rebalance a pair if the net earnings paying for it (see
+    /// `primary_goal`) exceed the planned fee by at least this much (satoshis, 0 = off)
+    #[serde(default)]
+    pub min_profit_margin_sats: u64,
+    /// Fill destinations towards target_spendable_percent by earnings rank:
+    /// the top earner all the way, the least earning barely past
+    /// max_spendable_percent
+    #[serde(default)]
+    pub earnings_weighted_targets: bool,
+    /// Whose earnings justify a rebalance's fees: "outbound" (the
+    /// destination's), "inbound" (the source's) or "balance" (both)
+    #[serde(default = "default_primary_goal")]
+    pub primary_goal: String,
+    /// When a pair isn't fully rebalanced and its destination is below this
+    /// spendable % (or its source above 100 minus it), recommend a swap
+    /// (percent, 0 = off)
+    #[serde(default)]
+    pub swap_severe_percent: f64,
+    /// Command that executes a recommended swap, called with the direction
+    /// ("in" or "out"), channel ID, peer node ID and amount in sats (unset =
+    /// only log the recommendation)
+    #[serde(default)]
+    pub swap_command: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct JudgeConfig {
     /// Disabled by default -- must explicitly opt-in
     #[serde(default)]
@@ -156,25 +396,118 @@ pub struct JudgeConfig {
     /// Minimum channel age in days before judgment
     #[serde(default = "default_min_age_days")]
     pub min_age_days: u64,
+    /// Never close a channel younger than this many days, even when its peer
+    /// is evaluated and flagged. At least min_age_days (0 = only
+    /// min_age_days applies)
+    #[serde(default)]
+    pub protect_until_days: u64,
     /// Evaluation window in days
     #[serde(default = "default_eval_window")]
     pub evaluation_window_days: u64,
     /// Estimated cost to reopen a channel (satoshis)
     #[serde(default = "default_reopen_cost")]
     pub estimated_reopen_cost_sats: u64,
+    /// Only recommend closing a peer whose expected improvement (after the
+    /// reopen cost) exceeds this (satoshis, 0 = any positive improvement)
+    #[serde(default)]
+    pub min_improvement_sats: u64,
     /// Use cooperative close (true) or force close (false)
     #[serde(default = "default_true")]
     pub cooperative_close: bool,
+    /// Force-close all channels with a peer that broadcast a revoked state.
+    /// Independent of `enabled`; runs every cycle
+    #[serde(default)]
+    pub panic_close_enabled: bool,
+    /// Minimum revoked-output amount before panic closing (satoshis)
+    #[serde(default)]
+    pub panic_close_min_revoked_sats: u64,
+    /// Evaluation windows a flagged peer stays on probation before being
+    /// closed (0 = close on the first flag)
+    #[serde(default)]
+    pub probation_windows: u32,
+    /// Fee multiplier for peers on probation
+    #[serde(default = "default_probation_fee_multiplier")]
+    pub probation_fee_multiplier: f64,
+    /// Skip peers online less than this share of the evaluation window
+    /// (percent, 0 = disabled). Uptime is sampled by the reconnector
+    #[serde(default = "default_min_uptime_percent")]
+    pub min_uptime_percent: f64,
+    /// Among peers due for closure, close those with several channels first
+    #[serde(default)]
+    pub prefer_redundant_closes: bool,
+    /// Closures executed per cycle. Raise it (up to 3) only for an initial
+    /// cleanup of a node with many bad channels; safe mode always uses 1
+    #[serde(default = "default_max_closures_per_cycle")]
+    pub max_closures_per_cycle: usize,
+    /// Earning rate (msat per sat of capacity) deducted from a peer for each
+    /// time it disconnected during the evaluation window (0 = ignore)
+    #[serde(default)]
+    pub instability_weight: f64,
+    /// Force-close when a cooperative close fails (typically because the
+    /// peer is offline). Not in safe mode; at most once a day per channel
+    #[serde(default)]
+    pub force_close_on_coop_failure: bool,
+    /// Before closing a channel that is mostly ours, lower its fees until
+    /// our share drops to `drain_target_percent` (or the drain times out),
+    /// so less of our balance goes on-chain
+    #[serde(default)]
+    pub drain_before_close: bool,
+    /// Our share of a channel (percent) at which a drain is done
+    #[serde(default = "default_drain_target_percent")]
+    pub drain_target_percent: f64,
+    /// Close a draining channel after this many days regardless
+    #[serde(default = "default_drain_timeout_days")]
+    pub drain_timeout_days: u64,
+    /// Fee multiplier while draining
+    #[serde(default = "default_drain_fee_multiplier")]
+    pub drain_fee_multiplier: f64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ReconnectorConfig {
     /// Enable automatic peer reconnection
     #[serde(default = "default_true")]
     pub enabled: bool,
+    /// Connection attempts per cycle (0 = unlimited). Peers waiting longest
+    /// since their last attempt go first
+    #[serde(default = "default_max_reconnect_attempts")]
+    pub max_attempts_per_cycle: usize,
+    /// Periodically update channel peers' addresses from their gossip node
+    /// announcements, so a peer that moved can still be reconnected
+    #[serde(default)]
+    pub refresh_from_gossip: bool,
+    /// Hours between gossip address refreshes
+    #[serde(default = "default_gossip_refresh_hours")]
+    pub gossip_refresh_hours: u64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ControlConfig {
+    /// Unix socket the daemon listens on for control commands (unset = off).
+    /// A file already there that isn't a socket is an error, never replaced.
+    #[serde(default)]
+    pub socket_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AuditConfig {
+    /// File to append a JSON line to for every action taken (unset = off)
+    #[serde(default)]
+    pub jsonl_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MetricsConfig {
+    /// File to write Prometheus text-format metrics to, for node-exporter's
+    /// textfile collector (unset = off)
+    #[serde(default)]
+    pub textfile_path: Option<PathBuf>,
+    /// Minimum seconds between rewrites of the file (0 = every cycle)
+    #[serde(default = "default_metrics_write_interval")]
+    pub write_interval_secs: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct OnchainFeesConfig {
     /// Provider: "mempool" or "none"
     #[serde(default = "default_fee_provider")]
@@ -188,6 +521,28 @@ pub struct OnchainFeesConfig {
     /// Percentile threshold: low -> high fee regime
     #[serde(default = "default_lo_to_hi")]
     pub lo_to_hi_percentile: f64,
+    /// Fee samples needed before percentiles are trusted
+    #[serde(default = "default_min_samples_for_regime")]
+    pub min_samples_for_regime: usize,
+    /// Regime assumed until enough samples exist: "high" or "low"
+    #[serde(default = "default_regime")]
+    pub default_regime: String,
+    /// Which mempool.space estimate is recorded as the fee sample:
+    /// "fastest", "half_hour", "hour", "economy" or "minimum"
+    #[serde(default = "default_fee_reference")]
+    pub reference: String,
+    /// When the API response lacks the `reference` estimate, record the
+    /// nearest one it has instead of skipping the sample
+    #[serde(default = "default_true")]
+    pub reference_fallback: bool,
+    /// With no fee samples yet, seed them at startup from recent blocks'
+    /// fee rates instead of waiting for `min_samples_for_regime` cycles
+    #[serde(default = "default_true")]
+    pub backfill_on_startup: bool,
+    /// Consecutive samples above the high threshold needed to switch to the
+    /// high regime (1 = switch on the first one)
+    #[serde(default = "default_sustained_high_samples")]
+    pub require_sustained_high_regime_samples: usize,
 }
 
 // Default value functions
@@ -206,6 +561,21 @@ fn default_true() -> bool {
 fn default_loop_interval() -> u64 {
     600
 }
+fn default_max_channels_per_peer() -> usize {
+    1
+}
+fn default_earnings_attribution() -> String {
+    "split".to_string()
+}
+fn default_cycle_timeout() -> u64 {
+    1800
+}
+fn default_max_cycle_timeouts() -> u32 {
+    3
+}
+fn default_safe_mode_max_fee_changes() -> usize {
+    5
+}
 fn default_min_channels_to_backoff() -> usize {
     4
 }
@@ -218,15 +588,46 @@ fn default_min_channel_sats() -> u64 {
 fn default_max_channel_sats() -> u64 {
     16_777_215
 }
+fn default_min_usable_channel_sats() -> u64 {
+    95_000
+}
+/// Share of `max_channel_sats` the default `min_usable_channel_sats` is
+/// capped at: what a max-size channel still leaves after two 1% reserves,
+/// with room for the 1000 sat reserve floor down to the 40k sat range.
+const DEFAULT_MIN_USABLE_MAX_PERCENT: u64 = 95;
 fn default_onchain_reserve() -> u64 {
     30_000
 }
+fn default_balance_mismatch_percent() -> f64 {
+    10.0
+}
 fn default_min_onchain_percent() -> f64 {
     10.0
 }
 fn default_max_onchain_percent() -> f64 {
     25.0
 }
+fn default_on_ranking_empty() -> String {
+    "fallback".to_string()
+}
+fn default_ranking_cache_ttl() -> u64 {
+    60
+}
+fn default_primary_goal() -> String {
+    "outbound".to_string()
+}
+fn default_selection() -> String {
+    "top".to_string()
+}
+fn default_address_preference() -> String {
+    "any".to_string()
+}
+fn default_open_retry_max_attempts() -> u32 {
+    3
+}
+fn default_failed_open_blacklist_hours() -> u64 {
+    168
+}
 fn default_base_msat() -> u32 {
     1000
 }
@@ -236,12 +637,27 @@ fn default_ppm() -> u32 {
 fn default_preferred_bin_size() -> u64 {
     200_000
 }
+fn default_balance_ratio_basis() -> String {
+    "spendable".to_string()
+}
+fn default_new_channel_protect_days() -> u64 {
+    7
+}
+fn default_fee_update_deadband() -> u32 {
+    2
+}
+fn default_global_fee_multiplier() -> f64 {
+    1.0
+}
 fn default_card_lifetime() -> u32 {
     288
 }
 fn default_price_step() -> i32 {
     2
 }
+fn default_price_theory_regress_percent() -> f64 {
+    50.0
+}
 fn default_trigger_probability() -> f64 {
     0.5
 }
@@ -260,15 +676,51 @@ fn default_rebalance_fee_ppm() -> u32 {
 fn default_max_total_fee() -> u64 {
     10_000
 }
+fn default_max_rebalance_amount() -> u64 {
+    500_000_000
+}
+fn default_min_rebalance_amount() -> u64 {
+    10_000_000
+}
 fn default_min_age_days() -> u64 {
     90
 }
+fn default_max_reconnect_attempts() -> usize {
+    10
+}
+fn default_gossip_refresh_hours() -> u64 {
+    24
+}
+fn default_max_closures_per_cycle() -> usize {
+    1
+}
 fn default_eval_window() -> u64 {
     30
 }
 fn default_reopen_cost() -> u64 {
     5000
 }
+fn default_min_uptime_percent() -> f64 {
+    50.0
+}
+fn default_liquidity_premium_multiplier() -> f64 {
+    1.25
+}
+fn default_liquidity_recoup_days() -> u64 {
+    90
+}
+fn default_drain_target_percent() -> f64 {
+    20.0
+}
+fn default_drain_timeout_days() -> u64 {
+    14
+}
+fn default_drain_fee_multiplier() -> f64 {
+    0.25
+}
+fn default_probation_fee_multiplier() -> f64 {
+    0.8
+}
 fn default_fee_provider() -> String {
     "mempool".to_string()
 }
@@ -281,6 +733,21 @@ fn default_hi_to_lo() -> f64 {
 fn default_lo_to_hi() -> f64 {
     23.0
 }
+fn default_min_samples_for_regime() -> usize {
+    6
+}
+fn default_sustained_high_samples() -> usize {
+    1
+}
+fn default_fee_reference() -> String {
+    "hour".to_string()
+}
+fn default_regime() -> String {
+    "high".to_string()
+}
+fn default_metrics_write_interval() -> u64 {
+    60
+}
 
 // Default implementations
 impl Default for GeneralConfig {
@@ -292,6 +759,19 @@ impl Default for GeneralConfig {
             enabled: true,
             dry_run: false,
             loop_interval_secs: default_loop_interval(),
+            concurrent_trackers: false,
+            safe_mode: false,
+            safe_mode_max_fee_changes: default_safe_mode_max_fee_changes(),
+            vacation_mode: false,
+            max_channels_per_peer: default_max_channels_per_peer(),
+            earnings_attribution: default_earnings_attribution(),
+            cycle_timeout_secs: default_cycle_timeout(),
+            max_cycle_timeouts: default_max_cycle_timeouts(),
+            tolerate_transient_failures: false,
+            persist_tick_count: true,
+            log_cycle_summary: true,
+            strategic_channels: Vec::new(),
+            strategic_peers: Vec::new(),
         }
     }
 }
@@ -304,17 +784,48 @@ impl Default for AutopilotConfig {
             max_proposals: default_max_proposals(),
             min_channel_sats: default_min_channel_sats(),
             max_channel_sats: default_max_channel_sats(),
+            min_usable_channel_sats: None,
             onchain_reserve_sats: default_onchain_reserve(),
+            onchain_reserve_percent: 0.0,
             min_onchain_percent: default_min_onchain_percent(),
+            balance_mismatch_percent: default_balance_mismatch_percent(),
+            prefer_channel_balances: false,
+            min_low_regime_duration_mins: 0,
+            max_total_deployed_sats: 0,
+            max_open_feerate_sat_per_vb: 0.0,
+            open_amount_granularity_sats: 0,
             max_onchain_percent: default_max_onchain_percent(),
             announce_channels: true,
             ranking_api_url: String::new(),
+            on_ranking_empty: default_on_ranking_empty(),
+            ranking_cache_ttl_mins: default_ranking_cache_ttl(),
             seed_nodes: Vec::new(),
             blacklist: Vec::new(),
+            selection: default_selection(),
+            stability_weight: 0.0,
+            older_earnings_weight: 0.0,
+            address_preference: default_address_preference(),
+            exclude_hardcoded: Vec::new(),
+            open_retry_max_attempts: default_open_retry_max_attempts(),
+            failed_open_blacklist_hours: default_failed_open_blacklist_hours(),
+            max_channels_per_operator: 0,
+            operators: BTreeMap::new(),
         }
     }
 }
 
+impl AutopilotConfig {
+    /// `min_usable_channel_sats` if set. The default is capped below
+    /// `max_channel_sats`, so a node that keeps its channels small doesn't
+    /// have every open skipped.
+    pub fn min_usable_channel_sats(&self) -> u64 {
+        self.min_usable_channel_sats.unwrap_or_else(|| {
+            default_min_usable_channel_sats()
+                .min(self.max_channel_sats * DEFAULT_MIN_USABLE_MAX_PERCENT / 100)
+        })
+    }
+}
+
 impl Default for FeesConfig {
     fn default() -> Self {
         Self {
@@ -323,11 +834,25 @@ impl Default for FeesConfig {
             default_ppm: default_ppm(),
             balance_modder_enabled: true,
             preferred_bin_size_sats: default_preferred_bin_size(),
+            balance_ratio_basis: default_balance_ratio_basis(),
+            freeze_balance_modder_in_high_regime: false,
+            balance_modder_min_channels: 0,
             price_theory_enabled: true,
             price_theory_card_lifetime_ticks: default_card_lifetime(),
+            price_theory_card_lifetime_hours: None,
             price_theory_max_step: default_price_step(),
+            price_theory_graduate_days: 0,
+            price_theory_regress_percent: default_price_theory_regress_percent(),
+            learn_fee_demand: false,
             competitor_fee_enabled: true,
             size_modder_enabled: true,
+            new_channel_protect_days: default_new_channel_protect_days(),
+            manage_private_channels: true,
+            fee_update_deadband_ppm: default_fee_update_deadband(),
+            max_fee_age_hours: 0,
+            global_multiplier: default_global_fee_multiplier(),
+            liquidity_premium_multiplier: default_liquidity_premium_multiplier(),
+            liquidity_recoup_days: default_liquidity_recoup_days(),
         }
     }
 }
@@ -341,7 +866,15 @@ impl Default for RebalancerConfig {
             source_gap_percent: default_source_gap(),
             target_spendable_percent: default_target_spendable(),
             max_fee_ppm: default_rebalance_fee_ppm(),
+            peer_max_fee_ppm: BTreeMap::new(),
             max_total_fee_sats: default_max_total_fee(),
+            max_rebalance_amount_msat: default_max_rebalance_amount(),
+            min_rebalance_amount_msat: default_min_rebalance_amount(),
+            min_profit_margin_sats: 0,
+            earnings_weighted_targets: false,
+            primary_goal: default_primary_goal(),
+            swap_severe_percent: 0.0,
+            swap_command: None,
         }
     }
 }
@@ -351,16 +884,36 @@ impl Default for JudgeConfig {
         Self {
             enabled: false,
             min_age_days: default_min_age_days(),
+            protect_until_days: 0,
             evaluation_window_days: default_eval_window(),
             estimated_reopen_cost_sats: default_reopen_cost(),
+            min_improvement_sats: 0,
             cooperative_close: true,
+            panic_close_enabled: false,
+            panic_close_min_revoked_sats: 0,
+            probation_windows: 0,
+            probation_fee_multiplier: default_probation_fee_multiplier(),
+            min_uptime_percent: default_min_uptime_percent(),
+            prefer_redundant_closes: false,
+            max_closures_per_cycle: default_max_closures_per_cycle(),
+            instability_weight: 0.0,
+            force_close_on_coop_failure: false,
+            drain_before_close: false,
+            drain_target_percent: default_drain_target_percent(),
+            drain_timeout_days: default_drain_timeout_days(),
+            drain_fee_multiplier: default_drain_fee_multiplier(),
         }
     }
 }
 
 impl Default for ReconnectorConfig {
     fn default() -> Self {
-        Self { enabled: true }
+        Self {
+            enabled: true,
+            max_attempts_per_cycle: default_max_reconnect_attempts(),
+            refresh_from_gossip: false,
+            gossip_refresh_hours: default_gossip_refresh_hours(),
+        }
     }
 }
 
@@ -371,6 +924,21 @@ impl Default for OnchainFeesConfig {
             mempool_api_url: default_mempool_url(),
             hi_to_lo_percentile: default_hi_to_lo(),
             lo_to_hi_percentile: default_lo_to_hi(),
+            min_samples_for_regime: default_min_samples_for_regime(),
+            default_regime: default_regime(),
+            reference: default_fee_reference(),
+            reference_fallback: true,
+            backfill_on_startup: true,
+            require_sustained_high_regime_samples: default_sustained_high_samples(),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            textfile_path: None,
+            write_interval_secs: default_metrics_write_interval(),
         }
     }
 }
@@ -378,17 +946,55 @@ impl Default for OnchainFeesConfig {
 impl Config {
     pub fn load(path: &Path) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
-        config.validate()?;
+        let mut config: Config = toml::from_str(&content)?;
+        config.finalize()?;
         Ok(config)
     }
 
+    /// Validate and fill in derived values. Run again after changing any
+    /// field of a loaded config (e.g. command-line overrides).
+    pub fn finalize(&mut self) -> anyhow::Result<()> {
+        self.validate()?;
+        self.resolve_card_lifetime();
+        Ok(())
+    }
+
+    /// The effective configuration, defaults included, as TOML. The API key
+    /// is redacted so the output is safe to paste into a bug report.
+    pub fn effective_toml(&self) -> anyhow::Result<String> {
+        let mut value = toml::Value::try_from(self)?;
+        if let Some(server) = value.get_mut("server").and_then(|s| s.as_table_mut()) {
+            server.insert("api_key".to_string(), "<redacted>".into());
+        }
+        Ok(toml::to_string_pretty(&value)?)
+    }
+
+    /// The `tracing` filter: `general.log_level`, plus a directive for each
+    /// module in `[log_levels]`.
+    pub fn log_filter(&self) -> String {
+        let mut filter = self.general.log_level.clone();
+        for (module, level) in &self.log_levels {
+            filter.push_str(&format!(",ldk_boss::{}={}", module, level));
+        }
+        filter
+    }
+
+    /// Convert price_theory_card_lifetime_hours (if set) into ticks, so a
+    /// card lives the same wall-clock time whatever the loop interval.
+    fn resolve_card_lifetime(&mut self) {
+        if let Some(hours) = self.fees.price_theory_card_lifetime_hours {
+            let ticks = (hours * 3600.0 / self.general.loop_interval_secs as f64).round();
+            self.fees.price_theory_card_lifetime_ticks = (ticks as u32).max(1);
+        }
+    }
+
     pub fn validate(&self) -> anyhow::Result<()> {
         // Hard limits (non-configurable safety rails)
         const ABS_MIN_CHANNEL_SATS: u64 = 20_000;
         const ABS_MAX_CHANNEL_SATS: u64 = 16_777_215;
         const ABS_MAX_FEE_PPM: u32 = 50_000;
         const ABS_MAX_PROPOSALS: usize = 5;
+        const ABS_MAX_CLOSURES_PER_CYCLE: usize = 3;
 
         if self.autopilot.min_channel_sats < ABS_MIN_CHANNEL_SATS {
             anyhow::bail!(
@@ -407,6 +1013,36 @@ impl Config {
         if self.autopilot.min_channel_sats > self.autopilot.max_channel_sats {
             anyhow::bail!("min_channel_sats > max_channel_sats");
         }
+        if self
+            .autopilot
+            .min_usable_channel_sats
+            .is_some_and(|sats| sats >= self.autopilot.max_channel_sats)
+        {
+            anyhow::bail!("min_usable_channel_sats must be below max_channel_sats");
+        }
+        if self.autopilot.max_total_deployed_sats != 0
+            && self.autopilot.max_total_deployed_sats < self.autopilot.min_channel_sats
+        {
+            anyhow::bail!(
+                "autopilot.max_total_deployed_sats ({}) is below min_channel_sats: \
+                 set it to 0 for no ceiling, or disable the autopilot",
+                self.autopilot.max_total_deployed_sats
+            );
+        }
+        let max_feerate = self.autopilot.max_open_feerate_sat_per_vb;
+        if !max_feerate.is_finite() || max_feerate < 0.0 {
+            anyhow::bail!(
+                "autopilot.max_open_feerate_sat_per_vb must be 0 (no ceiling) or positive, got {}",
+                max_feerate
+            );
+        }
+        if self.autopilot.open_amount_granularity_sats > self.autopilot.max_channel_sats {
+            anyhow::bail!(
+                "autopilot.open_amount_granularity_sats ({}) is above max_channel_sats ({})",
+                self.autopilot.open_amount_granularity_sats,
+                self.autopilot.max_channel_sats
+            );
+        }
         if self.autopilot.max_proposals > ABS_MAX_PROPOSALS {
             anyhow::bail!(
                 "max_proposals ({}) above absolute maximum ({})",
@@ -414,6 +1050,42 @@ impl Config {
                 ABS_MAX_PROPOSALS
             );
         }
+        if self.judge.max_closures_per_cycle == 0 {
+            anyhow::bail!("judge.max_closures_per_cycle must be > 0");
+        }
+        if self.judge.max_closures_per_cycle > ABS_MAX_CLOSURES_PER_CYCLE {
+            anyhow::bail!(
+                "judge.max_closures_per_cycle ({}) above absolute maximum ({})",
+                self.judge.max_closures_per_cycle,
+                ABS_MAX_CLOSURES_PER_CYCLE
+            );
+        }
+        // Channels younger than min_age_days are never evaluated, so a shorter
+        // protection would silently do nothing
+        if self.judge.protect_until_days != 0
+            && self.judge.protect_until_days < self.judge.min_age_days
+        {
+            anyhow::bail!(
+                "judge.protect_until_days ({}) must be 0 or at least min_age_days ({})",
+                self.judge.protect_until_days,
+                self.judge.min_age_days
+            );
+        }
+        if !(0.0..=100.0).contains(&self.autopilot.balance_mismatch_percent) {
+            anyhow::bail!(
+                "autopilot.balance_mismatch_percent ({}) must be between 0 and 100",
+                self.autopilot.balance_mismatch_percent
+            );
+        }
+        if !(0.0..=50.0).contains(&self.autopilot.onchain_reserve_percent) {
+            anyhow::bail!(
+                "autopilot.onchain_reserve_percent ({}) must be between 0 and 50",
+                self.autopilot.onchain_reserve_percent
+            );
+        }
+        if self.autopilot.open_retry_max_attempts == 0 {
+            anyhow::bail!("open_retry_max_attempts must be > 0");
+        }
         if self.fees.default_ppm > ABS_MAX_FEE_PPM {
             anyhow::bail!(
                 "default_ppm ({}) above absolute maximum ({})",
@@ -431,13 +1103,248 @@ impl Config {
         {
             anyhow::bail!("max_spendable_percent must be between 0 and 100");
         }
+        if self.rebalancer.max_rebalance_amount_msat == 0 {
+            anyhow::bail!("max_rebalance_amount_msat must be > 0");
+        }
+        if self.rebalancer.min_rebalance_amount_msat > self.rebalancer.max_rebalance_amount_msat {
+            anyhow::bail!(
+                "min_rebalance_amount_msat ({}) must not exceed max_rebalance_amount_msat ({})",
+                self.rebalancer.min_rebalance_amount_msat,
+                self.rebalancer.max_rebalance_amount_msat
+            );
+        }
+        let severe = self.rebalancer.swap_severe_percent;
+        if !(0.0..50.0).contains(&severe) {
+            anyhow::bail!(
+                "rebalancer.swap_severe_percent ({}) must be at least 0 and below 50",
+                severe
+            );
+        }
+        for (peer, ppm) in &self.rebalancer.peer_max_fee_ppm {
+            // One full-size chunk at this rate must fit the hard per-cycle cap
+            let chunk_fee_sats =
+                self.rebalancer.max_rebalance_amount_msat as f64 * *ppm as f64 / 1e9;
+            if chunk_fee_sats > crate::rebalancer::earnings::ABS_MAX_REBALANCE_FEE_SATS as f64 {
+                anyhow::bail!(
+                    "rebalancer.peer_max_fee_ppm for {} ({}) allows {:.0} sat on one \
+                     max_rebalance_amount_msat chunk, above the absolute maximum of {} sat",
+                    peer,
+                    ppm,
+                    chunk_fee_sats,
+                    crate::rebalancer::earnings::ABS_MAX_REBALANCE_FEE_SATS
+                );
+            }
+        }
+        if self.reconnector.refresh_from_gossip && self.reconnector.gossip_refresh_hours == 0 {
+            anyhow::bail!("reconnector.gossip_refresh_hours must be > 0");
+        }
+        for (module, level) in &self.log_levels {
+            if !LOG_MODULES.contains(&module.as_str()) {
+                anyhow::bail!(
+                    "log_levels: unknown module \"{}\" (expected one of {})",
+                    module,
+                    LOG_MODULES.join(", ")
+                );
+            }
+            if !matches!(
+                level.to_ascii_lowercase().as_str(),
+                "off" | "error" | "warn" | "info" | "debug" | "trace"
+            ) {
+                anyhow::bail!(
+                    "log_levels.{}: \"{}\" is not one of off, error, warn, info, debug, trace",
+                    module,
+                    level
+                );
+            }
+        }
         // Price theory bounds
         if self.fees.price_theory_card_lifetime_ticks == 0 {
             anyhow::bail!("price_theory_card_lifetime_ticks must be > 0");
         }
+        if self.fees.max_fee_age_hours != 0 && self.fees.max_fee_age_hours < 24 {
+            anyhow::bail!(
+                "fees.max_fee_age_hours ({}) must be 0 (off) or at least 24",
+                self.fees.max_fee_age_hours
+            );
+        }
+        let regress = self.fees.price_theory_regress_percent;
+        if !regress.is_finite() || regress <= 0.0 || regress > 100.0 {
+            anyhow::bail!(
+                "fees.price_theory_regress_percent ({}) must be in (0, 100]",
+                regress
+            );
+        }
+        if !self.fees.global_multiplier.is_finite() || self.fees.global_multiplier <= 0.0 {
+            anyhow::bail!(
+                "fees.global_multiplier ({}) must be a positive number",
+                self.fees.global_multiplier
+            );
+        }
+        for (name, weight) in [
+            ("judge.instability_weight", self.judge.instability_weight),
+            ("autopilot.stability_weight", self.autopilot.stability_weight),
+        ] {
+            if !weight.is_finite() || weight < 0.0 {
+                anyhow::bail!("{} ({}) must be a non-negative number", name, weight);
+            }
+        }
+        if !(0.0..=1.0).contains(&self.autopilot.older_earnings_weight) {
+            anyhow::bail!(
+                "autopilot.older_earnings_weight ({}) must be between 0 and 1",
+                self.autopilot.older_earnings_weight
+            );
+        }
+        if !(0.0..=100.0).contains(&self.judge.min_uptime_percent) {
+            anyhow::bail!(
+                "judge.min_uptime_percent ({}) must be between 0 and 100",
+                self.judge.min_uptime_percent
+            );
+        }
+        if !self.fees.liquidity_premium_multiplier.is_finite()
+            || self.fees.liquidity_premium_multiplier < 1.0
+        {
+            anyhow::bail!(
+                "fees.liquidity_premium_multiplier ({}) must be at least 1.0",
+                self.fees.liquidity_premium_multiplier
+            );
+        }
+        if !(0.0..=100.0).contains(&self.judge.drain_target_percent) {
+            anyhow::bail!(
+                "judge.drain_target_percent ({}) must be between 0 and 100",
+                self.judge.drain_target_percent
+            );
+        }
+        if !(self.judge.drain_fee_multiplier > 0.0 && self.judge.drain_fee_multiplier <= 1.0) {
+            anyhow::bail!(
+                "judge.drain_fee_multiplier ({}) must be in (0, 1]",
+                self.judge.drain_fee_multiplier
+            );
+        }
+        if !self.judge.probation_fee_multiplier.is_finite()
+            || self.judge.probation_fee_multiplier <= 0.0
+        {
+            anyhow::bail!(
+                "judge.probation_fee_multiplier ({}) must be a positive number",
+                self.judge.probation_fee_multiplier
+            );
+        }
+        if !matches!(
+            self.rebalancer.primary_goal.as_str(),
+            "balance" | "outbound" | "inbound"
+        ) {
+            anyhow::bail!(
+                "rebalancer.primary_goal must be \"balance\", \"outbound\" or \"inbound\", got \"{}\"",
+                self.rebalancer.primary_goal
+            );
+        }
+        if !matches!(self.autopilot.selection.as_str(), "top" | "weighted_random") {
+            anyhow::bail!(
+                "autopilot.selection must be \"top\" or \"weighted_random\", got \"{}\"",
+                self.autopilot.selection
+            );
+        }
+        if !matches!(
+            self.autopilot.on_ranking_empty.as_str(),
+            "fallback" | "skip" | "alert"
+        ) {
+            anyhow::bail!(
+                "autopilot.on_ranking_empty must be \"fallback\", \"skip\" or \"alert\", \
+                 got \"{}\"",
+                self.autopilot.on_ranking_empty
+            );
+        }
+        if self
+            .control
+            .socket_path
+            .as_ref()
+            .is_some_and(|p| p.as_os_str().is_empty())
+        {
+            anyhow::bail!("control.socket_path must not be empty (omit it to disable)");
+        }
+        if let Some(path) = &self.metrics.textfile_path {
+            if path.file_name().is_none() {
+                anyhow::bail!(
+                    "metrics.textfile_path must name a file (omit it to disable), got \"{}\"",
+                    path.display()
+                );
+            }
+        }
+        if self.general.cycle_timeout_secs != 0 && self.general.cycle_timeout_secs < 60 {
+            anyhow::bail!(
+                "general.cycle_timeout_secs ({}) must be 0 (no limit) or at least 60",
+                self.general.cycle_timeout_secs
+            );
+        }
+        if !matches!(
+            self.general.earnings_attribution.as_str(),
+            "in" | "out" | "split"
+        ) {
+            anyhow::bail!(
+                "general.earnings_attribution must be \"in\", \"out\" or \"split\", got \"{}\"",
+                self.general.earnings_attribution
+            );
+        }
+        if self.autopilot.ranking_cache_ttl_mins == 0 {
+            anyhow::bail!("autopilot.ranking_cache_ttl_mins must be > 0");
+        }
+        if !matches!(
+            self.autopilot.address_preference.as_str(),
+            "any" | "clearnet" | "tor"
+        ) {
+            anyhow::bail!(
+                "autopilot.address_preference must be \"any\", \"clearnet\" or \"tor\", got \"{}\"",
+                self.autopilot.address_preference
+            );
+        }
+        let mut operator_of: BTreeMap<&str, &str> = BTreeMap::new();
+        for (name, nodes) in &self.autopilot.operators {
+            for node in nodes {
+                if let Some(other) = operator_of.insert(node.as_str(), name.as_str()) {
+                    anyhow::bail!(
+                        "autopilot.operators lists {} under both \"{}\" and \"{}\"",
+                        node,
+                        other,
+                        name
+                    );
+                }
+            }
+        }
+        if !matches!(self.onchain_fees.default_regime.as_str(), "high" | "low") {
+            anyhow::bail!(
+                "onchain_fees.default_regime must be \"high\" or \"low\", got \"{}\"",
+                self.onchain_fees.default_regime
+            );
+        }
+        if !matches!(
+            self.onchain_fees.reference.as_str(),
+            "fastest" | "half_hour" | "hour" | "economy" | "minimum"
+        ) {
+            anyhow::bail!(
+                "onchain_fees.reference must be one of fastest, half_hour, hour, economy, \
+                 minimum; got \"{}\"",
+                self.onchain_fees.reference
+            );
+        }
+        if self.onchain_fees.require_sustained_high_regime_samples == 0 {
+            anyhow::bail!("onchain_fees.require_sustained_high_regime_samples must be > 0");
+        }
+        if self.general.loop_interval_secs == 0 {
+            anyhow::bail!("loop_interval_secs must be > 0");
+        }
+        if let Some(hours) = self.fees.price_theory_card_lifetime_hours {
+            if hours.is_nan() || hours <= 0.0 {
+                anyhow::bail!("price_theory_card_lifetime_hours must be > 0");
+            }
+        }
         if self.fees.preferred_bin_size_sats == 0 {
             anyhow::bail!("preferred_bin_size_sats must be > 0");
         }
+        if !matches!(self.fees.balance_ratio_basis.as_str(), "spendable" | "capacity") {
+            anyhow::bail!(
+                "fees.balance_ratio_basis must be \"spendable\" or \"capacity\", got \"{}\"",
+                self.fees.balance_ratio_basis
+            );
+        }
 
         // Cross-field: onchain percentile ordering
         if self.autopilot.min_onchain_percent >= self.autopilot.max_onchain_percent {
@@ -466,6 +1373,56 @@ impl Config {
         Ok(())
     }
 
+    /// Settings that are allowed but probably not what the operator wants.
+    /// Unlike `validate`, these never stop the daemon; the caller logs them.
+    pub fn soft_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.general.loop_interval_secs < 120 {
+            warnings.push(format!(
+                "general.loop_interval_secs of {} is very aggressive (default {})",
+                self.general.loop_interval_secs,
+                default_loop_interval()
+            ));
+        }
+        if self.autopilot.min_channel_sats < 100_000 {
+            warnings.push(format!(
+                "autopilot.min_channel_sats of {} is below the recommended 100000; \
+                 small channels rarely route",
+                self.autopilot.min_channel_sats
+            ));
+        }
+        if self.autopilot.onchain_reserve_sats < 25_000
+            && self.autopilot.onchain_reserve_percent == 0.0
+        {
+            warnings.push(format!(
+                "autopilot.onchain_reserve_sats of {} may not cover fee bumps on force closes",
+                self.autopilot.onchain_reserve_sats
+            ));
+        }
+        if self.fees.default_ppm > 2_000 {
+            warnings.push(format!(
+                "fees.default_ppm of {} is very high; little will route through this node",
+                self.fees.default_ppm
+            ));
+        }
+        if self.judge.enabled && self.judge.min_age_days < 30 {
+            warnings.push(format!(
+                "judge.min_age_days of {} gives new channels little time to prove themselves",
+                self.judge.min_age_days
+            ));
+        }
+        if self.judge.max_closures_per_cycle > 1 {
+            warnings.push(format!(
+                "judge.max_closures_per_cycle is {}: the judge may close several channels \
+                 every cycle. Set it back to 1 once the initial cleanup is done",
+                self.judge.max_closures_per_cycle
+            ));
+        }
+
+        warnings
+    }
+
     /// Create a config with all defaults for testing purposes.
     /// The TLS cert path is set to the provided path (must exist for validation).
     #[cfg(test)]
@@ -483,6 +1440,10 @@ impl Config {
             judge: JudgeConfig::default(),
             reconnector: ReconnectorConfig::default(),
             onchain_fees: OnchainFeesConfig::default(),
+            control: ControlConfig::default(),
+            audit: AuditConfig::default(),
+            metrics: MetricsConfig::default(),
+            log_levels: BTreeMap::new(),
         }
     }
 }
@@ -510,6 +1471,25 @@ mod tests {
         assert!(err.to_string().contains("min_channel_sats"));
     }
 
+    #[test]
+    fn test_min_usable_channel_default_follows_max() {
+        let mut config = make_valid_config();
+        assert_eq!(config.autopilot.min_usable_channel_sats(), 95_000);
+
+        // Small channels: the default steps down instead of failing validation
+        config.autopilot.min_channel_sats = 50_000;
+        config.autopilot.max_channel_sats = 80_000;
+        assert!(config.validate().is_ok(), "{}", config.validate().unwrap_err());
+        assert_eq!(config.autopilot.min_usable_channel_sats(), 76_000);
+
+        // An explicit value is still checked
+        config.autopilot.min_usable_channel_sats = Some(80_000);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("min_usable_channel_sats"));
+        config.autopilot.min_usable_channel_sats = Some(70_000);
+        assert_eq!(config.autopilot.min_usable_channel_sats(), 70_000);
+    }
+
     #[test]
     fn test_validate_max_channel_too_large() {
         let mut config = make_valid_config();
@@ -564,6 +1544,428 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_validate_rebalance_amount_ordering() {
+        let mut config = make_valid_config();
+        config.rebalancer.min_rebalance_amount_msat = 2_000_000_000;
+        config.rebalancer.max_rebalance_amount_msat = 1_000_000_000;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("min_rebalance_amount_msat"));
+    }
+
+    #[test]
+    fn test_card_lifetime_hours_converted_to_ticks() {
+        let mut config = make_valid_config();
+        config.general.loop_interval_secs = 300;
+        config.fees.price_theory_card_lifetime_hours = Some(48.0);
+        config.resolve_card_lifetime();
+        // 48h at 5-minute ticks
+        assert_eq!(config.fees.price_theory_card_lifetime_ticks, 576);
+
+        config.general.loop_interval_secs = 600;
+        config.resolve_card_lifetime();
+        assert_eq!(config.fees.price_theory_card_lifetime_ticks, 288);
+    }
+
+    #[test]
+    fn test_effective_toml_includes_defaults_and_redacts_key() {
+        let config = make_valid_config();
+        let out = config.effective_toml().unwrap();
+        assert!(out.contains("loop_interval_secs = 600"));
+        assert!(out.contains("api_key = \"<redacted>\""));
+        assert!(!out.contains(&config.server.api_key));
+    }
+
+    #[test]
+    fn test_effective_toml_parses_back() {
+        let mut config = make_valid_config();
+        config.general.loop_interval_secs = 321;
+        let reparsed: Config = toml::from_str(&config.effective_toml().unwrap()).unwrap();
+        assert_eq!(reparsed.general.loop_interval_secs, 321);
+        assert_eq!(reparsed.fees.default_ppm, config.fees.default_ppm);
+    }
+
+    #[test]
+    fn test_validate_address_preference() {
+        let mut config = make_valid_config();
+        config.autopilot.address_preference = "onion".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("address_preference"));
+
+        config.autopilot.address_preference = "tor".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_older_earnings_weight() {
+        let mut config = make_valid_config();
+        config.autopilot.older_earnings_weight = 1.5;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("older_earnings_weight"));
+
+        config.autopilot.older_earnings_weight = 0.25;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_stability_weights() {
+        let mut config = make_valid_config();
+        config.judge.instability_weight = -1.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("instability_weight"));
+
+        config.judge.instability_weight = 0.0;
+        config.autopilot.stability_weight = f64::INFINITY;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("stability_weight"));
+    }
+
+    #[test]
+    fn test_validate_min_uptime_percent() {
+        let mut config = make_valid_config();
+        config.judge.min_uptime_percent = 150.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("min_uptime_percent"));
+    }
+
+    #[test]
+    fn test_validate_liquidity_premium_multiplier() {
+        let mut config = make_valid_config();
+        config.fees.liquidity_premium_multiplier = 0.8;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("liquidity_premium_multiplier"));
+    }
+
+    #[test]
+    fn test_validate_drain_settings() {
+        let mut config = make_valid_config();
+        config.judge.drain_fee_multiplier = 1.5;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("drain_fee_multiplier"));
+
+        config.judge.drain_fee_multiplier = 0.25;
+        config.judge.drain_target_percent = 120.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("drain_target_percent"));
+    }
+
+    #[test]
+    fn test_validate_probation_fee_multiplier() {
+        let mut config = make_valid_config();
+        config.judge.probation_fee_multiplier = f64::NAN;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("probation_fee_multiplier"));
+    }
+
+    #[test]
+    fn test_validate_fee_reference() {
+        let mut config = make_valid_config();
+        config.onchain_fees.reference = "next_block".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("onchain_fees.reference"));
+
+        config.onchain_fees.reference = "economy".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_on_ranking_empty() {
+        let mut config = make_valid_config();
+        config.autopilot.on_ranking_empty = "ignore".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("on_ranking_empty"));
+
+        config.autopilot.on_ranking_empty = "alert".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_control_socket_path() {
+        let mut config = make_valid_config();
+        config.control.socket_path = Some(PathBuf::new());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("socket_path"));
+
+        config.control.socket_path = Some(PathBuf::from("/run/ldk-boss.sock"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_metrics_textfile_path() {
+        let mut config = make_valid_config();
+        config.metrics.textfile_path = Some(PathBuf::new());
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("textfile_path"));
+
+        config.metrics.textfile_path =
+            Some(PathBuf::from("/var/lib/node_exporter/textfile/ldkboss.prom"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_cycle_timeout() {
+        let mut config = make_valid_config();
+        config.general.cycle_timeout_secs = 5;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("cycle_timeout_secs"));
+
+        config.general.cycle_timeout_secs = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_earnings_attribution() {
+        let mut config = make_valid_config();
+        config.general.earnings_attribution = "both".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("earnings_attribution"));
+
+        config.general.earnings_attribution = "out".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_ranking_cache_ttl() {
+        let mut config = make_valid_config();
+        config.autopilot.ranking_cache_ttl_mins = 0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("ranking_cache_ttl_mins"));
+    }
+
+    #[test]
+    fn test_validate_global_multiplier() {
+        let mut config = make_valid_config();
+        config.fees.global_multiplier = 0.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("global_multiplier"));
+
+        config.fees.global_multiplier = 1.2;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_soft_warnings() {
+        let mut config = make_valid_config();
+        assert!(config.soft_warnings().is_empty());
+
+        config.general.loop_interval_secs = 30;
+        config.autopilot.min_channel_sats = 50_000;
+        let warnings = config.soft_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("loop_interval_secs of 30 is very aggressive"));
+        assert!(warnings[1].contains("below the recommended 100000"));
+        // Still a valid config
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_swap_severe_percent() {
+        let mut config = make_valid_config();
+        config.rebalancer.swap_severe_percent = 50.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("swap_severe_percent"));
+
+        config.rebalancer.swap_severe_percent = f64::NAN;
+        assert!(config.validate().is_err());
+
+        config.rebalancer.swap_severe_percent = 10.0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_peer_max_fee_ppm() {
+        let mut config = make_valid_config();
+        config.rebalancer.max_rebalance_amount_msat = 500_000_000;
+        config.rebalancer.peer_max_fee_ppm.insert("peer".to_string(), 200_000);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("peer_max_fee_ppm"));
+
+        config.rebalancer.peer_max_fee_ppm.insert("peer".to_string(), 5_000);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rebalancer_primary_goal() {
+        let mut config = make_valid_config();
+        config.rebalancer.primary_goal = "sideways".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("primary_goal"));
+
+        config.rebalancer.primary_goal = "inbound".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_max_total_deployed_sats() {
+        let mut config = make_valid_config();
+        config.autopilot.max_total_deployed_sats = config.autopilot.min_channel_sats - 1;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("max_total_deployed_sats"));
+
+        config.autopilot.max_total_deployed_sats = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_log_levels_filter_and_validation() {
+        let mut config = make_valid_config();
+        config.general.log_level = "info".to_string();
+        assert_eq!(config.log_filter(), "info");
+
+        config.log_levels.insert("judge".to_string(), "debug".to_string());
+        config.log_levels.insert("fees".to_string(), "warn".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.log_filter(), "info,ldk_boss::fees=warn,ldk_boss::judge=debug");
+
+        config.log_levels.insert("judge".to_string(), "loud".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("log_levels.judge"));
+
+        config.log_levels.remove("judge");
+        config.log_levels.insert("jduge".to_string(), "debug".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("unknown module"));
+    }
+
+    #[test]
+    fn test_validate_gossip_refresh_hours() {
+        let mut config = make_valid_config();
+        config.reconnector.gossip_refresh_hours = 0;
+        assert!(config.validate().is_ok());
+
+        config.reconnector.refresh_from_gossip = true;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("gossip_refresh_hours"));
+    }
+
+    #[test]
+    fn test_validate_max_open_feerate() {
+        let mut config = make_valid_config();
+        config.autopilot.max_open_feerate_sat_per_vb = -1.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("max_open_feerate_sat_per_vb"));
+
+        config.autopilot.max_open_feerate_sat_per_vb = f64::NAN;
+        assert!(config.validate().is_err());
+
+        config.autopilot.max_open_feerate_sat_per_vb = 40.0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_open_amount_granularity() {
+        let mut config = make_valid_config();
+        config.autopilot.open_amount_granularity_sats = config.autopilot.max_channel_sats + 1;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("open_amount_granularity_sats"));
+
+        config.autopilot.open_amount_granularity_sats = 100_000;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_operators() {
+        let mut config = make_valid_config();
+        config.autopilot.max_channels_per_operator = 2;
+        config
+            .autopilot
+            .operators
+            .insert("acme".to_string(), vec!["a".to_string(), "b".to_string()]);
+        assert!(config.validate().is_ok());
+
+        config
+            .autopilot
+            .operators
+            .insert("globex".to_string(), vec!["b".to_string()]);
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("autopilot.operators"));
+    }
+
+    #[test]
+    fn test_validate_onchain_reserve_percent() {
+        let mut config = make_valid_config();
+        config.autopilot.onchain_reserve_percent = 60.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("onchain_reserve_percent"));
+
+        config.autopilot.onchain_reserve_percent = f64::NAN;
+        assert!(config.validate().is_err());
+
+        config.autopilot.onchain_reserve_percent = 2.5;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_balance_mismatch_percent() {
+        let mut config = make_valid_config();
+        config.autopilot.balance_mismatch_percent = -1.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("balance_mismatch_percent"));
+    }
+
+    #[test]
+    fn test_validate_protect_until_days() {
+        let mut config = make_valid_config();
+        config.judge.min_age_days = 30;
+        config.judge.protect_until_days = 20;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("protect_until_days"));
+
+        config.judge.protect_until_days = 90;
+        assert!(config.validate().is_ok());
+        config.judge.protect_until_days = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_max_closures_per_cycle() {
+        let mut config = make_valid_config();
+        config.judge.max_closures_per_cycle = 0;
+        assert!(config.validate().is_err());
+
+        config.judge.max_closures_per_cycle = 4;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("above absolute maximum"));
+
+        config.judge.max_closures_per_cycle = 3;
+        assert!(config.validate().is_ok());
+        assert!(config.soft_warnings()[0].contains("max_closures_per_cycle is 3"));
+    }
+
+    #[test]
+    fn test_validate_price_theory_regress_percent() {
+        let mut config = make_valid_config();
+        config.fees.price_theory_regress_percent = 0.0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("price_theory_regress_percent"));
+
+        config.fees.price_theory_regress_percent = 120.0;
+        assert!(config.validate().is_err());
+
+        config.fees.price_theory_regress_percent = 30.0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_sustained_high_regime_samples() {
+        let mut config = make_valid_config();
+        config.onchain_fees.require_sustained_high_regime_samples = 0;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("require_sustained_high_regime_samples"));
+
+        config.onchain_fees.require_sustained_high_regime_samples = 3;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_max_fee_age_hours() {
+        let mut config = make_valid_config();
+        config.fees.max_fee_age_hours = 6;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("max_fee_age_hours"));
+
+        config.fees.max_fee_age_hours = 168;
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn test_validate_card_lifetime_zero() {
         let mut config = make_valid_config();
@@ -580,6 +1982,17 @@ mod tests {
         assert!(err.to_string().contains("preferred_bin_size_sats"));
     }
 
+    #[test]
+    fn test_validate_balance_ratio_basis() {
+        let mut config = make_valid_config();
+        config.fees.balance_ratio_basis = "outbound".to_string();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("balance_ratio_basis"));
+
+        config.fees.balance_ratio_basis = "capacity".to_string();
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn test_validate_onchain_percent_ordering() {
         let mut config = make_valid_config();
@@ -0,0 +1,318 @@
+/// Control socket: lets operators and tools steer a running daemon.
+///
+/// When `control.socket_path` is set, the daemon listens on that Unix socket
+/// for newline-delimited JSON requests such as `{"command": "pause"}` and
+/// answers each with one JSON line, `{"ok": true, ...}` or
+/// `{"ok": false, "error": "..."}`. Commands:
+///
+/// - `status`: paused flag, cycles run, when the last one finished
+/// - `pause` / `resume`: stop or restart cycles (the loop keeps ticking)
+/// - `run-once`: run a cycle now, even while paused
+/// - `reload-config`: re-read and validate the config file; applied before
+///   the next cycle. Server, database, log and control settings need a
+///   restart.
+///
+/// The daemon loop only reads flags from here between cycles, so nothing on
+/// the socket ever touches the database or races a running cycle.
+
+use crate::config::Config;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Notify;
+
+/// Re-reads the config file with command-line overrides applied.
+pub type ConfigLoader = Box<dyn Fn() -> anyhow::Result<Config> + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    paused: bool,
+    run_now: bool,
+    pending_config: Option<Config>,
+    cycles: u64,
+    in_cycle: bool,
+    last_cycle_at: Option<i64>,
+}
+
+pub struct Control {
+    state: Mutex<State>,
+    loader: ConfigLoader,
+    /// Wakes the daemon loop out of its sleep
+    wake: Notify,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    command: String,
+}
+
+impl Control {
+    pub fn new(loader: ConfigLoader) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            loader,
+            wake: Notify::new(),
+        }
+    }
+
+    /// Called by the loop before each cycle: whether to run it. A pending
+    /// `run-once` overrides a pause.
+    pub fn begin_cycle(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let run = !state.paused || state.run_now;
+        state.run_now = false;
+        state.in_cycle = run;
+        run
+    }
+
+    /// Called by the loop after a cycle it ran, ending at `at` (unix seconds).
+    pub fn end_cycle(&self, at: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.in_cycle = false;
+        state.cycles += 1;
+        state.last_cycle_at = Some(at);
+    }
+
+    /// A config accepted by `reload-config` since the last call, if any.
+    pub fn take_config(&self) -> Option<Config> {
+        self.state.lock().unwrap().pending_config.take()
+    }
+
+    /// Resolves when a command wants the loop to act before its sleep ends.
+    pub async fn woken(&self) {
+        self.wake.notified().await
+    }
+
+    /// Answer one request line.
+    pub fn handle(&self, line: &str) -> Value {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(r) => r,
+            Err(e) => return json!({ "ok": false, "error": format!("bad request: {}", e) }),
+        };
+
+        match request.command.as_str() {
+            "status" => {
+                let state = self.state.lock().unwrap();
+                json!({
+                    "ok": true,
+                    "paused": state.paused,
+                    "in_cycle": state.in_cycle,
+                    "cycles": state.cycles,
+                    "last_cycle_at": state.last_cycle_at,
+                    "reload_pending": state.pending_config.is_some(),
+                })
+            }
+            "pause" => {
+                self.state.lock().unwrap().paused = true;
+                info!("Control: paused");
+                json!({ "ok": true })
+            }
+            "resume" => {
+                self.state.lock().unwrap().paused = false;
+                info!("Control: resumed");
+                json!({ "ok": true })
+            }
+            "run-once" => {
+                self.state.lock().unwrap().run_now = true;
+                self.wake.notify_one();
+                info!("Control: cycle requested");
+                json!({ "ok": true })
+            }
+            "reload-config" => match (self.loader)() {
+                Ok(config) => {
+                    self.state.lock().unwrap().pending_config = Some(config);
+                    info!("Control: new config accepted, applying before the next cycle");
+                    json!({ "ok": true })
+                }
+                Err(e) => {
+                    warn!("Control: config reload rejected: {:#}", e);
+                    json!({ "ok": false, "error": format!("{:#}", e) })
+                }
+            },
+            other => json!({ "ok": false, "error": format!("unknown command \"{}\"", other) }),
+        }
+    }
+}
+
+/// Removes the socket file when dropped, so it goes away however the daemon
+/// loop exits.
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Bind the socket and serve connections in the background. A stale socket
+/// from a previous run is replaced; any other file at the path is an error,
+/// never deleted. The socket is only accessible to our own user: anyone who
+/// can connect can pause the daemon or swap its config.
+pub fn spawn(path: &Path, control: Arc<Control>) -> anyhow::Result<SocketFile> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if !meta.file_type().is_socket() => anyhow::bail!(
+            "control.socket_path {} exists and is not a socket",
+            path.display()
+        ),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let listener = bind_private(path)?;
+    let socket = SocketFile(path.to_path_buf());
+    info!("Control socket listening on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let control = control.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &control).await {
+                            debug!("Control connection closed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Control socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(socket)
+}
+
+/// Bind at `path` without a moment where others could connect: the socket
+/// is created in a directory only we can enter, restricted to 0600, then
+/// moved into place (replacing a stale socket).
+fn bind_private(path: &Path) -> anyhow::Result<UnixListener> {
+    let parent = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let private = parent.join(format!(".ldk-boss-control.{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let staged = private.join("sock");
+
+    let result = UnixListener::bind(&staged)
+        .map_err(anyhow::Error::from)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&private);
+    result
+}
+
+async fn serve(stream: UnixStream, control: &Control) -> anyhow::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut response = control.handle(&line).to_string();
+        response.push('\n');
+        write.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control() -> Control {
+        Control::new(Box::new(|| {
+            Ok(Config::test_default(std::path::PathBuf::from("/dev/null")))
+        }))
+    }
+
+    #[test]
+    fn test_pause_resume_and_run_once() {
+        let control = control();
+        assert!(control.begin_cycle());
+        control.end_cycle(1_700_000_000);
+
+        assert_eq!(control.handle(r#"{"command":"pause"}"#)["ok"], true);
+        assert!(!control.begin_cycle());
+
+        // Runs exactly one cycle despite the pause
+        control.handle(r#"{"command":"run-once"}"#);
+        assert!(control.begin_cycle());
+        control.end_cycle(1_700_000_600);
+        assert!(!control.begin_cycle());
+
+        control.handle(r#"{"command":"resume"}"#);
+        assert!(control.begin_cycle());
+
+        let status = control.handle(r#"{"command":"status"}"#);
+        assert_eq!(status["paused"], false);
+        assert_eq!(status["cycles"], 2);
+        assert_eq!(status["last_cycle_at"], 1_700_000_600);
+    }
+
+    #[test]
+    fn test_reload_and_errors() {
+        let control = control();
+        assert!(control.take_config().is_none());
+        assert_eq!(control.handle(r#"{"command":"reload-config"}"#)["ok"], true);
+        assert!(control.take_config().is_some());
+        assert!(control.take_config().is_none());
+
+        let failing = Control::new(Box::new(|| anyhow::bail!("bad toml")));
+        let resp = failing.handle(r#"{"command":"reload-config"}"#);
+        assert_eq!(resp["ok"], false);
+        assert!(resp["error"].as_str().unwrap().contains("bad toml"));
+
+        assert_eq!(control.handle(r#"{"command":"explode"}"#)["ok"], false);
+        assert_eq!(control.handle("not json")["ok"], false);
+    }
+
+    #[tokio::test]
+    async fn test_socket_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let socket = spawn(&path, Arc::new(control())).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"{\"command\":\"status\"}\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let resp: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(resp["ok"], true);
+        assert_eq!(resp["paused"], false);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_replaces_only_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // A stale socket from a previous run is replaced
+        let path = dir.path().join("control.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let socket = spawn(&path, Arc::new(control())).unwrap();
+        assert!(UnixStream::connect(&path).await.is_ok());
+        drop(socket);
+
+        // Anything else, e.g. a mistyped database path, is left alone
+        let path = dir.path().join("ldkboss.db");
+        std::fs::write(&path, b"data").unwrap();
+        let err = spawn(&path, Arc::new(control())).unwrap_err();
+        assert!(err.to_string().contains("not a socket"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+
+        // No staging directory is left behind
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(leftovers.len(), 1);
+    }
+}
@@ -0,0 +1,59 @@
+/// What one `run_cycle` did, logged as a single line when it ends so a node
+/// running at info level has a heartbeat without reading every module's
+/// messages (`general.log_cycle_summary`).
+///
+/// Each phase adds what it actually carried out; dry-run decisions and
+/// failed attempts don't count.
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CycleSummary {
+    pub fees_updated: usize,
+    pub opened: usize,
+    pub opened_sats: u64,
+    pub closed: usize,
+    /// Rebalance payments that went through (one per chunk)
+    pub rebalanced: usize,
+    pub rebalance_fee_msat: u64,
+    pub reconnected: usize,
+}
+
+impl CycleSummary {
+    /// The summary line for cycle `n`, which took `elapsed_ms`.
+    pub fn line(&self, n: u64, elapsed_ms: u128) -> String {
+        format!(
+            "Cycle #{} done in {}ms: fees updated {}, opened {} ({} sats), closed {}, \
+             rebalanced {} ({} sats fee), reconnected {}",
+            n,
+            elapsed_ms,
+            self.fees_updated,
+            self.opened,
+            self.opened_sats,
+            self.closed,
+            self.rebalanced,
+            self.rebalance_fee_msat / 1000,
+            self.reconnected
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        let summary = CycleSummary {
+            fees_updated: 3,
+            opened: 1,
+            opened_sats: 2_000_000,
+            rebalanced: 2,
+            rebalance_fee_msat: 1_500,
+            ..Default::default()
+        };
+        assert_eq!(
+            summary.line(7, 1234),
+            "Cycle #7 done in 1234ms: fees updated 3, opened 1 (2000000 sats), closed 0, \
+             rebalanced 2 (1 sats fee), reconnected 0"
+        );
+    }
+}
@@ -1,43 +1,144 @@
+use crate::clock::{Clock, SystemClock};
 use anyhow::Context;
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Connection;
+use std::ops::Deref;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upper bound on simultaneously open connections to the database file.
+const POOL_MAX_SIZE: u32 = 8;
+/// How long a writer waits on a locked database before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Database {
-    conn: Connection,
+    inner: Inner,
+    clock: Arc<dyn Clock>,
+}
+
+enum Inner {
+    /// File-backed database: WAL mode lets pooled readers run alongside a writer.
+    Pool(Pool<SqliteConnectionManager>),
+    /// In-memory database. Every new connection to `:memory:` would be a
+    /// separate, empty database, so this one can't be pooled.
+    Single(Connection),
+    /// One connection checked out of a pool and reused for every `conn()`
+    /// call, for work that must see a single transaction.
+    Pinned(PooledConnection<SqliteConnectionManager>),
+}
+
+/// A connection handed out by `Database::conn()`. Derefs to `Connection`.
+pub enum DbConn<'a> {
+    Pooled(PooledConnection<SqliteConnectionManager>),
+    Borrowed(&'a Connection),
+}
+
+impl Deref for DbConn<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            DbConn::Pooled(c) => c,
+            DbConn::Borrowed(c) => c,
+        }
+    }
 }
 
 impl Database {
     pub fn open(path: &Path) -> anyhow::Result<Self> {
-        let conn = Connection::open(path)
+        let manager = SqliteConnectionManager::file(path).with_init(|c| {
+            c.pragma_update(None, "synchronous", "NORMAL")?;
+            c.pragma_update(None, "foreign_keys", "ON")?;
+            c.busy_timeout(BUSY_TIMEOUT)
+        });
+        let pool = Pool::builder()
+            .max_size(POOL_MAX_SIZE)
+            .build(manager)
             .with_context(|| format!("Failed to open database at {}", path.display()))?;
 
-        // Enable WAL mode for crash safety
-        conn.pragma_update(None, "journal_mode", "WAL")?;
-        conn.pragma_update(None, "synchronous", "NORMAL")?;
-        conn.pragma_update(None, "foreign_keys", "ON")?;
+        // Enable WAL mode for crash safety (persistent, so once is enough)
+        pool.get()?.pragma_update(None, "journal_mode", "WAL")?;
 
-        let db = Self { conn };
+        let db = Self {
+            inner: Inner::Pool(pool),
+            clock: Arc::new(SystemClock),
+        };
         db.migrate()?;
         Ok(db)
     }
 
     pub fn open_in_memory() -> anyhow::Result<Self> {
         let conn = Connection::open_in_memory()?;
-        let db = Self { conn };
+        let db = Self {
+            inner: Inner::Single(conn),
+            clock: Arc::new(SystemClock),
+        };
         db.migrate()?;
         Ok(db)
     }
 
-    pub fn conn(&self) -> &Connection {
-        &self.conn
+    /// Get a connection. For a file-backed database this checks one out of
+    /// the pool; it is returned when the `DbConn` is dropped. Fails if the
+    /// pool times out (all connections busy, or the file unreachable), so the
+    /// cycle ends with an error instead of blocking the daemon.
+    pub fn conn(&self) -> anyhow::Result<DbConn<'_>> {
+        Ok(match &self.inner {
+            Inner::Pool(pool) => {
+                DbConn::Pooled(pool.get().context("Database: no connection available")?)
+            }
+            Inner::Single(conn) => DbConn::Borrowed(conn),
+            Inner::Pinned(conn) => DbConn::Borrowed(conn),
+        })
+    }
+
+    /// A view of this database where every `conn()` returns the same
+    /// connection, so a transaction opened on it covers all later queries.
+    /// Returns `None` for an in-memory database, which already behaves that way.
+    pub fn pinned(&self) -> anyhow::Result<Option<Database>> {
+        match &self.inner {
+            Inner::Pool(pool) => Ok(Some(Database {
+                inner: Inner::Pinned(pool.get()?),
+                clock: self.clock.clone(),
+            })),
+            Inner::Single(_) | Inner::Pinned(_) => Ok(None),
+        }
+    }
+
+    /// The current time, from this database's clock.
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    /// Replace the clock, e.g. with a `MockClock` in tests.
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
     }
 
     fn migrate(&self) -> anyhow::Result<()> {
-        self.conn.execute_batch(SCHEMA)?;
+        let conn = self.conn()?;
+        conn.execute_batch(SCHEMA)?;
+        // Columns added to a table after it was first created
+        add_column(&conn, "fee_history", "ppm_since", "REAL")?;
         Ok(())
     }
 }
 
+/// Add `column` to `table` unless an older schema already has it.
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> anyhow::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+    }
+    Ok(())
+}
+
 const SCHEMA: &str = r#"
 -- Forwarding earnings per channel, bucketed by day
 CREATE TABLE IF NOT EXISTS earnings (
@@ -63,6 +164,42 @@ CREATE TABLE IF NOT EXISTS rebalance_costs (
     PRIMARY KEY (channel_id, day_bucket, direction)
 );
 
+-- Individual rebalances (audit trail). rebalance_costs only keeps daily
+-- per-channel totals for the source side.
+CREATE TABLE IF NOT EXISTS rebalance_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    src_channel_id TEXT NOT NULL,
+    src_counterparty_node_id TEXT NOT NULL,
+    dst_channel_id TEXT NOT NULL,
+    dst_counterparty_node_id TEXT NOT NULL,
+    amount_msat INTEGER NOT NULL,
+    fee_spent_msat INTEGER NOT NULL,
+    rebalanced_at REAL NOT NULL
+);
+
+-- Per-peer connectivity, sampled once per cycle by the reconnector
+CREATE TABLE IF NOT EXISTS peer_uptime (
+    counterparty_node_id TEXT NOT NULL,
+    day_bucket INTEGER NOT NULL,
+    observations INTEGER NOT NULL DEFAULT 0,
+    connected INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (counterparty_node_id, day_bucket)
+);
+
+-- Per-peer connected -> disconnected transitions, bucketed by day
+CREATE TABLE IF NOT EXISTS peer_disconnects (
+    counterparty_node_id TEXT NOT NULL,
+    day_bucket INTEGER NOT NULL,
+    disconnects INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (counterparty_node_id, day_bucket)
+);
+
+-- Last observed connectivity per peer, to detect disconnects
+CREATE TABLE IF NOT EXISTS peer_connectivity (
+    counterparty_node_id TEXT NOT NULL PRIMARY KEY,
+    connected INTEGER NOT NULL
+);
+
 -- Channel lifecycle tracking
 CREATE TABLE IF NOT EXISTS channel_history (
     channel_id TEXT NOT NULL PRIMARY KEY,
@@ -95,6 +232,15 @@ CREATE TABLE IF NOT EXISTS price_theory_cards (
 CREATE INDEX IF NOT EXISTS idx_cards_node_pos
     ON price_theory_cards(counterparty_node_id, position, deck_order);
 
+-- Price theory: since when each peer's center has held, and whether the
+-- peer has graduated to a fixed fee at it
+CREATE TABLE IF NOT EXISTS price_theory_graduation (
+    counterparty_node_id TEXT PRIMARY KEY,
+    center_since REAL NOT NULL,
+    graduated_at REAL,
+    baseline_msat_per_day REAL
+);
+
 -- On-chain fee samples for fee regime detection
 CREATE TABLE IF NOT EXISTS onchain_fee_samples (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -112,6 +258,24 @@ CREATE TABLE IF NOT EXISTS autopilot_opens (
     reason TEXT
 );
 
+-- Failed autopilot opens: transient retries and temporary blacklist
+CREATE TABLE IF NOT EXISTS autopilot_open_failures (
+    node_id TEXT NOT NULL PRIMARY KEY,
+    address TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('transient', 'permanent')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    last_attempt_at REAL NOT NULL,
+    blacklisted_until REAL
+);
+
+-- Last good response from the external ranking API
+CREATE TABLE IF NOT EXISTS ranking_cache (
+    node_id TEXT NOT NULL PRIMARY KEY,
+    address TEXT NOT NULL,
+    score REAL NOT NULL
+);
+
 -- Channels closed by judge (audit trail)
 CREATE TABLE IF NOT EXISTS judge_closures (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -121,6 +285,20 @@ CREATE TABLE IF NOT EXISTS judge_closures (
     reason TEXT NOT NULL
 );
 
+-- Peers flagged by the judge and given more time before closure
+CREATE TABLE IF NOT EXISTS judge_probation (
+    counterparty_node_id TEXT NOT NULL PRIMARY KEY,
+    started_at REAL NOT NULL,
+    reason TEXT NOT NULL
+);
+
+-- Channels the judge is draining (cheap fees) before closing them
+CREATE TABLE IF NOT EXISTS judge_drains (
+    channel_id TEXT NOT NULL PRIMARY KEY,
+    counterparty_node_id TEXT NOT NULL,
+    started_at REAL NOT NULL
+);
+
 -- Pagination cursor and other sync state
 CREATE TABLE IF NOT EXISTS sync_state (
     key TEXT PRIMARY KEY,
@@ -135,6 +313,72 @@ CREATE TABLE IF NOT EXISTS peer_addresses (
     source TEXT NOT NULL DEFAULT 'autopilot'
 );
 
+-- What acquiring liquidity on a channel cost, while the fee premium recoups it
+CREATE TABLE IF NOT EXISTS liquidity_costs (
+    channel_id TEXT NOT NULL PRIMARY KEY,
+    cost_msat INTEGER NOT NULL,
+    registered_at REAL NOT NULL
+);
+
+-- Last reconnection attempt per peer, so capped attempts go round-robin
+CREATE TABLE IF NOT EXISTS reconnect_attempts (
+    node_id TEXT NOT NULL PRIMARY KEY,
+    last_attempt_at REAL NOT NULL
+);
+
+-- Last fees sent for each channel, so unchanged fees can be re-sent before
+-- their channel_update goes stale. updated_at moves with every send,
+-- ppm_since only when the ppm changes (NULL: same as updated_at).
+CREATE TABLE IF NOT EXISTS fee_history (
+    channel_id TEXT NOT NULL PRIMARY KEY,
+    base_msat INTEGER NOT NULL,
+    ppm INTEGER NOT NULL,
+    updated_at REAL NOT NULL,
+    ppm_since REAL
+);
+
+-- Fee rates each channel forwarded at, and the lowest rate above those at
+-- which it stopped forwarding. Kept after close to seed a returning peer
+CREATE TABLE IF NOT EXISTS fee_demand (
+    channel_id TEXT NOT NULL PRIMARY KEY,
+    counterparty_node_id TEXT NOT NULL,
+    lowest_forward_ppm INTEGER,
+    highest_forward_ppm INTEGER,
+    lowest_idle_ppm INTEGER,
+    updated_at REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_fee_demand_node
+    ON fee_demand(counterparty_node_id);
+
+-- Single-instance lock: at most one row, held by the running daemon
+CREATE TABLE IF NOT EXISTS instance_lock (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    owner TEXT NOT NULL,
+    pid INTEGER NOT NULL,
+    acquired_at REAL NOT NULL,
+    expires_at REAL NOT NULL
+);
+
+-- Operator-assigned peer labels
+CREATE TABLE IF NOT EXISTS peer_labels (
+    node_id TEXT NOT NULL PRIMARY KEY,
+    label TEXT NOT NULL,
+    updated_at REAL NOT NULL
+);
+
+-- Last successfully listed channels, for riding out a brief server outage
+CREATE TABLE IF NOT EXISTS channel_snapshot (
+    channel_id TEXT NOT NULL PRIMARY KEY,
+    user_channel_id TEXT NOT NULL,
+    counterparty_node_id TEXT NOT NULL,
+    channel_value_sats INTEGER NOT NULL,
+    outbound_capacity_msat INTEGER NOT NULL,
+    inbound_capacity_msat INTEGER NOT NULL,
+    is_channel_ready INTEGER NOT NULL,
+    is_usable INTEGER NOT NULL,
+    is_announced INTEGER NOT NULL
+);
+
 -- General run state
 CREATE TABLE IF NOT EXISTS run_state (
     key TEXT PRIMARY KEY,
@@ -149,15 +393,55 @@ mod tests {
     #[test]
     fn test_open_in_memory() {
         let db = Database::open_in_memory().unwrap();
-        assert!(db.conn().is_autocommit());
+        assert!(db.conn().unwrap().is_autocommit());
+    }
+
+    #[test]
+    fn test_file_database_pooled_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("test.db")).unwrap();
+
+        // Two connections checked out at once see each other's writes
+        let a = db.conn().unwrap();
+        let b = db.conn().unwrap();
+        a.execute("INSERT INTO run_state (key, value) VALUES ('k', 'v')", [])
+            .unwrap();
+        let value: String = b
+            .query_row("SELECT value FROM run_state WHERE key = 'k'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(value, "v");
+    }
+
+    #[test]
+    fn test_pinned_reuses_one_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("test.db")).unwrap();
+        let pinned = db.pinned().unwrap().unwrap();
+
+        let conn = pinned.conn().unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        pinned
+            .conn()
+            .unwrap()
+            .execute("INSERT INTO run_state (key, value) VALUES ('k', 'v')", [])
+            .unwrap();
+        tx.rollback().unwrap();
+
+        let count: i64 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM run_state", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+        assert!(Database::open_in_memory().unwrap().pinned().unwrap().is_none());
     }
 
     #[test]
     fn test_schema_tables_exist() {
         let db = Database::open_in_memory().unwrap();
         let tables: Vec<String> = {
-            let mut stmt = db
-                .conn()
+            let conn = db.conn().unwrap();
+            let mut stmt = conn
                 .prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")
                 .unwrap();
             stmt.query_map([], |row| row.get(0))
@@ -167,15 +451,31 @@ mod tests {
         };
 
         let expected = vec![
+            "autopilot_open_failures",
             "autopilot_opens",
             "channel_history",
+            "channel_snapshot",
             "earnings",
+            "fee_demand",
+            "fee_history",
+            "instance_lock",
             "judge_closures",
+            "judge_drains",
+            "judge_probation",
+            "liquidity_costs",
             "onchain_fee_samples",
             "peer_addresses",
+            "peer_connectivity",
+            "peer_disconnects",
+            "peer_labels",
+            "peer_uptime",
             "price_theory_cards",
             "price_theory_center",
+            "price_theory_graduation",
+            "ranking_cache",
             "rebalance_costs",
+            "rebalance_log",
+            "reconnect_attempts",
             "run_state",
             "sync_state",
         ];
@@ -0,0 +1,150 @@
+/// Recouping paid-for inbound liquidity.
+///
+/// An operator who bought inbound on a channel (liquidity ad, swap) records
+/// the cost with `ldk-boss liquidity-cost <channel_id> <sats>`. Until the
+/// channel's earnings since then cover it, or `liquidity_recoup_days` pass,
+/// its fees carry `liquidity_premium_multiplier`. Fees are only ever set on
+/// our side of a channel, so the premium applies to payments we forward out
+/// over it; the earnings that count are the channel's in both directions.
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::tracker::earnings as earnings_tracker;
+use log::info;
+
+/// Record what acquiring liquidity on a channel cost, replacing any earlier
+/// entry; 0 removes it.
+pub fn register(db: &Database, channel_id: &str, cost_sats: u64) -> anyhow::Result<()> {
+    if cost_sats == 0 {
+        db.conn()?
+            .execute("DELETE FROM liquidity_costs WHERE channel_id = ?1", [channel_id])?;
+    } else {
+        db.conn()?.execute(
+            "INSERT OR REPLACE INTO liquidity_costs (channel_id, cost_msat, registered_at) \
+             VALUES (?1, ?2, ?3)",
+            rusqlite::params![
+                channel_id,
+                cost_sats * 1000,
+                db.now().timestamp() as f64
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Progress on recouping a channel's registered cost.
+struct Recoup {
+    cost_msat: i64,
+    earned_msat: i64,
+    age_days: f64,
+}
+
+impl Recoup {
+    fn is_done(&self, config: &Config) -> bool {
+        self.earned_msat >= self.cost_msat
+            || self.age_days >= config.fees.liquidity_recoup_days as f64
+    }
+}
+
+/// None if no cost is registered for the channel.
+fn recoup(db: &Database, channel_id: &str) -> anyhow::Result<Option<Recoup>> {
+    let entry: Option<(i64, f64)> = db
+        .conn()?
+        .query_row(
+            "SELECT cost_msat, registered_at FROM liquidity_costs WHERE channel_id = ?1",
+            [channel_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok();
+    let Some((cost_msat, registered_at)) = entry else {
+        return Ok(None);
+    };
+
+    let (earned_msat, _) = earnings_tracker::earnings_since(db, channel_id, registered_at)?;
+    Ok(Some(Recoup {
+        cost_msat,
+        earned_msat,
+        age_days: (db.now().timestamp() as f64 - registered_at) / 86400.0,
+    }))
+}
+
+/// Fee multiplier for a channel: the premium while its cost is being
+/// recouped, 1.0 otherwise. Read-only, so the advisor and dry runs can ask.
+pub fn fee_modifier(config: &Config, db: &Database, channel_id: &str) -> anyhow::Result<f64> {
+    match recoup(db, channel_id)? {
+        Some(r) if !r.is_done(config) => Ok(config.fees.liquidity_premium_multiplier),
+        _ => Ok(1.0),
+    }
+}
+
+/// Remove a channel's entry once it is recouped or expired.
+pub fn drop_recouped(config: &Config, db: &Database, channel_id: &str) -> anyhow::Result<()> {
+    let Some(r) = recoup(db, channel_id)? else {
+        return Ok(());
+    };
+    if r.is_done(config) {
+        info!(
+            "Fee management: {} earned {} of {} sat liquidity cost in {:.0} days, dropping premium",
+            channel_id,
+            r.earned_msat / 1000,
+            r.cost_msat / 1000,
+            r.age_days
+        );
+        register(db, channel_id, 0)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_premium_until_recouped() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.fees.liquidity_premium_multiplier = 1.5;
+        assert_eq!(fee_modifier(&config, &db, "ch1").unwrap(), 1.0);
+
+        register(&db, "ch1", 2_000).unwrap();
+        assert_eq!(fee_modifier(&config, &db, "ch1").unwrap(), 1.5);
+
+        let now = chrono::Utc::now().timestamp();
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                 fee_earned_msat, amount_forwarded_msat, direction) \
+                 VALUES ('ch1', 'peer', ?1, 2000000, 0, 'out')",
+                [now - now % 86400],
+            )
+            .unwrap();
+        assert_eq!(fee_modifier(&config, &db, "ch1").unwrap(), 1.0);
+
+        // Asking doesn't remove the entry; dropping it does
+        let entries = || -> i64 {
+            db.conn()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM liquidity_costs", [], |r| r.get(0))
+                .unwrap()
+        };
+        assert_eq!(entries(), 1);
+        drop_recouped(&config, &db, "ch1").unwrap();
+        assert_eq!(entries(), 0);
+        assert_eq!(fee_modifier(&config, &db, "ch1").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_premium_expires() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        register(&db, "ch1", 2_000).unwrap();
+        let old = chrono::Utc::now().timestamp() as f64
+            - (config.fees.liquidity_recoup_days + 1) as f64 * 86400.0;
+        db.conn()
+            .unwrap()
+            .execute("UPDATE liquidity_costs SET registered_at = ?1", [old])
+            .unwrap();
+        assert_eq!(fee_modifier(&config, &db, "ch1").unwrap(), 1.0);
+    }
+}
@@ -1,130 +1,487 @@
 pub mod balance_modder;
 pub mod competitor;
+pub mod liquidity_cost;
 pub mod price_theory;
 pub mod setter;
 pub mod size_modder;
 
 use crate::client::LdkClient;
 use crate::config::Config;
+use crate::cycle_summary::CycleSummary;
 use crate::db::Database;
+use crate::judge::drain as judge_drain;
+use crate::judge::probation as judge_probation;
 use crate::state::NodeState;
+use crate::tracker::channels as channel_tracker;
+use crate::tracker::onchain_fees::{self, FeeRegime};
+use ldk_server_protos::types::Channel;
 use log::{debug, info};
 
 /// Hard limits on fee values
 pub const ABS_MIN_FEE_PPM: u32 = 1;
 pub const ABS_MAX_FEE_PPM: u32 = 50_000;
 
+/// A channel's fee clamp is logged at most this often.
+const CLAMP_LOG_INTERVAL_SECS: i64 = 86_400;
+
+/// `run_state` key prefix recording when a channel's clamp was last logged.
+/// `tracker::cleanup` prunes the keys of channels that are gone.
+pub const CLAMP_LOG_KEY_PREFIX: &str = "fee_clamp_logged:";
+
+/// Whether a clamp on this channel should be logged now; records the time
+/// if so. A channel stuck at a limit would otherwise log every cycle.
+fn should_log_clamp(db: &Database, channel_id: &str) -> anyhow::Result<bool> {
+    let key = format!("{}{}", CLAMP_LOG_KEY_PREFIX, channel_id);
+    let now = db.now().timestamp();
+    let last: Option<i64> = db
+        .conn()?
+        .query_row("SELECT value FROM run_state WHERE key = ?1", [&key], |row| {
+            row.get::<_, String>(0)
+        })
+        .ok()
+        .and_then(|v| v.parse().ok());
+    if last.is_some_and(|at| now - at < CLAMP_LOG_INTERVAL_SECS) {
+        return Ok(false);
+    }
+    db.conn()?.execute(
+        "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+        rusqlite::params![key, now.to_string()],
+    )?;
+    Ok(true)
+}
+
+/// Whether a channel is still inside its new-channel protection window.
+/// Channels we have no history for are not protected.
+pub fn is_new_channel_protected(config: &Config, db: &Database, channel_id: &str) -> bool {
+    if config.fees.new_channel_protect_days == 0 {
+        return false;
+    }
+    match channel_tracker::channel_age_days(db, channel_id) {
+        Ok(Some(age_days)) => age_days < config.fees.new_channel_protect_days as f64,
+        _ => false,
+    }
+}
+
+/// Whether the balance modder may only raise fees this cycle: with
+/// `freeze_balance_modder_in_high_regime`, while on-chain fees are high.
+/// Discounting an outbound-heavy channel invites it to be drained, and
+/// refilling it by rebalancing or splicing is then expensive.
+pub fn is_balance_modder_frozen(config: &Config, db: &Database) -> bool {
+    config.fees.freeze_balance_modder_in_high_regime
+        && onchain_fees::saved_regime(db) == Some(FeeRegime::High)
+}
+
+/// Our share of a channel's liquidity, 0 to 1, as the balance modder sees
+/// it (`fees.balance_ratio_basis`). LDK's outbound capacity already excludes
+/// our reserve, so dividing it by the full capacity never reaches 1; the
+/// "spendable" basis divides by what both sides can send instead. A channel
+/// with nothing spendable either way falls back to capacity.
+pub fn balance_ratio(config: &Config, channel: &Channel) -> f64 {
+    let spendable_msat = channel.outbound_capacity_msat + channel.inbound_capacity_msat;
+    if config.fees.balance_ratio_basis == "spendable" && spendable_msat > 0 {
+        channel.outbound_capacity_msat as f64 / spendable_msat as f64
+    } else {
+        channel.outbound_capacity_msat as f64 / (channel.channel_value_sats as f64 * 1000.0)
+    }
+}
+
+/// Whether the balance modder applies, given how many channels fee
+/// management is pricing. On a node with only a channel or two there are no
+/// alternative paths for its pricing to steer traffic to.
+pub fn is_balance_modder_active(config: &Config, channel_count: usize) -> bool {
+    config.fees.balance_modder_enabled && channel_count >= config.fees.balance_modder_min_channels
+}
+
+/// Whether fee management should touch this channel at all. A channel
+/// whose peer is disconnected is not usable, so its fee update waits until
+/// the reconnector has it back.
+pub fn is_fee_managed(config: &Config, channel: &Channel) -> bool {
+    channel.is_usable && (channel.is_announced || config.fees.manage_private_channels)
+}
+
+/// What every channel's fees are computed against this cycle.
+pub struct FeeContext<'a> {
+    pub own_node_id: &'a str,
+    pub own_capacity_sats: u64,
+    /// `is_balance_modder_active` for the channels being priced
+    pub balance_active: bool,
+    /// `is_balance_modder_frozen`
+    pub balance_frozen: bool,
+}
+
+impl<'a> FeeContext<'a> {
+    /// The context for pricing `channel_count` of the node's channels.
+    pub fn new(config: &Config, db: &Database, state: &'a NodeState, channel_count: usize) -> Self {
+        FeeContext {
+            own_node_id: &state.node_info.node_id,
+            own_capacity_sats: state.total_channel_capacity_sats(),
+            balance_active: is_balance_modder_active(config, channel_count),
+            balance_frozen: is_balance_modder_frozen(config, db),
+        }
+    }
+}
+
+/// The fees fee management sets on a channel, and the multipliers behind them.
+pub struct ComputedFees {
+    pub base_msat: u32,
+    /// After new-channel protection and clamping
+    pub ppm: u32,
+    /// Before clamping
+    pub unclamped_ppm: u32,
+    /// Competitor median ppm, when it was the baseline
+    pub competitor_ppm: Option<u32>,
+    pub balance_mult: f64,
+    pub price_mult: f64,
+    pub size_mult: f64,
+    pub probation_mult: f64,
+    pub drain_mult: f64,
+    pub liquidity_mult: f64,
+}
+
+/// Compute a channel's fees: the competitor (or default) baseline times every
+/// modifier, held up for new channels and clamped to the hard limits. Shared
+/// by `run` and the advisor so a report shows what a cycle would set.
+pub async fn compute_fees(
+    config: &Config,
+    client: &(impl LdkClient + Sync),
+    db: &Database,
+    ctx: &FeeContext<'_>,
+    channel: &Channel,
+) -> anyhow::Result<ComputedFees> {
+    // Phase 0: Competitor fee baseline (market-relative base fees)
+    let competitor = if config.fees.competitor_fee_enabled {
+        competitor::get_competitor_fees(client, &channel.counterparty_node_id, ctx.own_node_id)
+            .await
+    } else {
+        None
+    };
+    let (base_ppm, base_base_msat) = match &competitor {
+        Some(cf) => {
+            debug!(
+                "Fee management: competitor baseline for {}: {}ppm, {}msat",
+                channel.counterparty_node_id, cf.median_ppm, cf.median_base_msat
+            );
+            (cf.median_ppm, cf.median_base_msat)
+        }
+        None => (config.fees.default_ppm, config.fees.default_base_msat),
+    };
+
+    // New channels: don't let the balance modder price them cheap before
+    // price theory has gathered any data.
+    let protected = is_new_channel_protected(config, db, &channel.channel_id);
+
+    // Phase 1: Balance-based fee modifier
+    let balance_mult = if ctx.balance_active {
+        balance_modder::get_ratio_binned(
+            balance_ratio(config, channel),
+            channel.channel_value_sats,
+            config.fees.preferred_bin_size_sats,
+        )
+    } else {
+        1.0
+    };
+    let balance_mult = if protected || ctx.balance_frozen {
+        balance_mult.max(1.0)
+    } else {
+        balance_mult
+    };
+
+    // Phase 2: Price theory modifier
+    let price_mult = if config.fees.price_theory_enabled {
+        price_theory::get_fee_modifier(db, &channel.counterparty_node_id)?
+    } else {
+        1.0
+    };
+
+    // Phase 3: Size-based modifier (relative capacity vs competitors)
+    let size_mult = if config.fees.size_modder_enabled {
+        size_modder::get_size_modifier(
+            client,
+            &channel.counterparty_node_id,
+            ctx.own_node_id,
+            ctx.own_capacity_sats,
+        )
+        .await
+        .unwrap_or(1.0)
+    } else {
+        1.0
+    };
+
+    // Peers on judge probation get one more chance at adjusted fees
+    let probation_mult = judge_probation::fee_multiplier(config, db, &channel.counterparty_node_id);
+
+    // Channels the judge is draining before closing them
+    let drain_mult = judge_drain::fee_multiplier(config, db, &channel.channel_id);
+
+    // Premium while paid-for inbound liquidity is recouped
+    let liquidity_mult = liquidity_cost::fee_modifier(config, db, &channel.channel_id)?;
+
+    // Operator override applied last, on top of everything else
+    let combined_mult = balance_mult
+        * price_mult
+        * size_mult
+        * probation_mult
+        * drain_mult
+        * liquidity_mult
+        * config.fees.global_multiplier;
+
+    // Compute final fees using competitor baseline (or config default)
+    let base_msat = ((base_base_msat as f64) * combined_mult) as u32;
+    let ppm = ((base_ppm as f64) * combined_mult) as u32;
+
+    let ppm = if protected {
+        debug!(
+            "Fee management: {} is new, holding at >= {}ppm",
+            channel.channel_id, config.fees.default_ppm
+        );
+        ppm.max(config.fees.default_ppm)
+    } else {
+        ppm
+    };
+
+    Ok(ComputedFees {
+        base_msat,
+        ppm: ppm.clamp(ABS_MIN_FEE_PPM, ABS_MAX_FEE_PPM),
+        unclamped_ppm: ppm,
+        competitor_ppm: competitor.map(|cf| cf.median_ppm),
+        balance_mult,
+        price_mult,
+        size_mult,
+        probation_mult,
+        drain_mult,
+        liquidity_mult,
+    })
+}
+
 /// Run the fee management module: compute and apply fees for all usable channels.
 pub async fn run(
     config: &Config,
     client: &(impl LdkClient + Sync),
     db: &Database,
     state: &NodeState,
+    summary: &mut CycleSummary,
 ) -> anyhow::Result<()> {
-    let usable_channels: Vec<_> = state.channels.iter().filter(|c| c.is_usable).collect();
+    let usable_channels: Vec<_> = state
+        .channels
+        .iter()
+        .filter(|c| is_fee_managed(config, c) && state.is_automated(config, c))
+        .collect();
 
     if usable_channels.is_empty() {
-        debug!("Fee management: no usable channels");
+        debug!("Fee management: no usable channels to manage");
         return Ok(());
     }
 
     info!("Fee management: evaluating {} usable channels", usable_channels.len());
 
-    let own_node_id = &state.node_info.node_id;
-    let own_capacity_sats = state.total_channel_capacity_sats();
+    let ctx = FeeContext::new(config, db, state, usable_channels.len());
+    let mut fee_changes = 0usize;
+
+    if config.fees.balance_modder_enabled && !ctx.balance_active {
+        debug!(
+            "Fee management: {} channels, balance modder waits for {}",
+            usable_channels.len(),
+            config.fees.balance_modder_min_channels
+        );
+    }
+    if ctx.balance_frozen {
+        info!("Fee management: high on-chain fees, balance modder discounts paused");
+    }
 
     for channel in &usable_channels {
-        let channel_value_sats = channel.channel_value_sats;
-        if channel_value_sats == 0 {
+        if channel.channel_value_sats == 0 {
             continue;
         }
 
-        // Phase 0: Competitor fee baseline (market-relative base fees)
-        let (base_ppm, base_base_msat) = if config.fees.competitor_fee_enabled {
-            match competitor::get_competitor_fees(
-                client,
-                &channel.counterparty_node_id,
-                own_node_id,
-            )
-            .await
+        let fees = compute_fees(config, client, db, &ctx, channel).await?;
+        if !config.general.dry_run {
+            liquidity_cost::drop_recouped(config, db, &channel.channel_id)?;
+        }
+        let (base_msat, ppm) = (fees.base_msat, fees.ppm);
+
+        // Clamped to hard limits
+        if ppm != fees.unclamped_ppm && should_log_clamp(db, &channel.channel_id)? {
+            info!(
+                "Fee management: {} computed {}ppm, clamped to {}ppm \
+                 (balance x{:.2}, price x{:.2}, size x{:.2}, probation x{:.2}, \
+                 drain x{:.2}, liquidity x{:.2}, global x{:.2})",
+                channel.channel_id,
+                fees.unclamped_ppm,
+                ppm,
+                fees.balance_mult,
+                fees.price_mult,
+                fees.size_mult,
+                fees.probation_mult,
+                fees.drain_mult,
+                fees.liquidity_mult,
+                config.fees.global_multiplier
+            );
+        }
+
+        let (current_base, current_ppm) = setter::current_fees(db, channel);
+        if setter::is_material_change(config, current_base, current_ppm, base_msat, ppm) {
+            if config.general.safe_mode
+                && fee_changes >= config.general.safe_mode_max_fee_changes
             {
-                Some(cf) => {
-                    debug!(
-                        "Fee management: competitor baseline for {}: {}ppm, {}msat",
-                        channel.counterparty_node_id, cf.median_ppm, cf.median_base_msat
-                    );
-                    (cf.median_ppm, cf.median_base_msat)
-                }
-                None => (config.fees.default_ppm, config.fees.default_base_msat),
+                info!(
+                    "Fee management: safe mode, deferring {} ppm {}->{} to a later cycle",
+                    channel.channel_id, current_ppm, ppm
+                );
+                continue;
             }
-        } else {
-            (config.fees.default_ppm, config.fees.default_base_msat)
-        };
+            fee_changes += 1;
+        }
 
-        // Compute balance ratio: our outbound / total
-        let our_balance_ratio = channel.outbound_capacity_msat as f64
-            / (channel_value_sats as f64 * 1000.0);
+        // Apply if different from current
+        if setter::apply_if_changed(config, client, db, channel, base_msat, ppm).await? {
+            summary.fees_updated += 1;
+        }
+    }
 
-        // Phase 1: Balance-based fee modifier
-        let balance_mult = if config.fees.balance_modder_enabled {
-            balance_modder::get_ratio_binned(
-                our_balance_ratio,
-                channel_value_sats,
-                config.fees.preferred_bin_size_sats,
+    // Update price theory tick
+    if config.fees.price_theory_enabled {
+        let peer_ids: Vec<String> = usable_channels
+            .iter()
+            .map(|c| c.counterparty_node_id.clone())
+            .collect();
+        price_theory::update_tick(db, &peer_ids, &config.fees)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_channel(db: &Database, channel_id: &str, first_seen_at: f64) {
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES (?1, 'u', 'peer', 1000000, ?2, ?2, 1)",
+                rusqlite::params![channel_id, first_seen_at],
             )
-        } else {
-            1.0
-        };
+            .unwrap();
+    }
+
+    #[test]
+    fn test_balance_modder_min_channels() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        assert!(is_balance_modder_active(&config, 1));
+
+        config.fees.balance_modder_min_channels = 3;
+        assert!(!is_balance_modder_active(&config, 2));
+        assert!(is_balance_modder_active(&config, 3));
+
+        config.fees.balance_modder_enabled = false;
+        assert!(!is_balance_modder_active(&config, 3));
+    }
+
+    #[test]
+    fn test_balance_modder_frozen_only_in_high_regime() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        onchain_fees::save_regime(&db, FeeRegime::High).unwrap();
+        assert!(!is_balance_modder_frozen(&config, &db));
 
-        // Phase 2: Price theory modifier
-        let price_mult = if config.fees.price_theory_enabled {
-            price_theory::get_fee_modifier(db, &channel.counterparty_node_id)?
-        } else {
-            1.0
+        config.fees.freeze_balance_modder_in_high_regime = true;
+        assert!(is_balance_modder_frozen(&config, &db));
+        onchain_fees::save_regime(&db, FeeRegime::Low).unwrap();
+        assert!(!is_balance_modder_frozen(&config, &db));
+    }
+
+    #[test]
+    fn test_balance_ratio_excludes_reserves() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        // All funds ours, 1% reserve held back on our side
+        let channel = Channel {
+            channel_value_sats: 1_000_000,
+            outbound_capacity_msat: 990_000_000,
+            inbound_capacity_msat: 0,
+            ..Default::default()
         };
+        assert_eq!(balance_ratio(&config, &channel), 1.0);
 
-        // Phase 3: Size-based modifier (relative capacity vs competitors)
-        let size_mult = if config.fees.size_modder_enabled {
-            size_modder::get_size_modifier(
-                client,
-                &channel.counterparty_node_id,
-                own_node_id,
-                own_capacity_sats,
-            )
-            .await
-            .unwrap_or(1.0)
-        } else {
-            1.0
+        config.fees.balance_ratio_basis = "capacity".to_string();
+        assert!((balance_ratio(&config, &channel) - 0.99).abs() < 1e-9);
+
+        // Nothing spendable either way (e.g. not yet ready)
+        config.fees.balance_ratio_basis = "spendable".to_string();
+        let pending = Channel {
+            channel_value_sats: 1_000_000,
+            ..Default::default()
         };
+        assert_eq!(balance_ratio(&config, &pending), 0.0);
+    }
 
-        let combined_mult = balance_mult * price_mult * size_mult;
+    #[test]
+    fn test_new_channel_protection_window() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.fees.new_channel_protect_days = 7;
 
-        // Compute final fees using competitor baseline (or config default)
-        let base_msat = ((base_base_msat as f64) * combined_mult) as u32;
-        let ppm = ((base_ppm as f64) * combined_mult) as u32;
+        let now = chrono::Utc::now().timestamp() as f64;
+        insert_channel(&db, "young", now - 2.0 * 86400.0);
+        insert_channel(&db, "old", now - 30.0 * 86400.0);
 
-        // Clamp to hard limits
-        let ppm = ppm.clamp(ABS_MIN_FEE_PPM, ABS_MAX_FEE_PPM);
+        assert!(is_new_channel_protected(&config, &db, "young"));
+        assert!(!is_new_channel_protected(&config, &db, "old"));
+        assert!(!is_new_channel_protected(&config, &db, "unknown"));
 
-        // Apply if different from current
-        setter::apply_if_changed(
-            config,
-            client,
-            channel,
-            base_msat,
-            ppm,
-        )
-        .await?;
+        config.fees.new_channel_protect_days = 0;
+        assert!(!is_new_channel_protected(&config, &db, "young"));
     }
 
-    // Update price theory tick
-    if config.fees.price_theory_enabled {
-        let peer_ids: Vec<String> = usable_channels
-            .iter()
-            .map(|c| c.counterparty_node_id.clone())
-            .collect();
-        price_theory::update_tick(db, &peer_ids, &config.fees)?;
+    #[test]
+    fn test_private_channels_can_be_excluded() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let public = Channel {
+            is_usable: true,
+            is_announced: true,
+            ..Default::default()
+        };
+        let private = Channel {
+            is_usable: true,
+            is_announced: false,
+            ..Default::default()
+        };
+
+        assert!(is_fee_managed(&config, &public));
+        assert!(is_fee_managed(&config, &private));
+
+        config.fees.manage_private_channels = false;
+        assert!(is_fee_managed(&config, &public));
+        assert!(!is_fee_managed(&config, &private));
     }
 
-    Ok(())
+    #[test]
+    fn test_disconnected_peer_channels_deferred() {
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let disconnected = Channel {
+            is_channel_ready: true,
+            is_usable: false,
+            is_announced: true,
+            ..Default::default()
+        };
+        assert!(!is_fee_managed(&config, &disconnected));
+    }
+
+    #[test]
+    fn test_clamp_logging_rate_limited_per_channel() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(should_log_clamp(&db, "a").unwrap());
+        assert!(!should_log_clamp(&db, "a").unwrap());
+        assert!(should_log_clamp(&db, "b").unwrap());
+
+        db.conn()
+            .unwrap()
+            .execute(
+                "UPDATE run_state SET value = '0' WHERE key = 'fee_clamp_logged:a'",
+                [],
+            )
+            .unwrap();
+        assert!(should_log_clamp(&db, "a").unwrap());
+    }
 }
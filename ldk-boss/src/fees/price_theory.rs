@@ -11,15 +11,29 @@
 /// - After all 5 cards are played, the highest-earning card's price becomes the new center.
 /// - Fee multiplier = 1.2^price (if positive) or 1/0.8^(-price) (if negative).
 ///
+/// Graduation (not in CLBoss): once a peer's center has held for
+/// `price_theory_graduate_days` and it earned something over that time, it
+/// stops playing cards and is charged its center price outright. If its
+/// daily earnings later fall `price_theory_regress_percent` below what they
+/// were at graduation, a fresh deck is dealt and exploration resumes.
+///
+/// With `learn_fee_demand` (not in CLBoss), a new peer's first center is
+/// seeded from the fee rates its channels are known to forward at (see
+/// `tracker::demand`) rather than starting at 0.
+///
 /// Reference: clboss/Boss/Mod/FeeModderByPriceTheory.cpp
 
 use crate::config::FeesConfig;
 use crate::db::Database;
-use log::debug;
+use crate::tracker::demand;
+use log::{debug, info};
 
 /// Maximum absolute price (clamped)
 const MAX_PRICE: i32 = 10;
 
+/// Earnings of a graduated peer are compared over this many trailing days
+const REVIEW_WINDOW_DAYS: f64 = 7.0;
+
 /// Card positions
 const POS_DECK: i32 = 0;
 const POS_IN_PLAY: i32 = 1;
@@ -27,7 +41,21 @@ const POS_DISCARDED: i32 = 2;
 
 /// Get the fee multiplier for a given peer based on the price theory state.
 pub fn get_fee_modifier(db: &Database, counterparty_node_id: &str) -> anyhow::Result<f64> {
-    let conn = db.conn();
+    let conn = db.conn()?;
+
+    // Graduated peers are charged their center, whatever card was in play
+    let graduated = conn.query_row(
+        "SELECT c.price FROM price_theory_center c \
+         JOIN price_theory_graduation g USING (counterparty_node_id) \
+         WHERE c.counterparty_node_id = ?1 AND g.graduated_at IS NOT NULL",
+        [counterparty_node_id],
+        |row| row.get::<_, i32>(0),
+    );
+    match graduated {
+        Ok(center) => return Ok(price_to_multiplier(center)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {}
+        Err(e) => return Err(e.into()),
+    }
 
     // Find the in-play card for this peer
     let result = conn.query_row(
@@ -68,16 +96,22 @@ pub fn price_to_multiplier(price: i32) -> f64 {
 /// - Decrement lifetime of in-play cards.
 /// - If a card expires, discard it and draw a new one.
 /// - If the deck is empty, end the round and start a new one.
+/// - Graduated peers don't play; see `review_graduation`.
 pub fn update_tick(
     db: &Database,
     connected_peers: &[String],
     config: &FeesConfig,
 ) -> anyhow::Result<()> {
-    let conn = db.conn();
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
 
     for peer_id in connected_peers {
         // Ensure this peer has been initialized
-        ensure_initialized(conn, peer_id, config)?;
+        ensure_initialized(&conn, peer_id, config)?;
+
+        if review_graduation(&conn, peer_id, config, now)? {
+            continue;
+        }
 
         // Find in-play card
         let in_play = conn.query_row(
@@ -101,7 +135,7 @@ pub fn update_tick(
                         peer_id, card_id
                     );
                     // Try to draw a new card
-                    draw_card(conn, peer_id, config)?;
+                    draw_card(&conn, peer_id, config, now)?;
                 } else {
                     // Decrement lifetime
                     conn.execute(
@@ -112,7 +146,7 @@ pub fn update_tick(
             }
             Err(rusqlite::Error::QueryReturnedNoRows) => {
                 // No card in play: draw one
-                draw_card(conn, peer_id, config)?;
+                draw_card(&conn, peer_id, config, now)?;
             }
             Err(e) => return Err(e.into()),
         }
@@ -127,7 +161,7 @@ pub fn record_earnings(
     counterparty_node_id: &str,
     fee_msat: i64,
 ) -> anyhow::Result<()> {
-    db.conn().execute(
+    db.conn()?.execute(
         "UPDATE price_theory_cards SET earnings_msat = earnings_msat + ?1 \
          WHERE counterparty_node_id = ?2 AND position = ?3",
         rusqlite::params![fee_msat, counterparty_node_id, POS_IN_PLAY],
@@ -140,6 +174,7 @@ fn draw_card(
     conn: &rusqlite::Connection,
     peer_id: &str,
     config: &FeesConfig,
+    now: f64,
 ) -> anyhow::Result<()> {
     // Find next card in deck (lowest deck_order)
     let next_card = conn.query_row(
@@ -166,7 +201,7 @@ fn draw_card(
         }
         Err(rusqlite::Error::QueryReturnedNoRows) => {
             // Deck empty: end the round
-            end_round(conn, peer_id, config)?;
+            end_round(conn, peer_id, config, now)?;
             // Draw from the new deck
             let next = conn.query_row(
                 "SELECT id, price FROM price_theory_cards \
@@ -196,10 +231,12 @@ fn draw_card(
 }
 
 /// End a round: find the best-earning card, set its price as new center, rebuild deck.
+/// `now` (unix seconds) is when a new center took hold.
 fn end_round(
     conn: &rusqlite::Connection,
     peer_id: &str,
     config: &FeesConfig,
+    now: f64,
 ) -> anyhow::Result<()> {
     // Find the highest-earning discarded card
     let best = conn.query_row(
@@ -229,6 +266,29 @@ fn end_round(
         }
     };
 
+    // A new center restarts the clock towards graduation
+    let old_center: Option<i32> = conn
+        .query_row(
+            "SELECT price FROM price_theory_center WHERE counterparty_node_id = ?1",
+            [peer_id],
+            |row| row.get(0),
+        )
+        .ok();
+    if old_center == Some(new_center) {
+        conn.execute(
+            "INSERT OR IGNORE INTO price_theory_graduation (counterparty_node_id, center_since) \
+             VALUES (?1, ?2)",
+            rusqlite::params![peer_id, now],
+        )?;
+    } else {
+        conn.execute(
+            "INSERT INTO price_theory_graduation (counterparty_node_id, center_since) \
+             VALUES (?1, ?2) \
+             ON CONFLICT(counterparty_node_id) DO UPDATE SET center_since = ?2",
+            rusqlite::params![peer_id, now],
+        )?;
+    }
+
     // Update center
     conn.execute(
         "INSERT OR REPLACE INTO price_theory_center (counterparty_node_id, price) VALUES (?1, ?2)",
@@ -247,6 +307,110 @@ fn end_round(
     Ok(())
 }
 
+/// Graduate a peer whose center has held long enough, or send a graduated
+/// peer whose earnings dropped back to exploring. Returns whether the peer
+/// is (still) graduated, i.e. should not play this tick.
+fn review_graduation(
+    conn: &rusqlite::Connection,
+    peer_id: &str,
+    config: &FeesConfig,
+    now: f64,
+) -> anyhow::Result<bool> {
+    let row = conn.query_row(
+        "SELECT center_since, graduated_at, baseline_msat_per_day \
+         FROM price_theory_graduation WHERE counterparty_node_id = ?1",
+        [peer_id],
+        |row| {
+            Ok((
+                row.get::<_, f64>(0)?,
+                row.get::<_, Option<f64>>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+            ))
+        },
+    );
+    let (center_since, graduated_at, baseline) = match row {
+        Ok(r) => r,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    match graduated_at {
+        Some(graduated_at) => {
+            if config.price_theory_graduate_days > 0 {
+                let window_secs = REVIEW_WINDOW_DAYS * 86400.0;
+                if now - graduated_at < window_secs {
+                    return Ok(true);
+                }
+                let recent = fees_since(conn, peer_id, now - window_secs)? / REVIEW_WINDOW_DAYS;
+                let baseline = baseline.unwrap_or(0.0);
+                if recent >= baseline * (1.0 - config.price_theory_regress_percent / 100.0) {
+                    return Ok(true);
+                }
+                info!(
+                    "PriceTheory: peer {} down to {:.0} msat/day from {:.0} since graduating, \
+                     exploring again",
+                    peer_id, recent, baseline
+                );
+            }
+
+            conn.execute(
+                "UPDATE price_theory_graduation \
+                 SET center_since = ?2, graduated_at = NULL, baseline_msat_per_day = NULL \
+                 WHERE counterparty_node_id = ?1",
+                rusqlite::params![peer_id, now],
+            )?;
+            let center: i32 = conn
+                .query_row(
+                    "SELECT price FROM price_theory_center WHERE counterparty_node_id = ?1",
+                    [peer_id],
+                    |row| row.get(0),
+                )
+                .unwrap_or(0);
+            conn.execute(
+                "DELETE FROM price_theory_cards WHERE counterparty_node_id = ?1",
+                [peer_id],
+            )?;
+            create_deck(conn, peer_id, center, config)?;
+            Ok(false)
+        }
+        None => {
+            let days = config.price_theory_graduate_days;
+            if days == 0 || now - center_since < days as f64 * 86400.0 {
+                return Ok(false);
+            }
+            let baseline = fees_since(conn, peer_id, now - days as f64 * 86400.0)? / days as f64;
+            if baseline <= 0.0 {
+                // Nothing earned at this center; keep looking
+                return Ok(false);
+            }
+            conn.execute(
+                "UPDATE price_theory_graduation \
+                 SET graduated_at = ?2, baseline_msat_per_day = ?3 \
+                 WHERE counterparty_node_id = ?1",
+                rusqlite::params![peer_id, now, baseline],
+            )?;
+            info!(
+                "PriceTheory: peer {} center held for {} days, graduating to a fixed fee \
+                 ({:.0} msat/day)",
+                peer_id, days, baseline
+            );
+            Ok(true)
+        }
+    }
+}
+
+/// Fees earned through a peer's channels since `since` (unix seconds).
+fn fees_since(conn: &rusqlite::Connection, peer_id: &str, since: f64) -> anyhow::Result<f64> {
+    let bucket = since as i64 - (since as i64 % 86400);
+    let msat: i64 = conn.query_row(
+        "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings \
+         WHERE counterparty_node_id = ?1 AND day_bucket >= ?2",
+        rusqlite::params![peer_id, bucket],
+        |row| row.get(0),
+    )?;
+    Ok(msat as f64)
+}
+
 /// Ensure a peer has been initialized in the price theory system.
 fn ensure_initialized(
     conn: &rusqlite::Connection,
@@ -262,17 +426,50 @@ fn ensure_initialized(
         .unwrap_or(false);
 
     if !exists {
+        let center = if config.learn_fee_demand {
+            seeded_center(conn, peer_id)?
+        } else {
+            0
+        };
         conn.execute(
             "INSERT OR IGNORE INTO price_theory_center (counterparty_node_id, price) \
-             VALUES (?1, 0)",
-            [peer_id],
+             VALUES (?1, ?2)",
+            rusqlite::params![peer_id, center],
         )?;
-        create_deck(conn, peer_id, 0, config)?;
+        create_deck(conn, peer_id, center, config)?;
     }
 
     Ok(())
 }
 
+/// The price that moves the peer's channels from their current fee rate
+/// (set while no card was in play, so at a neutral price) to the rate its
+/// forwarding history suggests. 0 without both.
+fn seeded_center(conn: &rusqlite::Connection, peer_id: &str) -> anyhow::Result<i32> {
+    let Some(seed_ppm) = demand::peer_demand(conn, peer_id)?.seed_ppm() else {
+        return Ok(0);
+    };
+    let neutral_ppm: Option<f64> = conn.query_row(
+        "SELECT AVG(fh.ppm) FROM fee_history fh \
+         JOIN channel_history ch ON ch.channel_id = fh.channel_id \
+         WHERE ch.counterparty_node_id = ?1 AND ch.is_open = 1",
+        [peer_id],
+        |row| row.get(0),
+    )?;
+    let Some(neutral_ppm) = neutral_ppm.filter(|ppm| *ppm > 0.0) else {
+        return Ok(0);
+    };
+    let center = ((seed_ppm.max(1.0) / neutral_ppm).ln() / 1.2_f64.ln()).round() as i32;
+    let center = center.clamp(-MAX_PRICE, MAX_PRICE);
+    if center != 0 {
+        info!(
+            "PriceTheory: peer {} forwards around {:.0}ppm, starting at price {} instead of 0",
+            peer_id, seed_ppm, center
+        );
+    }
+    Ok(center)
+}
+
 /// Create a shuffled deck of 5 cards around the center price.
 fn create_deck(
     conn: &rusqlite::Connection,
@@ -337,11 +534,25 @@ mod tests {
             default_ppm: 100,
             balance_modder_enabled: true,
             preferred_bin_size_sats: 200_000,
+            balance_ratio_basis: "spendable".to_string(),
+            freeze_balance_modder_in_high_regime: false,
+            balance_modder_min_channels: 0,
             price_theory_enabled: true,
             price_theory_card_lifetime_ticks: 5, // Short for testing
+            price_theory_card_lifetime_hours: None,
             price_theory_max_step: 2,
+            price_theory_graduate_days: 0,
+            price_theory_regress_percent: 50.0,
+            learn_fee_demand: false,
             competitor_fee_enabled: true,
             size_modder_enabled: true,
+            new_channel_protect_days: 7,
+            manage_private_channels: true,
+            fee_update_deadband_ppm: 2,
+            max_fee_age_hours: 0,
+            global_multiplier: 1.0,
+            liquidity_premium_multiplier: 1.25,
+            liquidity_recoup_days: 90,
         }
     }
 
@@ -349,9 +560,9 @@ mod tests {
     fn test_ensure_initialized_creates_deck() {
         let db = crate::db::Database::open_in_memory().unwrap();
         let config = test_fees_config();
-        let conn = db.conn();
+        let conn = db.conn().unwrap();
 
-        ensure_initialized(conn, "peer1", &config).unwrap();
+        ensure_initialized(&conn, "peer1", &config).unwrap();
 
         // Should have 5 cards (step=2: prices -2,-1,0,1,2)
         let count: i64 = conn
@@ -374,14 +585,68 @@ mod tests {
         assert_eq!(center, 0);
     }
 
+    #[test]
+    fn test_center_seeded_from_demand() {
+        let db = crate::db::Database::open_in_memory().unwrap();
+        let mut config = test_fees_config();
+        config.learn_fee_demand = true;
+        let conn = db.conn().unwrap();
+        conn.execute(
+            "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+             channel_value_sats, first_seen_at, last_seen_at, is_open) \
+             VALUES ('ch', 'u', 'peer1', 1000000, 0, 0, 1)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO fee_history (channel_id, base_msat, ppm, updated_at) \
+             VALUES ('ch', 0, 100, 0)",
+            [],
+        )
+        .unwrap();
+        // Forwarded at 144ppm: 1.2^2 above the neutral 100ppm
+        demand::record_forward(&conn, "ch", "peer1", 144_000, 1_000_000_000, 0.0).unwrap();
+
+        ensure_initialized(&conn, "peer1", &config).unwrap();
+        let center: i32 = conn
+            .query_row(
+                "SELECT price FROM price_theory_center WHERE counterparty_node_id = 'peer1'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(center, 2);
+        let max_card: i32 = conn
+            .query_row(
+                "SELECT MAX(price) FROM price_theory_cards WHERE counterparty_node_id = 'peer1'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(max_card, 4);
+
+        // Without history, or with learning off, the center starts at 0
+        ensure_initialized(&conn, "peer2", &config).unwrap();
+        config.learn_fee_demand = false;
+        ensure_initialized(&conn, "peer3", &config).unwrap();
+        let seeded: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM price_theory_center WHERE price != 0",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(seeded, 1);
+    }
+
     #[test]
     fn test_ensure_initialized_idempotent() {
         let db = crate::db::Database::open_in_memory().unwrap();
         let config = test_fees_config();
-        let conn = db.conn();
+        let conn = db.conn().unwrap();
 
-        ensure_initialized(conn, "peer1", &config).unwrap();
-        ensure_initialized(conn, "peer1", &config).unwrap();
+        ensure_initialized(&conn, "peer1", &config).unwrap();
+        ensure_initialized(&conn, "peer1", &config).unwrap();
 
         let count: i64 = conn
             .query_row(
@@ -403,6 +668,7 @@ mod tests {
 
         let in_play: i64 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT COUNT(*) FROM price_theory_cards \
                  WHERE counterparty_node_id = 'peer1' AND position = 1",
@@ -423,6 +689,7 @@ mod tests {
 
         let lifetime_before: i32 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT lifetime FROM price_theory_cards \
                  WHERE counterparty_node_id = 'peer1' AND position = 1",
@@ -436,6 +703,7 @@ mod tests {
 
         let lifetime_after: i32 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT lifetime FROM price_theory_cards \
                  WHERE counterparty_node_id = 'peer1' AND position = 1",
@@ -462,6 +730,7 @@ mod tests {
 
         let discarded: i64 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT COUNT(*) FROM price_theory_cards \
                  WHERE counterparty_node_id = 'peer1' AND position = 2",
@@ -474,6 +743,7 @@ mod tests {
         // Should still have a card in play
         let in_play: i64 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT COUNT(*) FROM price_theory_cards \
                  WHERE counterparty_node_id = 'peer1' AND position = 1",
@@ -502,6 +772,7 @@ mod tests {
         // The center should have been updated
         let center: i32 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT price FROM price_theory_center WHERE counterparty_node_id = 'peer1'",
                 [],
@@ -512,6 +783,32 @@ mod tests {
         assert!(center >= -MAX_PRICE && center <= MAX_PRICE);
     }
 
+    #[test]
+    fn test_new_center_held_since_db_clock() {
+        let mut db = crate::db::Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        let mut config = test_fees_config();
+        config.price_theory_card_lifetime_ticks = 1;
+
+        // Five cards, each drawn and then expired, end the first round
+        for _ in 0..6 {
+            update_tick(&db, &["peer1".to_string()], &config).unwrap();
+            clock.advance_secs(600);
+        }
+        let center_since: f64 = db
+            .conn()
+            .unwrap()
+            .query_row(
+                "SELECT center_since FROM price_theory_graduation \
+                 WHERE counterparty_node_id = 'peer1'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(center_since, (1_700_000_000 + 5 * 600) as f64);
+    }
+
     #[test]
     fn test_record_earnings() {
         let db = crate::db::Database::open_in_memory().unwrap();
@@ -526,6 +823,7 @@ mod tests {
 
         let earnings: i64 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT earnings_msat FROM price_theory_cards \
                  WHERE counterparty_node_id = 'peer1' AND position = 1",
@@ -555,4 +853,106 @@ mod tests {
         // Should be some valid multiplier (depends on which card was drawn)
         assert!(mult > 0.0);
     }
+
+    /// Set peer1's center and graduation row; `graduated` is (at, baseline).
+    fn set_graduation(
+        db: &Database,
+        center: i32,
+        center_since: f64,
+        graduated: Option<(f64, f64)>,
+    ) {
+        let conn = db.conn().unwrap();
+        conn.execute(
+            "UPDATE price_theory_center SET price = ?1 WHERE counterparty_node_id = 'peer1'",
+            [center],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO price_theory_graduation \
+             (counterparty_node_id, center_since, graduated_at, baseline_msat_per_day) \
+             VALUES ('peer1', ?1, ?2, ?3)",
+            rusqlite::params![center_since, graduated.map(|g| g.0), graduated.map(|g| g.1)],
+        )
+        .unwrap();
+    }
+
+    fn add_earnings(db: &Database, fee_msat: i64) {
+        let now = db.now().timestamp();
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                 fee_earned_msat, amount_forwarded_msat, direction) \
+                 VALUES ('ch1', 'peer1', ?1, ?2, 0, 'in')",
+                rusqlite::params![now - now % 86400, fee_msat],
+            )
+            .unwrap();
+    }
+
+    fn in_play_lifetime(db: &Database) -> i32 {
+        db.conn()
+            .unwrap()
+            .query_row(
+                "SELECT lifetime FROM price_theory_cards \
+                 WHERE counterparty_node_id = 'peer1' AND position = 1",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stable_center_graduates_to_fixed_fee() {
+        let mut db = crate::db::Database::open_in_memory().unwrap();
+        db.set_clock(crate::clock::MockClock::at(1_700_000_000));
+        let mut config = test_fees_config();
+        config.price_theory_graduate_days = 10;
+        let peers = ["peer1".to_string()];
+        let now = db.now().timestamp() as f64;
+
+        update_tick(&db, &peers, &config).unwrap();
+        set_graduation(&db, 3, now - 11.0 * 86400.0, None);
+
+        // Held long enough but earned nothing: keeps playing
+        update_tick(&db, &peers, &config).unwrap();
+        let lifetime = in_play_lifetime(&db);
+
+        add_earnings(&db, 50_000);
+        update_tick(&db, &peers, &config).unwrap();
+        assert!((get_fee_modifier(&db, "peer1").unwrap() - price_to_multiplier(3)).abs() < 1e-9);
+
+        // Cards are frozen while graduated
+        update_tick(&db, &peers, &config).unwrap();
+        assert_eq!(in_play_lifetime(&db), lifetime);
+    }
+
+    #[test]
+    fn test_graduated_peer_regresses_when_earnings_drop() {
+        let mut db = crate::db::Database::open_in_memory().unwrap();
+        db.set_clock(crate::clock::MockClock::at(1_700_000_000));
+        let mut config = test_fees_config();
+        config.price_theory_graduate_days = 10;
+        let peers = ["peer1".to_string()];
+        let now = db.now().timestamp() as f64;
+
+        update_tick(&db, &peers, &config).unwrap();
+        // Graduated 8 days ago at 10k msat/day; the last week earned 7k in total
+        set_graduation(&db, 1, now - 30.0 * 86400.0, Some((now - 8.0 * 86400.0, 10_000.0)));
+        add_earnings(&db, 7_000);
+        update_tick(&db, &peers, &config).unwrap();
+
+        let graduated_at: Option<f64> = db
+            .conn()
+            .unwrap()
+            .query_row(
+                "SELECT graduated_at FROM price_theory_graduation \
+                 WHERE counterparty_node_id = 'peer1'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert!(graduated_at.is_none());
+        // Fresh deck around the center, with a card in play
+        assert_eq!(in_play_lifetime(&db), config.price_theory_card_lifetime_ticks as i32);
+    }
 }
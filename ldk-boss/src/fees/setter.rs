@@ -1,45 +1,153 @@
+use crate::audit;
 use crate::client::LdkClient;
 use crate::config::Config;
+use crate::db::Database;
 use ldk_server_protos::api::UpdateChannelConfigRequest;
 use ldk_server_protos::types::{Channel, ChannelConfig};
 use log::{debug, info};
+use serde_json::json;
 
-/// Apply fee configuration to a channel, but only if it differs from the current config.
+/// Whether moving from the current fees to the new ones is worth a channel
+/// update. A ppm move within `fee_update_deadband_ppm` is not: it would only
+/// spam gossip. Any base fee change is, whatever the ppm does, so a new
+/// `base_fee_msat` is never held back by the deadband.
+pub fn is_material_change(
+    config: &Config,
+    current_base_msat: u32,
+    current_ppm: u32,
+    new_base_msat: u32,
+    new_ppm: u32,
+) -> bool {
+    let deadband = config.fees.fee_update_deadband_ppm;
+    let ppm_delta = current_ppm.abs_diff(new_ppm);
+    current_base_msat != new_base_msat || ppm_delta > deadband
+}
+
+/// Current (base msat, ppm) of a channel, 0 where unset.
+///
+/// Usable channels are past funding and accept config updates, but the
+/// server may still report them without a `channel_config`. For those the
+/// fees we last sent are the best knowledge, so a channel is written once
+/// rather than looking changed every cycle. Never written: (0, 0).
+pub fn current_fees(db: &Database, channel: &Channel) -> (u32, u32) {
+    let Some(current) = channel.channel_config.as_ref() else {
+        return last_sent(db, &channel.channel_id).unwrap_or((0, 0));
+    };
+    (
+        current.forwarding_fee_base_msat.unwrap_or(0),
+        current.forwarding_fee_proportional_millionths.unwrap_or(0),
+    )
+}
+
+/// The (base msat, ppm) we last sent for a channel, if any.
+fn last_sent(db: &Database, channel_id: &str) -> Option<(u32, u32)> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT base_msat, ppm FROM fee_history WHERE channel_id = ?1",
+            [channel_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()
+}
+
+/// Whether a channel's fees were last sent more than `max_fee_age_hours`
+/// ago. A channel we have no record for isn't; `apply_if_changed` starts its
+/// clock.
+fn is_stale(config: &Config, db: &Database, channel_id: &str) -> anyhow::Result<bool> {
+    let max_age_hours = config.fees.max_fee_age_hours;
+    if max_age_hours == 0 {
+        return Ok(false);
+    }
+    let now = db.now().timestamp() as f64;
+    let updated_at: Option<f64> = db
+        .conn()?
+        .query_row(
+            "SELECT updated_at FROM fee_history WHERE channel_id = ?1",
+            [channel_id],
+            |row| row.get(0),
+        )
+        .ok();
+    Ok(updated_at.is_some_and(|at| now - at >= max_age_hours as f64 * 3600.0))
+}
+
+fn record_update(
+    db: &Database,
+    channel_id: &str,
+    base_msat: u32,
+    ppm: u32,
+) -> anyhow::Result<()> {
+    // A re-send of the same ppm keeps the time it took effect
+    db.conn()?.execute(
+        "INSERT INTO fee_history (channel_id, base_msat, ppm, updated_at, ppm_since) \
+         VALUES (?1, ?2, ?3, ?4, ?4) \
+         ON CONFLICT(channel_id) DO UPDATE SET base_msat = ?2, ppm = ?3, updated_at = ?4, \
+         ppm_since = CASE WHEN fee_history.ppm = ?3 \
+                          THEN COALESCE(fee_history.ppm_since, fee_history.updated_at) \
+                          ELSE ?4 END",
+        rusqlite::params![channel_id, base_msat, ppm, db.now().timestamp() as f64],
+    )?;
+    Ok(())
+}
+
+/// Apply fee configuration to a channel, but only if it differs materially
+/// from the current config, or the current one is older than
+/// `max_fee_age_hours` and needs re-sending to stay fresh in gossip. Returns
+/// whether fees were sent.
 pub async fn apply_if_changed(
     config: &Config,
     client: &(impl LdkClient + Sync),
+    db: &Database,
     channel: &Channel,
     new_base_msat: u32,
     new_ppm: u32,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<bool> {
     // Get current config
     let current = channel.channel_config.as_ref();
-    let current_base = current.and_then(|c| c.forwarding_fee_base_msat).unwrap_or(0);
-    let current_ppm = current
-        .and_then(|c| c.forwarding_fee_proportional_millionths)
-        .unwrap_or(0);
-
-    if current_base == new_base_msat && current_ppm == new_ppm {
+    let (current_base, current_ppm) = current_fees(db, channel);
+    if current.is_none() {
         debug!(
-            "Fee setter: channel {} unchanged (base={}msat, ppm={})",
-            channel.channel_id, new_base_msat, new_ppm
+            "Fee setter: channel {} reports no channel config, using last sent fees",
+            channel.channel_id
         );
-        return Ok(());
     }
 
-    info!(
-        "Fee setter: channel {} with {} -- base: {}->{}msat, ppm: {}->{}",
-        channel.channel_id,
-        channel.counterparty_node_id,
-        current_base,
-        new_base_msat,
-        current_ppm,
-        new_ppm,
-    );
+    let (new_base_msat, new_ppm) =
+        if is_material_change(config, current_base, current_ppm, new_base_msat, new_ppm) {
+            info!(
+                "Fee setter: channel {} with {} -- base: {}->{}msat, ppm: {}->{}",
+                channel.channel_id,
+                channel.counterparty_node_id,
+                current_base,
+                new_base_msat,
+                current_ppm,
+                new_ppm,
+            );
+            (new_base_msat, new_ppm)
+        } else if is_stale(config, db, &channel.channel_id)? {
+            info!(
+                "Fee setter: channel {} fees unchanged for {}h, re-sending {}msat/{}ppm",
+                channel.channel_id, config.fees.max_fee_age_hours, current_base, current_ppm
+            );
+            (current_base, current_ppm)
+        } else {
+            debug!(
+                "Fee setter: channel {} unchanged (base={}->{}msat, ppm={}->{})",
+                channel.channel_id, current_base, new_base_msat, current_ppm, new_ppm
+            );
+            // First sighting starts the clock towards a re-send
+            if config.fees.max_fee_age_hours > 0
+                && !config.general.dry_run
+                && last_sent(db, &channel.channel_id).is_none()
+            {
+                record_update(db, &channel.channel_id, current_base, current_ppm)?;
+            }
+            return Ok(false);
+        };
 
     if config.general.dry_run {
         info!("  (dry-run: not applying)");
-        return Ok(());
+        return Ok(false);
     }
 
     let request = UpdateChannelConfigRequest {
@@ -58,6 +166,133 @@ pub async fn apply_if_changed(
     };
 
     client.update_channel_config(request).await?;
+    record_update(db, &channel.channel_id, new_base_msat, new_ppm)?;
+    audit::emit(
+        config,
+        "fee_change",
+        json!({
+            "channel_id": channel.channel_id,
+            "counterparty_node_id": channel.counterparty_node_id,
+            "old_base_msat": current_base,
+            "old_ppm": current_ppm,
+            "base_msat": new_base_msat,
+            "ppm": new_ppm,
+        }),
+    );
 
-    Ok(())
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_material_change_deadband() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.fees.fee_update_deadband_ppm = 2;
+
+        assert!(!is_material_change(&config, 1000, 100, 1000, 100));
+        assert!(!is_material_change(&config, 1000, 100, 1000, 102));
+        assert!(is_material_change(&config, 1000, 100, 1000, 103));
+
+        // A base-only change is material whatever the deadband
+        assert!(is_material_change(&config, 1000, 100, 1010, 98));
+        assert!(is_material_change(&config, 1000, 100, 0, 100));
+        config.fees.fee_update_deadband_ppm = 1000;
+        assert!(is_material_change(&config, 1000, 100, 2000, 100));
+        assert!(!is_material_change(&config, 1000, 100, 1000, 900));
+
+        // Zero deadband: any difference counts, as before
+        config.fees.fee_update_deadband_ppm = 0;
+        assert!(!is_material_change(&config, 1000, 100, 1000, 100));
+        assert!(is_material_change(&config, 1000, 100, 1000, 101));
+        assert!(is_material_change(&config, 1000, 100, 1001, 100));
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_fees_resent_after_max_age() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.fees.max_fee_age_hours = 24;
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        let mock = crate::client::mock::MockLdkClient::new();
+        let channel = Channel {
+            channel_id: "ch1".to_string(),
+            channel_config: Some(ChannelConfig {
+                forwarding_fee_base_msat: Some(1000),
+                forwarding_fee_proportional_millionths: Some(100),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // First sighting starts the clock
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 101).await.unwrap();
+        assert!(mock.update_config_calls.lock().unwrap().is_empty());
+
+        clock.advance_secs(25 * 3600);
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 101).await.unwrap();
+        let calls = mock.update_config_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        let sent = calls[0].channel_config.as_ref().unwrap();
+        assert_eq!(sent.forwarding_fee_proportional_millionths, Some(100));
+        drop(calls);
+
+        // Fresh again
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 101).await.unwrap();
+        assert_eq!(mock.update_config_calls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_records_no_fees() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.dry_run = true;
+        config.fees.max_fee_age_hours = 24;
+        let db = Database::open_in_memory().unwrap();
+        let mock = crate::client::mock::MockLdkClient::new();
+        let channel = Channel {
+            channel_id: "ch1".to_string(),
+            channel_config: Some(ChannelConfig {
+                forwarding_fee_base_msat: Some(1000),
+                forwarding_fee_proportional_millionths: Some(100),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // Neither an unchanged channel's first sighting nor a change is kept
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 100).await.unwrap();
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 500).await.unwrap();
+        let rows: i64 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM fee_history", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+        assert!(mock.update_config_calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_channel_without_config_written_once() {
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let db = Database::open_in_memory().unwrap();
+        let mock = crate::client::mock::MockLdkClient::new();
+        let channel = Channel {
+            channel_id: "ch1".to_string(),
+            channel_config: None,
+            ..Default::default()
+        };
+
+        for _ in 0..3 {
+            apply_if_changed(&config, &mock, &db, &channel, 1000, 100).await.unwrap();
+        }
+        assert_eq!(mock.update_config_calls.lock().unwrap().len(), 1);
+        assert_eq!(current_fees(&db, &channel), (1000, 100));
+
+        // A real change is still sent
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 200).await.unwrap();
+        assert_eq!(mock.update_config_calls.lock().unwrap().len(), 2);
+    }
 }
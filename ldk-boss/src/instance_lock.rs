@@ -0,0 +1,140 @@
+/// Single-instance guard: two daemons on one database would fight over
+/// fees and could open the same channel twice.
+///
+/// The lock is a row in the database itself, so it covers every process
+/// using that file, however it was started. The holder renews it around
+/// every cycle; a lock that isn't renewed in time (the holder crashed or was
+/// killed) is taken over by the next instance. `--force` takes it over
+/// regardless, and the previous holder exits at its next renewal.
+
+use crate::config::Config;
+use crate::db::Database;
+use log::warn;
+
+pub struct InstanceLock<'a> {
+    db: &'a Database,
+    owner: String,
+}
+
+/// How long a lock stays valid without renewal: a whole cycle plus a sleep,
+/// with some slack. Without the watchdog nothing bounds a cycle, so it is
+/// allowed two loop intervals; a cycle that runs longer lets another
+/// instance take over, and this one exits at its next renewal.
+fn ttl_secs(config: &Config) -> f64 {
+    let cycle = match config.general.cycle_timeout_secs {
+        0 => 2 * config.general.loop_interval_secs,
+        secs => secs,
+    };
+    (config.general.loop_interval_secs + cycle + 300) as f64
+}
+
+impl<'a> InstanceLock<'a> {
+    pub fn acquire(db: &'a Database, config: &Config, force: bool) -> anyhow::Result<Self> {
+        let owner = format!("{}-{:08x}", std::process::id(), rand::random::<u32>());
+        let now = db.now().timestamp() as f64;
+        let conn = db.conn()?;
+
+        let taken = conn.execute(
+            "INSERT INTO instance_lock (id, owner, pid, acquired_at, expires_at) \
+             VALUES (1, ?1, ?2, ?3, ?4) \
+             ON CONFLICT(id) DO UPDATE SET owner = excluded.owner, pid = excluded.pid, \
+             acquired_at = excluded.acquired_at, expires_at = excluded.expires_at \
+             WHERE instance_lock.expires_at < ?3 OR ?5",
+            rusqlite::params![owner, std::process::id(), now, now + ttl_secs(config), force],
+        )?;
+        if taken == 0 {
+            let (pid, acquired_at): (u32, f64) = conn.query_row(
+                "SELECT pid, acquired_at FROM instance_lock WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            let since = chrono::DateTime::from_timestamp(acquired_at as i64, 0)
+                .map(|d| d.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default();
+            anyhow::bail!(
+                "the database is in use by another ldk-boss (pid {}, since {}); stop it \
+                 first, or pass --force if it is no longer running",
+                pid,
+                since
+            );
+        }
+        if force {
+            warn!("Instance lock: --force given, taking over the database lock");
+        }
+
+        Ok(Self { db, owner })
+    }
+
+    /// Extend the lock. Returns false if another instance has taken it
+    /// over, in which case this one must stop; an error only means the
+    /// database couldn't be reached this time.
+    pub fn renew(&self, config: &Config) -> anyhow::Result<bool> {
+        let now = self.db.now().timestamp() as f64;
+        let renewed = self.db.conn()?.execute(
+            "UPDATE instance_lock SET expires_at = ?2 WHERE id = 1 AND owner = ?1",
+            rusqlite::params![self.owner, now + ttl_secs(config)],
+        )?;
+        Ok(renewed > 0)
+    }
+}
+
+impl Drop for InstanceLock<'_> {
+    fn drop(&mut self) {
+        if let Ok(conn) = self.db.conn() {
+            let _ = conn.execute(
+                "DELETE FROM instance_lock WHERE id = 1 AND owner = ?1",
+                [&self.owner],
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_instance_refused_until_released() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+
+        let first = InstanceLock::acquire(&db, &config, false).unwrap();
+        let err = InstanceLock::acquire(&db, &config, false).err().unwrap();
+        assert!(err.to_string().contains("another ldk-boss"));
+        assert!(first.renew(&config).unwrap());
+
+        drop(first);
+        assert!(InstanceLock::acquire(&db, &config, false).is_ok());
+    }
+
+    #[test]
+    fn test_ttl_follows_loop_interval() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.loop_interval_secs = 60;
+        config.general.cycle_timeout_secs = 600;
+        assert_eq!(ttl_secs(&config), 960.0);
+
+        config.general.cycle_timeout_secs = 0;
+        assert_eq!(ttl_secs(&config), 480.0);
+    }
+
+    #[test]
+    fn test_expired_or_forced_takeover() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+
+        let first = InstanceLock::acquire(&db, &config, false).unwrap();
+        let second = InstanceLock::acquire(&db, &config, true).unwrap();
+        assert!(!first.renew(&config).unwrap());
+        // The old holder going away doesn't release the new holder's lock
+        drop(first);
+        assert!(InstanceLock::acquire(&db, &config, false).is_err());
+
+        db.conn()
+            .unwrap()
+            .execute("UPDATE instance_lock SET expires_at = 0", [])
+            .unwrap();
+        let _third = InstanceLock::acquire(&db, &config, false).unwrap();
+        assert!(!second.renew(&config).unwrap());
+    }
+}
@@ -6,7 +6,7 @@
 /// 3. Peers below median are closure candidates
 /// 4. For each candidate:
 ///    improvement = median_rate * channel_size - actual_earned - reopen_cost
-/// 5. If improvement > 0: recommend closure
+/// 5. If improvement > min_improvement (0 by default): recommend closure
 ///
 /// Reference: clboss/Boss/Mod/PeerJudge/Algo.cpp, README.md
 
@@ -32,6 +32,7 @@ pub struct CloseRecommendation {
 pub fn judge(
     peers: &[PeerInfo],
     reopen_cost_sats: u64,
+    min_improvement_sats: u64,
 ) -> Vec<CloseRecommendation> {
     if peers.is_empty() {
         return Vec::new();
@@ -63,6 +64,7 @@ pub fn judge(
     debug!("Judge: weighted median earning rate = {:.6} msat/sat", median_rate);
 
     let reopen_cost_msat = (reopen_cost_sats * 1000) as i64;
+    let min_improvement_msat = (min_improvement_sats * 1000) as i64;
 
     let mut recommendations = Vec::new();
 
@@ -77,7 +79,12 @@ pub fn judge(
         let expected_earnings = (median_rate * peer.total_channel_sats as f64 * 1000.0) as i64;
         let improvement = expected_earnings - peer.total_earned_msat - reopen_cost_msat;
 
-        if improvement > 0 {
+        if improvement > 0 && improvement <= min_improvement_msat {
+            debug!(
+                "Judge: peer {} improvement {} msat within min_improvement_sats {}, keeping",
+                peer.counterparty_node_id, improvement, min_improvement_sats
+            );
+        } else if improvement > 0 {
             debug!(
                 "Judge: peer {} rate={:.6}, expected={}, actual={}, improvement={}msat",
                 peer.counterparty_node_id,
@@ -105,6 +112,11 @@ pub fn judge(
 
 /// Compute the weighted median of a set of (value, weight) pairs.
 /// The values must be sorted in ascending order.
+///
+/// When the cumulative weight lands exactly on half the total, the median
+/// lies between that value and the next one and the two are averaged, as
+/// with an ordinary median of an even count. Otherwise an even split would
+/// always pick the lower value and bias the judge towards closing.
 fn weighted_median(data: &[(f64, f64)]) -> f64 {
     if data.is_empty() {
         return 0.0;
@@ -116,9 +128,17 @@ fn weighted_median(data: &[(f64, f64)]) -> f64 {
     let total_weight: f64 = data.iter().map(|(_, w)| w).sum();
     let half = total_weight / 2.0;
 
+    // Summing weights in another order can be off in the last bits
+    let tolerance = total_weight * 1e-12;
+
     let mut cumulative = 0.0;
-    for &(value, weight) in data {
+    for (i, &(value, weight)) in data.iter().enumerate() {
         cumulative += weight;
+        if (cumulative - half).abs() <= tolerance {
+            if let Some(&(next, _)) = data.get(i + 1) {
+                return (value + next) / 2.0;
+            }
+        }
         if cumulative >= half {
             return value;
         }
@@ -147,6 +167,26 @@ mod tests {
         assert!((median - 1.0).abs() < 0.001);
     }
 
+    #[test]
+    fn test_weighted_median_even_count_interpolates() {
+        let data = vec![(1.0, 1.0), (2.0, 1.0), (3.0, 1.0), (4.0, 1.0)];
+        assert!((weighted_median(&data) - 2.5).abs() < 0.001);
+
+        let data = vec![(1.0, 5.0), (3.0, 5.0)];
+        assert!((weighted_median(&data) - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_weighted_median_boundary_with_unequal_weights() {
+        // Half the weight (3 of 6) sits at or below 2.0 exactly
+        let data = vec![(1.0, 1.0), (2.0, 2.0), (5.0, 3.0)];
+        assert!((weighted_median(&data) - 3.5).abs() < 0.001);
+
+        // Past the half-way point: no interpolation
+        let data = vec![(1.0, 1.0), (2.0, 3.0), (5.0, 3.0)];
+        assert!((weighted_median(&data) - 2.0).abs() < 0.001);
+    }
+
     #[test]
     fn test_judge_no_close_when_all_equal() {
         let peers = vec![
@@ -166,7 +206,7 @@ mod tests {
                 total_earned_msat: 10_000,
             },
         ];
-        let recs = judge(&peers, 5000);
+        let recs = judge(&peers, 5000, 0);
         assert!(recs.is_empty(), "Equal performers should not be closed");
     }
 
@@ -195,9 +235,13 @@ mod tests {
                 total_earned_msat: 0,
             },
         ];
-        let recs = judge(&peers, 50);
+        let recs = judge(&peers, 50, 0);
         assert!(!recs.is_empty(), "Zero-earning peer should be recommended for closure");
         assert_eq!(recs[0].counterparty_node_id, "bad");
+
+        // An improvement of 9950 sat clears a 9000 sat floor but not 10000
+        assert_eq!(judge(&peers, 50, 9_000).len(), 1);
+        assert!(judge(&peers, 50, 10_000).is_empty());
     }
 
     #[test]
@@ -220,7 +264,7 @@ mod tests {
             },
         ];
         // With very high reopen cost, no closure should be recommended
-        let recs = judge(&peers, 1_000_000);
+        let recs = judge(&peers, 1_000_000, 0);
         assert!(
             recs.is_empty(),
             "High reopen cost should prevent closures"
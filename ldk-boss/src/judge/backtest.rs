@@ -0,0 +1,202 @@
+/// Replay the judge over past days (`ldk-boss judge-backtest`), to see which
+/// peers it would have recommended closing before trusting it with real
+/// closures.
+///
+/// For each of the last `days` days, the channels open at the time are
+/// rebuilt from `channel_history` and judged by `gatherer`/`algo` on the
+/// earnings, uptime and disconnects recorded up to that day, never later
+/// ones. Nothing is closed or written. As in a live run, strategic channels
+/// and channels younger than `protect_until_days` are judged but can't be
+/// closed. Not simulated: probation (every day is judged on its own),
+/// drains and the per-cycle closure limit.
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::judge::algo;
+use crate::judge::gatherer::{self, PeerChannels};
+use crate::state;
+use ldk_server_protos::types::Channel;
+use std::collections::BTreeMap;
+
+/// What the judge would have said about one peer over the backtest.
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    pub counterparty_node_id: String,
+    /// Days on which closure would have been recommended
+    pub days_flagged: u32,
+    /// First and last of those days (unix seconds)
+    pub first_flagged_at: i64,
+    pub last_flagged_at: i64,
+    /// The recommendation on the last of them
+    pub reason: String,
+    pub expected_improvement_msat: i64,
+    /// Whether we still have an open channel with the peer
+    pub still_open: bool,
+}
+
+struct HistoricalChannel {
+    channel_id: String,
+    counterparty_node_id: String,
+    channel_value_sats: u64,
+    first_seen_at: f64,
+}
+
+/// Judge each of the last `days` days. Peers flagged most often come first.
+pub fn run(config: &Config, db: &Database, days: u64) -> anyhow::Result<Vec<Verdict>> {
+    let now = db.now().timestamp();
+    let mut verdicts: BTreeMap<String, Verdict> = BTreeMap::new();
+
+    for back in (0..days as i64).rev() {
+        let at = now - back * 86400;
+        let channels = open_at(db, at as f64)?;
+        let infos = gatherer::assess(config, db, &by_peer(&channels, at as f64), at as f64)?;
+        // The live judge doesn't evaluate fewer peers either
+        if infos.len() < 3 {
+            continue;
+        }
+
+        let recommendations = algo::judge(
+            &infos,
+            config.judge.estimated_reopen_cost_sats,
+            config.judge.min_improvement_sats,
+        );
+        for rec in recommendations {
+            if !closeable(config, &channels, &rec.counterparty_node_id, at as f64) {
+                continue;
+            }
+            let verdict = verdicts
+                .entry(rec.counterparty_node_id.clone())
+                .or_insert_with(|| Verdict {
+                    counterparty_node_id: rec.counterparty_node_id.clone(),
+                    days_flagged: 0,
+                    first_flagged_at: at,
+                    last_flagged_at: at,
+                    reason: String::new(),
+                    expected_improvement_msat: 0,
+                    still_open: false,
+                });
+            verdict.days_flagged += 1;
+            verdict.last_flagged_at = at;
+            verdict.reason = rec.reason;
+            verdict.expected_improvement_msat = rec.expected_improvement_msat;
+        }
+    }
+
+    let conn = db.conn()?;
+    let mut verdicts: Vec<Verdict> = verdicts.into_values().collect();
+    for v in &mut verdicts {
+        v.still_open = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM channel_history \
+             WHERE counterparty_node_id = ?1 AND is_open = 1)",
+            [&v.counterparty_node_id],
+            |row| row.get(0),
+        )?;
+    }
+    verdicts.sort_by(|a, b| {
+        b.days_flagged
+            .cmp(&a.days_flagged)
+            .then(b.expected_improvement_msat.cmp(&a.expected_improvement_msat))
+    });
+    Ok(verdicts)
+}
+
+/// Channels that were open at `at`: seen by then, and either still open or
+/// last seen no earlier.
+fn open_at(db: &Database, at: f64) -> anyhow::Result<Vec<HistoricalChannel>> {
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare(
+        "SELECT channel_id, counterparty_node_id, channel_value_sats, first_seen_at \
+         FROM channel_history WHERE first_seen_at <= ?1 AND (is_open = 1 OR last_seen_at >= ?1)",
+    )?;
+    let rows = stmt.query_map([at], |row| {
+        Ok(HistoricalChannel {
+            channel_id: row.get(0)?,
+            counterparty_node_id: row.get(1)?,
+            channel_value_sats: row.get(2)?,
+            first_seen_at: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn by_peer(channels: &[HistoricalChannel], at: f64) -> Vec<PeerChannels> {
+    let mut peers: BTreeMap<&str, PeerChannels> = BTreeMap::new();
+    for ch in channels {
+        let peer = peers
+            .entry(ch.counterparty_node_id.as_str())
+            .or_insert_with(|| PeerChannels {
+                counterparty_node_id: ch.counterparty_node_id.clone(),
+                total_channel_sats: 0,
+                oldest_age_days: 0.0,
+            });
+        peer.total_channel_sats += ch.channel_value_sats;
+        peer.oldest_age_days = peer.oldest_age_days.max((at - ch.first_seen_at) / 86400.0);
+    }
+    peers.into_values().collect()
+}
+
+/// Whether any of the peer's channels at `at` was neither strategic nor
+/// still protected, as the live judge requires before closing.
+fn closeable(config: &Config, channels: &[HistoricalChannel], peer: &str, at: f64) -> bool {
+    channels
+        .iter()
+        .filter(|ch| ch.counterparty_node_id == peer)
+        .any(|ch| {
+            let channel = Channel {
+                channel_id: ch.channel_id.clone(),
+                counterparty_node_id: ch.counterparty_node_id.clone(),
+                ..Default::default()
+            };
+            let age_days = (at - ch.first_seen_at) / 86400.0;
+            !state::is_strategic(config, &channel)
+                && age_days >= config.judge.protect_until_days as f64
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backtest_uses_only_past_data() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.earnings_attribution = "out".to_string();
+        let now = chrono::Utc::now().timestamp();
+        let yesterday = now - 86400 - (now - 86400) % 86400;
+
+        let earned = [("a", 10_000_000), ("b", 10_000_000), ("c", 10_000_000), ("lazy", 0)];
+        for (peer, earned_msat) in earned {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO channel_history (channel_id, user_channel_id, \
+                     counterparty_node_id, channel_value_sats, first_seen_at, last_seen_at, \
+                     is_open) VALUES (?1, ?1, ?1, 1000000, ?2, ?2, 1)",
+                    rusqlite::params![peer, (now - 200 * 86400) as f64],
+                )
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                     fee_earned_msat, amount_forwarded_msat, direction) \
+                     VALUES (?1, ?1, ?2, ?3, 0, 'out')",
+                    rusqlite::params![peer, yesterday, earned_msat],
+                )
+                .unwrap();
+        }
+
+        // Before yesterday nobody had earned anything, so nobody stood out
+        let verdicts = run(&config, &db, 5).unwrap();
+        assert_eq!(verdicts.len(), 1);
+        assert_eq!(verdicts[0].counterparty_node_id, "lazy");
+        assert_eq!(verdicts[0].days_flagged, 2);
+        assert_eq!(verdicts[0].expected_improvement_msat, 5_000_000);
+        assert!(verdicts[0].still_open);
+
+        // Judged, but never closeable
+        config.general.strategic_peers = vec!["lazy".to_string()];
+        assert!(run(&config, &db, 5).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+/// Drain before close: closing a channel that is mostly ours sends our side
+/// on-chain. With `drain_before_close`, the judge first prices the channel
+/// down by `drain_fee_multiplier` so forwards move our balance out through
+/// it, and closes it once our share is at most `drain_target_percent`, or
+/// `drain_timeout_days` after the drain started.
+///
+/// A drain ends with the close, or is dropped when the judge stops flagging
+/// the peer.
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::judge::algo::CloseRecommendation;
+use ldk_server_protos::types::Channel;
+use log::{debug, info};
+
+fn is_enabled(config: &Config) -> bool {
+    config.judge.enabled && config.judge.drain_before_close
+}
+
+/// Our share of the channel, in percent.
+fn outbound_percent(channel: &Channel) -> f64 {
+    if channel.channel_value_sats == 0 {
+        return 0.0;
+    }
+    channel.outbound_capacity_msat as f64 * 100.0 / (channel.channel_value_sats as f64 * 1000.0)
+}
+
+/// Whether the judge may close this channel now. Starts a drain if it
+/// still holds too much of our balance.
+pub fn ready_to_close(config: &Config, db: &Database, channel: &Channel) -> anyhow::Result<bool> {
+    if !is_enabled(config) {
+        return Ok(true);
+    }
+    let ours = outbound_percent(channel);
+    if ours <= config.judge.drain_target_percent {
+        return Ok(true);
+    }
+
+    let now = db.now().timestamp() as f64;
+    let started_at: Option<f64> = db
+        .conn()?
+        .query_row(
+            "SELECT started_at FROM judge_drains WHERE channel_id = ?1",
+            [&channel.channel_id],
+            |row| row.get(0),
+        )
+        .ok();
+    match started_at {
+        None => {
+            info!(
+                "Judge: draining {} ({:.0}% ours) before closing it",
+                channel.channel_id, ours
+            );
+            db.conn()?.execute(
+                "INSERT INTO judge_drains (channel_id, counterparty_node_id, started_at) \
+                 VALUES (?1, ?2, ?3)",
+                rusqlite::params![channel.channel_id, channel.counterparty_node_id, now],
+            )?;
+            Ok(false)
+        }
+        Some(started_at) if now - started_at >= config.judge.drain_timeout_days as f64 * 86400.0 => {
+            info!(
+                "Judge: drain of {} timed out at {:.0}% ours, closing anyway",
+                channel.channel_id, ours
+            );
+            Ok(true)
+        }
+        Some(_) => {
+            debug!("Judge: {} still draining ({:.0}% ours)", channel.channel_id, ours);
+            Ok(false)
+        }
+    }
+}
+
+/// Forget a drain once its channel is closed.
+pub fn finish(db: &Database, channel_id: &str) -> anyhow::Result<()> {
+    db.conn()?
+        .execute("DELETE FROM judge_drains WHERE channel_id = ?1", [channel_id])?;
+    Ok(())
+}
+
+/// Drop drains of peers the judge no longer flags.
+pub fn retain_flagged(db: &Database, flagged: &[&CloseRecommendation]) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare("SELECT channel_id, counterparty_node_id FROM judge_drains")?;
+    let drains: Vec<(String, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (channel_id, peer) in drains {
+        if !flagged.iter().any(|r| r.counterparty_node_id == peer) {
+            info!("Judge: peer {} no longer flagged, drain of {} stopped", peer, channel_id);
+            conn.execute("DELETE FROM judge_drains WHERE channel_id = ?1", [&channel_id])?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the judge is draining this channel before closing it.
+pub fn is_draining(config: &Config, db: &Database, channel_id: &str) -> bool {
+    is_enabled(config)
+        && db.conn().is_ok_and(|conn| {
+            conn.query_row(
+                "SELECT 1 FROM judge_drains WHERE channel_id = ?1",
+                [channel_id],
+                |_| Ok(()),
+            )
+            .is_ok()
+        })
+}
+
+/// Fee multiplier for a channel: `drain_fee_multiplier` while it is being
+/// drained, 1.0 otherwise.
+pub fn fee_multiplier(config: &Config, db: &Database, channel_id: &str) -> f64 {
+    if is_draining(config, db, channel_id) {
+        config.judge.drain_fee_multiplier
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(outbound_sats: u64) -> Channel {
+        Channel {
+            channel_id: "ch1".to_string(),
+            counterparty_node_id: "peer".to_string(),
+            channel_value_sats: 1_000_000,
+            outbound_capacity_msat: outbound_sats * 1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_drain_then_close() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.judge.enabled = true;
+        config.judge.drain_before_close = true;
+        config.judge.drain_target_percent = 20.0;
+        config.judge.drain_timeout_days = 7;
+
+        // Mostly ours: drain first, at a lower fee
+        assert!(!ready_to_close(&config, &db, &channel(900_000)).unwrap());
+        assert_eq!(fee_multiplier(&config, &db, "ch1"), config.judge.drain_fee_multiplier);
+        assert!(!ready_to_close(&config, &db, &channel(500_000)).unwrap());
+
+        // Drained enough
+        assert!(ready_to_close(&config, &db, &channel(150_000)).unwrap());
+
+        // Or out of time
+        clock.advance_secs(7 * 86400);
+        assert!(ready_to_close(&config, &db, &channel(500_000)).unwrap());
+
+        finish(&db, "ch1").unwrap();
+        assert_eq!(fee_multiplier(&config, &db, "ch1"), 1.0);
+    }
+
+    #[test]
+    fn test_drain_dropped_when_peer_recovers() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.judge.enabled = true;
+        config.judge.drain_before_close = true;
+
+        assert!(!ready_to_close(&config, &db, &channel(900_000)).unwrap());
+        retain_flagged(&db, &[]).unwrap();
+        assert_eq!(fee_multiplier(&config, &db, "ch1"), 1.0);
+    }
+}
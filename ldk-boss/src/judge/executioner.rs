@@ -1,34 +1,46 @@
+use crate::audit;
 use crate::client::LdkClient;
 use crate::config::Config;
 use crate::db::Database;
 use crate::judge::algo::CloseRecommendation;
 use crate::state::NodeState;
 use ldk_server_protos::api::{CloseChannelRequest, ForceCloseChannelRequest};
-use log::{error, info};
+use ldk_server_protos::types::Channel;
+use log::{error, info, warn};
+use serde_json::json;
+
+/// A channel gets at most one force-close fallback per this many seconds,
+/// so a peer that keeps failing doesn't get a force close every cycle.
+const FALLBACK_RETRY_SECS: i64 = 86400;
 
 /// Execute a channel closure based on judge recommendation.
 ///
-/// Safety: Only closes ONE channel per cycle (hard limit).
+/// Closes at most one channel with the recommended peer, its smallest; how
+/// many recommendations are executed per cycle is up to the caller
+/// (`judge.max_closures_per_cycle`, 1 in safe mode). Returns whether a
+/// channel was closed.
 pub async fn execute_closure(
     config: &Config,
     client: &(impl LdkClient + Sync),
     db: &Database,
     state: &NodeState,
     recommendation: &CloseRecommendation,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<bool> {
     // Find the channel(s) with this peer
     let peer_channels: Vec<_> = state
         .channels
         .iter()
-        .filter(|c| c.counterparty_node_id == recommendation.counterparty_node_id && c.is_usable)
+        .filter(|c| c.counterparty_node_id == recommendation.counterparty_node_id)
+        .filter(|c| state.is_automated(config, c))
+        .filter(|c| !super::is_close_protected(config, db, &c.channel_id))
         .collect();
 
     if peer_channels.is_empty() {
         info!(
-            "Judge: peer {} has no usable channels to close",
+            "Judge: peer {} has no usable, unprotected channels to close",
             recommendation.counterparty_node_id
         );
-        return Ok(());
+        return Ok(false);
     }
 
     // Close the smallest channel with this peer first
@@ -37,20 +49,32 @@ pub async fn execute_closure(
         .min_by_key(|c| c.channel_value_sats)
         .unwrap();
 
+    if !config.general.dry_run && !super::drain::ready_to_close(config, db, channel)? {
+        return Ok(false);
+    }
+
+    // Labeled peers are easier to recognise in the logs
+    let peer = crate::labels::describe(db, &recommendation.counterparty_node_id);
     info!(
         "Judge: closing channel {} with peer {} ({} sat) -- {}",
         channel.channel_id,
-        recommendation.counterparty_node_id,
+        peer,
         channel.channel_value_sats,
         recommendation.reason,
     );
 
     if config.general.dry_run {
         info!("  (dry-run: not executing)");
-        return Ok(());
+        return Ok(false);
     }
 
-    let result = if config.judge.cooperative_close {
+    let cooperative = config.judge.cooperative_close || config.general.safe_mode;
+    if cooperative && !config.judge.cooperative_close {
+        info!("Judge: safe mode, closing cooperatively instead of force-closing");
+    }
+
+    let mut cooperative = cooperative;
+    let mut result = if cooperative {
         client
             .close_channel(CloseChannelRequest {
                 user_channel_id: channel.user_channel_id.clone(),
@@ -59,26 +83,52 @@ pub async fn execute_closure(
             .await
             .map(|_| ())
     } else {
-        client
-            .force_close_channel(ForceCloseChannelRequest {
-                user_channel_id: channel.user_channel_id.clone(),
-                counterparty_node_id: channel.counterparty_node_id.clone(),
-                force_close_reason: Some(recommendation.reason.clone()),
-            })
-            .await
-            .map(|_| ())
+        force_close(client, channel, &recommendation.reason).await
     };
 
+    // A coop close with an offline peer can't succeed; a force close is then
+    // the only way out. Any other failure is left for the next cycle
+    if let Err(e) = &result {
+        if cooperative && config.judge.force_close_on_coop_failure && !config.general.safe_mode {
+            warn!(
+                "Judge: cooperative close of {} with {} failed: {}",
+                channel.channel_id, peer, e
+            );
+            let blocked = if peer_offline(client, channel, e).await {
+                fallback_blocked(config, db, state, &channel.channel_id)?
+            } else {
+                Some("the peer is online, so the failure isn't one a force close fixes".to_string())
+            };
+            match blocked {
+                Some(why) => info!("Judge: not force-closing {}: {}", channel.channel_id, why),
+                None => {
+                    info!("Judge: force-closing {} instead", channel.channel_id);
+                    db.conn()?.execute(
+                        "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+                        rusqlite::params![
+                            fallback_key(&channel.channel_id),
+                            db.now().timestamp().to_string()
+                        ],
+                    )?;
+                    cooperative = false;
+                    result = force_close(client, channel, &recommendation.reason).await;
+                }
+            }
+        }
+    }
+
     match result {
         Ok(()) => {
             info!(
                 "Judge: successfully closed channel {} with {}",
-                channel.channel_id, recommendation.counterparty_node_id
+                channel.channel_id, peer
             );
 
+            super::drain::finish(db, &channel.channel_id)?;
+
             // Record in audit trail
-            let now = chrono::Utc::now().timestamp() as f64;
-            db.conn().execute(
+            let now = db.now().timestamp() as f64;
+            db.conn()?.execute(
                 "INSERT INTO judge_closures \
                  (channel_id, counterparty_node_id, closed_at, reason) \
                  VALUES (?1, ?2, ?3, ?4)",
@@ -89,14 +139,225 @@ pub async fn execute_closure(
                     recommendation.reason,
                 ],
             )?;
+            audit::emit(
+                config,
+                "close",
+                json!({
+                    "channel_id": channel.channel_id,
+                    "counterparty_node_id": recommendation.counterparty_node_id,
+                    "reason": recommendation.reason,
+                    "cooperative": cooperative,
+                }),
+            );
+            Ok(true)
         }
         Err(e) => {
             error!(
                 "Judge: failed to close channel {} with {}: {}",
-                channel.channel_id, recommendation.counterparty_node_id, e
+                channel.channel_id, peer, e
             );
+            Ok(false)
+        }
+    }
+}
+
+async fn force_close(
+    client: &(impl LdkClient + Sync),
+    channel: &Channel,
+    reason: &str,
+) -> anyhow::Result<()> {
+    client
+        .force_close_channel(ForceCloseChannelRequest {
+            user_channel_id: channel.user_channel_id.clone(),
+            counterparty_node_id: channel.counterparty_node_id.clone(),
+            force_close_reason: Some(reason.to_string()),
+        })
+        .await
+        .map(|_| ())
+}
+
+/// `run_state` key prefix recording a channel's last force-close fallback.
+/// `tracker::cleanup` prunes the keys of channels that are gone.
+pub const FALLBACK_KEY_PREFIX: &str = "force_close_fallback:";
+
+fn fallback_key(channel_id: &str) -> String {
+    format!("{}{}", FALLBACK_KEY_PREFIX, channel_id)
+}
+
+/// Whether a failed cooperative close is down to the peer being offline:
+/// the channel wasn't usable, the error says the peer is disconnected, or
+/// ListPeers doesn't show it connected. RPC timeouts, rate limits and other
+/// server errors say nothing about the peer, and if ListPeers fails too
+/// nothing is assumed.
+async fn peer_offline(
+    client: &(impl LdkClient + Sync),
+    channel: &Channel,
+    error: &anyhow::Error,
+) -> bool {
+    if !channel.is_usable {
+        return true;
+    }
+    let message = format!("{:#}", error).to_lowercase();
+    if message.contains("disconnected") || message.contains("not connected") {
+        return true;
+    }
+    match client.list_peers().await {
+        Ok(resp) => !resp
+            .peers
+            .iter()
+            .any(|p| p.node_id == channel.counterparty_node_id && p.is_connected),
+        Err(_) => false,
+    }
+}
+
+/// Why a failed coop close must not fall back to a force close, if it
+/// mustn't: one was already tried recently, or the on-chain balance is
+/// below the reserve that pays for fee-bumping the commitment transaction.
+fn fallback_blocked(
+    config: &Config,
+    db: &Database,
+    state: &NodeState,
+    channel_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let last_attempt = db
+        .conn()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [fallback_key(channel_id)],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok());
+    if let Some(at) = last_attempt {
+        if db.now().timestamp() - at < FALLBACK_RETRY_SECS {
+            return Ok(Some("a force close was already tried in the last day".to_string()));
         }
     }
 
-    Ok(())
+    let reserve = crate::autopilot::decider::reserve_sats(config, state.total_funds_sats());
+    let onchain = state.balances.spendable_onchain_balance_sats;
+    if onchain < reserve {
+        return Ok(Some(format!(
+            "on-chain balance {} sat is below the {} sat reserve",
+            onchain, reserve
+        )));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockLdkClient;
+    use ldk_server_protos::api::{GetBalancesResponse, GetNodeInfoResponse};
+
+    #[tokio::test]
+    async fn test_failed_close_writes_no_audit_row() {
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let db = Database::open_in_memory().unwrap();
+        let state = NodeState {
+            node_info: GetNodeInfoResponse::default(),
+            balances: GetBalancesResponse::default(),
+            channels: vec![Channel {
+                channel_id: "ch1".to_string(),
+                user_channel_id: "user_ch1".to_string(),
+                counterparty_node_id: "bad_peer".to_string(),
+                channel_value_sats: 1_000_000,
+                is_usable: true,
+                ..Default::default()
+            }],
+            closing: Default::default(),
+        };
+        let rec = CloseRecommendation {
+            counterparty_node_id: "bad_peer".to_string(),
+            reason: "underperforming".to_string(),
+            expected_improvement_msat: 0,
+        };
+        let closures = || -> i64 {
+            db.conn()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM judge_closures", [], |r| r.get(0))
+                .unwrap()
+        };
+
+        let mock = MockLdkClient::new();
+        mock.fail_next("close_channel", 1);
+        mock.fail_next("force_close_channel", 1);
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(closures(), 0);
+
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(closures(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_coop_failure_falls_back_to_force_close_once() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.judge.force_close_on_coop_failure = true;
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        let mut state = NodeState {
+            node_info: GetNodeInfoResponse::default(),
+            balances: GetBalancesResponse::default(),
+            channels: vec![Channel {
+                channel_id: "ch1".to_string(),
+                user_channel_id: "user_ch1".to_string(),
+                counterparty_node_id: "offline_peer".to_string(),
+                channel_value_sats: 1_000_000,
+                is_usable: true,
+                ..Default::default()
+            }],
+            closing: Default::default(),
+        };
+        let rec = CloseRecommendation {
+            counterparty_node_id: "offline_peer".to_string(),
+            reason: "underperforming".to_string(),
+            expected_improvement_msat: 0,
+        };
+
+        // Nothing on-chain to fee-bump with: no fallback
+        let mock = MockLdkClient::new();
+        mock.fail_always("close_channel");
+        mock.fail_always("force_close_channel");
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(mock.call_count("force_close_channel"), 0);
+
+        // Fallback is tried, fails, and isn't retried the next cycle
+        state.balances.spendable_onchain_balance_sats = 1_000_000;
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(mock.call_count("close_channel"), 3);
+        assert_eq!(mock.call_count("force_close_channel"), 1);
+
+        // ...until a day has passed
+        clock.advance_secs(FALLBACK_RETRY_SECS);
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(mock.call_count("force_close_channel"), 2);
+
+        // A connected peer: the failure is something else, no force close
+        let db = Database::open_in_memory().unwrap();
+        let mut mock = MockLdkClient::new();
+        mock.peers.peers = vec![ldk_server_protos::types::Peer {
+            node_id: "offline_peer".to_string(),
+            is_connected: true,
+            ..Default::default()
+        }];
+        mock.fail_always("close_channel");
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(mock.call_count("force_close_channel"), 0);
+
+        // A working force close is recorded as a closure
+        let db = Database::open_in_memory().unwrap();
+        let mock = MockLdkClient::new();
+        mock.fail_always("close_channel");
+        execute_closure(&config, &mock, &db, &state, &rec).await.unwrap();
+        assert_eq!(mock.force_close_calls.lock().unwrap().len(), 1);
+        let closures: i64 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM judge_closures", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(closures, 1);
+    }
 }
@@ -2,27 +2,38 @@ use crate::config::Config;
 use crate::db::Database;
 use crate::judge::algo::PeerInfo;
 use crate::state::NodeState;
-use crate::tracker::{channels as channel_tracker, earnings as earnings_tracker};
+use crate::tracker::{
+    channels as channel_tracker, earnings as earnings_tracker, uptime as uptime_tracker,
+};
 use log::debug;
 
+/// A peer's channels as the judge sees them at some point in time.
+pub struct PeerChannels {
+    pub counterparty_node_id: String,
+    /// Capacity of the channels being judged
+    pub total_channel_sats: u64,
+    /// Age of the oldest of them
+    pub oldest_age_days: f64,
+}
+
 /// Gather peer performance data for the judge algorithm.
 ///
-/// Only includes peers whose channels are old enough (min_age_days).
+/// Only includes peers whose channels are old enough (min_age_days) and,
+/// where connectivity was observed, that were online at least
+/// `min_uptime_percent` of the evaluation window.
+/// With `instability_weight` set, each disconnect in the window lowers the
+/// peer's earnings as if it earned that much less per sat of capacity.
 pub fn gather(
     config: &Config,
     db: &Database,
     state: &NodeState,
 ) -> anyhow::Result<Vec<PeerInfo>> {
-    let min_age = config.judge.min_age_days as f64;
-    let eval_window = config.judge.evaluation_window_days;
-    let since = chrono::Utc::now().timestamp() as f64 - (eval_window as f64 * 86400.0);
-
     let peers_channels = state.channels_by_peer();
-    let mut infos = Vec::new();
+    let mut peers = Vec::new();
 
     for (peer_id, channels) in &peers_channels {
-        // Only consider usable channels
-        let usable: Vec<_> = channels.iter().filter(|c| c.is_usable).collect();
+        // Only consider usable channels that aren't already closing
+        let usable: Vec<_> = channels.iter().filter(|c| state.is_active(c)).collect();
         if usable.is_empty() {
             continue;
         }
@@ -37,20 +48,78 @@ pub fn gather(
             }
         }
 
-        if oldest_age < min_age {
+        peers.push(PeerChannels {
+            counterparty_node_id: peer_id.to_string(),
+            total_channel_sats: usable.iter().map(|c| c.channel_value_sats).sum(),
+            oldest_age_days: oldest_age,
+        });
+    }
+
+    let infos = assess(config, db, &peers, db.now().timestamp() as f64)?;
+    debug!("Judge gatherer: {} peers eligible for evaluation", infos.len());
+
+    Ok(infos)
+}
+
+/// Apply `gather`'s age, uptime and instability rules to `peers`, with the
+/// evaluation window ending at `at` (unix seconds). Earnings, uptime and
+/// disconnects after `at`'s day are ignored, so this can look back.
+pub fn assess(
+    config: &Config,
+    db: &Database,
+    peers: &[PeerChannels],
+    at: f64,
+) -> anyhow::Result<Vec<PeerInfo>> {
+    let min_age = config.judge.min_age_days as f64;
+    let eval_window = config.judge.evaluation_window_days;
+    let since = at - (eval_window as f64 * 86400.0);
+
+    let mut infos = Vec::new();
+
+    for peer in peers {
+        let peer_id = &peer.counterparty_node_id;
+        if peer.oldest_age_days < min_age {
             debug!(
                 "Judge gatherer: peer {} channel age {:.0} days < min {} days, skipping",
-                peer_id, oldest_age, min_age
+                peer_id, peer.oldest_age_days, min_age
             );
             continue;
         }
 
-        // Sum channel capacity
-        let total_sats: u64 = usable.iter().map(|c| c.channel_value_sats).sum();
+        // Don't judge earnings a peer had no chance to make
+        if config.judge.min_uptime_percent > 0.0 {
+            if let Some(uptime) = uptime_tracker::uptime_percent_between(db, peer_id, since, at)? {
+                if uptime < config.judge.min_uptime_percent {
+                    debug!(
+                        "Judge gatherer: peer {} online {:.0}% of the window < min {}%, skipping",
+                        peer_id, uptime, config.judge.min_uptime_percent
+                    );
+                    continue;
+                }
+            }
+        }
+
+        let total_sats = peer.total_channel_sats;
 
         // Get earnings in evaluation window
-        let peer_earnings = earnings_tracker::peer_earnings_since(db, peer_id, since)?;
-        let total_earned = peer_earnings.total_net();
+        let peer_earnings = earnings_tracker::peer_earnings_between(db, peer_id, since, at)?;
+        let mut total_earned =
+            peer_earnings.attributed_net(&config.general.earnings_attribution);
+
+        // A flapping peer is a poor routing partner whatever it earned
+        if config.judge.instability_weight > 0.0 {
+            let disconnects = uptime_tracker::disconnects_between(db, peer_id, since, at)?;
+            let penalty_msat = (config.judge.instability_weight
+                * disconnects as f64
+                * total_sats as f64) as i64;
+            if penalty_msat > 0 {
+                debug!(
+                    "Judge gatherer: peer {} disconnected {} times, {} msat penalty",
+                    peer_id, disconnects, penalty_msat
+                );
+                total_earned -= penalty_msat;
+            }
+        }
 
         infos.push(PeerInfo {
             counterparty_node_id: peer_id.to_string(),
@@ -59,7 +128,5 @@ pub fn gather(
         });
     }
 
-    debug!("Judge gatherer: {} peers eligible for evaluation", infos.len());
-
     Ok(infos)
 }
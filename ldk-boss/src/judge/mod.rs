@@ -1,12 +1,31 @@
 pub mod algo;
+pub mod backtest;
+pub mod drain;
 pub mod executioner;
 pub mod gatherer;
+pub mod panic;
+pub mod probation;
+pub mod redundant;
 
 use crate::client::LdkClient;
 use crate::config::Config;
+use crate::cycle_summary::CycleSummary;
 use crate::db::Database;
 use crate::state::NodeState;
-use log::{debug, info};
+use crate::tracker::channels as channel_tracker;
+use log::{debug, info, warn};
+
+/// Whether a channel is still too young to be closed by the judge
+/// (`protect_until_days`). Channels we have no history for are not protected.
+pub fn is_close_protected(config: &Config, db: &Database, channel_id: &str) -> bool {
+    if config.judge.protect_until_days == 0 {
+        return false;
+    }
+    match channel_tracker::channel_age_days(db, channel_id) {
+        Ok(Some(age_days)) => age_days < config.judge.protect_until_days as f64,
+        _ => false,
+    }
+}
 
 /// Run the peer judge: evaluate channel performance and close underperformers.
 pub async fn run(
@@ -14,6 +33,7 @@ pub async fn run(
     client: &(impl LdkClient + Sync),
     db: &Database,
     state: &NodeState,
+    summary: &mut CycleSummary,
 ) -> anyhow::Result<()> {
     // Gather data for all peers with channels
     let peer_infos = gatherer::gather(config, db, state)?;
@@ -27,22 +47,96 @@ pub async fn run(
     let recommendations = algo::judge(
         &peer_infos,
         config.judge.estimated_reopen_cost_sats,
+        config.judge.min_improvement_sats,
     );
 
-    if recommendations.is_empty() {
+    // Runs even with no recommendations, so recovered peers leave probation
+    let mut to_close = probation::review(config, db, &recommendations)?;
+    // Evaluable isn't closeable: skip peers whose channels are all still protected
+    to_close.retain(|r| {
+        let closeable = state.channels.iter().any(|c| {
+            c.counterparty_node_id == r.counterparty_node_id
+                && state.is_automated(config, c)
+                && !is_close_protected(config, db, &c.channel_id)
+        });
+        if !closeable {
+            debug!(
+                "Judge: peer {} flagged, but its channels are strategic or younger than {} days",
+                r.counterparty_node_id, config.judge.protect_until_days
+            );
+        }
+        closeable
+    });
+    if config.judge.prefer_redundant_closes {
+        redundant::prefer_redundant(&mut to_close, state);
+    }
+    drain::retain_flagged(db, &to_close)?;
+
+    if to_close.is_empty() {
         debug!("Judge: no channels recommended for closure");
         return Ok(());
     }
 
     info!(
         "Judge: {} channels recommended for closure",
-        recommendations.len()
+        to_close.len()
     );
 
-    // Execute at most 1 closure per cycle (safety rail)
-    if let Some(first) = recommendations.first() {
-        executioner::execute_closure(config, client, db, state, first).await?;
+    // Execute at most max_closures_per_cycle closures (safety rail)
+    let limit = if config.general.safe_mode {
+        1
+    } else {
+        config.judge.max_closures_per_cycle
+    };
+    if limit > 1 {
+        warn!(
+            "Judge: max_closures_per_cycle = {}, closing up to {} channels this cycle",
+            limit,
+            limit.min(to_close.len())
+        );
+    }
+    // A failure with one peer doesn't hold up closing the others
+    for rec in to_close.iter().take(limit) {
+        match executioner::execute_closure(config, client, db, state, rec).await {
+            Ok(true) => summary.closed += 1,
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Judge: closing a channel with {} failed: {:#}",
+                rec.counterparty_node_id, e
+            ),
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_protection_window() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.judge.protect_until_days = 90;
+
+        let now = chrono::Utc::now().timestamp() as f64;
+        for (id, age_days) in [("young", 45.0), ("old", 120.0)] {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO channel_history (channel_id, user_channel_id, \
+                     counterparty_node_id, channel_value_sats, first_seen_at, last_seen_at, \
+                     is_open) VALUES (?1, 'u', 'peer', 1000000, ?2, ?2, 1)",
+                    rusqlite::params![id, now - age_days * 86400.0],
+                )
+                .unwrap();
+        }
+
+        assert!(is_close_protected(&config, &db, "young"));
+        assert!(!is_close_protected(&config, &db, "old"));
+
+        config.judge.protect_until_days = 0;
+        assert!(!is_close_protected(&config, &db, "young"));
+    }
+}
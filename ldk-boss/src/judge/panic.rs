@@ -0,0 +1,241 @@
+/// Panic close: force-close every remaining channel with a peer that tried
+/// to cheat us.
+///
+/// LDK Server doesn't surface peer-misbehavior events directly, but a
+/// `CounterpartyRevokedOutputClaimable` balance means the counterparty
+/// broadcast a revoked commitment transaction. That is unambiguous, so
+/// this bypasses the earnings-based judge entirely and runs every cycle.
+
+use crate::audit;
+use crate::client::LdkClient;
+use crate::config::Config;
+use crate::db::Database;
+use crate::state::NodeState;
+use ldk_server_protos::api::{ForceCloseChannelRequest, GetBalancesResponse};
+use ldk_server_protos::types::lightning_balance::BalanceType;
+use log::{error, info, warn};
+use serde_json::json;
+use std::collections::HashMap;
+
+pub const REASON: &str = "peer_misbehavior";
+
+/// Peers that broadcast a revoked state, with the total revoked-output
+/// amount we can claim from them (satoshis). Channels are mapped to peers
+/// through `channel_history`, since the channel is already closed.
+pub fn misbehaving_peers(
+    db: &Database,
+    balances: &GetBalancesResponse,
+) -> anyhow::Result<HashMap<String, u64>> {
+    let conn = db.conn()?;
+    let mut peers: HashMap<String, u64> = HashMap::new();
+
+    for balance in &balances.lightning_balances {
+        let Some(BalanceType::CounterpartyRevokedOutputClaimable(b)) = &balance.balance_type else {
+            continue;
+        };
+
+        let peer: Option<String> = conn
+            .query_row(
+                "SELECT counterparty_node_id FROM channel_history WHERE channel_id = ?1",
+                [&b.channel_id],
+                |row| row.get(0),
+            )
+            .ok();
+
+        match peer {
+            Some(peer) => *peers.entry(peer).or_insert(0) += b.amount_satoshis,
+            None => warn!(
+                "Panic close: revoked state broadcast on unknown channel {}",
+                b.channel_id
+            ),
+        }
+    }
+
+    Ok(peers)
+}
+
+/// Force-close all remaining channels with misbehaving peers.
+pub async fn run(
+    config: &Config,
+    client: &(impl LdkClient + Sync),
+    db: &Database,
+    state: &NodeState,
+) -> anyhow::Result<()> {
+    let peers = misbehaving_peers(db, &state.balances)?;
+
+    for (peer, revoked_sats) in &peers {
+        if *revoked_sats < config.judge.panic_close_min_revoked_sats {
+            continue;
+        }
+
+        let channels: Vec<_> = state
+            .channels
+            .iter()
+            .filter(|c| &c.counterparty_node_id == peer)
+            .collect();
+        if channels.is_empty() {
+            continue;
+        }
+
+        warn!(
+            "Panic close: {} broadcast a revoked state ({} sat claimable), \
+             force-closing {} remaining channel(s)",
+            peer,
+            revoked_sats,
+            channels.len()
+        );
+
+        if config.general.dry_run {
+            info!("  (dry-run: not executing)");
+            continue;
+        }
+        if config.general.safe_mode {
+            warn!("  (safe mode: force closes disabled, close these manually)");
+            continue;
+        }
+
+        for channel in channels {
+            let result = client
+                .force_close_channel(ForceCloseChannelRequest {
+                    user_channel_id: channel.user_channel_id.clone(),
+                    counterparty_node_id: channel.counterparty_node_id.clone(),
+                    force_close_reason: Some(REASON.to_string()),
+                })
+                .await;
+
+            match result {
+                Ok(_) => {
+                    let now = db.now().timestamp() as f64;
+                    db.conn()?.execute(
+                        "INSERT INTO judge_closures \
+                         (channel_id, counterparty_node_id, closed_at, reason) \
+                         VALUES (?1, ?2, ?3, ?4)",
+                        rusqlite::params![channel.channel_id, peer, now, REASON],
+                    )?;
+                    audit::emit(
+                        config,
+                        "close",
+                        json!({
+                            "channel_id": channel.channel_id,
+                            "counterparty_node_id": peer,
+                            "reason": REASON,
+                            "cooperative": false,
+                        }),
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "Panic close: failed to force-close {} with {}: {}",
+                        channel.channel_id, peer, e
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockLdkClient;
+    use ldk_server_protos::types::{Channel, CounterpartyRevokedOutputClaimable, LightningBalance};
+
+    fn insert_history(db: &Database, channel_id: &str, peer: &str, is_open: bool) {
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES (?1, 'u', ?2, 1000000, 0, 0, ?3)",
+                rusqlite::params![channel_id, peer, is_open],
+            )
+            .unwrap();
+    }
+
+    fn revoked_balance(channel_id: &str, amount_satoshis: u64) -> LightningBalance {
+        LightningBalance {
+            balance_type: Some(BalanceType::CounterpartyRevokedOutputClaimable(
+                CounterpartyRevokedOutputClaimable {
+                    channel_id: channel_id.to_string(),
+                    amount_satoshis,
+                    ..Default::default()
+                },
+            )),
+        }
+    }
+
+    fn make_channel(id: &str, peer: &str) -> Channel {
+        Channel {
+            channel_id: id.to_string(),
+            counterparty_node_id: peer.to_string(),
+            user_channel_id: format!("user_{}", id),
+            channel_value_sats: 1_000_000,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panic_close_force_closes_remaining_channels() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.dry_run = false;
+        insert_history(&db, "cheated", "bad_peer", false);
+
+        let mut mock = MockLdkClient::new();
+        mock.balances.lightning_balances = vec![revoked_balance("cheated", 50_000)];
+        mock.channels.channels = vec![
+            make_channel("other", "bad_peer"),
+            make_channel("fine", "good_peer"),
+        ];
+        let state = NodeState::collect(&mock, &db).await.unwrap();
+
+        run(&config, &mock, &db, &state).await.unwrap();
+
+        let calls = mock.force_close_calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].user_channel_id, "user_other");
+
+        let reason: String = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT reason FROM judge_closures", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(reason, REASON);
+    }
+
+    #[tokio::test]
+    async fn test_panic_close_respects_threshold() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.dry_run = false;
+        config.judge.panic_close_min_revoked_sats = 100_000;
+        insert_history(&db, "cheated", "bad_peer", false);
+
+        let mut mock = MockLdkClient::new();
+        mock.balances.lightning_balances = vec![revoked_balance("cheated", 50_000)];
+        mock.channels.channels = vec![make_channel("other", "bad_peer")];
+        let state = NodeState::collect(&mock, &db).await.unwrap();
+
+        run(&config, &mock, &db, &state).await.unwrap();
+        assert!(mock.force_close_calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_panic_close_disabled_in_safe_mode() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.dry_run = false;
+        config.general.safe_mode = true;
+        insert_history(&db, "cheated", "bad_peer", false);
+
+        let mut mock = MockLdkClient::new();
+        mock.balances.lightning_balances = vec![revoked_balance("cheated", 50_000)];
+        mock.channels.channels = vec![make_channel("other", "bad_peer")];
+        let state = NodeState::collect(&mock, &db).await.unwrap();
+
+        run(&config, &mock, &db, &state).await.unwrap();
+        assert!(mock.force_close_calls.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+/// Judge probation: a flagged peer gets more evaluation windows, with
+/// adjusted fees, before its channels are closed.
+///
+/// A peer is placed on probation the first time the judge flags it. If it is
+/// still flagged once `probation_windows` evaluation windows have passed, it
+/// is closed; if it drops off the list at any point, probation is lifted.
+/// With `probation_windows = 0` every flagged peer is closed right away.
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::judge::algo::CloseRecommendation;
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+
+/// Update probation state from this round's recommendations and return
+/// those whose probation has run out, in their original order.
+pub fn review<'a>(
+    config: &Config,
+    db: &Database,
+    recommendations: &'a [CloseRecommendation],
+) -> anyhow::Result<Vec<&'a CloseRecommendation>> {
+    if config.judge.probation_windows == 0 {
+        return Ok(recommendations.iter().collect());
+    }
+
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
+    let period_secs = config.judge.probation_windows as f64
+        * config.judge.evaluation_window_days as f64
+        * 86400.0;
+
+    let on_probation: HashMap<String, f64> = {
+        let mut stmt =
+            conn.prepare("SELECT counterparty_node_id, started_at FROM judge_probation")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    // Probation needs consecutive flags: anyone not flagged this time is cleared
+    let flagged: HashSet<&str> = recommendations
+        .iter()
+        .map(|r| r.counterparty_node_id.as_str())
+        .collect();
+    for peer in on_probation.keys() {
+        if !flagged.contains(peer.as_str()) {
+            info!("Judge: peer {} no longer flagged, probation lifted", peer);
+            conn.execute(
+                "DELETE FROM judge_probation WHERE counterparty_node_id = ?1",
+                [peer],
+            )?;
+        }
+    }
+
+    let mut to_close = Vec::new();
+    for rec in recommendations {
+        match on_probation.get(&rec.counterparty_node_id) {
+            None => {
+                info!(
+                    "Judge: peer {} placed on probation for {} window(s) -- {}",
+                    rec.counterparty_node_id, config.judge.probation_windows, rec.reason
+                );
+                conn.execute(
+                    "INSERT INTO judge_probation (counterparty_node_id, started_at, reason) \
+                     VALUES (?1, ?2, ?3)",
+                    rusqlite::params![rec.counterparty_node_id, now, rec.reason],
+                )?;
+            }
+            Some(started_at) if now - started_at >= period_secs => to_close.push(rec),
+            Some(started_at) => {
+                debug!(
+                    "Judge: peer {} on probation, {:.1} days left",
+                    rec.counterparty_node_id,
+                    (period_secs - (now - started_at)) / 86400.0
+                );
+            }
+        }
+    }
+
+    Ok(to_close)
+}
+
+/// Fee multiplier for a peer: `probation_fee_multiplier` while on probation,
+/// 1.0 otherwise.
+pub fn fee_multiplier(config: &Config, db: &Database, counterparty_node_id: &str) -> f64 {
+    if config.judge.probation_windows == 0 {
+        return 1.0;
+    }
+    let on_probation = db.conn().is_ok_and(|conn| {
+        conn.query_row(
+            "SELECT 1 FROM judge_probation WHERE counterparty_node_id = ?1",
+            [counterparty_node_id],
+            |_| Ok(()),
+        )
+        .is_ok()
+    });
+    if on_probation {
+        config.judge.probation_fee_multiplier
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(peer: &str) -> CloseRecommendation {
+        CloseRecommendation {
+            counterparty_node_id: peer.to_string(),
+            reason: "underperforming".to_string(),
+            expected_improvement_msat: 1000,
+        }
+    }
+
+    fn test_config() -> Config {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.judge.probation_windows = 1;
+        config
+    }
+
+    fn backdate(db: &Database, peer: &str, days: f64) {
+        let started = chrono::Utc::now().timestamp() as f64 - days * 86400.0;
+        db.conn()
+            .unwrap()
+            .execute(
+                "UPDATE judge_probation SET started_at = ?1 WHERE counterparty_node_id = ?2",
+                rusqlite::params![started, peer],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_first_flag_only_starts_probation() {
+        let db = Database::open_in_memory().unwrap();
+        let config = test_config();
+        let recs = vec![rec("slow")];
+
+        assert!(review(&config, &db, &recs).unwrap().is_empty());
+        assert_eq!(fee_multiplier(&config, &db, "slow"), config.judge.probation_fee_multiplier);
+        assert_eq!(fee_multiplier(&config, &db, "other"), 1.0);
+
+        // Still inside the window: not closed yet
+        assert!(review(&config, &db, &recs).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_closes_after_probation_expires() {
+        let db = Database::open_in_memory().unwrap();
+        let config = test_config();
+        let recs = vec![rec("slow")];
+
+        review(&config, &db, &recs).unwrap();
+        backdate(&db, "slow", config.judge.evaluation_window_days as f64 + 1.0);
+
+        let to_close = review(&config, &db, &recs).unwrap();
+        assert_eq!(to_close.len(), 1);
+        assert_eq!(to_close[0].counterparty_node_id, "slow");
+    }
+
+    #[test]
+    fn test_unflagged_peer_leaves_probation() {
+        let db = Database::open_in_memory().unwrap();
+        let config = test_config();
+
+        review(&config, &db, &[rec("slow")]).unwrap();
+        backdate(&db, "slow", config.judge.evaluation_window_days as f64 + 1.0);
+
+        // Recovered for one round, so the next flag starts over
+        review(&config, &db, &[]).unwrap();
+        assert!(review(&config, &db, &[rec("slow")]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_zero_windows_closes_immediately() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.judge.probation_windows = 0;
+
+        assert_eq!(review(&config, &db, &[rec("slow")]).unwrap().len(), 1);
+    }
+}
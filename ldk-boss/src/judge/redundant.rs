@@ -0,0 +1,114 @@
+/// Redundant channels: more than one channel to the same counterparty.
+///
+/// Fees are set per channel and the judge sums a peer's channels, so extra
+/// channels to one peer are rarely intentional. They are reported every
+/// cycle, and the judge can be told to close them before anything else.
+
+use crate::config::Config;
+use crate::judge::algo::CloseRecommendation;
+use crate::state::NodeState;
+use log::warn;
+
+/// Peers with more than `general.max_channels_per_peer` channels, with
+/// their channel count, sorted by peer. Empty when the limit is 0.
+pub fn over_limit(config: &Config, state: &NodeState) -> Vec<(String, usize)> {
+    let max = config.general.max_channels_per_peer;
+    if max == 0 {
+        return Vec::new();
+    }
+    let mut peers: Vec<(String, usize)> = state
+        .channels_by_peer()
+        .into_iter()
+        .map(|(peer, channels)| (peer, channels.len()))
+        .filter(|(_, count)| *count > max)
+        .collect();
+    peers.sort();
+    peers
+}
+
+/// Log a warning for every peer over the limit.
+pub fn report(config: &Config, state: &NodeState) {
+    for (peer, count) in over_limit(config, state) {
+        warn!(
+            "Peer {} has {} channels (max_channels_per_peer = {}); consider consolidating",
+            peer, count, config.general.max_channels_per_peer
+        );
+    }
+}
+
+/// Move recommendations for peers with more than one usable channel to the
+/// front, keeping the judge's order otherwise. Closing one of those leaves
+/// the peer connected, unlike closing a peer's only channel.
+pub fn prefer_redundant(recommendations: &mut [&CloseRecommendation], state: &NodeState) {
+    let by_peer = state.channels_by_peer();
+    let usable_count = |peer: &str| {
+        by_peer
+            .get(peer)
+            .map_or(0, |chs| chs.iter().filter(|c| state.is_active(c)).count())
+    };
+    recommendations.sort_by_key(|r| usable_count(&r.counterparty_node_id) <= 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ldk_server_protos::api::{GetBalancesResponse, GetNodeInfoResponse};
+    use ldk_server_protos::types::Channel;
+
+    fn make_channel(id: &str, peer: &str) -> Channel {
+        Channel {
+            channel_id: id.to_string(),
+            counterparty_node_id: peer.to_string(),
+            is_usable: true,
+            ..Default::default()
+        }
+    }
+
+    fn make_state(channels: Vec<Channel>) -> NodeState {
+        NodeState {
+            node_info: GetNodeInfoResponse::default(),
+            balances: GetBalancesResponse::default(),
+            channels,
+            closing: Default::default(),
+        }
+    }
+
+    fn rec(peer: &str) -> CloseRecommendation {
+        CloseRecommendation {
+            counterparty_node_id: peer.to_string(),
+            reason: "underperforming".to_string(),
+            expected_improvement_msat: 0,
+        }
+    }
+
+    #[test]
+    fn test_over_limit() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.max_channels_per_peer = 1;
+        let state = make_state(vec![
+            make_channel("a1", "a"),
+            make_channel("a2", "a"),
+            make_channel("b1", "b"),
+        ]);
+
+        assert_eq!(over_limit(&config, &state), vec![("a".to_string(), 2)]);
+
+        config.general.max_channels_per_peer = 0;
+        assert!(over_limit(&config, &state).is_empty());
+    }
+
+    #[test]
+    fn test_prefer_redundant_moves_multi_channel_peers_first() {
+        let state = make_state(vec![
+            make_channel("a1", "a"),
+            make_channel("b1", "b"),
+            make_channel("b2", "b"),
+        ]);
+        let (ra, rb) = (rec("a"), rec("b"));
+        let mut recs = vec![&ra, &rb];
+
+        prefer_redundant(&mut recs, &state);
+        assert_eq!(recs[0].counterparty_node_id, "b");
+        assert_eq!(recs[1].counterparty_node_id, "a");
+    }
+}
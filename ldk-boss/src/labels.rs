@@ -0,0 +1,67 @@
+/// Operator-assigned peer labels ("my LSP", "friend's node", ...), shown
+/// next to node IDs in status, advisory and rebalance output and in the
+/// judge's closure log lines. Set with `ldk-boss label <node_id> <label>`.
+
+use crate::db::Database;
+use std::collections::BTreeMap;
+
+/// Set a peer's label, or remove it when `label` is empty.
+pub fn set(db: &Database, node_id: &str, label: &str) -> anyhow::Result<()> {
+    let label = label.trim();
+    if label.is_empty() {
+        db.conn()?
+            .execute("DELETE FROM peer_labels WHERE node_id = ?1", [node_id])?;
+    } else {
+        db.conn()?.execute(
+            "INSERT OR REPLACE INTO peer_labels (node_id, label, updated_at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![node_id, label, db.now().timestamp() as f64],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn get(db: &Database, node_id: &str) -> Option<String> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT label FROM peer_labels WHERE node_id = ?1",
+            [node_id],
+            |row| row.get(0),
+        )
+        .ok()
+}
+
+/// Every label, by node ID.
+pub fn all(db: &Database) -> anyhow::Result<BTreeMap<String, String>> {
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare("SELECT node_id, label FROM peer_labels")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// `node_id (label)`, or just the node ID if it has no label.
+pub fn describe(db: &Database, node_id: &str) -> String {
+    match get(db, node_id) {
+        Some(label) => format!("{} ({})", node_id, label),
+        None => node_id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_replace_and_clear() {
+        let db = Database::open_in_memory().unwrap();
+        assert_eq!(describe(&db, "abc"), "abc");
+
+        set(&db, "abc", "testing").unwrap();
+        set(&db, "abc", " my LSP ").unwrap();
+        assert_eq!(describe(&db, "abc"), "abc (my LSP)");
+        assert_eq!(all(&db).unwrap().len(), 1);
+
+        set(&db, "abc", "").unwrap();
+        assert!(get(&db, "abc").is_none());
+    }
+}
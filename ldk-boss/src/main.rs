@@ -1,44 +1,104 @@
 #![allow(dead_code)]
 
 mod advisor;
+mod audit;
 mod autopilot;
 mod client;
+mod clock;
 mod config;
+mod control;
+mod cycle_summary;
 mod db;
+mod export;
 mod fees;
+mod instance_lock;
 mod judge;
+mod labels;
+mod metrics;
 mod rebalancer;
 mod reconnector;
 mod scheduler;
 mod state;
 mod tracker;
+mod watchdog;
 
 use crate::client::LdkClient;
 use clap::{Parser, Subcommand};
 use config::Config;
-use log::{error, info, warn};
+use cycle_summary::CycleSummary;
+use log::{debug, error, info, warn};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::signal;
 use tokio::sync::watch;
+use tracing::{info_span, Instrument};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(name = "ldk-boss", about = "Autopilot daemon for LDK Server")]
 struct Cli {
     /// Path to ldkboss.toml config file
     #[arg(short, long, default_value = "ldkboss.toml")]
     config: PathBuf,
 
+    /// Log decisions but execute nothing, whatever the config says
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Override general.log_level
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Override general.loop_interval_secs
+    #[arg(long, global = true, value_name = "SECS")]
+    loop_interval: Option<u64>,
+
+    /// Don't run the peer judge, whatever the config says
+    #[arg(long, global = true)]
+    disable_judge: bool,
+
+    /// Print the resolved config (defaults and overrides applied, API key
+    /// redacted) as TOML and exit
+    #[arg(long, global = true)]
+    config_print: bool,
+
+    /// Start even if another instance seems to hold the database lock
+    #[arg(long, global = true)]
+    force: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
-#[derive(Subcommand)]
+impl Cli {
+    /// Apply command-line overrides on top of the loaded config.
+    fn apply_overrides(&self, config: &mut Config) -> anyhow::Result<()> {
+        if self.dry_run {
+            config.general.dry_run = true;
+        }
+        if let Some(level) = &self.log_level {
+            config.general.log_level = level.clone();
+        }
+        if let Some(secs) = self.loop_interval {
+            config.general.loop_interval_secs = secs;
+        }
+        if self.disable_judge {
+            config.judge.enabled = false;
+        }
+        config.finalize()
+    }
+}
+
+#[derive(Subcommand, Clone)]
 enum Commands {
     /// Run as a background daemon (default)
     Daemon,
     /// Execute a single control cycle and exit
     RunOnce,
+    /// Collect state, update trackers and run just one module, ignoring its
+    /// schedule: fees, autopilot, judge, rebalancer or reconnector
+    Run { module: String },
     /// Print current status from the database
     Status,
     /// Print advisory recommendations without executing anything
@@ -47,26 +107,87 @@ enum Commands {
         #[arg(long)]
         json: bool,
     },
+    /// Show the actions a cycle would take right now, without touching the database
+    Plan {
+        /// Output as JSON instead of human-readable text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Explain which channels the rebalancer would pair, and why each pair
+    /// would or wouldn't be rebalanced
+    ExplainRebalance,
+    /// Show the on-chain fee regime the autopilot sees, and the samples and
+    /// thresholds behind it
+    FeeRegime,
+    /// List recent rebalances: source, destination, amount and fee
+    Rebalances {
+        /// How many days of history to include
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+    },
+    /// Replay the judge over past days and list the peers it would have
+    /// recommended closing, without closing anything
+    JudgeBacktest {
+        /// How many days to replay
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+    },
+    /// Validate the config file and print the effective settings, then exit
+    CheckConfig,
+    /// Export forwarding earnings as LND `fwdinghistory` JSON
+    ExportForwards {
+        /// How many days of history to include
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+    },
+    /// Label a peer (e.g. "my LSP") in reports and logs; omit the label to remove it
+    Label {
+        node_id: String,
+        label: Option<String>,
+    },
+    /// Record what inbound liquidity on a channel cost; its fees carry
+    /// fees.liquidity_premium_multiplier until earnings cover it. 0 removes it
+    LiquidityCost { channel_id: String, sats: u64 },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    let config = Config::load(&cli.config)?;
+    if matches!(cli.command, Some(Commands::CheckConfig)) {
+        check_config(&cli.config);
+    }
+
+    let mut config = Config::load(&cli.config)?;
+    cli.apply_overrides(&mut config)?;
 
-    // Initialize logging
-    let log_level = config.general.log_level.clone();
-    env_logger::Builder::new()
-        .filter_level(log_level.parse().unwrap_or(log::LevelFilter::Info))
-        .format_timestamp_secs()
+    if cli.config_print {
+        print!("{}", config.effective_toml()?);
+        return Ok(());
+    }
+
+    // Initialize logging. `log` records are bridged into `tracing`, so
+    // existing log macros pick up the cycle/module span they run in. Closing
+    // a span logs how long it took.
+    let filter = EnvFilter::try_new(config.log_filter())
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
         .init();
 
     info!("LDKBoss v{} starting", env!("CARGO_PKG_VERSION"));
 
+    for warning in config.soft_warnings() {
+        warn!("Config: {}", warning);
+    }
+
     if config.general.dry_run {
         warn!("DRY-RUN MODE: No actions will be executed");
     }
+    if config.general.vacation_mode {
+        warn!("VACATION MODE: fees only; autopilot, rebalancer and judge are paused");
+    }
     if !config.general.enabled {
         warn!("Master switch is OFF -- exiting");
         return Ok(());
@@ -74,23 +195,84 @@ async fn main() -> anyhow::Result<()> {
 
     let config = Arc::new(config);
 
+    // Used by the control socket's reload-config
+    let loader: control::ConfigLoader = {
+        let cli = cli.clone();
+        Box::new(move || {
+            let mut config = Config::load(&cli.config)?;
+            cli.apply_overrides(&mut config)?;
+            for warning in config.soft_warnings() {
+                warn!("Config: {}", warning);
+            }
+            Ok(config)
+        })
+    };
+
     // Initialize components
     let client = client::LdkBossClient::new(&config)?;
     let db = db::Database::open(&config.general.database_path)?;
 
     match cli.command.unwrap_or(Commands::Daemon) {
-        Commands::Daemon => run_daemon(config, client, db).await,
-        Commands::RunOnce => run_once(config, client, db).await,
-        Commands::Status => print_status(db),
+        Commands::Daemon => run_daemon(config, client, db, loader, cli.force).await,
+        Commands::RunOnce => run_once(config, client, db, cli.force).await,
+        Commands::Run { module } => run_module(config, client, db, &module, cli.force).await,
+        Commands::Status => print_status(&config, db),
         Commands::Advise { json } => run_advise(config, client, db, json).await,
+        Commands::Plan { json } => run_plan(config, client, db, json).await,
+        Commands::ExplainRebalance => run_explain_rebalance(config, client, db).await,
+        Commands::FeeRegime => print_fee_regime(&config, db),
+        Commands::Rebalances { days } => print_rebalances(db, days),
+        Commands::JudgeBacktest { days } => print_judge_backtest(&config, db, days),
+        Commands::CheckConfig => unreachable!("handled before startup"),
+        Commands::ExportForwards { days } => {
+            let since = db.now().timestamp() - (days as i64) * 86400;
+            export::forwarding_history(&db, since)?.print_json();
+            Ok(())
+        }
+        Commands::Label { node_id, label } => {
+            labels::set(&db, &node_id, label.as_deref().unwrap_or(""))?;
+            Ok(())
+        }
+        Commands::LiquidityCost { channel_id, sats } => {
+            fees::liquidity_cost::register(&db, &channel_id, sats)
+        }
+    }
+}
+
+/// Load and validate the config without starting anything; exits 0 if it
+/// is valid, 1 otherwise.
+fn check_config(path: &std::path::Path) -> ! {
+    match Config::load(path) {
+        Ok(config) => {
+            println!("OK: {}", path.display());
+            for warning in config.soft_warnings() {
+                println!("warning: {}", warning);
+            }
+            match config.effective_toml() {
+                Ok(toml) => {
+                    println!();
+                    print!("{}", toml);
+                }
+                Err(e) => eprintln!("(could not render effective config: {:#})", e),
+            }
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("Invalid config {}: {:#}", path.display(), e);
+            std::process::exit(1);
+        }
     }
 }
 
 async fn run_daemon(
-    config: Arc<Config>,
+    mut config: Arc<Config>,
     client: impl LdkClient,
     db: db::Database,
+    loader: control::ConfigLoader,
+    force: bool,
 ) -> anyhow::Result<()> {
+    let lock = instance_lock::InstanceLock::acquire(&db, &config, force)?;
+
     // Startup connectivity check
     info!("Verifying LDK Server connectivity...");
     match client.get_node_info().await {
@@ -105,6 +287,7 @@ async fn run_daemon(
             return Err(e.into());
         }
     }
+    backfill_fee_samples(&config, &db).await;
 
     // Shutdown signal
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
@@ -114,28 +297,62 @@ async fn run_daemon(
         let _ = shutdown_tx.send(true);
     });
 
-    let mut sched = scheduler::Scheduler::new(&config);
-    let interval = std::time::Duration::from_secs(config.general.loop_interval_secs);
+    let mut sched = scheduler::Scheduler::restore(&config, &db);
+    if sched.tick_count() > 0 {
+        info!("Resuming schedule at tick {}", sched.tick_count());
+    }
 
     info!(
         "Entering main loop (interval: {}s)",
         config.general.loop_interval_secs
     );
 
+    let control = Arc::new(control::Control::new(loader));
+    let _socket = match &config.control.socket_path {
+        Some(path) => Some(control::spawn(path, control.clone())?),
+        None => None,
+    };
+
+    let mut watchdog = watchdog::Watchdog::from_config(&config);
+    let mut cycle: u64 = 0;
     loop {
         if *shutdown_rx.borrow() {
             info!("Shutting down gracefully");
             break;
         }
 
-        if let Err(e) = run_cycle(&config, &client, &db, &mut sched).await {
-            error!("Cycle error: {:#}", e);
+        if let Some(new_config) = control.take_config() {
+            info!("Applying reloaded config");
+            config = Arc::new(new_config);
+            sched.reconfigure(&config);
+            watchdog = watchdog::Watchdog::from_config(&config);
         }
 
-        sched.tick();
+        renew_lock(&lock, &config)?;
+        if control.begin_cycle() {
+            cycle += 1;
+            let span = info_span!("cycle", n = cycle, tick = sched.tick_count());
+            let result = watchdog
+                .run(run_cycle(&config, &client, &db, &mut sched, cycle).instrument(span))
+                .await;
+            control.end_cycle(db.now().timestamp());
+            result?;
+            renew_lock(&lock, &config)?;
+
+            sched.tick();
+            if config.general.persist_tick_count {
+                if let Err(e) = sched.save(&db) {
+                    warn!("Failed to save scheduler tick count: {:#}", e);
+                }
+            }
+        } else {
+            debug!("Paused via control socket, skipping cycle");
+        }
 
+        let interval = std::time::Duration::from_secs(config.general.loop_interval_secs);
         tokio::select! {
             _ = tokio::time::sleep(interval) => {},
+            _ = control.woken() => {},
             _ = shutdown_rx.changed() => {
                 info!("Shutting down gracefully");
                 break;
@@ -150,59 +367,242 @@ async fn run_once(
     config: Arc<Config>,
     client: impl LdkClient,
     db: db::Database,
+    force: bool,
 ) -> anyhow::Result<()> {
+    let _lock = instance_lock::InstanceLock::acquire(&db, &config, force)?;
+    backfill_fee_samples(&config, &db).await;
     info!("Running single cycle...");
     let mut sched = scheduler::Scheduler::new_force_all(&config);
-    run_cycle(&config, &client, &db, &mut sched).await?;
+    let span = info_span!("cycle", n = 1u64, tick = sched.tick_count());
+    run_cycle(&config, &client, &db, &mut sched, 1)
+        .instrument(span)
+        .await?;
     info!("Single cycle complete");
     Ok(())
 }
 
+async fn run_module(
+    config: Arc<Config>,
+    client: impl LdkClient,
+    db: db::Database,
+    module: &str,
+    force: bool,
+) -> anyhow::Result<()> {
+    let _lock = instance_lock::InstanceLock::acquire(&db, &config, force)?;
+    backfill_fee_samples(&config, &db).await;
+    info!("Running {} once...", module);
+    run_single_module(&config, &client, &db, module)
+        .instrument(info_span!("run", module))
+        .await?;
+    info!("{} run complete", module);
+    Ok(())
+}
+
+/// Seed fee samples on a fresh database. If it fails, the regime just waits
+/// for samples to accumulate, as without the backfill.
+async fn backfill_fee_samples(config: &Config, db: &db::Database) {
+    if let Err(e) = tracker::onchain_fees::backfill(db, &config.onchain_fees).await {
+        warn!("Failed to backfill on-chain fee samples: {:#}", e);
+    }
+}
+
+/// One module's phase of `run_cycle`, on fresh state. The module's own
+/// `enabled` flag and vacation mode still apply; its errors are returned
+/// rather than logged, since there is no cycle to carry on with.
+pub async fn run_single_module(
+    config: &Config,
+    client: &(impl LdkClient + Sync),
+    db: &db::Database,
+    module: &str,
+) -> anyhow::Result<()> {
+    let (enabled, restructures) = match module {
+        "reconnector" => (config.reconnector.enabled, false),
+        "fees" => (config.fees.enabled, false),
+        "autopilot" => (config.autopilot.enabled, true),
+        "rebalancer" => (config.rebalancer.enabled, true),
+        "judge" => (config.judge.enabled, true),
+        _ => anyhow::bail!(
+            "unknown module {:?}; expected one of: {}",
+            module,
+            scheduler::MODULES.join(", ")
+        ),
+    };
+    if !enabled {
+        anyhow::bail!("{} is disabled ({}.enabled = false)", module, module);
+    }
+    if restructures && config.general.vacation_mode {
+        anyhow::bail!("vacation mode is on: {} doesn't run", module);
+    }
+
+    let node_state = state::NodeState::collect(client, db)
+        .instrument(info_span!("collect"))
+        .await?;
+    tracker::update(db, client, &node_state, config)
+        .instrument(info_span!("trackers"))
+        .await?;
+
+    let summary = &mut CycleSummary::default();
+    match module {
+        "reconnector" => reconnector::run(config, client, db, &node_state, summary).await?,
+        "fees" => fees::run(config, client, db, &node_state, summary).await?,
+        "autopilot" => autopilot::run(config, client, db, &node_state, summary).await?,
+        "rebalancer" => rebalancer::run(config, client, db, &node_state, summary).await?,
+        "judge" => judge::run(config, client, db, &node_state, summary).await?,
+        _ => unreachable!("checked above"),
+    }
+    record_run(db, module);
+    Ok(())
+}
+
+/// Renew the instance lock around a cycle. Losing it to another instance
+/// stops the daemon; a database error (e.g. busy) is logged and the next
+/// renewal tries again.
+fn renew_lock(lock: &instance_lock::InstanceLock, config: &Config) -> anyhow::Result<()> {
+    match lock.renew(config) {
+        Ok(true) => Ok(()),
+        Ok(false) => anyhow::bail!("another ldk-boss took over the database lock; exiting"),
+        Err(e) => {
+            warn!("Instance lock: renewal failed, retrying next time: {:#}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Note a module run for `status`. Failing to record it must not fail the cycle.
+fn record_run(db: &db::Database, module: &str) {
+    if let Err(e) = scheduler::record_run(db, module) {
+        warn!("Failed to record {} run: {:#}", module, e);
+    }
+}
+
+/// Run one cycle; `cycle` is its number in this run, for the summary line.
 pub async fn run_cycle(
     config: &Config,
     client: &(impl LdkClient + Sync),
     db: &db::Database,
     sched: &mut scheduler::Scheduler,
+    cycle: u64,
+) -> anyhow::Result<()> {
+    let started = std::time::Instant::now();
+    let mut summary = CycleSummary::default();
+    let result = run_phases(config, client, db, sched, &mut summary).await;
+
+    let line = summary.line(cycle, started.elapsed().as_millis());
+    match &result {
+        // What got done before the failure is still worth knowing
+        Err(e) => warn!("{}, then failed: {:#}", line, e),
+        Ok(()) if config.general.log_cycle_summary => info!("{}", line),
+        Ok(()) => debug!("{}", line),
+    }
+    result
+}
+
+/// The phases of `run_cycle`, adding what they do to `summary`.
+async fn run_phases(
+    config: &Config,
+    client: &(impl LdkClient + Sync),
+    db: &db::Database,
+    sched: &mut scheduler::Scheduler,
+    summary: &mut CycleSummary,
 ) -> anyhow::Result<()> {
     // Phase 1: Collect node state
-    let node_state = state::NodeState::collect(client, db).await?;
+    let (node_state, degraded) = if config.general.tolerate_transient_failures {
+        state::NodeState::collect_or_snapshot(client, db)
+            .instrument(info_span!("collect"))
+            .await?
+    } else {
+        let node_state = state::NodeState::collect(client, db)
+            .instrument(info_span!("collect"))
+            .await?;
+        (node_state, false)
+    };
 
     // Phase 2: Update trackers
-    tracker::update(db, client, &node_state, config).await?;
+    tracker::update(db, client, &node_state, config)
+        .instrument(info_span!("trackers"))
+        .await?;
+
+    // Phase 2.05: Metrics textfile
+    if let Err(e) = metrics::write_textfile(config, db, &node_state) {
+        warn!("Metrics export error: {:#}", e);
+    }
+
+    // Channels came from the snapshot: keep the books, touch nothing
+    if degraded {
+        warn!("Channel list unavailable: trackers updated, all channel actions skipped");
+        return Ok(());
+    }
+
+    // Phase 2.1: Report duplicate channels to the same peer
+    judge::redundant::report(config, &node_state);
+
+    // Phase 2.2: Panic close on peers that broadcast a revoked state
+    if config.judge.panic_close_enabled {
+        if let Err(e) = judge::panic::run(config, client, db, &node_state)
+            .instrument(info_span!("panic_close"))
+            .await
+        {
+            error!("Panic close error: {:#}", e);
+        }
+    }
 
     // Phase 2.5: Reconnect offline peers
     if config.reconnector.enabled {
-        if let Err(e) = reconnector::run(config, client, db, &node_state).await {
+        if let Err(e) = reconnector::run(config, client, db, &node_state, summary)
+            .instrument(info_span!("reconnector"))
+            .await
+        {
             error!("Reconnector error: {:#}", e);
         }
+        record_run(db, "reconnector");
     }
 
     // Phase 3: Fee management
     if config.fees.enabled {
-        if let Err(e) = fees::run(config, client, db, &node_state).await {
+        if let Err(e) = fees::run(config, client, db, &node_state, summary)
+            .instrument(info_span!("fees"))
+            .await
+        {
             error!("Fee management error: {:#}", e);
         }
+        record_run(db, "fees");
     }
 
+    // Vacation mode freezes topology: fees keep updating, nothing is
+    // opened, closed or rebalanced (panic close still runs, above)
+    let restructure = !config.general.vacation_mode;
+
     // Phase 4: Channel autopilot
-    if config.autopilot.enabled && sched.should_run_autopilot() {
-        if let Err(e) = autopilot::run(config, client, db, &node_state).await {
+    if restructure && config.autopilot.enabled && sched.should_run_autopilot() {
+        if let Err(e) = autopilot::run(config, client, db, &node_state, summary)
+            .instrument(info_span!("autopilot"))
+            .await
+        {
             error!("Autopilot error: {:#}", e);
         }
+        record_run(db, "autopilot");
     }
 
     // Phase 5: Rebalancing
-    if config.rebalancer.enabled && sched.should_run_rebalancer() {
-        if let Err(e) = rebalancer::run(config, client, db, &node_state).await {
+    if restructure && config.rebalancer.enabled && sched.should_run_rebalancer() {
+        if let Err(e) = rebalancer::run(config, client, db, &node_state, summary)
+            .instrument(info_span!("rebalancer"))
+            .await
+        {
             error!("Rebalancer error: {:#}", e);
         }
+        record_run(db, "rebalancer");
     }
 
     // Phase 6: Peer judgment
-    if config.judge.enabled && sched.should_run_judge() {
-        if let Err(e) = judge::run(config, client, db, &node_state).await {
+    if restructure && config.judge.enabled && sched.should_run_judge() {
+        if let Err(e) = judge::run(config, client, db, &node_state, summary)
+            .instrument(info_span!("judge"))
+            .await
+        {
             error!("Judge error: {:#}", e);
         }
+        record_run(db, "judge");
     }
 
     Ok(())
@@ -226,8 +626,53 @@ async fn run_advise(
     Ok(())
 }
 
-fn print_status(db: db::Database) -> anyhow::Result<()> {
-    let conn = db.conn();
+async fn run_plan(
+    config: Arc<Config>,
+    client: impl LdkClient,
+    db: db::Database,
+    json: bool,
+) -> anyhow::Result<()> {
+    let node_state = state::NodeState::collect(&client, &db).await?;
+    let plan = advisor::plan(&config, &client, &db, &node_state).await?;
+
+    if json {
+        plan.print_json();
+    } else {
+        plan.print_text();
+    }
+
+    Ok(())
+}
+
+/// "23 min ago", "4 hours ago", "3 days ago".
+fn format_ago(secs: i64) -> String {
+    let secs = secs.max(0);
+    if secs < 3600 {
+        format!("{} min ago", secs / 60)
+    } else if secs < 48 * 3600 {
+        format!("{} hours ago", secs / 3600)
+    } else {
+        format!("{} days ago", secs / 86400)
+    }
+}
+
+async fn run_explain_rebalance(
+    config: Arc<Config>,
+    client: impl LdkClient,
+    db: db::Database,
+) -> anyhow::Result<()> {
+    let node_state = state::NodeState::collect(&client, &db).await?;
+    let usable: Vec<_> = node_state
+        .channels
+        .iter()
+        .filter(|c| node_state.is_automated(&config, c))
+        .collect();
+    rebalancer::earnings::plan(&config, &db, &usable)?.print_text();
+    Ok(())
+}
+
+fn print_status(config: &Config, db: db::Database) -> anyhow::Result<()> {
+    let conn = db.conn()?;
 
     // Channel count
     let open_channels: i64 = conn
@@ -240,9 +685,11 @@ fn print_status(db: db::Database) -> anyhow::Result<()> {
 
     // Total earnings
     let total_earned: i64 = conn
-        .query_row("SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings", [], |r| {
-            r.get(0)
-        })
+        .query_row(
+            "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings WHERE direction = 'out'",
+            [],
+            |r| r.get(0),
+        )
         .unwrap_or(0);
 
     // Autopilot opens
@@ -265,6 +712,186 @@ fn print_status(db: db::Database) -> anyhow::Result<()> {
     );
     println!("Autopilot opens:        {}", total_opens);
     println!("Judge closures:         {}", total_closures);
+    println!();
+    let now = db.now().timestamp();
+    for module in scheduler::MODULES {
+        let ago = match scheduler::last_run(&db, module) {
+            Some(at) => format_ago(now - at),
+            None => "never".to_string(),
+        };
+        println!("{:<23} {}", format!("{} last ran:", module), ago);
+    }
+    if config.autopilot.enabled {
+        if let Some(reason) = autopilot::idle_reason(&db) {
+            println!("Autopilot idle:         {}", reason);
+        }
+    }
+    if let Some(since) = autopilot::candidate::ranking_alert_since(&db) {
+        let since = chrono::DateTime::from_timestamp(since, 0)
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        println!("ALERT: ranking API returning no candidates since {}", since);
+    }
+
+    let window_days = config.judge.evaluation_window_days;
+    let yields = tracker::earnings::peer_yields(
+        &db,
+        window_days,
+        &config.general.earnings_attribution,
+    )?;
+    if !yields.is_empty() {
+        println!();
+        println!(
+            "Peer yield (last {} days, sat earned per 1M sat capacity per day):",
+            window_days
+        );
+        for y in &yields {
+            println!(
+                "  {:>8.2}  {:>10} sat  {:>10.3} sat net  {}",
+                y.yield_per_million_per_day(),
+                y.capacity_sats,
+                y.net_earned_msat as f64 / 1000.0,
+                labels::describe(&db, &y.counterparty_node_id)
+            );
+        }
+    }
+
+    let labels = labels::all(&db)?;
+    if !labels.is_empty() {
+        println!();
+        println!("Labeled peers:");
+        for (node_id, label) in &labels {
+            println!("  {}  {}", node_id, label);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_fee_regime(config: &Config, db: db::Database) -> anyhow::Result<()> {
+    let fees_config = &config.onchain_fees;
+    let explained = tracker::onchain_fees::explain(&db, fees_config)?;
+
+    println!("On-chain fee regime");
+    println!("===================");
+    println!("Current regime:         {:?}", explained.regime);
+    match explained.latest {
+        Some(latest) => println!("Latest sample:          {:.1} sat/vB", latest),
+        None => println!("Latest sample:          (none)"),
+    }
+    println!(
+        "Samples (7 days):       {} (need {})",
+        explained.sample_count, fees_config.min_samples_for_regime
+    );
+    match explained.thresholds {
+        Some((lo, hi)) => {
+            println!(
+                "Low below:              {:.1} sat/vB ({}th percentile)",
+                lo, fees_config.hi_to_lo_percentile
+            );
+            println!(
+                "High above:             {:.1} sat/vB ({}th percentile, {} samples in a row)",
+                hi,
+                fees_config.lo_to_hi_percentile,
+                fees_config.require_sustained_high_regime_samples
+            );
+        }
+        None => println!(
+            "Thresholds:             not enough samples, assuming \"{}\"",
+            fees_config.default_regime
+        ),
+    }
+    let since = explained
+        .saved_since
+        .map(|at| format!(" since {}", format_ago(db.now().timestamp() - at)))
+        .unwrap_or_default();
+    println!(
+        "Saved (hysteresis):     {}{}",
+        explained.saved.as_deref().unwrap_or("(none)"),
+        since
+    );
+    if fees_config.provider == "none" {
+        println!("Note: onchain_fees.provider is \"none\", no new samples are taken");
+    }
+
+    Ok(())
+}
+
+fn print_rebalances(db: db::Database, days: u64) -> anyhow::Result<()> {
+    let since = db.now().timestamp() as f64 - days as f64 * 86400.0;
+    let rebalances = rebalancer::history::since(&db, since)?;
+
+    println!("Rebalances (last {} days)", days);
+    println!("=========================");
+    if rebalances.is_empty() {
+        println!("(none)");
+        return Ok(());
+    }
+
+    let mut total_amount_msat: u64 = 0;
+    let mut total_fee_msat: u64 = 0;
+    for r in &rebalances {
+        let date = chrono::DateTime::from_timestamp(r.rebalanced_at as i64, 0)
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        println!(
+            "{}  {} -> {}  {} sat, fee {:.3} sat ({} ppm)",
+            date,
+            labels::describe(&db, &r.src_counterparty_node_id),
+            labels::describe(&db, &r.dst_counterparty_node_id),
+            r.amount_msat / 1000,
+            r.fee_spent_msat as f64 / 1000.0,
+            r.fee_spent_msat * 1_000_000 / r.amount_msat.max(1),
+        );
+        total_amount_msat += r.amount_msat;
+        total_fee_msat += r.fee_spent_msat;
+    }
+    println!();
+    println!(
+        "{} rebalances, {} sat moved, {:.3} sat in fees",
+        rebalances.len(),
+        total_amount_msat / 1000,
+        total_fee_msat as f64 / 1000.0
+    );
+
+    Ok(())
+}
+
+fn print_judge_backtest(config: &Config, db: db::Database, days: u64) -> anyhow::Result<()> {
+    let verdicts = judge::backtest::run(config, &db, days)?;
+    let date = |at: i64| {
+        chrono::DateTime::from_timestamp(at, 0)
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    };
+
+    println!("Judge backtest (last {} days, nothing closed)", days);
+    println!("=============================================");
+    if verdicts.is_empty() {
+        println!("The judge would not have recommended closing any peer.");
+    }
+    for v in &verdicts {
+        println!(
+            "{}  flagged {} of {} days ({} to {}){}",
+            labels::describe(&db, &v.counterparty_node_id),
+            v.days_flagged,
+            days,
+            date(v.first_flagged_at),
+            date(v.last_flagged_at),
+            if v.still_open { "" } else { ", since closed" }
+        );
+        println!(
+            "    {}; expected improvement {:.3} sat",
+            v.reason,
+            v.expected_improvement_msat as f64 / 1000.0
+        );
+    }
+    println!();
+    println!(
+        "Each day is judged on its own: probation ({} windows), drains and \
+         max_closures_per_cycle ({}) are not simulated.",
+        config.judge.probation_windows, config.judge.max_closures_per_cycle
+    );
 
     Ok(())
 }
@@ -277,7 +904,7 @@ mod integration_tests {
     use crate::scheduler::Scheduler;
     use crate::tracker::onchain_fees;
     use ldk_server_protos::api::{GetBalancesResponse, ListChannelsResponse};
-    use ldk_server_protos::types::{Channel, ChannelConfig};
+    use ldk_server_protos::types::{Channel, ChannelConfig, ForwardedPayment};
 
     fn test_config() -> Config {
         let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
@@ -321,7 +948,7 @@ mod integration_tests {
             ..Default::default()
         };
 
-        let result = super::run_cycle(&config, &mock, &db, &mut sched).await;
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
         assert!(result.is_ok(), "Cycle should succeed with empty node: {:?}", result.err());
 
         // No channels → no fee updates
@@ -360,7 +987,7 @@ mod integration_tests {
             ..Default::default()
         };
 
-        let result = super::run_cycle(&config, &mock, &db, &mut sched).await;
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
         assert!(result.is_ok());
 
         let calls = mock.update_config_calls.lock().unwrap();
@@ -398,8 +1025,9 @@ mod integration_tests {
 
         // Set low fee regime so autopilot proceeds
         onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        config.onchain_fees.min_samples_for_regime = 1;
         // Insert a fee sample so regime detection works
-        db.conn().execute(
+        db.conn().unwrap().execute(
             "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (5.0, ?1)",
             [chrono::Utc::now().timestamp() as f64],
         ).unwrap();
@@ -416,7 +1044,7 @@ mod integration_tests {
         // No existing channels
         mock.channels = ListChannelsResponse { channels: vec![] };
 
-        let result = super::run_cycle(&config, &mock, &db, &mut sched).await;
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
         assert!(result.is_ok());
 
         // Should have attempted to open channels
@@ -428,6 +1056,7 @@ mod integration_tests {
 
         // Verify audit trail
         let audit_count: i64 = db.conn()
+            .unwrap()
             .query_row("SELECT COUNT(*) FROM autopilot_opens", [], |r| r.get(0))
             .unwrap();
         assert!(audit_count > 0, "Autopilot opens should be recorded");
@@ -468,7 +1097,7 @@ mod integration_tests {
         // Seed channel history (mark all as old enough)
         let old_time = chrono::Utc::now().timestamp() as f64 - 200.0 * 86400.0;
         for (ch_id, peer) in &[("ch1", "good1"), ("ch2", "good2"), ("ch3", "good3"), ("ch4", "bad_peer")] {
-            db.conn().execute(
+            db.conn().unwrap().execute(
                 "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
                  channel_value_sats, first_seen_at, last_seen_at, is_open) \
                  VALUES (?1, ?2, ?3, 1000000, ?4, ?5, 1)",
@@ -482,7 +1111,7 @@ mod integration_tests {
             now - (now % 86400)
         };
         for peer in &["good1", "good2", "good3"] {
-            db.conn().execute(
+            db.conn().unwrap().execute(
                 "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
                  fee_earned_msat, amount_forwarded_msat, direction) \
                  VALUES (?1, ?2, ?3, 10000000, 1000000000, 'in')",
@@ -490,8 +1119,10 @@ mod integration_tests {
             ).unwrap();
         }
         // bad_peer: zero earnings (no row needed)
+        // Only the incoming side was seeded: credit the peers with it in full
+        config.general.earnings_attribution = "in".to_string();
 
-        let result = super::run_cycle(&config, &mock, &db, &mut sched).await;
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
         assert!(result.is_ok());
 
         let close_calls = mock.close_channel_calls.lock().unwrap();
@@ -503,6 +1134,7 @@ mod integration_tests {
 
         // Verify audit trail
         let closure_count: i64 = db.conn()
+            .unwrap()
             .query_row("SELECT COUNT(*) FROM judge_closures", [], |r| r.get(0))
             .unwrap();
         assert_eq!(closure_count, 1);
@@ -524,7 +1156,8 @@ mod integration_tests {
 
         // Set low fee regime
         onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
-        db.conn().execute(
+        config.onchain_fees.min_samples_for_regime = 1;
+        db.conn().unwrap().execute(
             "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (5.0, ?1)",
             [chrono::Utc::now().timestamp() as f64],
         ).unwrap();
@@ -544,7 +1177,7 @@ mod integration_tests {
             ..Default::default()
         };
 
-        let result = super::run_cycle(&config, &mock, &db, &mut sched).await;
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
         assert!(result.is_ok());
 
         // Dry-run: NO mutations should happen
@@ -587,7 +1220,7 @@ mod integration_tests {
             ..Default::default()
         };
 
-        let result = super::run_cycle(&config, &mock, &db, &mut sched).await;
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
         assert!(result.is_ok());
 
         // All modules disabled: no API mutations
@@ -596,4 +1229,549 @@ mod integration_tests {
         assert!(mock.close_channel_calls.lock().unwrap().is_empty());
         assert!(mock.connect_peer_calls.lock().unwrap().is_empty());
     }
+
+    // -----------------------------------------------------------------------
+    // Test 7: Concurrent tracker updates still ingest earnings
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_cycle_concurrent_trackers() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.general.concurrent_trackers = true;
+        config.onchain_fees.provider = "none".to_string();
+        config.fees.enabled = false;
+        config.autopilot.enabled = false;
+        config.rebalancer.enabled = false;
+        config.judge.enabled = false;
+
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![make_channel("ch1", "peer_a", 1_000_000, 500_000_000)],
+        };
+        mock.forwarded_payments.forwarded_payments = vec![ForwardedPayment {
+            prev_channel_id: "ch0".to_string(),
+            next_channel_id: "ch1".to_string(),
+            prev_node_id: "peer_z".to_string(),
+            next_node_id: "peer_a".to_string(),
+            total_fee_earned_msat: Some(2_000),
+            outbound_amount_forwarded_msat: Some(1_000_000),
+            ..Default::default()
+        }];
+
+        let result = super::run_cycle(&config, &mock, &db, &mut sched, 1).await;
+        assert!(result.is_ok());
+
+        // The full fee on each side
+        for direction in ["in", "out"] {
+            let earned: i64 = db.conn()
+                .unwrap()
+                .query_row(
+                    "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings WHERE direction = ?1",
+                    [direction],
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert_eq!(earned, 2_000);
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Test 8: Plan computes recommendations without writing to the DB
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_plan_leaves_db_untouched() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.autopilot.enabled = true;
+
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        config.onchain_fees.min_samples_for_regime = 1;
+        db.conn().unwrap().execute(
+            "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (5.0, ?1)",
+            [chrono::Utc::now().timestamp() as f64],
+        ).unwrap();
+        // Expired blacklist entry: candidate selection would normally prune it
+        db.conn().unwrap().execute(
+            "INSERT INTO autopilot_open_failures \
+             (node_id, address, kind, attempts, last_error, last_attempt_at, blacklisted_until) \
+             VALUES ('old', '1.2.3.4:9735', 'permanent', 1, 'rejected', 0, 1)",
+            [],
+        ).unwrap();
+
+        let mut mock = MockLdkClient::new();
+        mock.balances = GetBalancesResponse {
+            spendable_onchain_balance_sats: 500_000,
+            total_onchain_balance_sats: 500_000,
+            ..Default::default()
+        };
+        mock.channels = ListChannelsResponse { channels: vec![] };
+
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+        let plan = crate::advisor::plan(&config, &mock, &db, &state).await.unwrap();
+        assert!(!plan.opens.is_empty(), "Plan should include channel opens");
+
+        let failures: i64 = db.conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM autopilot_open_failures", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(failures, 1, "Plan must not persist any DB writes");
+
+        // And nothing was executed
+        assert!(mock.open_channel_calls.lock().unwrap().is_empty());
+        assert!(mock.update_config_calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cycle_and_plan_on_file_database() {
+        // A file-backed database goes through the connection pool, and
+        // `plan` through a pinned connection holding a transaction
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("ldkboss.db")).unwrap();
+        let mut config = test_config();
+        config.autopilot.enabled = false;
+        config.rebalancer.enabled = false;
+        config.judge.enabled = false;
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![
+                make_channel("ch1", "peer_a", 1_000_000, 900_000_000),
+                make_channel("ch2", "peer_b", 1_000_000, 100_000_000),
+            ],
+        };
+        mock.balances = GetBalancesResponse {
+            total_lightning_balance_sats: 2_000_000,
+            ..Default::default()
+        };
+
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+        let count_rows = |db: &Database| -> i64 {
+            db.conn()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM run_state", [], |r| r.get(0))
+                .unwrap()
+        };
+        let before = count_rows(&db);
+        assert!(before > 0, "The cycle's writes should be persisted");
+
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+        let before = count_rows(&db);
+        crate::advisor::plan(&config, &mock, &db, &state).await.unwrap();
+        assert_eq!(count_rows(&db), before, "Plan must not persist any DB writes");
+
+        // The pinned connection went back to the pool: another cycle still runs
+        super::run_cycle(&config, &mock, &db, &mut sched, 2).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_plan_with_ranking_api() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.autopilot.enabled = true;
+        config.autopilot.ranking_api_url = "https://ranking.example".to_string();
+
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        config.onchain_fees.min_samples_for_regime = 1;
+        db.conn().unwrap().execute(
+            "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (5.0, ?1)",
+            [chrono::Utc::now().timestamp() as f64],
+        ).unwrap();
+
+        let mut mock = MockLdkClient::new();
+        mock.balances = GetBalancesResponse {
+            spendable_onchain_balance_sats: 500_000,
+            total_onchain_balance_sats: 500_000,
+            ..Default::default()
+        };
+        mock.channels = ListChannelsResponse { channels: vec![] };
+
+        // Storing the ranking response inside plan's transaction must not
+        // fail candidate selection
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+        let plan = crate::advisor::plan(&config, &mock, &db, &state).await.unwrap();
+        assert!(!plan.opens.is_empty(), "Plan should include channel opens");
+
+        let cached: i64 = db.conn()
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM run_state WHERE key = 'ranking_cache_fetched_at'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(cached, 0, "Plan must not persist the ranking cache");
+    }
+
+    #[tokio::test]
+    async fn test_advice_fees_match_cycle() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.fees.enabled = true;
+        config.fees.price_theory_enabled = false;
+        config.fees.liquidity_premium_multiplier = 1.5;
+        config.autopilot.enabled = false;
+        config.rebalancer.enabled = false;
+        config.judge.enabled = false;
+        crate::fees::liquidity_cost::register(&db, "ch2", 10_000).unwrap();
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![
+                make_channel("ch1", "peer_a", 1_000_000, 900_000_000),
+                make_channel("ch2", "peer_b", 1_000_000, 100_000_000),
+            ],
+        };
+
+        // Advice is computed by the same stack as the cycle, premium included,
+        // and leaves the liquidity cost in place
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        let advised = advisory.fees.iter().find(|f| f.channel_id == "ch2").unwrap();
+        assert_eq!(advised.liquidity_mult, 1.5);
+        let costs: i64 = db.conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM liquidity_costs", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(costs, 1);
+
+        let mut sched = Scheduler::new_force_all(&config);
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+        let calls = mock.update_config_calls.lock().unwrap();
+        for f in &advisory.fees {
+            let call = calls
+                .iter()
+                .find(|c| c.user_channel_id == format!("user_{}", f.channel_id))
+                .unwrap();
+            let set = call.channel_config.as_ref().unwrap();
+            assert_eq!(set.forwarding_fee_proportional_millionths, Some(f.suggested_ppm));
+            assert_eq!(set.forwarding_fee_base_msat, Some(f.suggested_base_msat));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_advice_respects_balance_modder_freeze() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.fees.price_theory_enabled = false;
+        config.fees.freeze_balance_modder_in_high_regime = true;
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::High).unwrap();
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![make_channel("ch1", "peer_a", 1_000_000, 900_000_000)],
+        };
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+
+        // High fees: no discount for the outbound-heavy channel
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        assert_eq!(advisory.fees[0].balance_mult, 1.0);
+
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        assert!(advisory.fees[0].balance_mult < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_advice_respects_balance_modder_min_channels() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.fees.price_theory_enabled = false;
+        config.fees.balance_modder_min_channels = 3;
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![
+                make_channel("ch1", "peer_a", 1_000_000, 900_000_000),
+                make_channel("ch2", "peer_b", 1_000_000, 100_000_000),
+            ],
+        };
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+
+        // Two channels: the balance modder waits, as it does in a cycle
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        assert!(advisory.fees.iter().all(|f| f.balance_mult == 1.0));
+
+        config.fees.balance_modder_min_channels = 2;
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        assert!(advisory.fees.iter().all(|f| f.balance_mult != 1.0));
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_advice_follows_primary_goal() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        let mut mock = MockLdkClient::new();
+        let mut src = make_channel("src", "full", 1_000_000, 950_000_000);
+        src.next_outbound_htlc_limit_msat = 950_000_000;
+        mock.channels = ListChannelsResponse {
+            channels: vec![make_channel("dst", "drained", 1_000_000, 50_000_000), src],
+        };
+        // Only the source earns, from payments coming in through it
+        let now = chrono::Utc::now().timestamp();
+        db.conn().unwrap().execute(
+            "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+             fee_earned_msat, amount_forwarded_msat, direction) \
+             VALUES ('src', 'full', ?1, 800000, 0, 'in')",
+            [now - now % 86400],
+        ).unwrap();
+        let state = crate::state::NodeState::collect(&mock, &db).await.unwrap();
+
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        assert!(advisory.rebalances.is_empty());
+
+        config.rebalancer.primary_goal = "inbound".to_string();
+        let advisory = crate::advisor::collect(&config, &mock, &db, &state).await.unwrap();
+        assert_eq!(advisory.rebalances.len(), 1);
+        let advice = &advisory.rebalances[0];
+        assert_eq!(advice.dest_peer, "drained");
+        assert!(advice.max_chunk_msat <= config.rebalancer.max_rebalance_amount_msat);
+        assert!(advice.max_fee_msat > 0 && advice.max_fee_msat <= 800_000);
+    }
+
+    // -----------------------------------------------------------------------
+    // Test 9: Safe mode caps fee changes per cycle
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_cycle_safe_mode_caps_fee_changes() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.general.safe_mode = true;
+        config.general.safe_mode_max_fee_changes = 1;
+        config.fees.enabled = true;
+        config.fees.balance_modder_enabled = true;
+        config.fees.price_theory_enabled = false;
+        config.autopilot.enabled = false;
+        config.rebalancer.enabled = false;
+        config.judge.enabled = false;
+
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![
+                make_channel("ch1", "peer_a", 1_000_000, 900_000_000),
+                make_channel("ch2", "peer_b", 1_000_000, 100_000_000),
+            ],
+        };
+        mock.balances = GetBalancesResponse {
+            total_lightning_balance_sats: 2_000_000,
+            ..Default::default()
+        };
+
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+
+        // Both channels want a change (see test 2), only one is allowed
+        assert_eq!(mock.update_config_calls.lock().unwrap().len(), 1);
+    }
+
+    // -----------------------------------------------------------------------
+    // Test 10: Vacation mode keeps fees but opens nothing
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_cycle_vacation_mode() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.general.vacation_mode = true;
+        config.fees.enabled = true;
+        config.fees.balance_modder_enabled = true;
+        config.fees.price_theory_enabled = false;
+        config.autopilot.enabled = true;
+        config.onchain_fees.min_samples_for_regime = 1;
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        db.conn().unwrap().execute(
+            "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (5.0, ?1)",
+            [chrono::Utc::now().timestamp() as f64],
+        ).unwrap();
+
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![make_channel("ch1", "peer_a", 1_000_000, 900_000_000)],
+        };
+        mock.balances = GetBalancesResponse {
+            spendable_onchain_balance_sats: 5_000_000,
+            total_onchain_balance_sats: 5_000_000,
+            total_lightning_balance_sats: 1_000_000,
+            ..Default::default()
+        };
+
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+
+        assert!(!mock.update_config_calls.lock().unwrap().is_empty());
+        assert!(mock.open_channel_calls.lock().unwrap().is_empty());
+    }
+
+    // -----------------------------------------------------------------------
+    // Test 11: A failed channel listing falls back to the snapshot, read-only
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_cycle_tolerates_list_channels_failure() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.general.tolerate_transient_failures = true;
+        config.fees.enabled = true;
+        config.fees.balance_modder_enabled = true;
+        config.fees.price_theory_enabled = false;
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![make_channel("ch1", "peer_a", 1_000_000, 900_000_000)],
+        };
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+        let fee_updates = mock.update_config_calls.lock().unwrap().len();
+
+        // Server hiccup: earnings are still ingested, fees are left alone
+        mock.fail_always("list_channels");
+        mock.forwarded_payments.forwarded_payments = vec![ForwardedPayment {
+            prev_channel_id: "ch0".to_string(),
+            next_channel_id: "ch1".to_string(),
+            prev_node_id: "peer_z".to_string(),
+            next_node_id: "peer_a".to_string(),
+            total_fee_earned_msat: Some(2_000),
+            ..Default::default()
+        }];
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+
+        assert_eq!(mock.update_config_calls.lock().unwrap().len(), fee_updates);
+        let earned: i64 = db
+            .conn()
+            .unwrap()
+            .query_row(
+                "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings WHERE direction = 'out'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(earned, 2_000);
+
+        // Without the option the cycle fails as before
+        config.general.tolerate_transient_failures = false;
+        assert!(super::run_cycle(&config, &mock, &db, &mut sched, 1).await.is_err());
+    }
+
+    // -----------------------------------------------------------------------
+    // Test 12: A failed open is recorded for retry and the plan carries on
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_cycle_failed_open_continues_plan() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.autopilot.enabled = true;
+        config.fees.enabled = false;
+        config.rebalancer.enabled = false;
+        config.judge.enabled = false;
+        config.onchain_fees.min_samples_for_regime = 1;
+        onchain_fees::save_regime(&db, onchain_fees::FeeRegime::Low).unwrap();
+        db.conn().unwrap().execute(
+            "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (5.0, ?1)",
+            [chrono::Utc::now().timestamp() as f64],
+        ).unwrap();
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut mock = MockLdkClient::new();
+        mock.balances = GetBalancesResponse {
+            spendable_onchain_balance_sats: 5_000_000,
+            total_onchain_balance_sats: 5_000_000,
+            ..Default::default()
+        };
+        mock.fail_nth("open_channel", 1);
+
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+
+        let attempted = mock.call_count("open_channel") as i64;
+        assert!(attempted >= 2, "plan should go on after the first open fails");
+        let count = |table: &str| -> i64 {
+            db.conn()
+                .unwrap()
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |r| r.get(0))
+                .unwrap()
+        };
+        assert_eq!(count("autopilot_opens"), attempted - 1);
+        assert_eq!(count("autopilot_open_failures"), 1);
+    }
+
+    // -----------------------------------------------------------------------
+    // Test 13: The reconnector runs every cycle unless disabled
+    // -----------------------------------------------------------------------
+    #[tokio::test]
+    async fn test_cycle_reconnects_offline_peers() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.fees.enabled = false;
+        config.reconnector.enabled = true;
+        let mut sched = Scheduler::new_force_all(&config);
+
+        let mut offline = make_channel("ch1", "peer_a", 1_000_000, 500_000_000);
+        offline.is_usable = false;
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse { channels: vec![offline] };
+        db.conn().unwrap().execute(
+            "INSERT INTO peer_addresses (node_id, address, source) \
+             VALUES ('peer_a', '10.0.0.1:9735', 'config')",
+            [],
+        ).unwrap();
+
+        let mut summary = super::CycleSummary::default();
+        super::run_phases(&config, &mock, &db, &mut sched, &mut summary).await.unwrap();
+        assert_eq!(summary.reconnected, 1);
+        {
+            let calls = mock.connect_peer_calls.lock().unwrap();
+            assert_eq!(calls.len(), 1);
+            assert_eq!(calls[0].node_pubkey, "peer_a");
+            assert_eq!(calls[0].address, "10.0.0.1:9735");
+        }
+
+        config.reconnector.enabled = false;
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+        assert_eq!(mock.connect_peer_calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_format_ago() {
+        assert_eq!(super::format_ago(23 * 60), "23 min ago");
+        assert_eq!(super::format_ago(4 * 3600 + 5), "4 hours ago");
+        assert_eq!(super::format_ago(3 * 86400), "3 days ago");
+    }
+
+    #[tokio::test]
+    async fn test_cycle_records_module_runs() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.fees.enabled = true;
+        config.judge.enabled = false;
+        let mut sched = Scheduler::new_force_all(&config);
+        let mock = MockLdkClient::new();
+
+        super::run_cycle(&config, &mock, &db, &mut sched, 1).await.unwrap();
+
+        assert!(crate::scheduler::last_run(&db, "fees").is_some());
+        assert!(crate::scheduler::last_run(&db, "judge").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_single_module() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = test_config();
+        config.fees.enabled = true;
+        config.judge.enabled = false;
+        let mut mock = MockLdkClient::new();
+        mock.channels = ListChannelsResponse {
+            channels: vec![make_channel("ch1", "peer_a", 1_000_000, 900_000_000)],
+        };
+
+        super::run_single_module(&config, &mock, &db, "fees").await.unwrap();
+        assert!(crate::scheduler::last_run(&db, "fees").is_some());
+        assert!(crate::scheduler::last_run(&db, "reconnector").is_none());
+        assert!(mock.open_channel_calls.lock().unwrap().is_empty());
+
+        let err = super::run_single_module(&config, &mock, &db, "judge").await.unwrap_err();
+        assert!(err.to_string().contains("judge.enabled"));
+        let err = super::run_single_module(&config, &mock, &db, "bogus").await.unwrap_err();
+        assert!(err.to_string().contains("unknown module"));
+    }
 }
@@ -0,0 +1,304 @@
+/// Metrics in the Prometheus text format, written to a file for
+/// node-exporter's textfile collector, so a home node needs no extra
+/// listener to be scraped.
+///
+/// With `metrics.textfile_path` set, the file is rewritten once the trackers
+/// have run, at most every `write_interval_secs`. It is written next to the
+/// target and renamed over it, so the collector never reads half a file;
+/// name it `*.prom` for the collector to pick it up.
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::scheduler;
+use crate::state::NodeState;
+use crate::tracker::onchain_fees;
+use anyhow::Context;
+use log::debug;
+use std::fmt::Write;
+
+/// `run_state` key holding when the file was last written (unix seconds).
+const WRITTEN_AT_KEY: &str = "metrics_written_at";
+
+/// Render every metric.
+pub fn render(config: &Config, db: &Database, state: &NodeState) -> anyhow::Result<String> {
+    let mut out = String::new();
+
+    metric(
+        &mut out,
+        "ldkboss_onchain_balance_sats",
+        "gauge",
+        "Total on-chain balance",
+        &[(String::new(), state.balances.total_onchain_balance_sats as f64)],
+    );
+    metric(
+        &mut out,
+        "ldkboss_channel_balance_sats",
+        "gauge",
+        "Our side of all channels",
+        &[(String::new(), state.channel_lightning_sats() as f64)],
+    );
+    let pending = state.channels.iter().filter(|c| !c.is_channel_ready).count();
+    metric(
+        &mut out,
+        "ldkboss_channels",
+        "gauge",
+        "Channels by state",
+        &[
+            (labels(&[("state", "usable")]), state.usable_channel_count() as f64),
+            (labels(&[("state", "pending")]), pending as f64),
+            (labels(&[("state", "closing")]), state.closing.len() as f64),
+        ],
+    );
+
+    let conn = db.conn()?;
+    let mut ratios = Vec::new();
+    let mut fees = Vec::new();
+    for ch in &state.channels {
+        let ch_labels = labels(&[
+            ("channel_id", &ch.channel_id),
+            ("peer_id", &ch.counterparty_node_id),
+        ]);
+        if ch.channel_value_sats > 0 {
+            let ratio = ch.outbound_capacity_msat as f64 / (ch.channel_value_sats * 1000) as f64;
+            ratios.push((ch_labels.clone(), ratio));
+        }
+        let ppm: Option<u32> = conn
+            .query_row(
+                "SELECT ppm FROM fee_history WHERE channel_id = ?1",
+                [&ch.channel_id],
+                |row| row.get(0),
+            )
+            .ok();
+        if let Some(ppm) = ppm {
+            fees.push((ch_labels, ppm as f64));
+        }
+    }
+    metric(
+        &mut out,
+        "ldkboss_channel_outbound_ratio",
+        "gauge",
+        "Share of the channel's capacity on our side",
+        &ratios,
+    );
+    metric(
+        &mut out,
+        "ldkboss_channel_fee_ppm",
+        "gauge",
+        "Proportional fee last set on the channel",
+        &fees,
+    );
+
+    let total = |sql: &str| -> anyhow::Result<f64> {
+        Ok(conn.query_row(sql, [], |row| row.get::<_, i64>(0))? as f64)
+    };
+    metric(
+        &mut out,
+        "ldkboss_fees_earned_msat_total",
+        "counter",
+        "Routing fees earned",
+        &[(
+            String::new(),
+            // Both directions record the full fee; count each forward once
+            total(
+                "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings \
+                 WHERE direction = 'out'",
+            )?,
+        )],
+    );
+    metric(
+        &mut out,
+        "ldkboss_rebalance_fees_paid_msat_total",
+        "counter",
+        "Fees paid for rebalancing",
+        &[(
+            String::new(),
+            total("SELECT COALESCE(SUM(fee_spent_msat), 0) FROM rebalance_log")?,
+        )],
+    );
+    metric(
+        &mut out,
+        "ldkboss_autopilot_opens_total",
+        "counter",
+        "Channels opened by the autopilot",
+        &[(String::new(), total("SELECT COUNT(*) FROM autopilot_opens")?)],
+    );
+    metric(
+        &mut out,
+        "ldkboss_judge_closures_total",
+        "counter",
+        "Channels closed by the judge",
+        &[(String::new(), total("SELECT COUNT(*) FROM judge_closures")?)],
+    );
+    drop(conn);
+
+    let low = onchain_fees::current_regime(db, &config.onchain_fees)?
+        == onchain_fees::FeeRegime::Low;
+    metric(
+        &mut out,
+        "ldkboss_onchain_fee_regime_low",
+        "gauge",
+        "1 in the low on-chain fee regime, 0 in the high one",
+        &[(String::new(), if low { 1.0 } else { 0.0 })],
+    );
+    let last_runs: Vec<_> = scheduler::MODULES
+        .iter()
+        .filter_map(|&module| {
+            let at = scheduler::last_run(db, module)?;
+            Some((labels(&[("module", module)]), at as f64))
+        })
+        .collect();
+    metric(
+        &mut out,
+        "ldkboss_module_last_run_timestamp_seconds",
+        "gauge",
+        "When each module last ran",
+        &last_runs,
+    );
+
+    Ok(out)
+}
+
+/// Rewrite `metrics.textfile_path` if it is set and `write_interval_secs`
+/// have passed since the last write (0 = every cycle).
+pub fn write_textfile(config: &Config, db: &Database, state: &NodeState) -> anyhow::Result<()> {
+    let Some(path) = &config.metrics.textfile_path else {
+        return Ok(());
+    };
+    let now = db.now().timestamp();
+    let written_at: Option<i64> = db
+        .conn()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [WRITTEN_AT_KEY],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse().ok());
+    if written_at.is_some_and(|at| now - at < config.metrics.write_interval_secs as i64) {
+        return Ok(());
+    }
+
+    let text = render(config, db, state)?;
+    let tmp = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    std::fs::write(&tmp, text).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to replace {}", path.display()));
+    }
+    debug!("Metrics: wrote {}", path.display());
+
+    db.conn()?.execute(
+        "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+        rusqlite::params![WRITTEN_AT_KEY, now.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Append one metric family. Each sample is its rendered label set (empty
+/// for none) and value; a family without samples is left out.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    if samples.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// `{key="value",...}`, with values escaped as the text format requires.
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ldk_server_protos::types::Channel;
+
+    fn make_state() -> NodeState {
+        NodeState {
+            node_info: Default::default(),
+            balances: ldk_server_protos::api::GetBalancesResponse {
+                total_onchain_balance_sats: 250_000,
+                ..Default::default()
+            },
+            channels: vec![Channel {
+                channel_id: "ch1".to_string(),
+                counterparty_node_id: "peer1".to_string(),
+                channel_value_sats: 1_000_000,
+                outbound_capacity_msat: 400_000_000,
+                is_channel_ready: true,
+                is_usable: true,
+                ..Default::default()
+            }],
+            closing: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO fee_history (channel_id, base_msat, ppm, updated_at) \
+                 VALUES ('ch1', 1000, 250, 0)",
+                [],
+            )
+            .unwrap();
+
+        let text = render(&config, &db, &make_state()).unwrap();
+        assert!(text.contains("# TYPE ldkboss_onchain_balance_sats gauge\n"));
+        assert!(text.contains("ldkboss_onchain_balance_sats 250000\n"));
+        assert!(text.contains("ldkboss_channels{state=\"usable\"} 1\n"));
+        let ch1 = "{channel_id=\"ch1\",peer_id=\"peer1\"}";
+        assert!(text.contains(&format!("ldkboss_channel_outbound_ratio{} 0.4\n", ch1)));
+        assert!(text.contains(&format!("ldkboss_channel_fee_ppm{} 250\n", ch1)));
+        assert!(text.contains("ldkboss_fees_earned_msat_total 0\n"));
+        // Nothing has run yet
+        assert!(!text.contains("ldkboss_module_last_run_timestamp_seconds"));
+
+        assert_eq!(labels(&[("a", "x\"y\\z")]), "{a=\"x\\\"y\\\\z\"}");
+    }
+
+    #[test]
+    fn test_write_textfile_respects_interval() {
+        let db = Database::open_in_memory().unwrap();
+        let dir = std::env::temp_dir().join(format!("ldkboss-metrics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ldkboss.prom");
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.metrics.textfile_path = Some(path.clone());
+
+        write_textfile(&config, &db, &make_state()).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("ldkboss_onchain_balance_sats 250000"));
+
+        // Too soon: the file is left alone
+        std::fs::remove_file(&path).unwrap();
+        write_textfile(&config, &db, &make_state()).unwrap();
+        assert!(!path.exists());
+
+        config.metrics.write_interval_secs = 0;
+        write_textfile(&config, &db, &make_state()).unwrap();
+        assert!(path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
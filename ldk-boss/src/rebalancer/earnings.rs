@@ -5,191 +5,504 @@
 ///
 /// Algorithm:
 /// - Destinations: channels where spendable < 25% of total (need more outbound)
+///   and the judge isn't draining before a close
 /// - Sources: channels where spendable > 27.5% of total (have excess outbound)
 /// - Sort by net earnings (highest first)
-/// - Pair top 20th percentile
+/// - Pair top 20th percentile. Each channel has one role and is paired at
+///   most once, so no two rebalances in a cycle undo each other
+/// - Spend on fees only what the side named by `primary_goal` earned: the
+///   destination's outbound earnings ("outbound"), the source's inbound
+///   earnings ("inbound"), or both ("balance")
+/// - Fill destinations to target_spendable_percent, or with
+///   earnings_weighted_targets, less the lower they rank by earnings
+/// - Split each pair's amount into chunks of at most max_rebalance_amount_msat,
+///   further capped by the HTLC limits of the source and destination channels
 /// - Execute via Bolt11Receive + Bolt11Send
+/// - With `swap_severe_percent`, recommend a swap for what a badly lopsided
+///   pair couldn't move (see `swap`)
 ///
 /// Reference: clboss/Boss/Mod/EarningsRebalancer.cpp
 
+use crate::audit;
 use crate::client::LdkClient;
 use crate::config::Config;
+use crate::cycle_summary::CycleSummary;
 use crate::db::Database;
+use crate::judge::drain as judge_drain;
+use crate::rebalancer::{history, swap};
 use crate::tracker::earnings as earnings_tracker;
 use ldk_server_protos::api::{Bolt11ReceiveRequest, Bolt11SendRequest};
 use ldk_server_protos::types::{
     bolt11_invoice_description, Bolt11InvoiceDescription, Channel, RouteParametersConfig,
 };
 use log::{debug, info, warn};
+use serde_json::json;
 
 /// Hard cap on rebalance fee per cycle (satoshis).
-const ABS_MAX_REBALANCE_FEE_SATS: u64 = 50_000;
+pub const ABS_MAX_REBALANCE_FEE_SATS: u64 = 50_000;
 /// Top percentile of channels to rebalance.
 const TOP_REBALANCING_PERCENTILE: f64 = 20.0;
 
-struct ChannelBalance {
-    counterparty_node_id: String,
-    channel_id: String,
-    spendable_msat: u64,
-    total_msat: u64,
-    spendable_percent: f64,
+pub struct ChannelBalance {
+    pub counterparty_node_id: String,
+    pub channel_id: String,
+    pub spendable_msat: u64,
+    pub total_msat: u64,
+    pub spendable_percent: f64,
+    /// Largest HTLC we can send out over this channel right now
+    pub max_send_htlc_msat: u64,
+    /// Largest HTLC we can receive over this channel, if either side
+    /// advertises a maximum
+    pub max_receive_htlc_msat: Option<u64>,
 }
 
-pub async fn run(
-    config: &Config,
-    client: &(impl LdkClient + Sync),
-    db: &Database,
-    channels: &[&Channel],
-) -> anyhow::Result<()> {
+/// How a channel was classified this round.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// Spendable below `max_spendable_percent`: wants more outbound
+    Destination,
+    /// Spendable above `max_spendable_percent + source_gap_percent`
+    Source,
+    /// In the gap between the two
+    Neither,
+    /// Would be a destination, but the judge is draining it before a close:
+    /// refilling it would undo the drain
+    Draining,
+}
+
+pub struct Assessment {
+    pub balance: ChannelBalance,
+    pub role: Role,
+    /// Net earnings over the last 30 days in the direction that matters for
+    /// the role (out for destinations, in for sources), msat. 0 for `Neither`.
+    pub net_earnings_msat: i64,
+}
+
+/// A source/destination pairing and what the rebalancer decided about it.
+pub struct PairPlan {
+    /// Index into `Plan::channels`
+    pub source: usize,
+    /// Index into `Plan::channels`
+    pub destination: usize,
+    pub amount_msat: u64,
+    /// Fee budget for the whole pair, before the per-cycle total cap
+    pub fee_budget_msat: u64,
+    /// Largest single payment: `max_rebalance_amount_msat`, lowered to what
+    /// the source can send and the destination can receive in one HTLC
+    pub max_chunk_msat: u64,
+    /// Net earnings that pay for this pair's fees, per `primary_goal`
+    pub benefit_msat: i64,
+    /// Why this pair is not rebalanced, if it isn't
+    pub skip: Option<String>,
+}
+
+pub struct Plan {
+    pub channels: Vec<Assessment>,
+    pub pairs: Vec<PairPlan>,
+}
+
+/// Fee rate (ppm) we'll pay to rebalance into a channel with `peer`:
+/// its `peer_max_fee_ppm` override, or `max_fee_ppm`.
+pub fn max_fee_ppm_for(config: &Config, peer: &str) -> u32 {
+    config
+        .rebalancer
+        .peer_max_fee_ppm
+        .get(peer)
+        .copied()
+        .unwrap_or(config.rebalancer.max_fee_ppm)
+}
+
+/// Classify channels and pair sources with destinations, recording why
+/// each pair is or isn't worth rebalancing. Executes nothing.
+pub fn plan(config: &Config, db: &Database, channels: &[&Channel]) -> anyhow::Result<Plan> {
     let max_spendable = config.rebalancer.max_spendable_percent;
     let source_gap = config.rebalancer.source_gap_percent;
     let target_pct = config.rebalancer.target_spendable_percent;
-    let max_fee_ppm = config.rebalancer.max_fee_ppm;
-
-    // Compute balances
-    let balances: Vec<ChannelBalance> = channels
-        .iter()
-        .filter_map(|ch| {
-            let total_msat = ch.channel_value_sats * 1000;
-            if total_msat == 0 {
-                return None;
-            }
-            let spendable_msat = ch.outbound_capacity_msat;
-            let spendable_percent = (spendable_msat as f64 / total_msat as f64) * 100.0;
-            Some(ChannelBalance {
+    let min_amount_msat = config.rebalancer.min_rebalance_amount_msat;
+    let max_amount_msat = config.rebalancer.max_rebalance_amount_msat;
+    let min_margin_msat = config.rebalancer.min_profit_margin_sats as i64 * 1000;
+
+    // Classify into sources and destinations
+    let since = db.now().timestamp() as f64 - 30.0 * 86400.0; // last 30 days
+
+    let mut assessed = Vec::new();
+    for ch in channels {
+        let total_msat = ch.channel_value_sats * 1000;
+        if total_msat == 0 {
+            continue;
+        }
+        let spendable_msat = ch.outbound_capacity_msat;
+        let spendable_percent = (spendable_msat as f64 / total_msat as f64) * 100.0;
+
+        let (role, net_earnings_msat) = if spendable_percent < max_spendable
+            && judge_drain::is_draining(config, db, &ch.channel_id)
+        {
+            (Role::Draining, 0)
+        } else if spendable_percent < max_spendable {
+            let earnings =
+                earnings_tracker::peer_earnings_since(db, &ch.counterparty_node_id, since)?;
+            (Role::Destination, earnings.out_net())
+        } else if spendable_percent > max_spendable + source_gap {
+            let earnings =
+                earnings_tracker::peer_earnings_since(db, &ch.counterparty_node_id, since)?;
+            (Role::Source, earnings.in_net())
+        } else {
+            (Role::Neither, 0)
+        };
+
+        assessed.push(Assessment {
+            balance: ChannelBalance {
                 counterparty_node_id: ch.counterparty_node_id.clone(),
                 channel_id: ch.channel_id.clone(),
                 spendable_msat,
                 total_msat,
                 spendable_percent,
-            })
-        })
-        .collect();
+                max_send_htlc_msat: ch.next_outbound_htlc_limit_msat,
+                max_receive_htlc_msat: [
+                    ch.counterparty_outbound_htlc_maximum_msat,
+                    ch.inbound_htlc_maximum_msat,
+                ]
+                .into_iter()
+                .flatten()
+                .min(),
+            },
+            role,
+            net_earnings_msat,
+        });
+    }
 
-    // Classify into sources and destinations
-    let since = chrono::Utc::now().timestamp() as f64 - 30.0 * 86400.0; // last 30 days
-
-    let mut destinations: Vec<(usize, i64)> = Vec::new(); // (index, out_net_earnings)
-    let mut sources: Vec<(usize, i64)> = Vec::new(); // (index, in_net_earnings)
-
-    for (i, bal) in balances.iter().enumerate() {
-        let peer_earnings = earnings_tracker::peer_earnings_since(
-            db,
-            &bal.counterparty_node_id,
-            since,
-        )?;
-
-        if bal.spendable_percent < max_spendable {
-            destinations.push((i, peer_earnings.out_net()));
-        } else if bal.spendable_percent > max_spendable + source_gap {
-            sources.push((i, peer_earnings.in_net()));
+    // Sort each side by net earnings (highest first)
+    let ranked = |role: Role| {
+        let mut idx: Vec<usize> = (0..assessed.len())
+            .filter(|&i| assessed[i].role == role)
+            .collect();
+        idx.sort_by(|&a, &b| assessed[b].net_earnings_msat.cmp(&assessed[a].net_earnings_msat));
+        idx
+    };
+    let destinations = ranked(Role::Destination);
+    let sources = ranked(Role::Source);
+
+    // Pair the top percentile; the rest are kept, with the reason, so they
+    // can be explained
+    let num = destinations.len().min(sources.len());
+    let num_rebalance = ((num as f64 * TOP_REBALANCING_PERCENTILE / 100.0) as usize).max(1);
+
+    let mut pairs = Vec::new();
+    for (i, (&dst_idx, &src_idx)) in destinations.iter().zip(&sources).enumerate() {
+        let dst = &assessed[dst_idx];
+        let src = &assessed[src_idx];
+
+        // Compute amounts
+        let dest_target_pct = if config.rebalancer.earnings_weighted_targets {
+            weighted_target_percent(max_spendable, target_pct, i, destinations.len())
+        } else {
+            target_pct
+        };
+        let dest_target_msat = (dst.balance.total_msat as f64 * dest_target_pct / 100.0) as u64;
+        let dest_needed_msat = dest_target_msat.saturating_sub(dst.balance.spendable_msat);
+
+        let src_min_allowed_msat =
+            (src.balance.total_msat as f64 * (max_spendable + source_gap) / 100.0) as u64;
+        let src_budget_msat = src.balance.spendable_msat.saturating_sub(src_min_allowed_msat);
+
+        let amount_msat = dest_needed_msat.min(src_budget_msat);
+
+        // Every rebalance adds outbound on the destination and inbound on the
+        // source; the goal says which of the two is worth paying for
+        let (benefit_msat, benefactor) = match config.rebalancer.primary_goal.as_str() {
+            "inbound" => (src.net_earnings_msat, "source"),
+            "balance" => (dst.net_earnings_msat + src.net_earnings_msat, "pair"),
+            _ => (dst.net_earnings_msat, "destination"),
+        };
+
+        // Fee spent on this pair is capped at those net earnings
+        let max_fee_ppm = max_fee_ppm_for(config, &dst.balance.counterparty_node_id);
+        let planned_fee_msat = (amount_msat as f64 * max_fee_ppm as f64 / 1_000_000.0) as u64;
+        let fee_budget_msat = planned_fee_msat.min(benefit_msat.max(0) as u64);
+
+        // Each chunk is one self-payment that leaves through the source and
+        // comes back through the destination, so it must fit in one HTLC on
+        // both. Larger amounts are still moved, just in more chunks.
+        let max_chunk_msat = max_amount_msat
+            .min(src.balance.max_send_htlc_msat)
+            .min(dst.balance.max_receive_htlc_msat.unwrap_or(u64::MAX));
+
+        let skip = if i >= num_rebalance {
+            Some(format!(
+                "outside the top {}% of pairs ({} of {})",
+                TOP_REBALANCING_PERCENTILE, num_rebalance, num
+            ))
+        } else if benefit_msat <= 0 {
+            // Don't throw good money after bad
+            Some(format!(
+                "{} net earnings {} msat <= 0",
+                benefactor, benefit_msat
+            ))
+        } else if min_margin_msat > 0
+            && benefit_msat - (planned_fee_msat as i64) < min_margin_msat
+        {
+            Some(format!(
+                "{} net earnings {} msat don't beat the planned fee {} msat \
+                 by min_profit_margin_sats {}",
+                benefactor, benefit_msat, planned_fee_msat, config.rebalancer.min_profit_margin_sats
+            ))
+        } else if amount_msat == 0 {
+            Some("nothing to move (destination at target or source at its floor)".to_string())
+        } else if amount_msat < min_amount_msat {
+            Some(format!(
+                "amount {} msat below min_rebalance_amount_msat {}",
+                amount_msat, min_amount_msat
+            ))
+        } else if max_chunk_msat < min_amount_msat {
+            Some(format!(
+                "HTLC limit {} msat (source send {}, destination receive {}) below \
+                 min_rebalance_amount_msat {}",
+                max_chunk_msat,
+                src.balance.max_send_htlc_msat,
+                dst.balance
+                    .max_receive_htlc_msat
+                    .map_or("unlimited".to_string(), |m| m.to_string()),
+                min_amount_msat
+            ))
+        } else if fee_budget_msat == 0 {
+            Some("fee budget is 0".to_string())
+        } else {
+            None
+        };
+
+        pairs.push(PairPlan {
+            source: src_idx,
+            destination: dst_idx,
+            amount_msat,
+            fee_budget_msat,
+            max_chunk_msat,
+            benefit_msat,
+            skip,
+        });
+    }
+
+    Ok(Plan {
+        channels: assessed,
+        pairs,
+    })
+}
+
+/// Fill target for the destination ranked `rank` (0 = highest earnings) of
+/// `count`: `target_pct` for the top one, stepping down evenly towards
+/// `max_spendable` for the last.
+fn weighted_target_percent(
+    max_spendable: f64,
+    target_pct: f64,
+    rank: usize,
+    count: usize,
+) -> f64 {
+    let floor = max_spendable.min(target_pct);
+    let weight = (count - rank) as f64 / count as f64;
+    floor + (target_pct - floor) * weight
+}
+
+impl Plan {
+    /// Human-readable explanation of every channel's role and every pair.
+    pub fn print_text(&self) {
+        println!("Channels");
+        for a in &self.channels {
+            let role = match a.role {
+                Role::Destination => format!("destination, out net {} msat", a.net_earnings_msat),
+                Role::Source => format!("source, in net {} msat", a.net_earnings_msat),
+                Role::Neither => "neither (inside the source gap)".to_string(),
+                Role::Draining => "draining before close, not refilled".to_string(),
+            };
+            println!(
+                "  {} ({})  {:.1}% spendable  {}",
+                a.balance.channel_id,
+                a.balance.counterparty_node_id,
+                a.balance.spendable_percent,
+                role
+            );
+        }
+
+        println!();
+        println!("Pairs");
+        if self.pairs.is_empty() {
+            println!("  (none: need at least one source and one destination)");
+        }
+        for p in &self.pairs {
+            let src = &self.channels[p.source].balance;
+            let dst = &self.channels[p.destination].balance;
+            let verdict = match &p.skip {
+                Some(reason) => format!("SKIP: {}", reason),
+                None => "REBALANCE".to_string(),
+            };
+            println!(
+                "  {} -> {}  amount {} msat in chunks of <= {} msat, fee budget {} msat  {}",
+                src.counterparty_node_id,
+                dst.counterparty_node_id,
+                p.amount_msat,
+                p.max_chunk_msat,
+                p.fee_budget_msat,
+                verdict
+            );
         }
     }
+}
+
+pub async fn run(
+    config: &Config,
+    client: &(impl LdkClient + Sync),
+    db: &Database,
+    channels: &[&Channel],
+    summary: &mut CycleSummary,
+) -> anyhow::Result<()> {
+    let min_amount_msat = config.rebalancer.min_rebalance_amount_msat;
+
+    let plan = plan(config, db, channels)?;
 
-    if destinations.is_empty() || sources.is_empty() {
+    if plan.pairs.is_empty() {
         debug!("Rebalancer: nothing to do (no source/destination pairs)");
         return Ok(());
     }
 
-    // Sort destinations by out_net_earnings (highest first)
-    destinations.sort_by(|a, b| b.1.cmp(&a.1));
-    // Sort sources by in_net_earnings (highest first)
-    sources.sort_by(|a, b| b.1.cmp(&a.1));
-
-    // Pair the top percentile
-    let num = destinations.len().min(sources.len());
-    let num_rebalance = ((num as f64 * TOP_REBALANCING_PERCENTILE / 100.0) as usize).max(1);
-
     let max_total_fee = config
         .rebalancer
         .max_total_fee_sats
         .min(ABS_MAX_REBALANCE_FEE_SATS);
     let mut total_fee_spent: u64 = 0;
+    // Pairs left unfinished, by index, with the amount not moved
+    let mut shortfalls: Vec<(usize, u64)> = Vec::new();
 
-    for i in 0..num_rebalance {
-        let (dst_idx, dst_earnings) = destinations[i];
-        let (src_idx, _src_earnings) = sources[i];
-
-        let dst = &balances[dst_idx];
-        let src = &balances[src_idx];
+    for (pair_idx, pair) in plan.pairs.iter().enumerate() {
+        let src = &plan.channels[pair.source].balance;
+        let dst = &plan.channels[pair.destination].balance;
 
-        // If destination has negative out-earnings, skip (don't throw good money after bad)
-        if dst_earnings <= 0 {
-            info!(
-                "Rebalancer: peer {} has negative net earnings ({}msat), skipping",
-                dst.counterparty_node_id, dst_earnings
+        if let Some(reason) = &pair.skip {
+            debug!(
+                "Rebalancer: {} -> {} skipped: {}",
+                src.counterparty_node_id, dst.counterparty_node_id, reason
             );
-            break; // List is sorted, so everything after is worse
+            continue;
         }
+        let amount_msat = pair.amount_msat;
+        let max_fee_ppm = max_fee_ppm_for(config, &dst.counterparty_node_id);
 
-        // Compute amounts
-        let dest_target_msat = (dst.total_msat as f64 * target_pct / 100.0) as u64;
-        let dest_needed_msat = dest_target_msat.saturating_sub(dst.spendable_msat);
+        // Fee spent on this pair is capped at the net earnings that justify
+        // it, across all chunks.
+        let mut fee_remaining = pair.benefit_msat.max(0) as u64;
+        let mut moved_msat: u64 = 0;
 
-        let src_min_allowed_msat =
-            (src.total_msat as f64 * (max_spendable + source_gap) / 100.0) as u64;
-        let src_budget_msat = src.spendable_msat.saturating_sub(src_min_allowed_msat);
+        // Split into chunks of at most max_chunk_msat: smaller payments route
+        // far more reliably than one large one, and none may exceed the
+        // endpoints' HTLC limits.
+        while moved_msat < amount_msat {
+            let chunk_msat = (amount_msat - moved_msat).min(pair.max_chunk_msat);
+            if chunk_msat < min_amount_msat {
+                break;
+            }
 
-        let amount_msat = dest_needed_msat.min(src_budget_msat);
-        if amount_msat == 0 {
-            continue;
-        }
+            // Compute fee budget
+            let fee_budget_msat = (chunk_msat as f64 * max_fee_ppm as f64 / 1_000_000.0) as u64;
+            // Cap at the remaining net earnings
+            let fee_budget_msat = fee_budget_msat.min(fee_remaining);
+            // Cap at remaining total budget
+            let remaining_budget = (max_total_fee * 1000).saturating_sub(total_fee_spent);
+            let fee_budget_msat = fee_budget_msat.min(remaining_budget);
 
-        // Compute fee budget
-        let fee_budget_msat = (amount_msat as f64 * max_fee_ppm as f64 / 1_000_000.0) as u64;
-        // Cap at destination's net earnings
-        let fee_budget_msat = fee_budget_msat.min(dst_earnings as u64);
-        // Cap at remaining total budget
-        let remaining_budget = (max_total_fee * 1000).saturating_sub(total_fee_spent);
-        let fee_budget_msat = fee_budget_msat.min(remaining_budget);
+            if fee_budget_msat == 0 {
+                break;
+            }
 
-        if fee_budget_msat == 0 {
-            continue;
-        }
+            info!(
+                "Rebalancer: {} -> {} ({} msat, {}/{} msat done), max fee {} msat",
+                src.counterparty_node_id,
+                dst.counterparty_node_id,
+                chunk_msat,
+                moved_msat,
+                amount_msat,
+                fee_budget_msat
+            );
 
-        info!(
-            "Rebalancer: {} -> {} ({} msat), max fee {} msat",
-            src.counterparty_node_id, dst.counterparty_node_id, amount_msat, fee_budget_msat
-        );
+            if config.general.dry_run {
+                info!("  (dry-run: not executing)");
+                moved_msat += chunk_msat;
+                continue;
+            }
 
-        if config.general.dry_run {
-            info!("  (dry-run: not executing)");
-            continue;
-        }
+            // Execute via self-invoice
+            match execute_rebalance(client, chunk_msat, fee_budget_msat).await {
+                Ok(fee_paid) => {
+                    total_fee_spent += fee_paid;
+                    summary.rebalanced += 1;
+                    summary.rebalance_fee_msat += fee_paid;
+                    fee_remaining = fee_remaining.saturating_sub(fee_paid);
+                    moved_msat += chunk_msat;
+                    info!("Rebalancer: success, fee paid: {} msat", fee_paid);
 
-        // Execute via self-invoice
-        match execute_rebalance(client, amount_msat, fee_budget_msat).await {
-            Ok(fee_paid) => {
-                total_fee_spent += fee_paid;
-                info!("Rebalancer: success, fee paid: {} msat", fee_paid);
-
-                // Record in rebalance_costs
-                let now_bucket = {
-                    let now = chrono::Utc::now().timestamp();
-                    now - (now % 86400)
-                };
-                let conn = db.conn();
-                conn.execute(
-                    "INSERT INTO rebalance_costs \
-                     (channel_id, counterparty_node_id, day_bucket, fee_spent_msat, \
-                      amount_rebalanced_msat, direction) \
-                     VALUES (?1, ?2, ?3, ?4, ?5, 'out') \
-                     ON CONFLICT(channel_id, day_bucket, direction) DO UPDATE SET \
-                     fee_spent_msat = fee_spent_msat + ?4, \
-                     amount_rebalanced_msat = amount_rebalanced_msat + ?5",
-                    rusqlite::params![
-                        src.channel_id,
-                        src.counterparty_node_id,
-                        now_bucket,
-                        fee_paid,
-                        amount_msat,
-                    ],
-                )?;
-            }
-            Err(e) => {
-                warn!("Rebalancer: failed: {}", e);
+                    // Record in rebalance_costs
+                    let now_bucket = {
+                        let now = db.now().timestamp();
+                        now - (now % 86400)
+                    };
+                    let conn = db.conn()?;
+                    conn.execute(
+                        "INSERT INTO rebalance_costs \
+                         (channel_id, counterparty_node_id, day_bucket, fee_spent_msat, \
+                          amount_rebalanced_msat, direction) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, 'out') \
+                         ON CONFLICT(channel_id, day_bucket, direction) DO UPDATE SET \
+                         fee_spent_msat = fee_spent_msat + ?4, \
+                         amount_rebalanced_msat = amount_rebalanced_msat + ?5",
+                        rusqlite::params![
+                            src.channel_id,
+                            src.counterparty_node_id,
+                            now_bucket,
+                            fee_paid,
+                            chunk_msat,
+                        ],
+                    )?;
+                    history::record(
+                        &conn,
+                        &history::RebalanceRecord {
+                            src_channel_id: src.channel_id.clone(),
+                            src_counterparty_node_id: src.counterparty_node_id.clone(),
+                            dst_channel_id: dst.channel_id.clone(),
+                            dst_counterparty_node_id: dst.counterparty_node_id.clone(),
+                            amount_msat: chunk_msat,
+                            fee_spent_msat: fee_paid,
+                            rebalanced_at: db.now().timestamp() as f64,
+                        },
+                    )?;
+                    audit::emit(
+                        config,
+                        "rebalance",
+                        json!({
+                            "src_channel_id": src.channel_id,
+                            "src_counterparty_node_id": src.counterparty_node_id,
+                            "dst_channel_id": dst.channel_id,
+                            "dst_counterparty_node_id": dst.counterparty_node_id,
+                            "amount_msat": chunk_msat,
+                            "fee_msat": fee_paid,
+                        }),
+                    );
+                }
+                Err(e) => {
+                    // Stop chunking this pair; later cycles pick up the rest.
+                    warn!(
+                        "Rebalancer: failed after {}/{} msat: {}",
+                        moved_msat, amount_msat, e
+                    );
+                    break;
+                }
             }
         }
+
+        let remaining_msat = amount_msat - moved_msat;
+        if remaining_msat >= min_amount_msat {
+            shortfalls.push((pair_idx, remaining_msat));
+        }
+    }
+
+    if config.rebalancer.swap_severe_percent > 0.0 && !shortfalls.is_empty() {
+        let provider = swap::default_provider(config);
+        swap::fallback(config, db, provider.as_ref(), &plan, &shortfalls).await?;
     }
 
     Ok(())
@@ -236,3 +549,221 @@ async fn execute_rebalance(
     // TODO: Query ListPayments after payment to get exact fee.
     Ok(max_fee_msat)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_channel(id: &str, peer: &str, outbound_msat: u64) -> Channel {
+        Channel {
+            channel_id: id.to_string(),
+            counterparty_node_id: peer.to_string(),
+            channel_value_sats: 1_000_000,
+            outbound_capacity_msat: outbound_msat,
+            next_outbound_htlc_limit_msat: outbound_msat,
+            is_usable: true,
+            ..Default::default()
+        }
+    }
+
+    fn insert_out_earnings(db: &Database, peer: &str, fee_msat: i64) {
+        let now = chrono::Utc::now().timestamp();
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                 fee_earned_msat, amount_forwarded_msat, direction) \
+                 VALUES ('ch', ?1, ?2, ?3, 0, 'out')",
+                rusqlite::params![peer, now - now % 86400, fee_msat],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_plan_explains_skipped_and_selected_pairs() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let dst = make_channel("dst", "drained", 50_000_000);
+        let src = make_channel("src", "full", 950_000_000);
+        let mid = make_channel("mid", "middle", 260_000_000);
+        let channels = vec![&dst, &src, &mid];
+
+        // Destination hasn't earned anything: not worth paying to refill
+        let explained = plan(&config, &db, &channels).unwrap();
+        assert_eq!(explained.channels[0].role, Role::Destination);
+        assert_eq!(explained.channels[1].role, Role::Source);
+        assert_eq!(explained.channels[2].role, Role::Neither);
+        assert_eq!(explained.pairs.len(), 1);
+        assert!(explained.pairs[0].skip.as_ref().unwrap().contains("net earnings"));
+
+        insert_out_earnings(&db, "drained", 1_000_000);
+        let explained = plan(&config, &db, &channels).unwrap();
+        assert!(explained.pairs[0].skip.is_none(), "{:?}", explained.pairs[0].skip);
+        assert!(explained.pairs[0].amount_msat > 0);
+        assert!(explained.pairs[0].fee_budget_msat > 0);
+    }
+
+    #[test]
+    fn test_draining_channel_not_refilled() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.judge.enabled = true;
+        config.judge.drain_before_close = true;
+        let dst = make_channel("dst", "drained", 50_000_000);
+        let src = make_channel("src", "full", 950_000_000);
+        insert_out_earnings(&db, "drained", 1_000_000);
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO judge_drains (channel_id, counterparty_node_id, started_at) \
+                 VALUES ('dst', 'drained', 0)",
+                [],
+            )
+            .unwrap();
+
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert_eq!(explained.channels[0].role, Role::Draining);
+        assert!(explained.pairs.is_empty());
+    }
+
+    #[test]
+    fn test_pairs_never_share_a_channel() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let owned: Vec<Channel> = (0..6)
+            .map(|i| {
+                let spendable = if i % 2 == 0 { 50_000_000 } else { 950_000_000 };
+                make_channel(&format!("ch{}", i), &format!("peer{}", i), spendable)
+            })
+            .chain([make_channel("extra_src", "peer_x", 900_000_000)])
+            .collect();
+        let channels: Vec<&Channel> = owned.iter().collect();
+
+        let explained = plan(&config, &db, &channels).unwrap();
+        assert_eq!(explained.pairs.len(), 3);
+        let mut used = std::collections::HashSet::new();
+        for p in &explained.pairs {
+            assert!(used.insert(p.source), "source {} reused", p.source);
+            assert!(used.insert(p.destination), "destination {} reused", p.destination);
+        }
+    }
+
+    #[test]
+    fn test_peer_fee_ppm_override() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.rebalancer.max_fee_ppm = 1000;
+        let dst = make_channel("dst", "drained", 50_000_000);
+        let src = make_channel("src", "full", 950_000_000);
+        let channels = vec![&dst, &src];
+        insert_out_earnings(&db, "drained", 1_000_000_000);
+
+        let base = plan(&config, &db, &channels).unwrap().pairs[0].fee_budget_msat;
+        assert_eq!(base, 675_000);
+
+        // The override follows the destination's peer, not the source's
+        config.rebalancer.peer_max_fee_ppm.insert("full".to_string(), 100);
+        assert_eq!(plan(&config, &db, &channels).unwrap().pairs[0].fee_budget_msat, base);
+        config.rebalancer.peer_max_fee_ppm.insert("drained".to_string(), 3000);
+        assert_eq!(plan(&config, &db, &channels).unwrap().pairs[0].fee_budget_msat, 3 * base);
+    }
+
+    #[test]
+    fn test_plan_caps_chunks_at_htlc_limits() {
+        let db = Database::open_in_memory().unwrap();
+        let config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        insert_out_earnings(&db, "drained", 1_000_000);
+        let mut dst = make_channel("dst", "drained", 50_000_000);
+        let mut src = make_channel("src", "full", 950_000_000);
+
+        src.next_outbound_htlc_limit_msat = 30_000_000;
+        dst.inbound_htlc_maximum_msat = Some(20_000_000);
+        dst.counterparty_outbound_htlc_maximum_msat = Some(40_000_000);
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert!(explained.pairs[0].skip.is_none(), "{:?}", explained.pairs[0].skip);
+        assert_eq!(explained.pairs[0].max_chunk_msat, 20_000_000);
+
+        // Too small to be worth a payment at all
+        src.next_outbound_htlc_limit_msat = config.rebalancer.min_rebalance_amount_msat - 1;
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert!(explained.pairs[0].skip.as_ref().unwrap().contains("HTLC limit"));
+    }
+
+    #[test]
+    fn test_plan_requires_profit_margin() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let dst = make_channel("dst", "drained", 50_000_000);
+        let src = make_channel("src", "full", 950_000_000);
+        // Moves 675k sat (source floor), planned fee 675 sat; earned 1000 sat
+        insert_out_earnings(&db, "drained", 1_000_000);
+
+        config.rebalancer.min_profit_margin_sats = 200;
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert!(explained.pairs[0].skip.is_none(), "{:?}", explained.pairs[0].skip);
+
+        config.rebalancer.min_profit_margin_sats = 400;
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert!(explained.pairs[0].skip.as_ref().unwrap().contains("min_profit_margin_sats"));
+    }
+
+    #[test]
+    fn test_earnings_weighted_targets() {
+        assert_eq!(weighted_target_percent(25.0, 75.0, 0, 4), 75.0);
+        assert_eq!(weighted_target_percent(25.0, 75.0, 3, 4), 37.5);
+
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let top = make_channel("top", "top_earner", 50_000_000);
+        let low = make_channel("low", "low_earner", 50_000_000);
+        let src1 = make_channel("src1", "full1", 950_000_000);
+        let src2 = make_channel("src2", "full2", 950_000_000);
+        insert_out_earnings(&db, "top_earner", 5_000_000);
+        insert_out_earnings(&db, "low_earner", 1_000_000);
+        let channels = vec![&top, &low, &src1, &src2];
+
+        // Both destinations would be filled to 75%, capped by the source floor
+        let explained = plan(&config, &db, &channels).unwrap();
+        assert_eq!(explained.pairs[1].amount_msat, 675_000_000);
+
+        // Second of two only goes to 50%
+        config.rebalancer.earnings_weighted_targets = true;
+        let explained = plan(&config, &db, &channels).unwrap();
+        let second = &explained.channels[explained.pairs[1].destination];
+        assert_eq!(second.balance.channel_id, "low");
+        assert_eq!(explained.pairs[0].amount_msat, 675_000_000);
+        assert_eq!(explained.pairs[1].amount_msat, 450_000_000);
+    }
+
+    #[test]
+    fn test_primary_goal_picks_who_pays() {
+        let db = Database::open_in_memory().unwrap();
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        let dst = make_channel("dst", "drained", 50_000_000);
+        let src = make_channel("src", "full", 950_000_000);
+        // Only the source earns, from payments coming in through it
+        let now = chrono::Utc::now().timestamp();
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                 fee_earned_msat, amount_forwarded_msat, direction) \
+                 VALUES ('src', 'full', ?1, 800000, 0, 'in')",
+                [now - now % 86400],
+            )
+            .unwrap();
+
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert!(explained.pairs[0].skip.as_ref().unwrap().contains("destination net earnings"));
+
+        config.rebalancer.primary_goal = "inbound".to_string();
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert!(explained.pairs[0].skip.is_none(), "{:?}", explained.pairs[0].skip);
+        assert_eq!(explained.pairs[0].benefit_msat, 800_000);
+
+        insert_out_earnings(&db, "drained", 200_000);
+        config.rebalancer.primary_goal = "balance".to_string();
+        let explained = plan(&config, &db, &[&dst, &src]).unwrap();
+        assert_eq!(explained.pairs[0].benefit_msat, 1_000_000);
+    }
+}
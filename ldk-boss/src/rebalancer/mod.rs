@@ -1,7 +1,10 @@
 pub mod earnings;
+pub mod history;
+pub mod swap;
 
 use crate::client::LdkClient;
 use crate::config::Config;
+use crate::cycle_summary::CycleSummary;
 use crate::db::Database;
 use crate::state::NodeState;
 use log::debug;
@@ -12,13 +15,14 @@ pub async fn run(
     client: &(impl LdkClient + Sync),
     db: &Database,
     state: &NodeState,
+    summary: &mut CycleSummary,
 ) -> anyhow::Result<()> {
-    let usable: Vec<_> = state.channels.iter().filter(|c| c.is_usable).collect();
+    let usable: Vec<_> = state.channels.iter().filter(|c| state.is_automated(config, c)).collect();
 
     if usable.len() < 2 {
         debug!("Rebalancer: need at least 2 usable channels");
         return Ok(());
     }
 
-    earnings::run(config, client, db, &usable).await
+    earnings::run(config, client, db, &usable, summary).await
 }
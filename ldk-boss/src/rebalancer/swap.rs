@@ -0,0 +1,340 @@
+/// Submarine swap fallback for imbalances circular rebalancing can't fix.
+///
+/// When a pair the rebalancer decided to move isn't fully moved (the fee
+/// budget ran out or no route was found) and one of its channels is badly
+/// lopsided, a swap through an external service may be cheaper: a loop in
+/// (pay on-chain, receive over the channel) adds outbound to a drained
+/// destination, a loop out (pay over the channel, receive on-chain) sheds
+/// outbound from a full source.
+///
+/// Swaps are handed to a `SwapProvider`. Without `rebalancer.swap_command`
+/// the recommendation is only logged; with it, the command is run. Implement
+/// `SwapProvider` to plug in a service of your own and pass it to `fallback`.
+
+use super::earnings::{PairPlan, Plan};
+use crate::audit;
+use crate::config::Config;
+use crate::db::Database;
+use log::{info, warn};
+use serde_json::json;
+
+/// Swaps executed per cycle; further recommendations are only logged.
+const MAX_SWAPS_PER_CYCLE: usize = 1;
+/// A swap takes on-chain confirmations to settle, and until then the channel
+/// still looks lopsided. No second swap is started on a channel this soon.
+const SWAP_COOLDOWN_SECS: i64 = 86_400;
+
+/// `run_state` key prefix recording when a channel's last swap was started.
+/// `tracker::cleanup` prunes the keys of channels that are gone.
+pub const SWAP_KEY_PREFIX: &str = "swap_started:";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwapDirection {
+    /// Loop in: pay on-chain, receive over the channel. Adds outbound
+    In,
+    /// Loop out: pay over the channel, receive on-chain. Adds inbound
+    Out,
+}
+
+impl SwapDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwapDirection::In => "in",
+            SwapDirection::Out => "out",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapRequest {
+    pub channel_id: String,
+    pub counterparty_node_id: String,
+    pub direction: SwapDirection,
+    pub amount_msat: u64,
+}
+
+/// A swap service.
+#[async_trait::async_trait(?Send)]
+pub trait SwapProvider {
+    /// Short name for logs.
+    fn name(&self) -> &'static str;
+
+    /// Whether `swap` does anything; if not, requests are only logged.
+    fn executes(&self) -> bool;
+
+    /// Carry out the swap.
+    async fn swap(&self, request: &SwapRequest) -> anyhow::Result<()>;
+}
+
+/// Logs recommendations and executes nothing.
+pub struct LogOnly;
+
+/// Runs `rebalancer.swap_command` with the direction ("in" or "out"), the
+/// channel ID, the peer's node ID and the amount in sats as arguments. A
+/// zero exit status is success.
+pub struct CommandProvider {
+    pub command: String,
+}
+
+#[async_trait::async_trait(?Send)]
+impl SwapProvider for LogOnly {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn executes(&self) -> bool {
+        false
+    }
+
+    async fn swap(&self, _request: &SwapRequest) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SwapProvider for CommandProvider {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn executes(&self) -> bool {
+        true
+    }
+
+    async fn swap(&self, request: &SwapRequest) -> anyhow::Result<()> {
+        let output = tokio::process::Command::new(&self.command)
+            .arg(request.direction.as_str())
+            .arg(&request.channel_id)
+            .arg(&request.counterparty_node_id)
+            .arg((request.amount_msat / 1000).to_string())
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} exited with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The provider configured by `rebalancer.swap_command`.
+pub fn default_provider(config: &Config) -> Box<dyn SwapProvider> {
+    match &config.rebalancer.swap_command {
+        Some(command) => Box::new(CommandProvider {
+            command: command.clone(),
+        }),
+        None => Box::new(LogOnly),
+    }
+}
+
+/// The swap that would finish `pair`, if one of its channels is past
+/// `swap_severe_percent` from its edge: a loop in for a destination below it,
+/// otherwise a loop out for a source above 100 minus it. None when
+/// `swap_severe_percent` is 0.
+pub fn swap_for(
+    config: &Config,
+    plan: &Plan,
+    pair: &PairPlan,
+    remaining_msat: u64,
+) -> Option<SwapRequest> {
+    let severe = config.rebalancer.swap_severe_percent;
+    if severe <= 0.0 {
+        return None;
+    }
+    let dst = &plan.channels[pair.destination].balance;
+    let src = &plan.channels[pair.source].balance;
+
+    let (channel, direction) = if dst.spendable_percent < severe {
+        (dst, SwapDirection::In)
+    } else if src.spendable_percent > 100.0 - severe {
+        (src, SwapDirection::Out)
+    } else {
+        return None;
+    };
+    Some(SwapRequest {
+        channel_id: channel.channel_id.clone(),
+        counterparty_node_id: channel.counterparty_node_id.clone(),
+        direction,
+        amount_msat: remaining_msat,
+    })
+}
+
+/// When a swap was last started on this channel (unix seconds), if ever.
+fn last_started(db: &Database, channel_id: &str) -> Option<i64> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [format!("{}{}", SWAP_KEY_PREFIX, channel_id)],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
+
+/// Recommend, and with an executing provider carry out, swaps for pairs the
+/// circular rebalance left unfinished. `shortfalls` holds each such pair's
+/// index in `plan.pairs` with the amount still to move. A channel with a swap
+/// started in the last `SWAP_COOLDOWN_SECS` is assumed to have it still in
+/// flight and gets no other.
+pub async fn fallback(
+    config: &Config,
+    db: &Database,
+    provider: &dyn SwapProvider,
+    plan: &Plan,
+    shortfalls: &[(usize, u64)],
+) -> anyhow::Result<()> {
+    let mut executed = 0;
+    for &(pair_idx, remaining_msat) in shortfalls {
+        let Some(request) = swap_for(config, plan, &plan.pairs[pair_idx], remaining_msat) else {
+            continue;
+        };
+        info!(
+            "Rebalancer: circular rebalance fell {} msat short; recommend swap {} of {} msat \
+             on {} ({})",
+            remaining_msat,
+            request.direction.as_str(),
+            request.amount_msat,
+            request.channel_id,
+            request.counterparty_node_id
+        );
+
+        if !provider.executes() || config.general.dry_run || config.general.safe_mode {
+            continue;
+        }
+        let now = db.now().timestamp();
+        if last_started(db, &request.channel_id).is_some_and(|at| now - at < SWAP_COOLDOWN_SECS) {
+            info!(
+                "Rebalancer: a swap on {} started within the last day, not executing another",
+                request.channel_id
+            );
+            continue;
+        }
+        if executed >= MAX_SWAPS_PER_CYCLE {
+            info!("Rebalancer: swap limit for this cycle reached, not executing");
+            continue;
+        }
+        executed += 1;
+        // Recorded before the attempt: a failed command may still have
+        // started the swap
+        let key = format!("{}{}", SWAP_KEY_PREFIX, request.channel_id);
+        db.conn()?.execute(
+            "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+            rusqlite::params![key, now.to_string()],
+        )?;
+        match provider.swap(&request).await {
+            Ok(()) => {
+                info!(
+                    "Rebalancer: swap {} of {} msat on {} done via {}",
+                    request.direction.as_str(),
+                    request.amount_msat,
+                    request.channel_id,
+                    provider.name()
+                );
+                audit::emit(
+                    config,
+                    "swap",
+                    json!({
+                        "channel_id": request.channel_id,
+                        "counterparty_node_id": request.counterparty_node_id,
+                        "direction": request.direction.as_str(),
+                        "amount_msat": request.amount_msat,
+                    }),
+                );
+            }
+            Err(e) => warn!(
+                "Rebalancer: swap {} on {} via {} failed: {:#}",
+                request.direction.as_str(),
+                request.channel_id,
+                provider.name(),
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rebalancer::earnings;
+    use ldk_server_protos::types::Channel;
+    use std::cell::RefCell;
+
+    struct Recording {
+        requests: RefCell<Vec<SwapRequest>>,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl SwapProvider for Recording {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn executes(&self) -> bool {
+            true
+        }
+
+        async fn swap(&self, request: &SwapRequest) -> anyhow::Result<()> {
+            self.requests.borrow_mut().push(request.clone());
+            Ok(())
+        }
+    }
+
+    fn make_channel(id: &str, peer: &str, outbound_msat: u64) -> Channel {
+        Channel {
+            channel_id: id.to_string(),
+            counterparty_node_id: peer.to_string(),
+            channel_value_sats: 1_000_000,
+            outbound_capacity_msat: outbound_msat,
+            next_outbound_htlc_limit_msat: outbound_msat,
+            is_usable: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_swaps_only_severe_imbalances() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.rebalancer.swap_severe_percent = 10.0;
+        let drained = make_channel("drained", "d", 50_000_000);
+        let full = make_channel("full", "f", 800_000_000);
+        let channels = vec![&drained, &full];
+        let plan = earnings::plan(&config, &db, &channels).unwrap();
+        let provider = Recording {
+            requests: RefCell::new(Vec::new()),
+        };
+
+        fallback(&config, &db, &provider, &plan, &[(0, 300_000_000)]).await.unwrap();
+        let requests = provider.requests.borrow().clone();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].channel_id, "drained");
+        assert_eq!(requests[0].direction, SwapDirection::In);
+        assert_eq!(requests[0].amount_msat, 300_000_000);
+
+        // The first swap is still in flight the next cycle
+        fallback(&config, &db, &provider, &plan, &[(0, 300_000_000)]).await.unwrap();
+        assert_eq!(provider.requests.borrow().len(), 1);
+        clock.advance_secs(SWAP_COOLDOWN_SECS);
+        fallback(&config, &db, &provider, &plan, &[(0, 300_000_000)]).await.unwrap();
+        assert_eq!(provider.requests.borrow().len(), 2);
+
+        // 5% spendable isn't below a 4% threshold, and 80% isn't above 96%
+        config.rebalancer.swap_severe_percent = 4.0;
+        assert!(swap_for(&config, &plan, &plan.pairs[0], 300_000_000).is_none());
+
+        // Dry run only recommends
+        config.rebalancer.swap_severe_percent = 10.0;
+        config.general.dry_run = true;
+        clock.advance_secs(SWAP_COOLDOWN_SECS);
+        fallback(&config, &db, &provider, &plan, &[(0, 300_000_000)]).await.unwrap();
+        assert_eq!(provider.requests.borrow().len(), 2);
+    }
+}
@@ -1,6 +1,38 @@
 use crate::config::Config;
+use crate::db::Database;
 use rand::Rng;
 
+/// `run_state` key holding the tick count across restarts.
+const TICK_COUNT_KEY: &str = "scheduler_tick_count";
+
+/// Modules whose last run is recorded, in the order `status` lists them.
+pub const MODULES: &[&str] = &["reconnector", "fees", "autopilot", "rebalancer", "judge"];
+
+/// Remember that `module` ran just now (successfully or not).
+pub fn record_run(db: &Database, module: &str) -> anyhow::Result<()> {
+    db.conn()?.execute(
+        "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+        rusqlite::params![
+            format!("last_run:{}", module),
+            db.now().timestamp().to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+/// When `module` last ran (unix seconds), if ever.
+pub fn last_run(db: &Database, module: &str) -> Option<i64> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = ?1",
+            [format!("last_run:{}", module)],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
+
 /// Manages timing of periodic tasks with randomized jitter.
 pub struct Scheduler {
     tick_count: u64,
@@ -27,6 +59,40 @@ impl Scheduler {
         }
     }
 
+    /// Create a normal scheduler, continuing from the tick count saved by the
+    /// previous run if `general.persist_tick_count` is set. Otherwise every
+    /// restart is back at tick 0, which satisfies every interval, so each
+    /// restart runs the judge and autopilot straight away. Restoring keeps
+    /// them on their normal schedule instead.
+    pub fn restore(config: &Config, db: &Database) -> Self {
+        let mut s = Self::new(config);
+        if config.general.persist_tick_count {
+            s.tick_count = db
+                .conn()
+                .ok()
+                .and_then(|conn| {
+                    conn.query_row(
+                        "SELECT value FROM run_state WHERE key = ?1",
+                        [TICK_COUNT_KEY],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .ok()
+                })
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+        }
+        s
+    }
+
+    /// Save the tick count for `restore`.
+    pub fn save(&self, db: &Database) -> anyhow::Result<()> {
+        db.conn()?.execute(
+            "INSERT OR REPLACE INTO run_state (key, value) VALUES (?1, ?2)",
+            rusqlite::params![TICK_COUNT_KEY, self.tick_count.to_string()],
+        )?;
+        Ok(())
+    }
+
     /// Create a scheduler that forces all modules to run (for run-once mode).
     pub fn new_force_all(config: &Config) -> Self {
         let mut s = Self::new(config);
@@ -34,6 +100,11 @@ impl Scheduler {
         s
     }
 
+    /// Pick up settings from a reloaded config, keeping the tick count.
+    pub fn reconfigure(&mut self, config: &Config) {
+        self.trigger_probability = config.rebalancer.trigger_probability;
+    }
+
     pub fn tick(&mut self) {
         self.tick_count += 1;
     }
@@ -142,6 +213,41 @@ mod tests {
         assert!(sched.should_run_judge());
     }
 
+    #[test]
+    fn test_record_and_read_last_run() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        assert_eq!(last_run(&db, "judge"), None);
+
+        record_run(&db, "judge").unwrap();
+        clock.advance_secs(600);
+        assert_eq!(last_run(&db, "judge"), Some(1_700_000_000));
+        assert_eq!(last_run(&db, "fees"), None);
+
+        record_run(&db, "judge").unwrap();
+        assert_eq!(last_run(&db, "judge"), Some(1_700_000_600));
+    }
+
+    #[test]
+    fn test_tick_count_survives_restart() {
+        let mut config = test_config();
+        let db = Database::open_in_memory().unwrap();
+        let mut sched = Scheduler::restore(&config, &db);
+        for _ in 0..35 {
+            sched.tick();
+        }
+        sched.save(&db).unwrap();
+
+        let mut sched = Scheduler::restore(&config, &db);
+        assert_eq!(sched.tick_count(), 35);
+        sched.tick();
+        assert!(sched.should_run_judge());
+
+        config.general.persist_tick_count = false;
+        assert_eq!(Scheduler::restore(&config, &db).tick_count(), 0);
+    }
+
     #[test]
     fn test_rebalancer_interval_gating() {
         let config = test_config();
@@ -1,37 +1,108 @@
 use crate::client::LdkClient;
+use crate::config::Config;
 use crate::db::Database;
 use ldk_server_protos::api::{GetBalancesResponse, GetNodeInfoResponse};
 use ldk_server_protos::types::Channel;
-use log::debug;
+use log::{debug, warn};
+use std::collections::HashSet;
+
+/// A channel snapshot older than this is not trusted in place of a live list.
+const MAX_SNAPSHOT_AGE_SECS: i64 = 3600;
 
 /// Shared snapshot of node state collected at the start of each cycle.
 pub struct NodeState {
     pub node_info: GetNodeInfoResponse,
     pub balances: GetBalancesResponse,
     pub channels: Vec<Channel>,
+    /// Channels we have asked to close that are still listed. LDK stops
+    /// reporting a channel as usable once shutdown starts, but a close we
+    /// sent this cycle or last may not be reflected yet.
+    pub closing: HashSet<String>,
+}
+
+/// Whether the operator has reserved this channel, by ID or by peer
+/// (`general.strategic_channels` / `strategic_peers`). Strategic channels
+/// get no fee changes, rebalances or judge closures; only panic close, which
+/// answers a peer broadcasting a revoked state, still applies.
+pub fn is_strategic(config: &Config, channel: &Channel) -> bool {
+    config.general.strategic_channels.contains(&channel.channel_id)
+        || config.general.strategic_peers.contains(&channel.counterparty_node_id)
 }
 
 impl NodeState {
     /// Collect fresh node state from LDK Server.
-    pub async fn collect(client: &(impl LdkClient + Sync), _db: &Database) -> anyhow::Result<Self> {
+    pub async fn collect(client: &(impl LdkClient + Sync), db: &Database) -> anyhow::Result<Self> {
+        let channels = client.list_channels().await?.channels;
+        Self::assemble(client, db, channels).await
+    }
+
+    /// Like `collect`, but if listing channels fails, fall back to the
+    /// channels saved by the last successful call (if recent). The flag is
+    /// true when the snapshot was used: the state is then only good for
+    /// bookkeeping, not for acting on channels.
+    pub async fn collect_or_snapshot(
+        client: &(impl LdkClient + Sync),
+        db: &Database,
+    ) -> anyhow::Result<(Self, bool)> {
+        match client.list_channels().await {
+            Ok(resp) => {
+                save_snapshot(db, &resp.channels)?;
+                Ok((Self::assemble(client, db, resp.channels).await?, false))
+            }
+            Err(e) => match load_snapshot(db)? {
+                Some(channels) => {
+                    warn!(
+                        "Listing channels failed ({:#}); using the last snapshot of {} channels",
+                        e,
+                        channels.len()
+                    );
+                    Ok((Self::assemble(client, db, channels).await?, true))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn assemble(
+        client: &(impl LdkClient + Sync),
+        db: &Database,
+        channels: Vec<Channel>,
+    ) -> anyhow::Result<Self> {
         let node_info = client.get_node_info().await?;
         let balances = client.get_balances().await?;
-        let channels_resp = client.list_channels().await?;
 
         debug!(
             "Collected state: {} channels, {}sat onchain, {}sat lightning",
-            channels_resp.channels.len(),
+            channels.len(),
             balances.spendable_onchain_balance_sats,
             balances.total_lightning_balance_sats,
         );
 
+        let closing = closing_channels(db, &channels)?;
+        if !closing.is_empty() {
+            debug!("{} listed channel(s) are closing", closing.len());
+        }
+
         Ok(Self {
             node_info,
             balances,
-            channels: channels_resp.channels,
+            channels,
+            closing,
         })
     }
 
+    /// Usable and not on its way out: safe to update fees on, rebalance
+    /// through or judge.
+    pub fn is_active(&self, channel: &Channel) -> bool {
+        channel.is_usable && !self.closing.contains(&channel.channel_id)
+    }
+
+    /// Active and not strategic: a channel the fee, rebalancer and judge
+    /// modules may act on.
+    pub fn is_automated(&self, config: &Config, channel: &Channel) -> bool {
+        self.is_active(channel) && !is_strategic(config, channel)
+    }
+
     /// Total channel capacity in satoshis.
     pub fn total_channel_capacity_sats(&self) -> u64 {
         self.channels.iter().map(|c| c.channel_value_sats).sum()
@@ -44,11 +115,37 @@ impl NodeState {
 
     /// On-chain balance as percentage of total.
     pub fn onchain_percent(&self) -> f64 {
-        let total = self.total_funds_sats();
-        if total == 0 {
+        self.onchain_percent_of(self.total_funds_sats())
+    }
+
+    /// Spendable on-chain balance as percentage of `total_sats`.
+    pub fn onchain_percent_of(&self, total_sats: u64) -> f64 {
+        if total_sats == 0 {
             return 100.0;
         }
-        (self.balances.spendable_onchain_balance_sats as f64 / total as f64) * 100.0
+        (self.balances.spendable_onchain_balance_sats as f64 / total_sats as f64) * 100.0
+    }
+
+    /// Our side of the listed channels, from the channel list rather than
+    /// the balances call. Excludes our channel reserves, so it runs a
+    /// little below `total_lightning_balance_sats` even when both are fresh.
+    pub fn channel_lightning_sats(&self) -> u64 {
+        self.channels
+            .iter()
+            .map(|c| c.outbound_capacity_msat / 1000)
+            .sum()
+    }
+
+    /// Capacity (sats) of channels we funded whose funding transaction isn't
+    /// broadcast yet. Those funds still show in the on-chain balance; once
+    /// the funding is broadcast the balance excludes them on its own.
+    pub fn pending_outbound_sats(&self) -> u64 {
+        self.channels
+            .iter()
+            .filter(|c| c.is_outbound && !c.is_channel_ready && c.funding_txo.is_none())
+            .filter(|c| !self.closing.contains(&c.channel_id))
+            .map(|c| c.channel_value_sats)
+            .sum()
     }
 
     /// Number of usable channels.
@@ -68,3 +165,174 @@ impl NodeState {
         map
     }
 }
+
+/// Listed channels that the judge or panic close has already closed.
+fn closing_channels(db: &Database, channels: &[Channel]) -> anyhow::Result<HashSet<String>> {
+    let conn = db.conn()?;
+    let mut stmt = conn.prepare("SELECT 1 FROM judge_closures WHERE channel_id = ?1")?;
+    let mut closing = HashSet::new();
+    for ch in channels {
+        if stmt.exists([&ch.channel_id])? {
+            closing.insert(ch.channel_id.clone());
+        }
+    }
+    Ok(closing)
+}
+
+/// Replace the saved channel snapshot. Keeps the fields the trackers and
+/// reports read.
+fn save_snapshot(db: &Database, channels: &[Channel]) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM channel_snapshot", [])?;
+    for ch in channels {
+        tx.execute(
+            "INSERT INTO channel_snapshot (channel_id, user_channel_id, counterparty_node_id, \
+             channel_value_sats, outbound_capacity_msat, inbound_capacity_msat, \
+             is_channel_ready, is_usable, is_announced) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                ch.channel_id,
+                ch.user_channel_id,
+                ch.counterparty_node_id,
+                ch.channel_value_sats,
+                ch.outbound_capacity_msat,
+                ch.inbound_capacity_msat,
+                ch.is_channel_ready,
+                ch.is_usable,
+                ch.is_announced,
+            ],
+        )?;
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO run_state (key, value) VALUES ('channel_snapshot_at', ?1)",
+        [db.now().timestamp().to_string()],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// The saved channel snapshot, unless missing or older than
+/// `MAX_SNAPSHOT_AGE_SECS`.
+fn load_snapshot(db: &Database) -> anyhow::Result<Option<Vec<Channel>>> {
+    let conn = db.conn()?;
+    let taken_at: Option<i64> = conn
+        .query_row(
+            "SELECT value FROM run_state WHERE key = 'channel_snapshot_at'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .and_then(|v| v.parse().ok());
+    match taken_at {
+        Some(at) if db.now().timestamp() - at <= MAX_SNAPSHOT_AGE_SECS => {}
+        _ => return Ok(None),
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT channel_id, user_channel_id, counterparty_node_id, channel_value_sats, \
+         outbound_capacity_msat, inbound_capacity_msat, is_channel_ready, is_usable, \
+         is_announced FROM channel_snapshot",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Channel {
+            channel_id: row.get(0)?,
+            user_channel_id: row.get(1)?,
+            counterparty_node_id: row.get(2)?,
+            channel_value_sats: row.get(3)?,
+            outbound_capacity_msat: row.get(4)?,
+            inbound_capacity_msat: row.get(5)?,
+            is_channel_ready: row.get(6)?,
+            is_usable: row.get(7)?,
+            is_announced: row.get(8)?,
+            ..Default::default()
+        })
+    })?;
+    Ok(Some(rows.collect::<Result<_, _>>()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockLdkClient;
+
+    #[tokio::test]
+    async fn test_collect_flags_channels_we_closed() {
+        let db = Database::open_in_memory().unwrap();
+        let mut mock = MockLdkClient::new();
+        for id in ["ch1", "ch2"] {
+            mock.channels.channels.push(Channel {
+                channel_id: id.to_string(),
+                counterparty_node_id: "peer".to_string(),
+                is_usable: true,
+                ..Default::default()
+            });
+        }
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO judge_closures (channel_id, counterparty_node_id, closed_at, reason) \
+                 VALUES ('ch1', 'peer', 0, 'test')",
+                [],
+            )
+            .unwrap();
+
+        let state = NodeState::collect(&mock, &db).await.unwrap();
+        assert!(!state.is_active(&state.channels[0]));
+        assert!(state.is_active(&state.channels[1]));
+    }
+
+    #[test]
+    fn test_strategic_channels_not_automated() {
+        let mut config = Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.general.strategic_channels = vec!["ch1".to_string()];
+        config.general.strategic_peers = vec!["partner".to_string()];
+        let channel = |id: &str, peer: &str| Channel {
+            channel_id: id.to_string(),
+            counterparty_node_id: peer.to_string(),
+            is_usable: true,
+            ..Default::default()
+        };
+        let state = NodeState {
+            node_info: GetNodeInfoResponse::default(),
+            balances: GetBalancesResponse::default(),
+            channels: vec![channel("ch1", "peer"), channel("ch2", "partner"), channel("ch3", "peer")],
+            closing: Default::default(),
+        };
+
+        let automated: Vec<_> = state
+            .channels
+            .iter()
+            .filter(|c| state.is_automated(&config, c))
+            .map(|c| c.channel_id.as_str())
+            .collect();
+        assert_eq!(automated, vec!["ch3"]);
+        assert!(state.channels.iter().all(|c| state.is_active(c)));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_used_when_listing_fails() {
+        let db = Database::open_in_memory().unwrap();
+        let mut mock = MockLdkClient::new();
+        mock.channels.channels.push(Channel {
+            channel_id: "ch1".to_string(),
+            counterparty_node_id: "peer".to_string(),
+            channel_value_sats: 1_000_000,
+            is_usable: true,
+            ..Default::default()
+        });
+
+        let (_, degraded) = NodeState::collect_or_snapshot(&mock, &db).await.unwrap();
+        assert!(!degraded);
+
+        mock.fail_always("list_channels");
+        let (state, degraded) = NodeState::collect_or_snapshot(&mock, &db).await.unwrap();
+        assert!(degraded);
+        assert_eq!(state.channels.len(), 1);
+        assert_eq!(state.channels[0].channel_value_sats, 1_000_000);
+
+        // Without a snapshot the failure is passed on
+        let empty = Database::open_in_memory().unwrap();
+        assert!(NodeState::collect_or_snapshot(&mock, &empty).await.is_err());
+    }
+}
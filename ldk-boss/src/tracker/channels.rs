@@ -5,8 +5,8 @@ use std::collections::HashSet;
 
 /// Update channel_history table: detect new channels, mark closed ones.
 pub fn update(db: &Database, channels: &[Channel]) -> anyhow::Result<()> {
-    let conn = db.conn();
-    let now = chrono::Utc::now().timestamp() as f64;
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
 
     // Get currently-known open channels
     let mut known_open: HashSet<String> = HashSet::new();
@@ -74,10 +74,9 @@ pub fn update(db: &Database, channels: &[Channel]) -> anyhow::Result<()> {
 }
 
 /// Get channel age in days for a given channel_id.
-#[allow(dead_code)]
 pub fn channel_age_days(db: &Database, channel_id: &str) -> anyhow::Result<Option<f64>> {
-    let conn = db.conn();
-    let now = chrono::Utc::now().timestamp() as f64;
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
     let result = conn.query_row(
         "SELECT first_seen_at FROM channel_history WHERE channel_id = ?1",
         [channel_id],
@@ -122,6 +121,7 @@ mod tests {
         // Verify both channels recorded
         let count: i64 = db
             .conn()
+            .unwrap()
             .query_row("SELECT COUNT(*) FROM channel_history WHERE is_open = 1", [], |r| r.get(0))
             .unwrap();
         assert_eq!(count, 2);
@@ -145,6 +145,7 @@ mod tests {
         // ch2 should be marked closed
         let is_open: bool = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT is_open FROM channel_history WHERE channel_id = 'ch2'",
                 [],
@@ -156,6 +157,7 @@ mod tests {
         // ch1 should still be open
         let is_open: bool = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT is_open FROM channel_history WHERE channel_id = 'ch1'",
                 [],
@@ -167,13 +169,16 @@ mod tests {
 
     #[test]
     fn test_channel_updates_last_seen() {
-        let db = Database::open_in_memory().unwrap();
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
 
         let channels = vec![make_channel("ch1", "peer_a", 1_000_000)];
         update(&db, &channels).unwrap();
 
         let first_seen: f64 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT last_seen_at FROM channel_history WHERE channel_id = 'ch1'",
                 [],
@@ -181,14 +186,12 @@ mod tests {
             )
             .unwrap();
 
-        // Small sleep to ensure timestamp changes
-        std::thread::sleep(std::time::Duration::from_millis(10));
-
-        // Update again
+        clock.advance_secs(600);
         update(&db, &channels).unwrap();
 
         let second_seen: f64 = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT last_seen_at FROM channel_history WHERE channel_id = 'ch1'",
                 [],
@@ -196,7 +199,8 @@ mod tests {
             )
             .unwrap();
 
-        assert!(second_seen >= first_seen);
+        assert_eq!(first_seen, 1_700_000_000.0);
+        assert_eq!(second_seen, first_seen + 600.0);
     }
 
     #[test]
@@ -231,6 +235,7 @@ mod tests {
 
         let count: i64 = db
             .conn()
+            .unwrap()
             .query_row("SELECT COUNT(*) FROM channel_history WHERE is_open = 1", [], |r| r.get(0))
             .unwrap();
         assert_eq!(count, 0);
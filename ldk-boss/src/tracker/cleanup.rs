@@ -0,0 +1,203 @@
+use crate::db::Database;
+use crate::fees;
+use crate::judge::executioner;
+use crate::rebalancer::swap;
+use log::{debug, info};
+
+/// Remove per-peer state for peers we no longer have any open channel with.
+///
+/// Price theory decks, judge probations and cached peer addresses are keyed
+/// by peer and would otherwise accumulate forever on nodes with channel churn. Addresses from
+/// config seed nodes and the hardcoded list are kept: they are candidates,
+/// not (necessarily) peers, and get re-seeded every cycle anyway. Per-channel
+/// `run_state` keys (the judge's force-close fallback, swap cooldowns, fee
+/// clamp logging) go with their channel.
+///
+/// Must run after the channel tracker so new channels are already recorded.
+pub fn prune_closed_peers(db: &Database) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+
+    let cards = conn.execute(
+        "DELETE FROM price_theory_cards WHERE counterparty_node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    let centers = conn.execute(
+        "DELETE FROM price_theory_center WHERE counterparty_node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    let probation = conn.execute(
+        "DELETE FROM judge_probation WHERE counterparty_node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    conn.execute(
+        "DELETE FROM peer_uptime WHERE counterparty_node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    // Disconnect counts are kept (they expire on their own): the autopilot
+    // uses them to judge former peers as candidates
+    conn.execute(
+        "DELETE FROM peer_connectivity WHERE counterparty_node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    conn.execute(
+        "DELETE FROM reconnect_attempts WHERE node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    let addresses = conn.execute(
+        "DELETE FROM peer_addresses WHERE source NOT IN ('config', 'hardcoded') \
+         AND node_id NOT IN \
+         (SELECT counterparty_node_id FROM channel_history WHERE is_open = 1)",
+        [],
+    )?;
+    // Per-channel state kept in run_state, for channels that are gone
+    for prefix in [
+        executioner::FALLBACK_KEY_PREFIX,
+        swap::SWAP_KEY_PREFIX,
+        fees::CLAMP_LOG_KEY_PREFIX,
+    ] {
+        conn.execute(
+            "DELETE FROM run_state WHERE substr(key, 1, length(?1)) = ?1 \
+             AND substr(key, length(?1) + 1) NOT IN \
+             (SELECT channel_id FROM channel_history WHERE is_open = 1)",
+            [prefix],
+        )?;
+    }
+
+    if cards + centers + probation + addresses > 0 {
+        info!(
+            "Cleanup: removed {} price theory cards, {} centers, {} probations, \
+             {} peer addresses for closed peers",
+            cards, centers, probation, addresses
+        );
+    } else {
+        debug!("Cleanup: no orphaned per-peer state");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_channel(db: &Database, channel_id: &str, peer: &str, is_open: bool) {
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES (?1, 'u', ?2, 1000000, 0, 0, ?3)",
+                rusqlite::params![channel_id, peer, is_open],
+            )
+            .unwrap();
+    }
+
+    fn count(db: &Database, table: &str, node_column: &str, node: &str) -> i64 {
+        db.conn()
+            .unwrap()
+            .query_row(
+                &format!("SELECT COUNT(*) FROM {} WHERE {} = ?1", table, node_column),
+                [node],
+                |r| r.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_prune_closed_peers() {
+        let db = Database::open_in_memory().unwrap();
+        insert_channel(&db, "ch_open", "alive", true);
+        insert_channel(&db, "ch_closed", "gone", false);
+
+        for peer in ["alive", "gone"] {
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO price_theory_center (counterparty_node_id, price) VALUES (?1, 0)",
+                    [peer],
+                )
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO price_theory_cards \
+                     (counterparty_node_id, position, deck_order, price, lifetime) \
+                     VALUES (?1, 0, 0, 0, 1)",
+                    [peer],
+                )
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO peer_addresses (node_id, address, source) \
+                     VALUES (?1, '1.2.3.4:9735', 'listpeers')",
+                    [peer],
+                )
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "INSERT INTO reconnect_attempts (node_id, last_attempt_at) VALUES (?1, 0)",
+                    [peer],
+                )
+                .unwrap();
+        }
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO peer_addresses (node_id, address, source) \
+                 VALUES ('seed', '5.6.7.8:9735', 'config')",
+                [],
+            )
+            .unwrap();
+
+        prune_closed_peers(&db).unwrap();
+
+        assert_eq!(count(&db, "price_theory_cards", "counterparty_node_id", "alive"), 1);
+        assert_eq!(count(&db, "price_theory_center", "counterparty_node_id", "alive"), 1);
+        assert_eq!(count(&db, "peer_addresses", "node_id", "alive"), 1);
+        assert_eq!(count(&db, "reconnect_attempts", "node_id", "alive"), 1);
+
+        assert_eq!(count(&db, "price_theory_cards", "counterparty_node_id", "gone"), 0);
+        assert_eq!(count(&db, "price_theory_center", "counterparty_node_id", "gone"), 0);
+        assert_eq!(count(&db, "peer_addresses", "node_id", "gone"), 0);
+        assert_eq!(count(&db, "reconnect_attempts", "node_id", "gone"), 0);
+
+        assert_eq!(count(&db, "peer_addresses", "node_id", "seed"), 1);
+    }
+
+    #[test]
+    fn test_prune_closed_channel_run_state() {
+        let db = Database::open_in_memory().unwrap();
+        insert_channel(&db, "ch_open", "alive", true);
+        insert_channel(&db, "ch_closed", "gone", false);
+        for key in [
+            "force_close_fallback:ch_open",
+            "force_close_fallback:ch_closed",
+            "swap_started:ch_closed",
+            "fee_clamp_logged:ch_closed",
+            "other",
+        ] {
+            db.conn()
+                .unwrap()
+                .execute("INSERT INTO run_state (key, value) VALUES (?1, '0')", [key])
+                .unwrap();
+        }
+
+        prune_closed_peers(&db).unwrap();
+
+        let keys: Vec<String> = {
+            let conn = db.conn().unwrap();
+            let mut stmt = conn.prepare("SELECT key FROM run_state ORDER BY key").unwrap();
+            let rows = stmt.query_map([], |r| r.get(0)).unwrap();
+            rows.collect::<Result<_, _>>().unwrap()
+        };
+        assert_eq!(keys, vec!["force_close_fallback:ch_open", "other"]);
+    }
+}
@@ -0,0 +1,222 @@
+/// Per-channel fee demand, learned from forwarding (`fees.learn_fee_demand`).
+///
+/// Every forward says the outbound channel's fee rate was one the network
+/// would pay; a fee held for `IDLE_DAYS` without a single forward says it
+/// wasn't. Each channel keeps the lowest and highest rate it forwarded at
+/// and the lowest rate above those at which it went idle. Price theory uses
+/// them to start a peer's center where its channels are known to forward
+/// instead of at the neutral price.
+
+use crate::db::Database;
+
+/// A fee held this long without an outbound forward counts as idle.
+const IDLE_DAYS: i64 = 3;
+
+/// What a channel's forwards say about the fee rates it bears.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Demand {
+    pub lowest_forward_ppm: Option<u32>,
+    pub highest_forward_ppm: Option<u32>,
+    /// Lowest rate above `highest_forward_ppm` that saw no forwards
+    pub lowest_idle_ppm: Option<u32>,
+}
+
+/// Record a forward out of `channel_id` for `amount_msat` that earned
+/// `fee_msat`, at the proportional rate it paid (net of the base fee last set).
+pub fn record_forward(
+    conn: &rusqlite::Connection,
+    channel_id: &str,
+    counterparty_node_id: &str,
+    fee_msat: u64,
+    amount_msat: u64,
+    now: f64,
+) -> anyhow::Result<()> {
+    if amount_msat == 0 || channel_id.is_empty() {
+        return Ok(());
+    }
+    let base_msat: u64 = conn
+        .query_row(
+            "SELECT base_msat FROM fee_history WHERE channel_id = ?1",
+            [channel_id],
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+    let ppm = (fee_msat.saturating_sub(base_msat) as f64 * 1_000_000.0 / amount_msat as f64)
+        .round() as u32;
+
+    conn.execute(
+        "INSERT INTO fee_demand (channel_id, counterparty_node_id, lowest_forward_ppm, \
+         highest_forward_ppm, updated_at) VALUES (?1, ?2, ?3, ?3, ?4) \
+         ON CONFLICT(channel_id) DO UPDATE SET \
+         lowest_forward_ppm = MIN(COALESCE(lowest_forward_ppm, ?3), ?3), \
+         highest_forward_ppm = MAX(COALESCE(highest_forward_ppm, ?3), ?3), \
+         lowest_idle_ppm = CASE WHEN lowest_idle_ppm <= ?3 THEN NULL ELSE lowest_idle_ppm END, \
+         updated_at = ?4",
+        rusqlite::params![channel_id, counterparty_node_id, ppm, now],
+    )?;
+    Ok(())
+}
+
+/// Record open channels whose current fee (from `fee_history`) has been held
+/// for `IDLE_DAYS` without an outbound forward. Held counts from the last
+/// ppm change, so re-sending an unchanged fee (`max_fee_age_hours`) doesn't
+/// restart it. Only a rate above the highest one the channel forwarded at,
+/// and below any idle rate already known, is kept.
+pub fn record_idle(db: &Database) -> anyhow::Result<()> {
+    let now = db.now().timestamp();
+    let held_since = now - IDLE_DAYS * 86400;
+    db.conn()?.execute(
+        "INSERT INTO fee_demand (channel_id, counterparty_node_id, lowest_idle_ppm, updated_at) \
+         SELECT fh.channel_id, ch.counterparty_node_id, fh.ppm, ?1 \
+         FROM fee_history fh JOIN channel_history ch ON ch.channel_id = fh.channel_id \
+         WHERE ch.is_open = 1 AND COALESCE(fh.ppm_since, fh.updated_at) < ?2 \
+         AND NOT EXISTS (SELECT 1 FROM earnings e WHERE e.channel_id = fh.channel_id \
+                         AND e.direction = 'out' AND e.amount_forwarded_msat > 0 \
+                         AND e.day_bucket >= \
+                             CAST(COALESCE(fh.ppm_since, fh.updated_at) AS INTEGER) \
+                             / 86400 * 86400) \
+         ON CONFLICT(channel_id) DO UPDATE SET \
+         lowest_idle_ppm = excluded.lowest_idle_ppm, updated_at = excluded.updated_at \
+         WHERE (fee_demand.highest_forward_ppm IS NULL \
+                OR excluded.lowest_idle_ppm > fee_demand.highest_forward_ppm) \
+         AND (fee_demand.lowest_idle_ppm IS NULL \
+              OR excluded.lowest_idle_ppm < fee_demand.lowest_idle_ppm)",
+        rusqlite::params![now as f64, held_since as f64],
+    )?;
+    Ok(())
+}
+
+/// The demand seen across all of a peer's channels, closed ones included:
+/// the widest forwarding range, and the lowest idle rate.
+pub fn peer_demand(
+    conn: &rusqlite::Connection,
+    counterparty_node_id: &str,
+) -> anyhow::Result<Demand> {
+    Ok(conn.query_row(
+        "SELECT MIN(lowest_forward_ppm), MAX(highest_forward_ppm), MIN(lowest_idle_ppm) \
+         FROM fee_demand WHERE counterparty_node_id = ?1",
+        [counterparty_node_id],
+        |row| {
+            Ok(Demand {
+                lowest_forward_ppm: row.get(0)?,
+                highest_forward_ppm: row.get(1)?,
+                lowest_idle_ppm: row.get(2)?,
+            })
+        },
+    )?)
+}
+
+impl Demand {
+    /// Where to start pricing: the geometric middle of the forwarding range,
+    /// kept below the idle rate. None if nothing was ever forwarded.
+    pub fn seed_ppm(&self) -> Option<f64> {
+        let (low, high) = (self.lowest_forward_ppm?, self.highest_forward_ppm?);
+        let mid = ((low.max(1) as f64) * (high.max(1) as f64)).sqrt();
+        Some(match self.lowest_idle_ppm {
+            Some(idle) => mid.min(idle as f64),
+            None => mid,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_fee(db: &Database, channel_id: &str, ppm: u32, updated_at: f64) {
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO fee_history (channel_id, base_msat, ppm, updated_at) \
+                 VALUES (?1, 1000, ?2, ?3)",
+                rusqlite::params![channel_id, ppm, updated_at],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_forwards_and_idle_build_the_curve() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        let now = db.now().timestamp() as f64;
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES ('ch', 'u', 'peer', 1000000, 0, 0, 1)",
+                [],
+            )
+            .unwrap();
+        set_fee(&db, "ch", 100, now);
+
+        // 1000 msat base + 100 ppm of 1M sat, then 400 ppm of 1M sat
+        record_forward(&db.conn().unwrap(), "ch", "peer", 101_000, 1_000_000_000, now).unwrap();
+        record_forward(&db.conn().unwrap(), "ch", "peer", 401_000, 1_000_000_000, now).unwrap();
+        let demand = peer_demand(&db.conn().unwrap(), "peer").unwrap();
+        assert_eq!(demand.lowest_forward_ppm, Some(100));
+        assert_eq!(demand.highest_forward_ppm, Some(400));
+        assert_eq!(demand.lowest_idle_ppm, None);
+        assert_eq!(demand.seed_ppm(), Some(200.0));
+
+        // 800 ppm without forwards: idle there once held for IDLE_DAYS
+        clock.advance_secs(86400);
+        set_fee(&db, "ch", 800, db.now().timestamp() as f64);
+        clock.advance_secs(IDLE_DAYS * 86400 - 3600);
+        record_idle(&db).unwrap();
+        assert_eq!(peer_demand(&db.conn().unwrap(), "peer").unwrap().lowest_idle_ppm, None);
+        clock.advance_secs(2 * 3600);
+        record_idle(&db).unwrap();
+        let demand = peer_demand(&db.conn().unwrap(), "peer").unwrap();
+        assert_eq!(demand.lowest_idle_ppm, Some(800));
+
+        // A later forward at that rate shows it wasn't the limit after all
+        let now = db.now().timestamp() as f64;
+        record_forward(&db.conn().unwrap(), "ch", "peer", 801_000, 1_000_000_000, now).unwrap();
+        assert_eq!(peer_demand(&db.conn().unwrap(), "peer").unwrap().lowest_idle_ppm, None);
+    }
+
+    #[tokio::test]
+    async fn test_idle_survives_unchanged_fee_resends() {
+        use crate::fees::setter::apply_if_changed;
+        use ldk_server_protos::types::{Channel, ChannelConfig};
+
+        let mut config = crate::config::Config::test_default(std::path::PathBuf::from("/dev/null"));
+        config.fees.max_fee_age_hours = 24;
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES ('ch', 'u', 'peer', 1000000, 0, 0, 1)",
+                [],
+            )
+            .unwrap();
+        let mock = crate::client::mock::MockLdkClient::new();
+        let channel = Channel {
+            channel_id: "ch".to_string(),
+            counterparty_node_id: "peer".to_string(),
+            channel_config: Some(ChannelConfig {
+                forwarding_fee_base_msat: Some(1000),
+                forwarding_fee_proportional_millionths: Some(800),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // 800 ppm held, and re-sent every 25h, without a forward
+        apply_if_changed(&config, &mock, &db, &channel, 1000, 800).await.unwrap();
+        for _ in 0..3 {
+            clock.advance_secs(25 * 3600);
+            apply_if_changed(&config, &mock, &db, &channel, 1000, 800).await.unwrap();
+        }
+        assert_eq!(mock.update_config_calls.lock().unwrap().len(), 3);
+
+        record_idle(&db).unwrap();
+        assert_eq!(peer_demand(&db.conn().unwrap(), "peer").unwrap().lowest_idle_ppm, Some(800));
+    }
+}
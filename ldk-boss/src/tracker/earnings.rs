@@ -1,5 +1,6 @@
 use crate::client::LdkClient;
 use crate::db::Database;
+use crate::tracker::demand;
 use ldk_server_protos::types::PageToken;
 use log::{debug, info};
 
@@ -10,11 +11,22 @@ fn day_bucket(timestamp_secs: f64) -> i64 {
 }
 
 /// Incrementally fetch new forwarded payments and record earnings.
-pub async fn ingest(db: &Database, client: &(impl LdkClient + Sync)) -> anyhow::Result<()> {
-    let conn = db.conn();
+///
+/// Each forward is recorded against both channels with its full fee, so
+/// each direction's rows stand on their own (the rebalancer budgets from
+/// one direction). A total across both directions counts every fee twice;
+/// use `direction = 'out'` for node totals and
+/// `PeerEarnings::attributed_net` for a peer's. With `learn_demand`, the
+/// rate each forward paid is fed to `demand`.
+pub async fn ingest(
+    db: &Database,
+    client: &(impl LdkClient + Sync),
+    learn_demand: bool,
+) -> anyhow::Result<()> {
+    let conn = db.conn()?;
 
     // Load pagination cursor
-    let saved_token = load_page_token(conn)?;
+    let saved_token = load_page_token(&conn)?;
     let mut page_token = saved_token;
     let mut total_ingested = 0u64;
 
@@ -24,7 +36,7 @@ pub async fn ingest(db: &Database, client: &(impl LdkClient + Sync)) -> anyhow::
         for fwd in &resp.forwarded_payments {
             let fee_msat = fwd.total_fee_earned_msat.unwrap_or(0);
             let amount_msat = fwd.outbound_amount_forwarded_msat.unwrap_or(0);
-            let now_bucket = day_bucket(chrono::Utc::now().timestamp() as f64);
+            let now_bucket = day_bucket(db.now().timestamp() as f64);
 
             // Record incoming side (prev_channel_id)
             if !fwd.prev_channel_id.is_empty() {
@@ -62,6 +74,16 @@ pub async fn ingest(db: &Database, client: &(impl LdkClient + Sync)) -> anyhow::
                         amount_msat,
                     ],
                 )?;
+                if learn_demand {
+                    demand::record_forward(
+                        &conn,
+                        &fwd.next_channel_id,
+                        &fwd.next_node_id,
+                        fee_msat,
+                        amount_msat,
+                        db.now().timestamp() as f64,
+                    )?;
+                }
             }
 
             total_ingested += 1;
@@ -69,7 +91,7 @@ pub async fn ingest(db: &Database, client: &(impl LdkClient + Sync)) -> anyhow::
 
         // Save pagination state
         if let Some(ref token) = resp.next_page_token {
-            save_page_token(conn, token)?;
+            save_page_token(&conn, token)?;
             page_token = Some(token.clone());
         } else {
             // No more pages
@@ -97,7 +119,7 @@ pub fn earnings_since(
     channel_id: &str,
     since_timestamp: f64,
 ) -> anyhow::Result<(i64, i64)> {
-    let conn = db.conn();
+    let conn = db.conn()?;
     let bucket = day_bucket(since_timestamp);
     let row = conn.query_row(
         "SELECT COALESCE(SUM(fee_earned_msat), 0), COALESCE(SUM(amount_forwarded_msat), 0) \
@@ -114,14 +136,39 @@ pub fn peer_earnings_since(
     counterparty_node_id: &str,
     since_timestamp: f64,
 ) -> anyhow::Result<PeerEarnings> {
-    let conn = db.conn();
-    let bucket = day_bucket(since_timestamp);
+    peer_earnings_in(db, counterparty_node_id, day_bucket(since_timestamp), None)
+}
+
+/// Like `peer_earnings_since`, but only up to and including the day of
+/// `until_timestamp`, for looking back at an earlier point in time.
+pub fn peer_earnings_between(
+    db: &Database,
+    counterparty_node_id: &str,
+    since_timestamp: f64,
+    until_timestamp: f64,
+) -> anyhow::Result<PeerEarnings> {
+    peer_earnings_in(
+        db,
+        counterparty_node_id,
+        day_bucket(since_timestamp),
+        Some(day_bucket(until_timestamp)),
+    )
+}
+
+fn peer_earnings_in(
+    db: &Database,
+    counterparty_node_id: &str,
+    bucket: i64,
+    until_bucket: Option<i64>,
+) -> anyhow::Result<PeerEarnings> {
+    let conn = db.conn()?;
 
     let in_earned: i64 = conn
         .query_row(
             "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings \
-             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 AND direction = 'in'",
-            rusqlite::params![counterparty_node_id, bucket],
+             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 \
+             AND (?3 IS NULL OR day_bucket <= ?3) AND direction = 'in'",
+            rusqlite::params![counterparty_node_id, bucket, until_bucket],
             |r| r.get(0),
         )
         .unwrap_or(0);
@@ -129,8 +176,9 @@ pub fn peer_earnings_since(
     let out_earned: i64 = conn
         .query_row(
             "SELECT COALESCE(SUM(fee_earned_msat), 0) FROM earnings \
-             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 AND direction = 'out'",
-            rusqlite::params![counterparty_node_id, bucket],
+             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 \
+             AND (?3 IS NULL OR day_bucket <= ?3) AND direction = 'out'",
+            rusqlite::params![counterparty_node_id, bucket, until_bucket],
             |r| r.get(0),
         )
         .unwrap_or(0);
@@ -138,8 +186,9 @@ pub fn peer_earnings_since(
     let in_rebalance_cost: i64 = conn
         .query_row(
             "SELECT COALESCE(SUM(fee_spent_msat), 0) FROM rebalance_costs \
-             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 AND direction = 'in'",
-            rusqlite::params![counterparty_node_id, bucket],
+             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 \
+             AND (?3 IS NULL OR day_bucket <= ?3) AND direction = 'in'",
+            rusqlite::params![counterparty_node_id, bucket, until_bucket],
             |r| r.get(0),
         )
         .unwrap_or(0);
@@ -147,8 +196,9 @@ pub fn peer_earnings_since(
     let out_rebalance_cost: i64 = conn
         .query_row(
             "SELECT COALESCE(SUM(fee_spent_msat), 0) FROM rebalance_costs \
-             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 AND direction = 'out'",
-            rusqlite::params![counterparty_node_id, bucket],
+             WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 \
+             AND (?3 IS NULL OR day_bucket <= ?3) AND direction = 'out'",
+            rusqlite::params![counterparty_node_id, bucket, until_bucket],
             |r| r.get(0),
         )
         .unwrap_or(0);
@@ -181,6 +231,85 @@ impl PeerEarnings {
     pub fn total_net(&self) -> i64 {
         self.in_net() + self.out_net()
     }
+    /// Fees credited to the peer per `general.earnings_attribution`: those
+    /// of forwards arriving through it ("in"), leaving through it ("out"),
+    /// or half of each ("split"). Both directions record a forward's full
+    /// fee, so unlike `total_net` this counts each fee once.
+    pub fn attributed_earnings(&self, attribution: &str) -> i64 {
+        match attribution {
+            "in" => self.in_earnings_msat,
+            "out" => self.out_earnings_msat,
+            _ => (self.in_earnings_msat + self.out_earnings_msat) / 2,
+        }
+    }
+    /// `attributed_earnings` less everything spent rebalancing the peer's
+    /// channels
+    pub fn attributed_net(&self, attribution: &str) -> i64 {
+        self.attributed_earnings(attribution)
+            - self.in_expenditures_msat
+            - self.out_expenditures_msat
+    }
+}
+
+/// A peer's net earnings normalized by capacity and time, for reports.
+pub struct PeerYield {
+    pub counterparty_node_id: String,
+    /// Capacity of our open channels with the peer
+    pub capacity_sats: u64,
+    /// Net earnings over `days`, attributed as the judge counts them
+    pub net_earned_msat: i64,
+    /// The window, cut short if our oldest channel with the peer is younger
+    pub days: f64,
+}
+
+impl PeerYield {
+    /// Sats earned per million sats of capacity per day: the judge's
+    /// earned-per-size rate, scaled to be readable and comparable across
+    /// channel sizes.
+    pub fn yield_per_million_per_day(&self) -> f64 {
+        if self.capacity_sats == 0 || self.days <= 0.0 {
+            return 0.0;
+        }
+        self.net_earned_msat as f64 / 1000.0 / self.capacity_sats as f64 * 1_000_000.0
+            / self.days
+    }
+}
+
+/// Yield of every peer we have an open channel with, over the last
+/// `window_days`, highest first.
+pub fn peer_yields(
+    db: &Database,
+    window_days: u64,
+    attribution: &str,
+) -> anyhow::Result<Vec<PeerYield>> {
+    let now = db.now().timestamp() as f64;
+    let peers: Vec<(String, i64, f64)> = {
+        let conn = db.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT counterparty_node_id, SUM(channel_value_sats), MIN(first_seen_at) \
+             FROM channel_history WHERE is_open = 1 GROUP BY counterparty_node_id",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    let mut yields = Vec::with_capacity(peers.len());
+    for (peer, capacity_sats, first_seen_at) in peers {
+        let days = (window_days as f64).min((now - first_seen_at) / 86400.0);
+        let since = now - days * 86400.0;
+        yields.push(PeerYield {
+            net_earned_msat: peer_earnings_since(db, &peer, since)?.attributed_net(attribution),
+            counterparty_node_id: peer,
+            capacity_sats: capacity_sats as u64,
+            days,
+        });
+    }
+    yields.sort_by(|a, b| {
+        b.yield_per_million_per_day()
+            .partial_cmp(&a.yield_per_million_per_day())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    Ok(yields)
 }
 
 fn load_page_token(conn: &rusqlite::Connection) -> anyhow::Result<Option<PageToken>> {
@@ -248,6 +377,20 @@ mod tests {
         assert_eq!(day_bucket(next_day), 1704067200 + 86400);
     }
 
+    #[test]
+    fn test_attributed_earnings_count_fee_once() {
+        let pe = PeerEarnings {
+            in_earnings_msat: 1000,
+            out_earnings_msat: 3000,
+            in_expenditures_msat: 100,
+            out_expenditures_msat: 200,
+        };
+        assert_eq!(pe.attributed_earnings("in"), 1000);
+        assert_eq!(pe.attributed_earnings("out"), 3000);
+        assert_eq!(pe.attributed_earnings("split"), 2000);
+        assert_eq!(pe.attributed_net("split"), 1700);
+    }
+
     #[test]
     fn test_peer_earnings_net_calculations() {
         let pe = PeerEarnings {
@@ -285,22 +428,59 @@ mod tests {
         assert_eq!(pe.total_net(), 0);
     }
 
+    #[test]
+    fn test_peer_yields_normalize_by_size_and_age() {
+        let mut db = crate::db::Database::open_in_memory().unwrap();
+        db.set_clock(crate::clock::MockClock::at(1_700_000_000));
+        let now = db.now().timestamp() as f64;
+        let conn = db.conn().unwrap();
+        // big: 10M sat for 30+ days, earned 5000 sat; small: 500k sat for
+        // 10 days, earned 1000 sat
+        for (ch, peer, sats, age_days, fee_msat) in [
+            ("c1", "big", 10_000_000, 60.0, 5_000_000),
+            ("c2", "small", 500_000, 10.0, 1_000_000),
+        ] {
+            conn.execute(
+                "INSERT INTO channel_history (channel_id, user_channel_id, counterparty_node_id, \
+                 channel_value_sats, first_seen_at, last_seen_at, is_open) \
+                 VALUES (?1, ?1, ?2, ?3, ?4, ?5, 1)",
+                rusqlite::params![ch, peer, sats, now - age_days * 86400.0, now],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, \
+                 fee_earned_msat, amount_forwarded_msat, direction) \
+                 VALUES (?1, ?2, ?3, ?4, 0, 'out')",
+                rusqlite::params![ch, peer, day_bucket(now), fee_msat],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let yields = peer_yields(&db, 30, "out").unwrap();
+        assert_eq!(yields[0].counterparty_node_id, "small");
+        // 1000 sat / 0.5M sat / 10 days
+        assert!((yields[0].yield_per_million_per_day() - 200.0).abs() < 0.01);
+        // 5000 sat / 10M sat / 30 days
+        assert!((yields[1].yield_per_million_per_day() - 16.67).abs() < 0.01);
+    }
+
     #[test]
     fn test_load_page_token_round_trip() {
         let db = crate::db::Database::open_in_memory().unwrap();
-        let conn = db.conn();
+        let conn = db.conn().unwrap();
 
         // Initially no token
-        assert!(load_page_token(conn).unwrap().is_none());
+        assert!(load_page_token(&conn).unwrap().is_none());
 
         // Save and load
         let token = PageToken {
             index: 42,
             token: "abc123".to_string(),
         };
-        save_page_token(conn, &token).unwrap();
+        save_page_token(&conn, &token).unwrap();
 
-        let loaded = load_page_token(conn).unwrap().unwrap();
+        let loaded = load_page_token(&conn).unwrap().unwrap();
         assert_eq!(loaded.index, 42);
         assert_eq!(loaded.token, "abc123");
     }
@@ -316,7 +496,7 @@ mod tests {
     #[test]
     fn test_earnings_since_with_data() {
         let db = crate::db::Database::open_in_memory().unwrap();
-        let conn = db.conn();
+        let conn = db.conn().unwrap();
 
         // Insert earnings
         conn.execute(
@@ -338,7 +518,7 @@ mod tests {
     #[test]
     fn test_peer_earnings_since_with_data() {
         let db = crate::db::Database::open_in_memory().unwrap();
-        let conn = db.conn();
+        let conn = db.conn().unwrap();
 
         conn.execute(
             "INSERT INTO earnings (channel_id, counterparty_node_id, day_bucket, fee_earned_msat, amount_forwarded_msat, direction) \
@@ -1,6 +1,9 @@
 pub mod channels;
+pub mod cleanup;
+pub mod demand;
 pub mod earnings;
 pub mod onchain_fees;
+pub mod uptime;
 
 use crate::client::LdkClient;
 use crate::config::Config;
@@ -15,7 +18,35 @@ pub async fn update(
     config: &Config,
 ) -> anyhow::Result<()> {
     channels::update(db, &state.channels)?;
-    earnings::ingest(db, client).await?;
-    onchain_fees::update(db, &config.onchain_fees).await?;
+    cleanup::prune_closed_peers(db)?;
+
+    if config.general.concurrent_trackers {
+        // Both are dominated by network round-trips. They are polled on the
+        // current task (not spawned): each statement runs to completion
+        // between awaits, and an in-memory `Database` is a single
+        // `Connection`, which is not `Sync`.
+        let (earnings_res, fees_res) = tokio::join!(
+            earnings::ingest(
+                db,
+                client,
+                config.fees.learn_fee_demand,
+            ),
+            onchain_fees::update(db, &config.onchain_fees),
+        );
+        earnings_res?;
+        fees_res?;
+    } else {
+        earnings::ingest(
+            db,
+            client,
+            config.fees.learn_fee_demand,
+        )
+        .await?;
+        onchain_fees::update(db, &config.onchain_fees).await?;
+    }
+    onchain_fees::refresh_regime(db, &config.onchain_fees)?;
+    if config.fees.learn_fee_demand {
+        demand::record_idle(db)?;
+    }
     Ok(())
 }
@@ -1,6 +1,6 @@
 use crate::config::OnchainFeesConfig;
 use crate::db::Database;
-use log::{debug, warn};
+use log::{debug, info, warn};
 use serde::Deserialize;
 
 /// On-chain fee regime: low fees are favorable for channel operations.
@@ -10,15 +10,129 @@ pub enum FeeRegime {
     High,
 }
 
-/// Mempool.space recommended fees response.
+/// Mempool.space recommended fees response. Every field is optional and
+/// snake_case names are accepted too, so a changed response degrades to
+/// missing estimates instead of failing to parse.
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct MempoolFees {
-    fastest_fee: f64,
-    half_hour_fee: f64,
-    hour_fee: f64,
-    economy_fee: f64,
-    minimum_fee: f64,
+    #[serde(default, alias = "fastest_fee")]
+    fastest_fee: Option<f64>,
+    #[serde(default, alias = "half_hour_fee")]
+    half_hour_fee: Option<f64>,
+    #[serde(default, alias = "hour_fee")]
+    hour_fee: Option<f64>,
+    #[serde(default, alias = "economy_fee")]
+    economy_fee: Option<f64>,
+    #[serde(default, alias = "minimum_fee")]
+    minimum_fee: Option<f64>,
+}
+
+/// Estimates from fastest to slowest, as `onchain_fees.reference` names them.
+const REFERENCES: &[&str] = &["fastest", "half_hour", "hour", "economy", "minimum"];
+
+impl MempoolFees {
+    /// The estimate named by `onchain_fees.reference` (validated at load),
+    /// if the response had a usable one.
+    fn reference(&self, reference: &str) -> Option<f64> {
+        let fee = match reference {
+            "fastest" => self.fastest_fee,
+            "half_hour" => self.half_hour_fee,
+            "economy" => self.economy_fee,
+            "minimum" => self.minimum_fee,
+            _ => self.hour_fee,
+        };
+        fee.filter(|f| f.is_finite() && *f > 0.0)
+    }
+
+    /// The present estimate closest to `reference`, the faster one on a tie.
+    fn nearest(&self, reference: &str) -> Option<(&'static str, f64)> {
+        let at = REFERENCES.iter().position(|r| *r == reference).unwrap_or(2);
+        let mut by_distance: Vec<usize> = (0..REFERENCES.len()).collect();
+        by_distance.sort_by_key(|i| (i.abs_diff(at), *i));
+        by_distance
+            .into_iter()
+            .find_map(|i| Some((REFERENCES[i], self.reference(REFERENCES[i])?)))
+    }
+}
+
+/// One point of mempool.space's block fee-rate history: fee-rate
+/// percentiles (sat/vB) of a group of recent blocks.
+#[derive(Deserialize)]
+struct BlockFeeRates {
+    timestamp: i64,
+    #[serde(default, rename = "avgFee_10")]
+    avg_fee_10: Option<f64>,
+    #[serde(default, rename = "avgFee_25")]
+    avg_fee_25: Option<f64>,
+    #[serde(default, rename = "avgFee_50")]
+    avg_fee_50: Option<f64>,
+    #[serde(default, rename = "avgFee_75")]
+    avg_fee_75: Option<f64>,
+    #[serde(default, rename = "avgFee_90")]
+    avg_fee_90: Option<f64>,
+}
+
+impl BlockFeeRates {
+    /// The percentile standing in for `onchain_fees.reference`: what got
+    /// into blocks at higher percentiles confirmed against more competition.
+    fn reference(&self, reference: &str) -> Option<f64> {
+        let fee = match reference {
+            "fastest" => self.avg_fee_90,
+            "half_hour" => self.avg_fee_75,
+            "economy" => self.avg_fee_25,
+            "minimum" => self.avg_fee_10,
+            _ => self.avg_fee_50,
+        };
+        fee.filter(|f| f.is_finite() && *f > 0.0)
+    }
+}
+
+/// Seed an empty sample table from the fee rates of the last day's blocks
+/// (`onchain_fees.backfill_on_startup`), so the regime is known from the
+/// first cycle instead of after `min_samples_for_regime` of them. Returns
+/// how many samples were added.
+pub async fn backfill(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result<usize> {
+    if !config.backfill_on_startup || config.provider == "none" {
+        return Ok(0);
+    }
+    let existing: i64 = db
+        .conn()?
+        .query_row("SELECT COUNT(*) FROM onchain_fee_samples", [], |row| row.get(0))?;
+    if existing > 0 {
+        return Ok(0);
+    }
+
+    let url = format!("{}/v1/mining/blocks/fee-rates/24h", config.mempool_api_url);
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?;
+    let history: Vec<BlockFeeRates> = client.get(&url).send().await?.json().await?;
+
+    let added = store_backfill(db, &history, &config.reference)?;
+    info!("On-chain fees: backfilled {} samples from recent blocks", added);
+    Ok(added)
+}
+
+fn store_backfill(
+    db: &Database,
+    history: &[BlockFeeRates],
+    reference: &str,
+) -> anyhow::Result<usize> {
+    let conn = db.conn()?;
+    let cutoff = db.now().timestamp() - 7 * 86400;
+    let mut added = 0;
+    for point in history.iter().filter(|p| p.timestamp >= cutoff) {
+        let Some(feerate) = point.reference(reference) else {
+            continue;
+        };
+        conn.execute(
+            "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (?1, ?2)",
+            rusqlite::params![feerate, point.timestamp as f64],
+        )?;
+        added += 1;
+    }
+    Ok(added)
 }
 
 /// Poll fee estimator for current fee estimates and record a sample.
@@ -29,7 +143,7 @@ pub async fn update(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result
     }
 
     // Try to fetch from mempool.space (or configured URL)
-    let feerate = match fetch_mempool_fee(&config.mempool_api_url).await {
+    let feerate = match fetch_mempool_fee(config).await {
         Ok(fee) => fee,
         Err(e) => {
             warn!("Failed to fetch on-chain fees from mempool.space: {}", e);
@@ -37,8 +151,8 @@ pub async fn update(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result
         }
     };
 
-    let conn = db.conn();
-    let now = chrono::Utc::now().timestamp() as f64;
+    let conn = db.conn()?;
+    let now = db.now().timestamp() as f64;
 
     conn.execute(
         "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (?1, ?2)",
@@ -63,12 +177,15 @@ pub async fn update(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result
 /// If the current fee is below the `hi_to_lo_percentile` of history: Low regime.
 /// If above `lo_to_hi_percentile`: High regime.
 /// Otherwise: maintain previous state (hysteresis).
-pub fn current_regime(
-    db: &Database,
-    hi_to_lo_pct: f64,
-    lo_to_hi_pct: f64,
-) -> anyhow::Result<FeeRegime> {
-    let conn = db.conn();
+///
+/// Going high takes `require_sustained_high_regime_samples` consecutive
+/// samples above the threshold; until then a spike also keeps the previous
+/// state.
+///
+/// With fewer than `min_samples_for_regime` samples the percentiles are
+/// meaningless, so the configured `default_regime` is returned instead.
+pub fn current_regime(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result<FeeRegime> {
+    let conn = db.conn()?;
 
     // Get all samples ordered by feerate
     let mut stmt = conn.prepare(
@@ -79,16 +196,24 @@ pub fn current_regime(
         .filter_map(|r| r.ok())
         .collect();
 
-    if feerates.is_empty() {
-        // No data yet -- assume high fee regime to be conservative
-        // (CLBoss initializes with low-fee history to be conservative;
-        // we go the other direction since we don't want to open channels
-        // before we have fee data)
-        return Ok(FeeRegime::High);
-    }
-
     let n = feerates.len();
 
+    if n == 0 || n < config.min_samples_for_regime {
+        // Not enough data yet -- use the configured default, "high" unless
+        // overridden (CLBoss initializes with low-fee history to be
+        // conservative; we go the other direction since we don't want to
+        // open channels before we have fee data)
+        debug!(
+            "Fee regime: only {} samples (need {}), assuming {}",
+            n, config.min_samples_for_regime, config.default_regime
+        );
+        return Ok(if config.default_regime == "low" {
+            FeeRegime::Low
+        } else {
+            FeeRegime::High
+        });
+    }
+
     // Get the latest fee
     let latest: f64 = conn
         .query_row(
@@ -98,18 +223,32 @@ pub fn current_regime(
         )
         .unwrap_or(0.0);
 
-    // Compute percentile thresholds
-    let lo_idx = ((hi_to_lo_pct / 100.0) * n as f64) as usize;
-    let hi_idx = ((lo_to_hi_pct / 100.0) * n as f64) as usize;
-
-    let lo_threshold = feerates[lo_idx.min(n - 1)];
-    let hi_threshold = feerates[hi_idx.min(n - 1)];
+    let (lo_threshold, hi_threshold) = thresholds(&feerates, config);
+
+    let sustained_high = || -> anyhow::Result<bool> {
+        let needed = config.require_sustained_high_regime_samples;
+        let mut stmt = conn.prepare(
+            "SELECT feerate_sat_per_vb FROM onchain_fee_samples \
+             ORDER BY sampled_at DESC LIMIT ?1",
+        )?;
+        let recent: Vec<f64> = stmt
+            .query_map([needed as i64], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(recent.len() == needed && recent.iter().all(|f| *f >= hi_threshold))
+    };
 
     if latest <= lo_threshold {
         Ok(FeeRegime::Low)
-    } else if latest >= hi_threshold {
+    } else if latest >= hi_threshold && sustained_high()? {
         Ok(FeeRegime::High)
     } else {
+        if latest >= hi_threshold {
+            debug!(
+                "Fee regime: {:.1} sat/vB is high but not yet for {} samples",
+                latest, config.require_sustained_high_regime_samples
+            );
+        }
         // Hysteresis: check saved state
         let saved = conn
             .query_row(
@@ -117,7 +256,7 @@ pub fn current_regime(
                 [],
                 |row| row.get::<_, String>(0),
             )
-            .unwrap_or_else(|_| "high".to_string());
+            .unwrap_or_else(|_| config.default_regime.clone());
 
         if saved == "low" {
             Ok(FeeRegime::Low)
@@ -127,23 +266,164 @@ pub fn current_regime(
     }
 }
 
-/// Save the current fee regime for hysteresis.
+/// Low and high feerate thresholds: the `hi_to_lo_percentile` and
+/// `lo_to_hi_percentile` of `feerates`, which must be sorted and non-empty.
+fn thresholds(feerates: &[f64], config: &OnchainFeesConfig) -> (f64, f64) {
+    let n = feerates.len();
+    let lo_idx = ((config.hi_to_lo_percentile / 100.0) * n as f64) as usize;
+    let hi_idx = ((config.lo_to_hi_percentile / 100.0) * n as f64) as usize;
+    (feerates[lo_idx.min(n - 1)], feerates[hi_idx.min(n - 1)])
+}
+
+/// The inputs to `current_regime` and its result, for the `fee-regime`
+/// command.
+pub struct RegimeExplanation {
+    pub regime: FeeRegime,
+    pub sample_count: usize,
+    /// Most recent sample (sat/vB)
+    pub latest: Option<f64>,
+    /// (low, high) thresholds, once there are enough samples
+    pub thresholds: Option<(f64, f64)>,
+    /// Regime saved at the last refresh, which the hysteresis band keeps
+    pub saved: Option<String>,
+    /// When the saved regime was entered (unix seconds)
+    pub saved_since: Option<i64>,
+}
+
+pub fn explain(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result<RegimeExplanation> {
+    let regime = current_regime(db, config)?;
+    let conn = db.conn()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT feerate_sat_per_vb FROM onchain_fee_samples ORDER BY feerate_sat_per_vb ASC",
+    )?;
+    let feerates: Vec<f64> = stmt
+        .query_map([], |row| row.get(0))?
+        .filter_map(|r| r.ok())
+        .collect();
+    let latest = conn
+        .query_row(
+            "SELECT feerate_sat_per_vb FROM onchain_fee_samples ORDER BY sampled_at DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .ok();
+    let run_state = |key: &str| -> Option<String> {
+        conn.query_row("SELECT value FROM run_state WHERE key = ?1", [key], |row| {
+            row.get(0)
+        })
+        .ok()
+    };
+
+    let enough = !feerates.is_empty() && feerates.len() >= config.min_samples_for_regime;
+    Ok(RegimeExplanation {
+        regime,
+        sample_count: feerates.len(),
+        latest,
+        thresholds: enough.then(|| thresholds(&feerates, config)),
+        saved: run_state("fee_regime"),
+        saved_since: run_state("fee_regime_since").and_then(|v| v.parse().ok()),
+    })
+}
+
+/// Recompute the regime from the latest samples and persist it, so the
+/// hysteresis band keeps the previous regime across cycles and restarts.
+/// Must run every cycle, after the new sample is recorded.
+pub fn refresh_regime(db: &Database, config: &OnchainFeesConfig) -> anyhow::Result<FeeRegime> {
+    let regime = current_regime(db, config)?;
+    save_regime(db, regime)?;
+    debug!("Fee regime: {:?}", regime);
+    Ok(regime)
+}
+
+/// Save the current fee regime for hysteresis, and when it was entered.
 pub fn save_regime(db: &Database, regime: FeeRegime) -> anyhow::Result<()> {
     let value = match regime {
         FeeRegime::Low => "low",
         FeeRegime::High => "high",
     };
-    db.conn().execute(
+    let conn = db.conn()?;
+    let previous: Option<String> = conn
+        .query_row(
+            "SELECT value FROM run_state WHERE key = 'fee_regime'",
+            [],
+            |row| row.get(0),
+        )
+        .ok();
+    // Unchanged: only fill in a missing start time
+    let since_sql = if previous.as_deref() == Some(value) {
+        "INSERT OR IGNORE INTO run_state (key, value) VALUES ('fee_regime_since', ?1)"
+    } else {
+        "INSERT OR REPLACE INTO run_state (key, value) VALUES ('fee_regime_since', ?1)"
+    };
+    conn.execute(since_sql, [db.now().timestamp().to_string()])?;
+    conn.execute(
         "INSERT OR REPLACE INTO run_state (key, value) VALUES ('fee_regime', ?1)",
         [value],
     )?;
     Ok(())
 }
 
+/// The regime saved by the last `refresh_regime`, if any.
+pub fn saved_regime(db: &Database) -> Option<FeeRegime> {
+    let saved: String = db
+        .conn()
+        .ok()?
+        .query_row(
+            "SELECT value FROM run_state WHERE key = 'fee_regime'",
+            [],
+            |row| row.get(0),
+        )
+        .ok()?;
+    match saved.as_str() {
+        "low" => Some(FeeRegime::Low),
+        "high" => Some(FeeRegime::High),
+        _ => None,
+    }
+}
+
+/// The most recent fee sample as (sat/vB, sampled at in unix seconds), if
+/// there is one.
+pub fn latest_sample(db: &Database) -> Option<(f64, f64)> {
+    db.conn()
+        .ok()?
+        .query_row(
+            "SELECT feerate_sat_per_vb, sampled_at FROM onchain_fee_samples \
+             ORDER BY sampled_at DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()
+}
+
+/// When the saved regime turned Low (unix seconds), if it is Low.
+pub fn low_regime_since(db: &Database) -> Option<i64> {
+    let conn = db.conn().ok()?;
+    let saved: String = conn
+        .query_row(
+            "SELECT value FROM run_state WHERE key = 'fee_regime'",
+            [],
+            |row| row.get(0),
+        )
+        .ok()?;
+    if saved != "low" {
+        return None;
+    }
+    conn.query_row(
+        "SELECT value FROM run_state WHERE key = 'fee_regime_since'",
+        [],
+        |row| row.get::<_, String>(0),
+    )
+    .ok()?
+    .parse()
+    .ok()
+}
+
 /// Insert a fee sample directly (for testing).
 #[cfg(test)]
 fn insert_sample(db: &Database, feerate: f64, sampled_at: f64) {
     db.conn()
+        .unwrap()
         .execute(
             "INSERT INTO onchain_fee_samples (feerate_sat_per_vb, sampled_at) VALUES (?1, ?2)",
             rusqlite::params![feerate, sampled_at],
@@ -151,8 +431,8 @@ fn insert_sample(db: &Database, feerate: f64, sampled_at: f64) {
         .unwrap();
 }
 
-async fn fetch_mempool_fee(api_url: &str) -> anyhow::Result<f64> {
-    let url = format!("{}/v1/fees/recommended", api_url);
+async fn fetch_mempool_fee(config: &OnchainFeesConfig) -> anyhow::Result<f64> {
+    let url = format!("{}/v1/fees/recommended", config.mempool_api_url);
 
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(10))
@@ -165,8 +445,27 @@ async fn fetch_mempool_fee(api_url: &str) -> anyhow::Result<f64> {
         .json()
         .await?;
 
-    // Use the "hour" fee as our reference (moderate urgency)
-    Ok(resp.hour_fee)
+    pick_estimate(&resp, config)
+}
+
+/// The configured estimate, or with `reference_fallback` the nearest one
+/// present when the response lacks it.
+fn pick_estimate(fees: &MempoolFees, config: &OnchainFeesConfig) -> anyhow::Result<f64> {
+    if let Some(fee) = fees.reference(&config.reference) {
+        return Ok(fee);
+    }
+    if !config.reference_fallback {
+        anyhow::bail!("response has no \"{}\" estimate", config.reference);
+    }
+    let Some((name, fee)) = fees.nearest(&config.reference) else {
+        anyhow::bail!("response has no usable fee estimate at all");
+    };
+    warn!(
+        "Mempool API response has no \"{}\" estimate, recording \"{}\" ({} sat/vB) \
+         instead; the API may have changed",
+        config.reference, name, fee
+    );
+    Ok(fee)
 }
 
 #[cfg(test)]
@@ -174,10 +473,77 @@ mod tests {
     use super::*;
     use crate::db::Database;
 
+    fn fees_config(min_samples: usize) -> OnchainFeesConfig {
+        OnchainFeesConfig {
+            min_samples_for_regime: min_samples,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mempool_reference_selection() {
+        let fees: MempoolFees = serde_json::from_str(
+            r#"{"fastestFee":30,"halfHourFee":20,"hourFee":10,"economyFee":5,"minimumFee":1}"#,
+        )
+        .unwrap();
+        assert_eq!(fees.reference("fastest"), Some(30.0));
+        assert_eq!(fees.reference("half_hour"), Some(20.0));
+        assert_eq!(fees.reference("hour"), Some(10.0));
+        assert_eq!(fees.reference("economy"), Some(5.0));
+        assert_eq!(fees.reference("minimum"), Some(1.0));
+    }
+
+    #[test]
+    fn test_mempool_changed_schema_falls_back() {
+        let mut config = fees_config(6);
+        // hourFee gone, one field renamed to snake_case, an unknown one added
+        let fees: MempoolFees = serde_json::from_str(
+            r#"{"fastestFee":30,"half_hour_fee":20,"economyFee":5,"nextBlockFee":40}"#,
+        )
+        .unwrap();
+        assert_eq!(fees.reference("half_hour"), Some(20.0));
+        assert_eq!(fees.reference("hour"), None);
+        assert_eq!(pick_estimate(&fees, &config).unwrap(), 20.0);
+
+        config.reference = "minimum".to_string();
+        assert_eq!(pick_estimate(&fees, &config).unwrap(), 5.0);
+
+        config.reference_fallback = false;
+        assert!(pick_estimate(&fees, &config).is_err());
+
+        let empty: MempoolFees = serde_json::from_str("{}").unwrap();
+        config.reference_fallback = true;
+        assert!(pick_estimate(&empty, &config).is_err());
+    }
+
+    #[test]
+    fn test_backfill_from_block_history() {
+        let mut db = Database::open_in_memory().unwrap();
+        db.set_clock(crate::clock::MockClock::at(1_700_000_000));
+        let now = db.now().timestamp();
+        let history: Vec<BlockFeeRates> = serde_json::from_str(&format!(
+            r#"[{{"avgHeight":1,"timestamp":{},"avgFee_0":1,"avgFee_10":2,"avgFee_25":3,
+                 "avgFee_50":5,"avgFee_75":8,"avgFee_90":13,"avgFee_100":50}},
+                {{"avgHeight":2,"timestamp":{},"avgFee_50":4}},
+                {{"avgHeight":3,"timestamp":{},"avgFee_90":20}},
+                {{"avgHeight":0,"timestamp":{},"avgFee_50":9}}]"#,
+            now - 3600,
+            now - 1800,
+            now - 600,
+            now - 8 * 86400
+        ))
+        .unwrap();
+
+        // The third point has no median, the last is older than we keep
+        assert_eq!(store_backfill(&db, &history, "hour").unwrap(), 2);
+        assert_eq!(latest_sample(&db).map(|(feerate, _)| feerate), Some(4.0));
+        assert_eq!(history[0].reference("fastest"), Some(13.0));
+    }
+
     #[test]
     fn test_regime_no_data_defaults_high() {
         let db = Database::open_in_memory().unwrap();
-        let regime = current_regime(&db, 17.0, 23.0).unwrap();
+        let regime = current_regime(&db, &fees_config(6)).unwrap();
         assert_eq!(regime, FeeRegime::High);
     }
 
@@ -193,7 +559,7 @@ mod tests {
         // Insert a very low latest sample
         insert_sample(&db, 1.0, now + 1.0);
 
-        let regime = current_regime(&db, 17.0, 23.0).unwrap();
+        let regime = current_regime(&db, &fees_config(6)).unwrap();
         assert_eq!(regime, FeeRegime::Low);
     }
 
@@ -209,7 +575,7 @@ mod tests {
         // Insert a very high latest sample
         insert_sample(&db, 99.0, now + 1.0);
 
-        let regime = current_regime(&db, 17.0, 23.0).unwrap();
+        let regime = current_regime(&db, &fees_config(6)).unwrap();
         assert_eq!(regime, FeeRegime::High);
     }
 
@@ -226,15 +592,32 @@ mod tests {
         insert_sample(&db, 20.0, now + 1.0);
 
         // Default state is "high" (no saved state)
-        let regime = current_regime(&db, 17.0, 23.0).unwrap();
+        let regime = current_regime(&db, &fees_config(6)).unwrap();
         assert_eq!(regime, FeeRegime::High);
 
         // Save "low" state and check hysteresis preserves it
         save_regime(&db, FeeRegime::Low).unwrap();
-        let regime = current_regime(&db, 17.0, 23.0).unwrap();
+        let regime = current_regime(&db, &fees_config(6)).unwrap();
         assert_eq!(regime, FeeRegime::Low);
     }
 
+    #[test]
+    fn test_refresh_regime_persists_across_band() {
+        let db = Database::open_in_memory().unwrap();
+        let now = 1704067200.0;
+        for i in 1..=100 {
+            insert_sample(&db, i as f64, now - (100 - i) as f64 * 600.0);
+        }
+
+        // Cheap sample: regime drops to Low and is saved
+        insert_sample(&db, 1.0, now + 1.0);
+        assert_eq!(refresh_regime(&db, &fees_config(6)).unwrap(), FeeRegime::Low);
+
+        // Next sample lands inside the band: the saved Low must hold
+        insert_sample(&db, 20.0, now + 2.0);
+        assert_eq!(refresh_regime(&db, &fees_config(6)).unwrap(), FeeRegime::Low);
+    }
+
     #[test]
     fn test_save_and_load_regime() {
         let db = Database::open_in_memory().unwrap();
@@ -242,6 +625,7 @@ mod tests {
         save_regime(&db, FeeRegime::Low).unwrap();
         let val: String = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT value FROM run_state WHERE key = 'fee_regime'",
                 [],
@@ -253,6 +637,7 @@ mod tests {
         save_regime(&db, FeeRegime::High).unwrap();
         let val: String = db
             .conn()
+            .unwrap()
             .query_row(
                 "SELECT value FROM run_state WHERE key = 'fee_regime'",
                 [],
@@ -262,13 +647,94 @@ mod tests {
         assert_eq!(val, "high");
     }
 
+    #[test]
+    fn test_explain_reports_inputs() {
+        let mut db = Database::open_in_memory().unwrap();
+        db.set_clock(crate::clock::MockClock::at(1_700_000_000));
+        let explained = explain(&db, &fees_config(3)).unwrap();
+        assert_eq!(explained.sample_count, 0);
+        assert!(explained.latest.is_none() && explained.thresholds.is_none());
+
+        let now = db.now().timestamp() as f64;
+        for (i, fee) in [10.0, 20.0, 30.0, 5.0].iter().enumerate() {
+            insert_sample(&db, *fee, now + i as f64);
+        }
+        save_regime(&db, FeeRegime::High).unwrap();
+        let explained = explain(&db, &fees_config(3)).unwrap();
+        assert_eq!(explained.sample_count, 4);
+        assert_eq!(explained.latest, Some(5.0));
+        assert_eq!(explained.thresholds, Some((5.0, 5.0)));
+        assert_eq!(explained.regime, FeeRegime::Low);
+        assert_eq!(explained.saved.as_deref(), Some("high"));
+    }
+
+    #[test]
+    fn test_low_regime_since_tracks_transitions() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = crate::clock::MockClock::at(1_700_000_000);
+        db.set_clock(clock.clone());
+        save_regime(&db, FeeRegime::High).unwrap();
+        assert_eq!(low_regime_since(&db), None);
+
+        clock.advance_secs(600);
+        save_regime(&db, FeeRegime::Low).unwrap();
+        assert_eq!(low_regime_since(&db), Some(1_700_000_600));
+
+        // Staying low keeps the start time
+        clock.advance_secs(3600);
+        save_regime(&db, FeeRegime::Low).unwrap();
+        assert_eq!(low_regime_since(&db), Some(1_700_000_600));
+
+        save_regime(&db, FeeRegime::High).unwrap();
+        clock.advance_secs(600);
+        save_regime(&db, FeeRegime::Low).unwrap();
+        assert_eq!(low_regime_since(&db), Some(1_700_004_800));
+    }
+
     #[test]
     fn test_regime_single_sample() {
         let db = Database::open_in_memory().unwrap();
         // Single sample: latest is 5.0, only data point
         // lo_threshold = feerates[0] = 5.0, latest <= lo_threshold → Low
         insert_sample(&db, 5.0, 1704067200.0);
-        let regime = current_regime(&db, 17.0, 23.0).unwrap();
+        let regime = current_regime(&db, &fees_config(1)).unwrap();
+        assert_eq!(regime, FeeRegime::Low);
+    }
+
+    #[test]
+    fn test_regime_below_min_samples_uses_default() {
+        let db = Database::open_in_memory().unwrap();
+        insert_sample(&db, 5.0, 1704067200.0);
+
+        // One sample would compute as Low, but isn't enough history
+        let regime = current_regime(&db, &fees_config(6)).unwrap();
+        assert_eq!(regime, FeeRegime::High);
+
+        let mut config = fees_config(6);
+        config.default_regime = "low".to_string();
+        let regime = current_regime(&db, &config).unwrap();
         assert_eq!(regime, FeeRegime::Low);
     }
+
+    #[test]
+    fn test_regime_high_requires_sustained_samples() {
+        let db = Database::open_in_memory().unwrap();
+        let now = 1704067200.0;
+        for i in 1..=100 {
+            insert_sample(&db, i as f64, now - (100 - i) as f64 * 600.0);
+        }
+        let mut config = fees_config(6);
+        config.require_sustained_high_regime_samples = 3;
+
+        insert_sample(&db, 1.0, now + 1.0);
+        assert_eq!(refresh_regime(&db, &config).unwrap(), FeeRegime::Low);
+
+        // A two-sample spike leaves the low regime in place
+        insert_sample(&db, 99.0, now + 2.0);
+        insert_sample(&db, 99.0, now + 3.0);
+        assert_eq!(refresh_regime(&db, &config).unwrap(), FeeRegime::Low);
+
+        insert_sample(&db, 99.0, now + 4.0);
+        assert_eq!(refresh_regime(&db, &config).unwrap(), FeeRegime::High);
+    }
 }
@@ -0,0 +1,208 @@
+use crate::db::Database;
+use std::collections::HashSet;
+
+/// Observations older than this are dropped.
+const RETENTION_DAYS: i64 = 180;
+
+/// Record one connectivity observation for each peer we have a ready
+/// channel with, bucketed by day, and count a disconnect for each peer that
+/// was connected at the previous observation but isn't now.
+pub fn record(
+    db: &Database,
+    channel_peers: &HashSet<String>,
+    disconnected: &HashSet<String>,
+) -> anyhow::Result<()> {
+    let conn = db.conn()?;
+    let now = db.now().timestamp();
+    let bucket = now - (now % 86400);
+
+    for peer in channel_peers {
+        let connected = !disconnected.contains(peer);
+        conn.execute(
+            "INSERT INTO peer_uptime (counterparty_node_id, day_bucket, observations, connected) \
+             VALUES (?1, ?2, 1, ?3) \
+             ON CONFLICT(counterparty_node_id, day_bucket) DO UPDATE SET \
+             observations = observations + 1, connected = connected + ?3",
+            rusqlite::params![peer, bucket, connected as i64],
+        )?;
+
+        let was_connected = conn
+            .query_row(
+                "SELECT connected FROM peer_connectivity WHERE counterparty_node_id = ?1",
+                [peer],
+                |row| row.get::<_, bool>(0),
+            )
+            .unwrap_or(false);
+        if was_connected && !connected {
+            conn.execute(
+                "INSERT INTO peer_disconnects (counterparty_node_id, day_bucket, disconnects) \
+                 VALUES (?1, ?2, 1) \
+                 ON CONFLICT(counterparty_node_id, day_bucket) DO UPDATE SET \
+                 disconnects = disconnects + 1",
+                rusqlite::params![peer, bucket],
+            )?;
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO peer_connectivity (counterparty_node_id, connected) \
+             VALUES (?1, ?2)",
+            rusqlite::params![peer, connected],
+        )?;
+    }
+
+    conn.execute(
+        "DELETE FROM peer_uptime WHERE day_bucket < ?1",
+        [bucket - RETENTION_DAYS * 86400],
+    )?;
+    conn.execute(
+        "DELETE FROM peer_disconnects WHERE day_bucket < ?1",
+        [bucket - RETENTION_DAYS * 86400],
+    )?;
+
+    Ok(())
+}
+
+/// Share of observations since `since` (unix seconds) in which the peer was
+/// connected, in percent. `None` if we never observed it.
+pub fn uptime_percent_since(
+    db: &Database,
+    counterparty_node_id: &str,
+    since: f64,
+) -> anyhow::Result<Option<f64>> {
+    uptime_percent_in(db, counterparty_node_id, since, None)
+}
+
+/// Like `uptime_percent_since`, up to and including the day of `until`.
+pub fn uptime_percent_between(
+    db: &Database,
+    counterparty_node_id: &str,
+    since: f64,
+    until: f64,
+) -> anyhow::Result<Option<f64>> {
+    uptime_percent_in(db, counterparty_node_id, since, Some(until))
+}
+
+fn uptime_percent_in(
+    db: &Database,
+    counterparty_node_id: &str,
+    since: f64,
+    until: Option<f64>,
+) -> anyhow::Result<Option<f64>> {
+    let (observations, connected): (i64, i64) = db.conn()?.query_row(
+        "SELECT COALESCE(SUM(observations), 0), COALESCE(SUM(connected), 0) \
+         FROM peer_uptime WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 \
+         AND (?3 IS NULL OR day_bucket <= ?3)",
+        rusqlite::params![counterparty_node_id, bucket(since), until.map(bucket)],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    if observations == 0 {
+        return Ok(None);
+    }
+    Ok(Some(connected as f64 * 100.0 / observations as f64))
+}
+
+/// Times the peer went from connected to disconnected since `since` (unix
+/// seconds).
+pub fn disconnects_since(
+    db: &Database,
+    counterparty_node_id: &str,
+    since: f64,
+) -> anyhow::Result<u64> {
+    disconnects_in(db, counterparty_node_id, since, None)
+}
+
+/// Like `disconnects_since`, up to and including the day of `until`.
+pub fn disconnects_between(
+    db: &Database,
+    counterparty_node_id: &str,
+    since: f64,
+    until: f64,
+) -> anyhow::Result<u64> {
+    disconnects_in(db, counterparty_node_id, since, Some(until))
+}
+
+fn disconnects_in(
+    db: &Database,
+    counterparty_node_id: &str,
+    since: f64,
+    until: Option<f64>,
+) -> anyhow::Result<u64> {
+    let count: i64 = db.conn()?.query_row(
+        "SELECT COALESCE(SUM(disconnects), 0) FROM peer_disconnects \
+         WHERE counterparty_node_id = ?1 AND day_bucket >= ?2 \
+         AND (?3 IS NULL OR day_bucket <= ?3)",
+        rusqlite::params![counterparty_node_id, bucket(since), until.map(bucket)],
+        |row| row.get(0),
+    )?;
+    Ok(count as u64)
+}
+
+/// Start of the UTC day `timestamp` falls in.
+fn bucket(timestamp: f64) -> i64 {
+    timestamp as i64 - (timestamp as i64 % 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    /// An hour into a UTC day
+    const START: i64 = 1_699_920_000 + 3600;
+
+    #[test]
+    fn test_uptime_percent() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = MockClock::at(START);
+        db.set_clock(clock.clone());
+        let peers: HashSet<String> = ["flaky".to_string(), "solid".to_string()].into();
+        let down: HashSet<String> = ["flaky".to_string()].into();
+
+        record(&db, &peers, &HashSet::new()).unwrap();
+        for _ in 0..3 {
+            clock.advance_secs(600);
+            record(&db, &peers, &down).unwrap();
+        }
+
+        let since = START as f64;
+        assert_eq!(uptime_percent_since(&db, "flaky", since).unwrap(), Some(25.0));
+        assert_eq!(uptime_percent_since(&db, "solid", since).unwrap(), Some(100.0));
+        assert_eq!(uptime_percent_since(&db, "unseen", since).unwrap(), None);
+
+        // The next day's observations are outside a window ending on the first
+        clock.advance_secs(86400);
+        record(&db, &peers, &HashSet::new()).unwrap();
+        assert_eq!(uptime_percent_between(&db, "flaky", since, since).unwrap(), Some(25.0));
+        assert_eq!(uptime_percent_since(&db, "flaky", since).unwrap(), Some(40.0));
+        let next_day = db.now().timestamp() as f64;
+        assert_eq!(uptime_percent_since(&db, "flaky", next_day).unwrap(), Some(100.0));
+    }
+
+    #[test]
+    fn test_disconnects_count_transitions() {
+        let mut db = Database::open_in_memory().unwrap();
+        let clock = MockClock::at(START);
+        db.set_clock(clock.clone());
+        let peers: HashSet<String> = ["flappy".to_string()].into();
+        let down: HashSet<String> = ["flappy".to_string()].into();
+
+        // Down from the start isn't a disconnect; then up, down, down, up, down
+        for d in [&down, &HashSet::new(), &down, &down, &HashSet::new(), &down] {
+            record(&db, &peers, d).unwrap();
+            clock.advance_secs(600);
+        }
+
+        let since = START as f64;
+        assert_eq!(disconnects_since(&db, "flappy", since).unwrap(), 2);
+        assert_eq!(disconnects_since(&db, "unseen", since).unwrap(), 0);
+
+        // One more the next day
+        clock.advance_secs(86400);
+        record(&db, &peers, &HashSet::new()).unwrap();
+        record(&db, &peers, &down).unwrap();
+        assert_eq!(disconnects_since(&db, "flappy", since).unwrap(), 3);
+        assert_eq!(disconnects_between(&db, "flappy", since, since).unwrap(), 2);
+        let next_day = db.now().timestamp() as f64;
+        assert_eq!(disconnects_since(&db, "flappy", next_day).unwrap(), 1);
+    }
+}
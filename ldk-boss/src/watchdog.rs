@@ -0,0 +1,102 @@
+/// Cycle watchdog: a cycle that stops making progress is abandoned instead
+/// of stalling the daemon forever.
+///
+/// Only an await that never completes can be cut short this way; a module
+/// that blocks its thread is out of reach of any timeout on the same task.
+/// After `max_consecutive` abandoned cycles in a row the daemon gives up, so
+/// a supervisor can restart it.
+
+use log::error;
+use std::future::Future;
+use std::time::Duration;
+
+pub struct Watchdog {
+    /// `None` disables the timeout
+    timeout: Option<Duration>,
+    /// 0 = never give up
+    max_consecutive: u32,
+    consecutive: u32,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Option<Duration>, max_consecutive: u32) -> Self {
+        Self {
+            timeout,
+            max_consecutive,
+            consecutive: 0,
+        }
+    }
+
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let secs = config.general.cycle_timeout_secs;
+        Self::new(
+            (secs > 0).then(|| Duration::from_secs(secs)),
+            config.general.max_cycle_timeouts,
+        )
+    }
+
+    /// Run one cycle under the timeout. The cycle's own error is logged and
+    /// swallowed, as before; an error is returned only once too many cycles
+    /// in a row have timed out.
+    pub async fn run(
+        &mut self,
+        cycle: impl Future<Output = anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        let result = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, cycle).await,
+            None => Ok(cycle.await),
+        };
+
+        match result {
+            Ok(res) => {
+                self.consecutive = 0;
+                if let Err(e) = res {
+                    error!("Cycle error: {:#}", e);
+                }
+                Ok(())
+            }
+            Err(_) => {
+                self.consecutive += 1;
+                error!(
+                    "Cycle timed out after {:?} and was abandoned ({} in a row)",
+                    self.timeout.unwrap_or_default(),
+                    self.consecutive
+                );
+                if self.max_consecutive > 0 && self.consecutive >= self.max_consecutive {
+                    anyhow::bail!(
+                        "{} consecutive cycles timed out; exiting so the process can be restarted",
+                        self.consecutive
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hang() -> impl Future<Output = anyhow::Result<()>> {
+        std::future::pending()
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_consecutive_timeouts() {
+        let mut watchdog = Watchdog::new(Some(Duration::from_millis(10)), 2);
+
+        assert!(watchdog.run(hang()).await.is_ok());
+        assert!(watchdog.run(hang()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_completed_cycle_resets_count() {
+        let mut watchdog = Watchdog::new(Some(Duration::from_millis(10)), 2);
+
+        assert!(watchdog.run(hang()).await.is_ok());
+        // A failing cycle still finished, so it isn't a hang
+        assert!(watchdog.run(async { anyhow::bail!("boom") }).await.is_ok());
+        assert!(watchdog.run(hang()).await.is_ok());
+    }
+}